@@ -4,15 +4,14 @@ use imageproc::filter::median_filter;
 use imageproc::rect::Rect;
 use log::info;
 use rustc_hash::FxHashMap as HashMap;
-use std::{
-    error::Error,
-    io::{BufReader, BufWriter},
-    path::Path,
-};
+use std::{io::BufReader, path::Path};
 
-use crate::io::{bytes::FromToBytes, fs::FileSystem, heightmap::HeightMap, xyz::XyzInternalReader};
+use crate::error::Result;
+use crate::io::{
+    bytes::FromToBytes, fs::FileSystem, heightmap::HeightMap, write_png, xyz::XyzInternalReader,
+};
 
-pub fn blocks(fs: &impl FileSystem, tmpfolder: &Path) -> Result<(), Box<dyn Error>> {
+pub fn blocks(fs: &impl FileSystem, tmpfolder: &Path) -> Result<()> {
     info!("Identifying blocks...");
 
     let heightmap_in = tmpfolder.join("xyz2.hmap");
@@ -40,8 +39,8 @@ pub fn blocks(fs: &impl FileSystem, tmpfolder: &Path) -> Result<(), Box<dyn Erro
 
     let xyz_file_in = tmpfolder.join("xyztemp.xyz.bin");
     let file = BufReader::new(fs.open(&xyz_file_in)?);
-    let mut reader = XyzInternalReader::new(file).unwrap();
-    while let Some(r) = reader.next().unwrap() {
+    let mut reader = XyzInternalReader::new(file)?;
+    while let Some(r) = reader.next()? {
         let (x, y, h) = (r.x, r.y, r.z);
         let r3 = r.classification;
         let r4 = r.number_of_returns;
@@ -73,14 +72,7 @@ pub fn blocks(fs: &impl FileSystem, tmpfolder: &Path) -> Result<(), Box<dyn Erro
         }
     }
 
-    img2.write_to(
-        &mut BufWriter::new(
-            fs.create(tmpfolder.join("blocks2.png"))
-                .expect("error saving png"),
-        ),
-        image::ImageFormat::Png,
-    )
-    .expect("error saving png");
+    write_png(fs, &img2, tmpfolder.join("blocks2.png"))?;
 
     let mut img = DynamicImage::ImageRgb8(img);
 
@@ -89,14 +81,7 @@ pub fn blocks(fs: &impl FileSystem, tmpfolder: &Path) -> Result<(), Box<dyn Erro
     let filter_size = 2;
     img = image::DynamicImage::ImageRgb8(median_filter(&img.to_rgb8(), filter_size, filter_size));
 
-    img.write_to(
-        &mut BufWriter::new(
-            fs.create(tmpfolder.join("blocks.png"))
-                .expect("error saving png"),
-        ),
-        image::ImageFormat::Png,
-    )
-    .expect("error saving png");
+    write_png(fs, &img, tmpfolder.join("blocks.png"))?;
     info!("Done");
     Ok(())
 }
@@ -0,0 +1,846 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use crate::io::raster::is_raster;
+use crate::util::suggest;
+
+/// A subcommand of the command line, with its usage and a short description for the help.
+pub struct CommandHelp {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub about: &'static str,
+}
+
+const fn command(name: &'static str, usage: &'static str, about: &'static str) -> CommandHelp {
+    CommandHelp { name, usage, about }
+}
+
+/// All the subcommands, in the order they are listed in the help.
+pub const COMMANDS: &[CommandHelp] = &[
+    command(
+        "blocks",
+        "blocks",
+        "Draws the buildings and other blocks of the temp folder to blocks.png",
+    ),
+    command(
+        "checkconfig",
+        "checkconfig",
+        "Checks the config for unknown keys, invalid values and inconsistent settings",
+    ),
+    command(
+        "cliffgeneralize",
+        "cliffgeneralize",
+        "Joins and simplifies the cliffs of the temp folder into cliffsgeneralized.dxf",
+    ),
+    command(
+        "dotknolls",
+        "dotknolls",
+        "Identifies the dot knolls of the temp folder, writes dotknolls.dxf",
+    ),
+    command(
+        "dxfmerge",
+        "dxfmerge",
+        "Merges the dxf files of the batch output folder",
+    ),
+    command(
+        "exportheightmap",
+        "exportheightmap <input .hmap> <output .tif, .asc or .png> [hillshade]",
+        "Exports a heightmap of the temp folder as a raster, or as a hillshade",
+    ),
+    command(
+        "ground",
+        "ground <input> <output .xyz.bin>",
+        "Classifies the ground points of an unclassified point cloud",
+    ),
+    command(
+        "help",
+        "help [command]",
+        "Shows the help of all the commands or of one command",
+    ),
+    command(
+        "internal2xyz",
+        "internal2xyz <input .xyz.bin> <output .xyz>",
+        "Converts an internal point file to a text file",
+    ),
+    command(
+        "knolldetector",
+        "knolldetector",
+        "Detects the knolls of the temp folder",
+    ),
+    command(
+        "makecliffs",
+        "makecliffs",
+        "Generates the cliffs of the temp folder",
+    ),
+    command(
+        "makeheight",
+        "makeheight",
+        "Generates the vegetation height raster of the temp folder",
+    ),
+    command(
+        "makevege",
+        "makevege",
+        "Generates the vegetation of the temp folder",
+    ),
+    command(
+        "merge",
+        "merge [scale]",
+        "Merges the dxf files and the vegetation images of the batch output folder",
+    ),
+    command(
+        "mtkshaperender",
+        "mtkshaperender",
+        "Renders the shape files of the temp folder",
+    ),
+    command(
+        "pngmerge",
+        "pngmerge [scale]",
+        "Merges the map tiles of the batch output folder, scaled down by scale (4 by default)",
+    ),
+    command(
+        "pngmergedepr",
+        "pngmergedepr [scale]",
+        "Merges the map tiles with depressions of the batch output folder",
+    ),
+    command(
+        "pngmergevege",
+        "pngmergevege [scale]",
+        "Merges the vegetation images of the batch output folder",
+    ),
+    command(
+        "pointdxfcrop",
+        "pointdxfcrop <input> <output> <minx> <miny> <maxx> <maxy>",
+        "Crops the points of a dxf file",
+    ),
+    command(
+        "polylinedxfcrop",
+        "polylinedxfcrop <input> <output> <minx> <miny> <maxx> <maxy>",
+        "Crops the polylines of a dxf file",
+    ),
+    command(
+        "presets",
+        "presets [name]",
+        "Lists the config presets, or prints one as a complete config file",
+    ),
+    command(
+        "profile",
+        "profile <line file or x1,y1;x2,y2;...> <output> [step]",
+        "Draws an elevation and vegetation profile along a line",
+    ),
+    command(
+        "render",
+        "render <northlines angle> <northlines width> [nodepressions]",
+        "Renders the map of the temp folder",
+    ),
+    command(
+        "smoothjoin",
+        "smoothjoin",
+        "Smooths and joins the contours of the temp folder",
+    ),
+    command(
+        "startthread",
+        "startthread [thread]",
+        "Runs one batch processing worker",
+    ),
+    command(
+        "unzipmtk",
+        "unzipmtk <zip files...>",
+        "Unzips and renders Maastotietokanta shape files",
+    ),
+    command(
+        "vege",
+        "vege",
+        "Generates the vegetation of the temp folder, like makevege",
+    ),
+    command(
+        "xyz2contours",
+        "xyz2contours <interval> <input> <heightmap output or null> <dxf output>",
+        "Makes a heightmap and contours from a point file",
+    ),
+    command(
+        "xyzfixer",
+        "xyzfixer <input> <output>",
+        "Rewrites a text point file in the standard column order",
+    ),
+    command(
+        "xyzknolls",
+        "xyzknolls",
+        "Raises the knolls of the heightmap of the temp folder",
+    ),
+];
+
+/// Commands of the Perl version that are recognized but not implemented.
+const NOT_IMPLEMENTED: &[&str] = &["makecliffsold"];
+
+/// Commands of the Perl version that are replaced by another command of this version.
+const REPLACED: &[(&str, &str)] = &[("ground2", "ground"), ("groundfix", "ground")];
+
+/// A parsed command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// No command: batch processes the input folder, or renders the temp folder.
+    Default,
+    /// Processes a point cloud or elevation raster.
+    Process {
+        input: PathBuf,
+        norender: bool,
+    },
+    /// Processes Maastotietokanta zip files.
+    Zip {
+        files: Vec<String>,
+    },
+    Blocks,
+    CheckConfig,
+    CliffGeneralize,
+    DotKnolls,
+    /// `merge` also merges the vegetation images, scaled down by the scale.
+    DxfMerge {
+        vegetation_scale: Option<f64>,
+    },
+    ExportHeightmap {
+        input: PathBuf,
+        output: PathBuf,
+        hillshade: bool,
+    },
+    Ground {
+        input: PathBuf,
+        output: PathBuf,
+    },
+    Help {
+        command: Option<String>,
+    },
+    Internal2Xyz {
+        input: String,
+        output: String,
+    },
+    KnollDetector,
+    MakeCliffs,
+    MakeHeight,
+    MakeVege,
+    MtkShapeRender,
+    NotImplemented {
+        command: String,
+    },
+    PngMerge {
+        scale: f64,
+        depressions: bool,
+    },
+    PngMergeVege {
+        scale: f64,
+    },
+    DxfCrop {
+        input: PathBuf,
+        output: PathBuf,
+        bounds: [f64; 4],
+        points: bool,
+    },
+    /// Lists the presets, or prints the named one as ini.
+    Presets {
+        name: Option<String>,
+    },
+    Profile {
+        line: String,
+        output: PathBuf,
+        step: f64,
+    },
+    Render {
+        angle: f64,
+        nwidth: usize,
+        nodepressions: bool,
+    },
+    SmoothJoin,
+    StartThread {
+        thread: String,
+    },
+    UnzipMtk {
+        files: Vec<String>,
+    },
+    Vege,
+    Version,
+    Xyz2Contours {
+        interval: f64,
+        input: String,
+        heightmap: Option<String>,
+        dxf: String,
+    },
+    XyzFixer {
+        input: PathBuf,
+        output: PathBuf,
+    },
+    XyzKnolls,
+}
+
+/// The parsed command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cli {
+    /// Thread id, the suffix of the temp folder and output files.
+    pub thread: String,
+    /// Config files given with `--config`, each overriding the keys of the ones before it.
+    pub config: Vec<PathBuf>,
+    /// Whether a missing `pullauta.ini` is an error instead of being created, `--no-create-config`.
+    pub no_create_config: bool,
+    /// Directory given with `--workdir` to run in.
+    pub workdir: Option<PathBuf>,
+    pub command: Command,
+}
+
+/// Error in the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliError {
+    UnknownCommand {
+        name: String,
+        suggestion: Option<&'static str>,
+    },
+    UnknownOption(String),
+    MissingOptionValue(&'static str),
+    MissingArgument {
+        command: &'static str,
+        argument: &'static str,
+    },
+    InvalidValue {
+        command: &'static str,
+        argument: &'static str,
+        value: String,
+        expected: &'static str,
+    },
+    UnexpectedArgument {
+        command: &'static str,
+        value: String,
+    },
+    Replaced {
+        name: &'static str,
+        replacement: &'static str,
+    },
+}
+
+impl CliError {
+    /// The command whose usage helps with the error, if any.
+    pub fn command(&self) -> Option<&'static str> {
+        match self {
+            CliError::MissingArgument { command, .. }
+            | CliError::InvalidValue { command, .. }
+            | CliError::UnexpectedArgument { command, .. }
+            | CliError::Replaced {
+                replacement: command,
+                ..
+            } => Some(command),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::UnknownCommand { name, suggestion } => {
+                write!(f, "unknown command or input file '{}'", name)?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, ", did you mean '{}'?", suggestion)?;
+                }
+                Ok(())
+            }
+            CliError::UnknownOption(option) => write!(f, "unknown option '{}'", option),
+            CliError::MissingOptionValue(option) => write!(f, "{} needs a value", option),
+            CliError::MissingArgument { command, argument } => {
+                write!(f, "{} is missing the argument <{}>", command, argument)
+            }
+            CliError::InvalidValue {
+                command,
+                argument,
+                value,
+                expected,
+            } => write!(
+                f,
+                "invalid value '{}' for <{}> of {}, expected {}",
+                value, argument, command, expected
+            ),
+            CliError::UnexpectedArgument { command, value } => {
+                write!(f, "unexpected argument '{}' for {}", value, command)
+            }
+            CliError::Replaced { name, replacement } => write!(
+                f,
+                "{} of the Perl version is replaced by {} in this version",
+                name, replacement
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// A value parsed from a positional argument.
+trait FromArg: Sized {
+    const EXPECTED: &'static str;
+    fn from_arg(arg: &str) -> Option<Self>;
+}
+
+impl FromArg for f64 {
+    const EXPECTED: &'static str = "a number";
+    fn from_arg(arg: &str) -> Option<Self> {
+        arg.trim().parse().ok().filter(|v: &f64| v.is_finite())
+    }
+}
+
+impl FromArg for usize {
+    const EXPECTED: &'static str = "a whole number";
+    fn from_arg(arg: &str) -> Option<Self> {
+        arg.trim().parse().ok()
+    }
+}
+
+impl FromArg for String {
+    const EXPECTED: &'static str = "text";
+    fn from_arg(arg: &str) -> Option<Self> {
+        Some(arg.to_string())
+    }
+}
+
+impl FromArg for PathBuf {
+    const EXPECTED: &'static str = "a path";
+    fn from_arg(arg: &str) -> Option<Self> {
+        Some(PathBuf::from(arg))
+    }
+}
+
+/// The positional arguments of a command, consumed in order.
+struct Args {
+    command: &'static str,
+    args: std::vec::IntoIter<String>,
+}
+
+impl Args {
+    fn required<T: FromArg>(&mut self, argument: &'static str) -> Result<T, CliError> {
+        self.optional(argument)?.ok_or(CliError::MissingArgument {
+            command: self.command,
+            argument,
+        })
+    }
+
+    fn optional<T: FromArg>(&mut self, argument: &'static str) -> Result<Option<T>, CliError> {
+        self.args
+            .next()
+            .map(|value| {
+                T::from_arg(&value).ok_or(CliError::InvalidValue {
+                    command: self.command,
+                    argument,
+                    value,
+                    expected: T::EXPECTED,
+                })
+            })
+            .transpose()
+    }
+
+    /// Consumes an optional keyword, like `nodepressions`.
+    fn keyword(&mut self, keyword: &str) -> Result<bool, CliError> {
+        match self.args.next() {
+            None => Ok(false),
+            Some(value) if value == keyword => Ok(true),
+            Some(value) => Err(CliError::UnexpectedArgument {
+                command: self.command,
+                value,
+            }),
+        }
+    }
+
+    fn rest(self) -> Vec<String> {
+        self.args.collect()
+    }
+
+    /// Fails if there are arguments left.
+    fn finish(mut self) -> Result<(), CliError> {
+        match self.args.next() {
+            None => Ok(()),
+            Some(value) => Err(CliError::UnexpectedArgument {
+                command: self.command,
+                value,
+            }),
+        }
+    }
+
+    /// Ignores the arguments left, which the Perl version took for some commands.
+    fn ignore_rest(self) {
+        let command = self.command;
+        let rest = self.rest();
+        if !rest.is_empty() {
+            warn!("Ignoring the arguments {:?} of {}", rest, command);
+        }
+    }
+}
+
+/// Whether an argument is an input file to process, told by its extension.
+fn is_input_file(arg: &str) -> bool {
+    let lowercase = arg.to_lowercase();
+    lowercase.ends_with(".las")
+        || lowercase.ends_with(".laz")
+        || lowercase.ends_with(".xyz")
+        || lowercase.ends_with(".xyz.bin")
+        || is_raster(Path::new(&lowercase))
+}
+
+impl Cli {
+    /// Parses the command line arguments, without the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, CliError> {
+        let mut config = Vec::new();
+        let mut no_create_config = false;
+        let mut workdir = None;
+        let mut help = false;
+        let mut version = false;
+        let mut positional = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                positional.extend(args.by_ref());
+                break;
+            }
+            if !arg.starts_with('-') || arg == "-" || f64::from_arg(&arg).is_some() {
+                positional.push(arg);
+                continue;
+            }
+            let (option, value) = match arg.split_once('=') {
+                Some((option, value)) => (option.to_string(), Some(value.to_string())),
+                None => (arg.clone(), None),
+            };
+            match option.as_str() {
+                "-h" | "--help" => help = true,
+                "-V" | "--version" => version = true,
+                "--config" => {
+                    let value = value.or_else(|| args.next());
+                    config.push(PathBuf::from(
+                        value.ok_or(CliError::MissingOptionValue("--config"))?,
+                    ));
+                }
+                "--no-create-config" => no_create_config = true,
+                "--workdir" => {
+                    let value = value.or_else(|| args.next());
+                    workdir = Some(PathBuf::from(
+                        value.ok_or(CliError::MissingOptionValue("--workdir"))?,
+                    ));
+                }
+                _ => return Err(CliError::UnknownOption(arg)),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let mut first = positional.next();
+        // a leading number is the thread id
+        let mut thread = String::new();
+        if let Some(arg) = &first {
+            if arg.trim().parse::<usize>().is_ok() {
+                thread = arg.clone();
+                first = positional.next();
+            }
+        }
+
+        let command = if version {
+            Command::Version
+        } else if help {
+            Command::Help { command: first }
+        } else {
+            match first {
+                None => Command::Default,
+                Some(first) => parse_command(first, positional.collect())?,
+            }
+        };
+
+        Ok(Cli {
+            thread,
+            config,
+            no_create_config,
+            workdir,
+            command,
+        })
+    }
+}
+
+fn parse_command(name: String, args: Vec<String>) -> Result<Command, CliError> {
+    if is_input_file(&name) {
+        // anything after the input file but `norender` was ignored by earlier versions
+        let norender = args.iter().any(|a| a == "norender");
+        return Ok(Command::Process {
+            input: PathBuf::from(name),
+            norender,
+        });
+    }
+    if name.to_lowercase().ends_with(".zip") {
+        let mut files = vec![name];
+        files.extend(args);
+        return Ok(Command::Zip { files });
+    }
+    if let Some(&(name, replacement)) = REPLACED.iter().find(|(c, _)| *c == name) {
+        return Err(CliError::Replaced { name, replacement });
+    }
+    if let Some(&command) = NOT_IMPLEMENTED.iter().find(|&&c| c == name) {
+        return Ok(Command::NotImplemented {
+            command: command.to_string(),
+        });
+    }
+    let Some(help) = COMMANDS.iter().find(|c| c.name == name) else {
+        return Err(CliError::UnknownCommand {
+            suggestion: suggest(&name, COMMANDS.iter().map(|c| c.name)),
+            name,
+        });
+    };
+
+    let mut args = Args {
+        command: help.name,
+        args: args.into_iter(),
+    };
+    let command = match help.name {
+        "blocks" => Command::Blocks,
+        "checkconfig" => Command::CheckConfig,
+        "cliffgeneralize" => Command::CliffGeneralize,
+        "dotknolls" => Command::DotKnolls,
+        "dxfmerge" => Command::DxfMerge {
+            vegetation_scale: None,
+        },
+        "exportheightmap" => {
+            let input = args.required("input")?;
+            let output = args.required("output")?;
+            let hillshade = args.keyword("hillshade")?;
+            Command::ExportHeightmap {
+                input,
+                output,
+                hillshade,
+            }
+        }
+        "ground" => Command::Ground {
+            input: args.required("input")?,
+            output: args.required("output")?,
+        },
+        "help" => Command::Help {
+            command: args.optional("command")?,
+        },
+        "internal2xyz" => Command::Internal2Xyz {
+            input: args.required("input")?,
+            output: args.required("output")?,
+        },
+        "knolldetector" => Command::KnollDetector,
+        "makecliffs" => Command::MakeCliffs,
+        "makeheight" => Command::MakeHeight,
+        "makevege" => Command::MakeVege,
+        "merge" => Command::DxfMerge {
+            vegetation_scale: Some(args.optional("scale")?.unwrap_or(1.0)),
+        },
+        "mtkshaperender" => Command::MtkShapeRender,
+        "pngmerge" | "pngmergedepr" => Command::PngMerge {
+            scale: args.optional("scale")?.unwrap_or(4.0),
+            depressions: help.name == "pngmergedepr",
+        },
+        "pngmergevege" => Command::PngMergeVege {
+            scale: args.optional("scale")?.unwrap_or(1.0),
+        },
+        "pointdxfcrop" | "polylinedxfcrop" => Command::DxfCrop {
+            input: args.required("input")?,
+            output: args.required("output")?,
+            bounds: [
+                args.required("minx")?,
+                args.required("miny")?,
+                args.required("maxx")?,
+                args.required("maxy")?,
+            ],
+            points: help.name == "pointdxfcrop",
+        },
+        "presets" => Command::Presets {
+            name: args.optional("name")?,
+        },
+        "profile" => Command::Profile {
+            line: args.required("line")?,
+            output: args.required("output")?,
+            step: args.optional("step")?.unwrap_or(1.0),
+        },
+        "render" => {
+            let angle = args.required("northlines angle")?;
+            let nwidth = args.required("northlines width")?;
+            let nodepressions = args.keyword("nodepressions")?;
+            Command::Render {
+                angle,
+                nwidth,
+                nodepressions,
+            }
+        }
+        "smoothjoin" => Command::SmoothJoin,
+        "startthread" => Command::StartThread {
+            thread: args
+                .optional::<usize>("thread")?
+                .map_or(String::new(), |t| {
+                    if t == 0 {
+                        String::new()
+                    } else {
+                        t.to_string()
+                    }
+                }),
+        },
+        "unzipmtk" => {
+            let files = args.rest();
+            if files.is_empty() {
+                return Err(CliError::MissingArgument {
+                    command: help.name,
+                    argument: "zip files",
+                });
+            }
+            return Ok(Command::UnzipMtk { files });
+        }
+        "vege" => Command::Vege,
+        "xyz2contours" => {
+            let interval = args.required("interval")?;
+            let input = args.required("input")?;
+            let heightmap: String = args.required("heightmap output")?;
+            let dxf = args.required("dxf output")?;
+            Command::Xyz2Contours {
+                interval,
+                input,
+                heightmap: Some(heightmap).filter(|h| h != "null" && !h.is_empty()),
+                dxf,
+            }
+        }
+        "xyzfixer" => Command::XyzFixer {
+            input: args.required("input")?,
+            output: args.required("output")?,
+        },
+        "xyzknolls" => Command::XyzKnolls,
+        _ => unreachable!("command {} is not parsed", help.name),
+    };
+
+    // the commands without arguments took some in the Perl version, still accept them
+    if help.usage == help.name {
+        args.ignore_rest();
+    } else {
+        args.finish()?;
+    }
+    Ok(command)
+}
+
+/// Help of the whole command line.
+pub fn help() -> String {
+    let mut help = format!(
+        "Karttapullautin v{}\n\n\
+USAGE:\n    pullauta [OPTIONS] [thread] [input file | zip files... | command [arguments]]\n\n\
+Without a command, processes all the input files in batch mode (batch=1 in the ini file),\n\
+or renders the map of the temp folder.\n\n\
+INPUT:\n    <input.las | .laz | .xyz | .xyz.bin | .tif | .asc> [norender]\n                      \
+Processes a point cloud or an elevation raster\n    <zip files...>    Renders Maastotietokanta zip files\n\n\
+OPTIONS:\n    --config <path>   Config file to use instead of pullauta.ini, give several to stack them\n    \
+--no-create-config\n                      Fails if there is no pullauta.ini instead of creating it\n    \
+--workdir <path>  Directory to run in, relative paths are read from it\n    \
+-h, --help        Shows this help, or the help of a command\n    \
+-V, --version     Shows the version\n\nCOMMANDS:\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    for command in COMMANDS {
+        help.push_str(&format!("    {:<18}{}\n", command.name, command.about));
+    }
+    help.push_str("\nRun 'pullauta <command> --help' for the arguments of a command. See README.md for more details.");
+    help
+}
+
+/// Help of a single command, `None` if there is no such command.
+pub fn command_help(name: &str) -> Option<String> {
+    COMMANDS.iter().find(|c| c.name == name).map(|c| {
+        format!(
+            "{}\n\nUSAGE:\n    pullauta [OPTIONS] [thread] {}",
+            c.about, c.usage
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli, CliError> {
+        Cli::parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_parse() {
+        let cli = parse("3 render 45 2 nodepressions").unwrap();
+        assert_eq!(cli.thread, "3");
+        assert_eq!(
+            cli.command,
+            Command::Render {
+                angle: 45.0,
+                nwidth: 2,
+                nodepressions: true
+            }
+        );
+
+        let cli =
+            parse("--workdir=project L3323H3.laz --config base.ini --config=project.ini").unwrap();
+        assert_eq!(
+            cli.config,
+            vec![PathBuf::from("base.ini"), PathBuf::from("project.ini")]
+        );
+        assert!(!cli.no_create_config);
+        assert_eq!(cli.workdir, Some(PathBuf::from("project")));
+        assert_eq!(
+            cli.command,
+            Command::Process {
+                input: PathBuf::from("L3323H3.laz"),
+                norender: false
+            }
+        );
+
+        assert_eq!(
+            parse("polylinedxfcrop in.dxf out.dxf -10 -20.5 30 40")
+                .unwrap()
+                .command,
+            Command::DxfCrop {
+                input: PathBuf::from("in.dxf"),
+                output: PathBuf::from("out.dxf"),
+                bounds: [-10.0, -20.5, 30.0, 40.0],
+                points: false
+            }
+        );
+        assert_eq!(parse("").unwrap().command, Command::Default);
+        assert_eq!(
+            parse("--config base.ini checkconfig").unwrap().command,
+            Command::CheckConfig
+        );
+        assert_eq!(
+            parse("render --help").unwrap().command,
+            Command::Help {
+                command: Some("render".into())
+            }
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse("render 45").unwrap_err(),
+            CliError::MissingArgument {
+                command: "render",
+                argument: "northlines width"
+            }
+        );
+        assert_eq!(
+            parse("render north 2").unwrap_err().to_string(),
+            "invalid value 'north' for <northlines angle> of render, expected a number"
+        );
+        assert_eq!(
+            parse("pngmerge 1 2").unwrap_err(),
+            CliError::UnexpectedArgument {
+                command: "pngmerge",
+                value: "2".into()
+            }
+        );
+        assert_eq!(
+            parse("rendr 45 2").unwrap_err().to_string(),
+            "unknown command or input file 'rendr', did you mean 'render'?"
+        );
+        assert_eq!(
+            parse("--verbose").unwrap_err(),
+            CliError::UnknownOption("--verbose".into())
+        );
+        assert_eq!(
+            parse("groundfix in.laz out.xyz.bin").unwrap_err(),
+            CliError::Replaced {
+                name: "groundfix",
+                replacement: "ground"
+            }
+        );
+    }
+}
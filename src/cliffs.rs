@@ -2,23 +2,22 @@ use image::{Rgb, RgbImage};
 use log::info;
 use rand::distributions;
 use rand::prelude::*;
+use rustc_hash::FxHashMap as HashMap;
 use std::borrow::Cow;
-use std::error::Error;
 use std::io::{BufReader, BufWriter, Write};
 use std::path::Path;
 
 use crate::config::Config;
+use crate::error::{Error, Result, ResultExt};
 use crate::io::bytes::FromToBytes;
+use crate::io::dxf::{read_polylines, write_polylines, Polyline};
 use crate::io::fs::FileSystem;
 use crate::io::heightmap::HeightMap;
+use crate::io::write_png;
 use crate::io::xyz::XyzInternalReader;
 use crate::vec2d::Vec2D;
 
-pub fn makecliffs(
-    fs: &impl FileSystem,
-    config: &Config,
-    tmpfolder: &Path,
-) -> Result<(), Box<dyn Error>> {
+pub fn makecliffs(fs: &impl FileSystem, config: &Config, tmpfolder: &Path) -> Result<()> {
     info!("Identifying cliffs...");
 
     let &Config {
@@ -28,6 +27,7 @@ pub fn makecliffs(
         steep_factor,
         flat_place,
         mut no_small_ciffs,
+        nodata_distance,
         ..
     } = config;
 
@@ -94,7 +94,12 @@ pub fn makecliffs(
     let xyz_file_in = tmpfolder.join("xyztemp.xyz.bin");
 
     let mut rng = rand::thread_rng();
-    let randdist = distributions::Bernoulli::new(cliff_thin).unwrap();
+    let randdist = distributions::Bernoulli::new(cliff_thin).map_err(|_| {
+        Error::invalid(format!(
+            "cliffthin must be between 0 and 1, not {}",
+            cliff_thin
+        ))
+    })?;
 
     let mut reader = XyzInternalReader::new(BufReader::new(fs.open(&xyz_file_in)?))?;
     while let Some(r) = reader.next()? {
@@ -115,21 +120,19 @@ pub fn makecliffs(
     let w = ((xmax - xmin).floor() / 3.0) as usize;
     let h = ((ymax - ymin).floor() / 3.0) as usize;
 
-    let f2 = fs
-        .create(tmpfolder.join("c2g.dxf"))
-        .expect("Unable to create file");
+    let c2g_output = tmpfolder.join("c2g.dxf");
+    let f2 = fs.create(&c2g_output).with_file(&c2g_output)?;
     let mut f2 = BufWriter::new(f2);
 
-    write!(&mut f2,"  0\r\nSECTION\r\n  2\r\nHEADER\r\n  9\r\n$EXTMIN\r\n 10\r\n{}\r\n 20\r\n{}\r\n  9\r\n$EXTMAX\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nENDSEC\r\n  0\r\nSECTION\r\n  2\r\nENTITIES\r\n  0\r\n", xmin, ymin, xmax, ymax).expect("Cannot write dxf file");
+    write!(&mut f2,"  0\r\nSECTION\r\n  2\r\nHEADER\r\n  9\r\n$EXTMIN\r\n 10\r\n{}\r\n 20\r\n{}\r\n  9\r\n$EXTMAX\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nENDSEC\r\n  0\r\nSECTION\r\n  2\r\nENTITIES\r\n  0\r\n", xmin, ymin, xmax, ymax)?;
 
-    let f3 = fs
-        .create(tmpfolder.join("c3g.dxf"))
-        .expect("Unable to create file");
+    let c3g_output = tmpfolder.join("c3g.dxf");
+    let f3 = fs.create(&c3g_output).with_file(&c3g_output)?;
     let mut f3 = BufWriter::new(f3);
 
     write!(&mut f3, "  0\r\nSECTION\r\n  2\r\nHEADER\r\n  9\r\n$EXTMIN\r\n 10\r\n{}\r\n 20\r\n{}\r\n  9\r\n$EXTMAX\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nENDSEC\r\n  0\r\nSECTION\r\n  2\r\nENTITIES\r\n  0\r\n",
             xmin, ymin, xmax, ymax
-    ).expect("Cannot write dxf file");
+    )?;
 
     // temporary vector to reuse memory allocations
     let mut t = Vec::<(f64, f64, f64)>::new();
@@ -226,7 +229,13 @@ pub fn makecliffs(
                     for &(xt, yt, ht) in t.iter() {
                         let temp = h0 - ht;
                         let dist = ((x0 - xt).powi(2) + (y0 - yt).powi(2)).sqrt();
-                        if dist > 0.0 {
+                        if dist > 0.0
+                            && !hmap.is_unmapped_at(
+                                (x0 + xt) / 2.0,
+                                (y0 + yt) / 2.0,
+                                nodata_distance,
+                            )
+                        {
                             let imgx = ((x0 + xt) / 2.0 - xmin + 0.5).floor() as u32;
                             let imgy = ((y0 + yt) / 2.0 - ymin + 0.5).floor() as u32;
                             if steep < no_small_ciffs
@@ -240,8 +249,7 @@ pub fn makecliffs(
                                     img.put_pixel(imgx, imgy, Rgb([0, 0, 0]));
                                     f2.write_all(
                                         b"POLYLINE\r\n 66\r\n1\r\n  8\r\ncliff2\r\n  0\r\n",
-                                    )
-                                    .expect("Cannot write dxf file");
+                                    )?;
                                     write!(
                                         &mut f2,
                                         "VERTEX\r\n  8\r\ncliff2\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nVERTEX\r\n  8\r\ncliff2\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nSEQEND\r\n  0\r\n",
@@ -249,13 +257,12 @@ pub fn makecliffs(
                                         (y0 + yt) / 2.0 - cliff_length * (x0 - xt) / dist,
                                         (x0 + xt) / 2.0 - cliff_length * (y0 - yt) / dist,
                                         (y0 + yt) / 2.0 + cliff_length * (x0 - xt) / dist,
-                                    ).expect("Cannot write dxf file");
+                                    )?;
                                 }
                             }
 
                             if temp > limit2 && temp > (limit2 + (dist - limit2) * 0.85) {
-                                f3.write_all(b"POLYLINE\r\n 66\r\n1\r\n  8\r\ncliff3\r\n  0\r\n")
-                                    .expect("Cannot write dxf file");
+                                f3.write_all(b"POLYLINE\r\n 66\r\n1\r\n  8\r\ncliff3\r\n  0\r\n")?;
                                 write!(
                                     &mut f3,
                                     "VERTEX\r\n  8\r\ncliff3\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nVERTEX\r\n  8\r\ncliff3\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nSEQEND\r\n  0\r\n",
@@ -263,7 +270,7 @@ pub fn makecliffs(
                                     (y0 + yt) / 2.0 - cliff_length * (x0 - xt) / dist,
                                     (x0 + xt) / 2.0 - cliff_length * (y0 - yt) / dist,
                                     (y0 + yt) / 2.0 + cliff_length * (x0 - xt) / dist,
-                                ).expect("Cannot write dxf file");
+                                )?;
                             }
                         }
                     }
@@ -272,8 +279,7 @@ pub fn makecliffs(
         }
     }
 
-    f2.write_all(b"ENDSEC\r\n  0\r\nEOF\r\n")
-        .expect("Cannot write dxf file");
+    f2.write_all(b"ENDSEC\r\n  0\r\nEOF\r\n")?;
     let c2_limit = 2.6 * 2.75;
 
     // if we drop this already here, we can reuse the memory for the second list_alt
@@ -337,8 +343,7 @@ pub fn makecliffs(
                         let temp = h0 - ht;
                         let dist = ((x0 - xt).powi(2) + (y0 - yt).powi(2)).sqrt();
                         if dist > 0.0 && temp > limit && temp > (limit + (dist - limit) * 0.85) {
-                            f3.write_all(b"POLYLINE\r\n 66\r\n1\r\n  8\r\ncliff4\r\n  0\r\n")
-                                .expect("Cannot write dxf file");
+                            f3.write_all(b"POLYLINE\r\n 66\r\n1\r\n  8\r\ncliff4\r\n  0\r\n")?;
                             write!(
                                 &mut f3,
                                 "VERTEX\r\n  8\r\ncliff4\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nVERTEX\r\n  8\r\ncliff4\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nSEQEND\r\n  0\r\n",
@@ -346,7 +351,7 @@ pub fn makecliffs(
                                 (y0 + yt) / 2.0 - cliff_length * (x0 - xt) / dist,
                                 (x0 + xt) / 2.0 - cliff_length * (y0 - yt) / dist,
                                 (y0 + yt) / 2.0 + cliff_length * (x0 - xt) / dist,
-                            ).expect("Cannot write dxf file");
+                            )?;
                         }
                     }
                 }
@@ -354,18 +359,224 @@ pub fn makecliffs(
         }
     }
 
-    f3.write_all(b"ENDSEC\r\n  0\r\nEOF\r\n")
-        .expect("Cannot write dxf file");
+    f3.write_all(b"ENDSEC\r\n  0\r\nEOF\r\n")?;
 
-    img.write_to(
-        &mut BufWriter::new(
-            fs.create(tmpfolder.join("c2.png"))
-                .expect("could not save output png"),
-        ),
-        image::ImageFormat::Png,
-    )
-    .expect("could not save output png");
+    write_png(fs, &img, tmpfolder.join("c2.png"))?;
 
     info!("Done");
     Ok(())
 }
+
+/// Generalizes the cliffs of `c2g.dxf` and `c3g.dxf` into `cliffsgeneralized.dxf`. The short
+/// cliff segments are chained into continuous polylines where the end of one segment touches the
+/// start of another (keeping the direction, which tells the side of the cliff), the chains are
+/// simplified, and cliffs that are still too short for the map are dropped.
+///
+/// The result is a standalone output for drawing the map by hand, nothing else reads it: the
+/// rendering, the batch outputs and `dxfmerge` keep using `c2g.dxf` and `c3g.dxf`.
+pub fn cliffgeneralize(fs: &impl FileSystem, config: &Config, tmpfolder: &Path) -> Result<()> {
+    info!("Generalizing cliffs...");
+
+    let &Config {
+        cliff_join_distance,
+        cliff_min_length,
+        cliff_simplify,
+        ..
+    } = config;
+
+    let mut cliffs = Vec::new();
+    for file in ["c2g.dxf", "c3g.dxf"] {
+        let segments = read_polylines(fs, &tmpfolder.join(file))?;
+        let count = segments.len();
+
+        // chain separately per layer so different cliff types are not mixed
+        let mut layers: Vec<Vec<Polyline>> = Vec::new();
+        for segment in segments {
+            match layers.iter_mut().find(|l| l[0].layer == segment.layer) {
+                Some(layer) => layer.push(segment),
+                None => layers.push(vec![segment]),
+            }
+        }
+        let before = cliffs.len();
+        for layer in layers {
+            for mut cliff in chain_polylines(layer, cliff_join_distance) {
+                cliff.points = simplify(&cliff.points, cliff_simplify);
+                if polyline_length(&cliff.points) >= cliff_min_length {
+                    cliffs.push(cliff);
+                }
+            }
+        }
+        info!(
+            "{}: {} segments generalized into {} cliffs",
+            file,
+            count,
+            cliffs.len() - before
+        );
+    }
+
+    write_polylines(fs, &tmpfolder.join("cliffsgeneralized.dxf"), &cliffs)?;
+
+    info!("Done");
+    Ok(())
+}
+
+/// Chains polylines into longer polylines wherever the start of one is within `distance` of the
+/// end of another.
+fn chain_polylines(polylines: Vec<Polyline>, distance: f64) -> Vec<Polyline> {
+    let distance = distance.max(f64::EPSILON);
+    let cell = |(x, y): (f64, f64)| ((x / distance).floor() as i64, (y / distance).floor() as i64);
+    // find the closest unused polyline whose endpoint (start or end) is near a point
+    let closest = |index: &HashMap<(i64, i64), Vec<usize>>,
+                   used: &[bool],
+                   endpoints: &dyn Fn(usize) -> (f64, f64),
+                   p: (f64, f64)| {
+        let (cx, cy) = cell(p);
+        let mut best: Option<(usize, f64)> = None;
+        for nx in cx - 1..=cx + 1 {
+            for ny in cy - 1..=cy + 1 {
+                for &i in index.get(&(nx, ny)).into_iter().flatten() {
+                    let (x, y) = endpoints(i);
+                    let d = ((x - p.0).powi(2) + (y - p.1).powi(2)).sqrt();
+                    if !used[i] && d <= distance && best.is_none_or(|(_, bd)| d < bd) {
+                        best = Some((i, d));
+                    }
+                }
+            }
+        }
+        best.map(|(i, _)| i)
+    };
+
+    let start = |i: usize| polylines[i].points[0];
+    let end = |i: usize| polylines[i].points[polylines[i].points.len() - 1];
+    let mut starts: HashMap<(i64, i64), Vec<usize>> = HashMap::default();
+    let mut ends: HashMap<(i64, i64), Vec<usize>> = HashMap::default();
+    for i in 0..polylines.len() {
+        starts.entry(cell(start(i))).or_default().push(i);
+        ends.entry(cell(end(i))).or_default().push(i);
+    }
+
+    let mut used = vec![false; polylines.len()];
+    let mut chains = Vec::new();
+    for i in 0..polylines.len() {
+        if used[i] {
+            continue;
+        }
+        used[i] = true;
+        let mut points = std::collections::VecDeque::from(polylines[i].points.clone());
+
+        // extend forwards from the end, then backwards from the start
+        while let Some(j) = closest(&starts, &used, &start, *points.back().unwrap()) {
+            used[j] = true;
+            points.extend(polylines[j].points.iter().skip(1));
+        }
+        while let Some(j) = closest(&ends, &used, &end, *points.front().unwrap()) {
+            used[j] = true;
+            let n = polylines[j].points.len();
+            for &p in polylines[j].points[..n - 1].iter().rev() {
+                points.push_front(p);
+            }
+        }
+
+        chains.push(Polyline {
+            layer: polylines[i].layer.clone(),
+            points: points.into(),
+        });
+    }
+    chains
+}
+
+/// Simplifies a polyline with the Douglas-Peucker algorithm, removing vertices that are closer
+/// than `tolerance` to the simplified line.
+fn simplify(points: &[(f64, f64)], tolerance: f64) -> Vec<(f64, f64)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = stack.pop() {
+        let (x0, y0) = points[first];
+        let (x1, y1) = points[last];
+        let length = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+
+        let mut farthest = (0, 0.0);
+        for i in first + 1..last {
+            let (x, y) = points[i];
+            let d = if length > 0.0 {
+                ((x1 - x0) * (y0 - y) - (x0 - x) * (y1 - y0)).abs() / length
+            } else {
+                ((x - x0).powi(2) + (y - y0).powi(2)).sqrt()
+            };
+            if d > farthest.1 {
+                farthest = (i, d);
+            }
+        }
+        if farthest.1 > tolerance {
+            keep[farthest.0] = true;
+            stack.push((first, farthest.0));
+            stack.push((farthest.0, last));
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter(|(_, k)| *k)
+        .map(|(p, _)| *p)
+        .collect()
+}
+
+fn polyline_length(points: &[(f64, f64)]) -> f64 {
+    points
+        .windows(2)
+        .map(|w| ((w[1].0 - w[0].0).powi(2) + (w[1].1 - w[0].1).powi(2)).sqrt())
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn segment(points: &[(f64, f64)]) -> Polyline {
+        Polyline {
+            layer: "cliff2".to_string(),
+            points: points.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_chain_polylines() {
+        let segments = vec![
+            segment(&[(1.0, 0.0), (2.0, 0.0)]),
+            segment(&[(10.0, 0.0), (11.0, 0.0)]),
+            segment(&[(0.0, 0.0), (0.95, 0.0)]),
+            segment(&[(2.05, 0.0), (3.0, 0.0)]),
+        ];
+
+        let chains = chain_polylines(segments, 0.1);
+        assert_eq!(
+            chains,
+            vec![
+                segment(&[(0.0, 0.0), (1.0, 0.0), (2.0, 0.0), (3.0, 0.0)]),
+                segment(&[(10.0, 0.0), (11.0, 0.0)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_simplify() {
+        let points = [
+            (0.0, 0.0),
+            (1.0, 0.05),
+            (2.0, -0.05),
+            (3.0, 0.0),
+            (3.0, 2.0),
+        ];
+        assert_eq!(
+            simplify(&points, 0.1),
+            vec![(0.0, 0.0), (3.0, 0.0), (3.0, 2.0)]
+        );
+    }
+}
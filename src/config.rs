@@ -1,11 +1,29 @@
-use std::{path::Path, str::FromStr};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use ini::Ini;
+use log::{debug, error, info, warn};
+
+use crate::error::Result;
+use crate::interpolation::Interpolation;
+use crate::io::crs::Crs;
+use crate::io::text::TextColumn;
+use crate::io::xyz::COMPRESSED_RESOLUTION;
+use crate::thinning::{GridKeep, Thinning};
+use crate::util::suggest;
 
 /// The config parsed from the .ini configuration file.
 pub struct Config {
     pub batch: bool,
     pub processes: u64,
+    /// How many more times a failed tile of a batch is tried.
+    pub retries: u32,
+    /// Seconds after which the in-progress marker of a tile of a batch is stale.
+    pub stale_timeout: u64,
 
     pub experimental_use_in_memory_fs: bool,
 
@@ -19,21 +37,40 @@ pub struct Config {
 
     pub lazfolder: String,
     pub batchoutfolder: String,
+    /// The folder of the workspaces of the workers of a batch.
+    pub workfolder: String,
     pub savetempfiles: bool,
     pub savetempfolders: bool,
+    /// Write a data quality report of each tile in batch mode.
+    pub qareport: bool,
+    /// Resolution of the coordinates of the temp point files in meters, 0 to keep them exact.
+    pub temp_resolution: f64,
 
     pub scalefactor: f64,
     pub vege_bitmode: bool,
+    pub geotiff: bool,
+    /// Coordinate reference system overriding the one of the input files.
+    pub crs: Option<Crs>,
     pub zoff: f64,
     pub thinfactor: f64,
+    /// How the points of las/laz files are thinned, from `thinmode` and `thinfactor`.
+    pub thinning: Thinning,
 
     pub skipknolldetection: bool,
-    pub vegemode: bool,
+    /// Whether green and yellow are detected from the vegetation height raster, `vegemode=2`.
+    pub vege_height: bool,
+    /// Canopy height raster for maps made from an elevation raster, `None` to skip vegetation.
+    pub vegetation_raster: Option<String>,
 
     pub xfactor: f64,
     pub yfactor: f64,
     pub zfactor: f64,
 
+    /// Delimiter of text point files, `None` to detect it and a space for any whitespace.
+    pub xyz_delimiter: Option<char>,
+    /// Columns of text point files, empty to detect them.
+    pub xyz_columns: Vec<TextColumn>,
+
     pub contour_interval: f64,
     pub basemapcontours: f64,
 
@@ -41,6 +78,13 @@ pub struct Config {
 
     pub water_class: u8,
 
+    /// Method for filling the heightmap cells without ground points.
+    pub interpolation: Interpolation,
+    /// Power of the distance of inverse distance weighting.
+    pub idw_power: f64,
+    /// Cells farther than this from real points, in meters, are unmapped. 0 disables the check.
+    pub nodata_distance: f64,
+
     // merge
     pub inidotknolls: f64,
     pub smoothing: f64,
@@ -56,6 +100,9 @@ pub struct Config {
     pub steep_factor: f64,
     pub flat_place: f64,
     pub no_small_ciffs: f64,
+    pub cliff_join_distance: f64,
+    pub cliff_min_length: f64,
+    pub cliff_simplify: f64,
 
     // vegetation
     pub zones: Vec<Zone>,
@@ -88,6 +135,8 @@ pub struct Config {
 
     // render
     pub buildingcolor: (u8, u8, u8),
+    pub nodata_hatch_spacing: f64,
+    pub nodata_hatch_color: (u8, u8, u8),
     pub vectorconf: String,
     pub mtkskiplayers: Vec<String>,
     pub cliffdebug: bool,
@@ -99,6 +148,13 @@ pub struct Config {
     pub gaplength: f64,
     pub minimumgap: u32,
     pub label_depressions: bool,
+
+    // ground
+    pub ground_cellsize: f64,
+    pub ground_maxwindow: f64,
+    pub ground_slope: f64,
+    pub ground_initialdistance: f64,
+    pub ground_maxdistance: f64,
 }
 
 pub struct Zone {
@@ -110,19 +166,155 @@ pub struct Zone {
 
 const DEFAULT_CONFIG_FILE: &str = "pullauta.ini";
 
-impl Config {
-    pub fn load_or_create_default() -> Result<Self, Box<dyn std::error::Error>> {
-        let path = Path::new(DEFAULT_CONFIG_FILE);
-        // populate the default if no file was found
-        if !path.exists() {
-            std::fs::write(path, include_bytes!("../pullauta.default.ini"))?;
+/// Environment variable listing the config files to read when none are given, separated like the
+/// directories of `PATH`.
+pub const CONFIG_ENV: &str = "PULLAUTA_CONFIG";
+
+/// Environment variable that, set to 1, makes a missing `pullauta.ini` an error instead of
+/// creating it with the defaults.
+pub const NO_CREATE_CONFIG_ENV: &str = "PULLAUTA_NO_CREATE_CONFIG";
+
+/// The config files listed in `PULLAUTA_CONFIG`, as given. Relative paths are relative to the
+/// directory pullauta is run in, like the ones given with `--config`.
+pub fn env_config_paths() -> Vec<PathBuf> {
+    std::env::var_os(CONFIG_ENV)
+        .filter(|list| !list.is_empty())
+        .map_or_else(Vec::new, |list| std::env::split_paths(&list).collect())
+}
+
+/// Prefix of the environment variables overriding single keys of the config, like
+/// `PULLAUTA_CONTOUR_INTERVAL=2.5` for `contour_interval`.
+pub const ENV_PREFIX: &str = "PULLAUTA_";
+
+/// A built-in set of config values for the lidar data of a region, selected with `preset=name`.
+pub struct Preset {
+    pub name: &'static str,
+    /// The keys of the preset, as ini. The first line is a comment describing the data and where
+    /// the values were tested.
+    pub ini: &'static str,
+}
+
+/// All the built-in presets, ini files of a `presets` folder. A preset is only added with values
+/// tested against field checked maps of its data.
+pub const PRESETS: &[Preset] = &[];
+
+impl Preset {
+    pub fn find(name: &str) -> Option<&'static Preset> {
+        PRESETS.iter().find(|p| p.name == name.trim())
+    }
+
+    /// One line description of the data the preset is meant for.
+    pub fn about(&self) -> &'static str {
+        self.ini
+            .lines()
+            .next()
+            .unwrap_or("")
+            .trim_start_matches('#')
+            .trim()
+    }
+
+    /// A complete config file with the values of the preset: the default config with the keys
+    /// of the preset replaced, and the ones it does not have appended.
+    pub fn to_ini(&self) -> String {
+        let preset = Ini::load_from_str(self.ini).unwrap_or_default();
+        let values = preset.general_section();
+        let mut written: Vec<&str> = Vec::new();
+        let mut ini = format!("# Config from the preset {}: {}\n", self.name, self.about());
+        for line in include_str!("../pullauta.default.ini").lines() {
+            let trimmed = line.trim();
+            let key = match trimmed.find(['=', ':']) {
+                Some(end) if !trimmed.starts_with('#') && !trimmed.starts_with(';') => {
+                    trimmed[..end].trim()
+                }
+                _ => {
+                    ini.push_str(line);
+                    ini.push('\n');
+                    continue;
+                }
+            };
+            let list = LISTS.iter().find(|prefix| {
+                numbered(key, prefix).is_some()
+                    && values.iter().any(|(k, _)| numbered(k, prefix).is_some())
+            });
+            if let Some(prefix) = list {
+                // the whole list of the preset goes in place of the first key of the list
+                for (k, v) in values.iter().filter(|(k, _)| numbered(k, prefix).is_some()) {
+                    if !written.contains(&k) {
+                        ini.push_str(&format!("{}={}\n", k, v));
+                        written.push(k);
+                    }
+                }
+            } else if let Some(value) = values.get(key) {
+                ini.push_str(&format!("{}={}\n", key, value));
+                written.push(key);
+            } else {
+                ini.push_str(line);
+                ini.push('\n');
+            }
+        }
+        let rest: Vec<(&str, &str)> = values
+            .iter()
+            .filter(|(k, _)| !written.contains(k))
+            .collect();
+        if !rest.is_empty() {
+            ini.push_str(&format!("\n# other keys of the preset {}\n", self.name));
+            for (k, v) in rest {
+                ini.push_str(&format!("{}={}\n", k, v));
+            }
         }
-        Self::from_file(path)
+        ini
+    }
+}
+
+impl Config {
+    pub fn load_or_create_default() -> Result<Self> {
+        Self::load(&[], true)
+    }
+
+    /// Loads the config from a stack of ini files, the keys of each file overriding the ones of
+    /// the files before it, and then the keys set by `PULLAUTA_*` environment variables.
+    ///
+    /// Without files, the files listed in `PULLAUTA_CONFIG` are read, or else `pullauta.ini` of
+    /// the current directory. That one is created with the defaults if it does not exist, unless
+    /// `create_default` is false or `PULLAUTA_NO_CREATE_CONFIG=1`.
+    ///
+    /// The problems found by [`RawConfig::check`] are logged, and errors make the loading fail.
+    pub fn load(paths: &[PathBuf], create_default: bool) -> Result<Self> {
+        Self::from_raw(&RawConfig::read(paths, create_default)?)
     }
 
-    fn from_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
-        let conf = Ini::load_from_file(path)?;
+    /// Reads the config from an ini file, which must exist.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let mut raw = RawConfig::default();
+        raw.add_file(path)?;
+        raw.resolve_preset();
+        Self::from_raw(&raw)
+    }
+
+    /// Checks the keys read and parses them, logging the problems found.
+    pub fn from_raw(raw: &RawConfig) -> Result<Self> {
+        let mut errors = 0;
+        for issue in raw.check() {
+            match issue.severity {
+                Severity::Warning => warn!("{}", issue),
+                Severity::Error => {
+                    error!("{}", issue);
+                    errors += 1;
+                }
+            }
+        }
+        if errors > 0 {
+            return Err(format!(
+                "The config has {} error{}, check it with 'pullauta checkconfig'",
+                errors,
+                if errors == 1 { "" } else { "s" }
+            )
+            .into());
+        }
+        Self::from_ini(&raw.ini)
+    }
 
+    fn from_ini(conf: &Ini) -> Result<Self> {
         let gs = conf.general_section();
 
         // only one can be set at a time
@@ -153,17 +345,31 @@ impl Config {
         let pnorthlinesangle: f64 = parse_typed(gs, "northlinesangle", 0.0);
         let pnorthlineswidth: usize = parse_typed(gs, "northlineswidth", 0);
 
-        let processes: u64 = gs.get("processes").unwrap().parse::<u64>().unwrap();
+        let processes: u64 = parse_typed(gs, "processes", 2);
+        let retries: u32 = parse_typed(gs, "retries", 1);
+        let stale_timeout: u64 = parse_typed(gs, "staletimeout", 600);
         let experimental_use_in_memory_fs: bool =
             gs.get("experimental_use_in_memory_fs").unwrap_or("0") == "1";
 
         let lazfolder = gs.get("lazfolder").unwrap_or("").to_string();
         let batchoutfolder = gs.get("batchoutfolder").unwrap_or("").to_string();
-        let savetempfiles: bool = gs.get("savetempfiles").unwrap() == "1";
-        let savetempfolders: bool = gs.get("savetempfolders").unwrap() == "1";
+        let workfolder = gs.get("workfolder").unwrap_or("work").to_string();
+        let savetempfiles: bool = gs.get("savetempfiles").unwrap_or("0") == "1";
+        let savetempfolders: bool = gs.get("savetempfolders").unwrap_or("0") == "1";
+        let qareport: bool = gs.get("qareport").unwrap_or("0") == "1";
+        let temp_resolution = parse_typed(gs, "tempresolution", COMPRESSED_RESOLUTION);
 
         let scalefactor: f64 = parse_typed(gs, "scalefactor", 1.0);
         let vege_bitmode: bool = gs.get("vege_bitmode").unwrap_or("0") == "1";
+        let geotiff: bool = gs.get("geotiff").unwrap_or("0") == "1";
+        let crs = match gs.get("crs").unwrap_or("").trim() {
+            "" => None,
+            crs => Some(Crs::parse(crs)?),
+        };
+        let vegetation_raster = match gs.get("vegetationraster").unwrap_or("").trim() {
+            "" => None,
+            path => Some(path.to_string()),
+        };
         let zoff = parse_typed(gs, "zoffset", 0.0);
         let mut thinfactor: f64 = parse_typed(gs, "thinfactor", 1.0);
         if !(0.0..=1.0).contains(&thinfactor) {
@@ -176,14 +382,35 @@ impl Config {
         if thinfactor == 0.0 {
             thinfactor = 1.0;
         }
+        let thinning = match gs.get("thinmode").unwrap_or("").trim() {
+            "" | "seeded" | "random" if thinfactor == 1.0 => Thinning::None,
+            "seeded" => Thinning::Seeded {
+                factor: thinfactor,
+                seed: parse_typed(gs, "thinseed", 0),
+            },
+            "" | "random" => Thinning::Random(thinfactor),
+            "grid" => {
+                let keep = gs.get("thinkeep").unwrap_or("");
+                Thinning::Grid {
+                    cell_size: parse_typed(gs, "thincellsize", 0.5),
+                    keep: GridKeep::from_name(keep)
+                        .ok_or_else(|| format!("Invalid thinkeep {:?}", keep))?,
+                }
+            }
+            mode => return Err(format!("Invalid thinmode {:?}", mode).into()),
+        };
 
         let skipknolldetection = gs.get("skipknolldetection").unwrap_or("0") == "1";
-        let vegemode: bool = gs.get("vegemode").unwrap_or("0") == "1";
-        if vegemode {
-            return Err("vegemode=1 not implemented, use perl version"
-                .to_string()
-                .into());
-        }
+        let vege_height = match gs.get("vegemode").unwrap_or("0").trim() {
+            "" | "0" => false,
+            "2" => true,
+            "1" => {
+                return Err("vegemode=1 not implemented, use perl version \
+                    (vegemode=2 is the vegetation height raster mode of this version)"
+                    .into())
+            }
+            mode => return Err(format!("Invalid vegemode {:?}", mode).into()),
+        };
 
         let mut xfactor: f64 = parse_typed(gs, "coordxfactor", 1.0);
         let mut yfactor: f64 = parse_typed(gs, "coordyfactor", 1.0);
@@ -198,6 +425,9 @@ impl Config {
             zfactor = 1.0;
         }
 
+        let xyz_delimiter = parse_delimiter(gs.get("xyzdelimiter").unwrap_or(""))?;
+        let xyz_columns = parse_columns(gs.get("xyzcolumns").unwrap_or(""))?;
+
         let contour_interval: f64 = parse_typed(gs, "contour_interval", 5.0);
 
         let basemapcontours: f64 = parse_typed(gs, "basemapinterval", 0.0);
@@ -206,6 +436,12 @@ impl Config {
 
         let water_class = parse_typed(gs, "waterclass", 9);
 
+        let interpolation_name = gs.get("interpolation").unwrap_or("");
+        let interpolation = Interpolation::from_name(interpolation_name)
+            .ok_or_else(|| format!("Invalid interpolation {:?}", interpolation_name))?;
+        let idw_power: f64 = parse_typed(gs, "idwpower", 2.0);
+        let nodata_distance: f64 = parse_typed(gs, "nodatadistance", 0.0);
+
         let inidotknolls: f64 = parse_typed(gs, "knolls", 0.8);
         let smoothing: f64 = parse_typed(gs, "smoothing", 1.0);
         let curviness: f64 = parse_typed(gs, "curviness", 1.0);
@@ -228,6 +464,9 @@ impl Config {
         let steep_factor: f64 = parse_typed(gs, "cliffsteepfactor", 0.33);
         let flat_place: f64 = parse_typed(gs, "cliffflatplace", 6.6);
         let no_small_ciffs: f64 = parse_typed(gs, "cliffnosmallciffs", 0.0);
+        let cliff_join_distance: f64 = parse_typed(gs, "cliffgeneralizejoin", 0.5);
+        let cliff_min_length: f64 = parse_typed(gs, "cliffgeneralizeminlength", 4.0);
+        let cliff_simplify: f64 = parse_typed(gs, "cliffgeneralizesimplify", 0.3);
 
         // vegetation
 
@@ -239,13 +478,12 @@ impl Config {
                 break;
             }
 
-            let mut parts = zone.split('|');
-
+            let [low, high, roof, factor] = parse_tuple(&format!("zone{}", i), zone)?;
             zones.push(Zone {
-                low: parts.next().unwrap().parse::<f64>().unwrap(),
-                high: parts.next().unwrap().parse::<f64>().unwrap(),
-                roof: parts.next().unwrap().parse::<f64>().unwrap(),
-                factor: parts.next().unwrap().parse::<f64>().unwrap(),
+                low,
+                high,
+                roof,
+                factor,
             });
             i += 1;
         }
@@ -258,10 +496,7 @@ impl Config {
                     break;
                 }
                 // parse the threshold values
-                let mut parts = last_threshold.split('|');
-                let v0: f64 = parts.next().unwrap().parse::<f64>().unwrap();
-                let v1: f64 = parts.next().unwrap().parse::<f64>().unwrap();
-                let v2: f64 = parts.next().unwrap().parse::<f64>().unwrap();
+                let [v0, v1, v2] = parse_tuple(&format!("thresold{}", i), last_threshold)?;
 
                 thresholds.push((v0, v1, v2));
                 i += 1;
@@ -269,12 +504,7 @@ impl Config {
             thresholds
         };
 
-        let greenshades = gs
-            .get("greenshades")
-            .unwrap_or("")
-            .split('|')
-            .map(|v| v.parse::<f64>().unwrap())
-            .collect::<Vec<f64>>();
+        let greenshades = parse_list("greenshades", gs.get("greenshades").unwrap_or(""))?;
         let yellowheight: f64 = parse_typed(gs, "yellowheight", 0.9);
         let yellowthreshold: f64 = parse_typed(gs, "yellowthresold", 0.9);
         let greenground: f64 = parse_typed(gs, "greenground", 0.9);
@@ -312,6 +542,19 @@ impl Config {
             )
         };
 
+        let nodata_hatch_spacing: f64 = parse_typed(gs, "nodatahatchspacing", 10.0);
+        let nodata_hatch_color: (u8, u8, u8) = {
+            let mut split = gs
+                .get("nodatahatchcolor")
+                .unwrap_or("128,128,128")
+                .split(',');
+            (
+                split.next().unwrap_or("0").parse::<u8>().unwrap_or(0),
+                split.next().unwrap_or("0").parse::<u8>().unwrap_or(0),
+                split.next().unwrap_or("0").parse::<u8>().unwrap_or(0),
+            )
+        };
+
         let vectorconf = gs.get("vectorconf").unwrap_or("").into();
         let mtkskiplayers: Vec<String> = gs
             .get("mtkskiplayers")
@@ -328,9 +571,17 @@ impl Config {
         let gaplength: f64 = parse_typed(gs, "gaplength", 12.0);
         let minimumgap: u32 = parse_typed(gs, "minimumgap", 30);
         let label_depressions: bool = gs.get("label_formlines_depressions").unwrap_or("0") == "1";
+
+        let ground_cellsize: f64 = parse_typed(gs, "groundcellsize", 1.0);
+        let ground_maxwindow: f64 = parse_typed(gs, "groundmaxwindow", 20.0);
+        let ground_slope: f64 = parse_typed(gs, "groundslope", 0.3);
+        let ground_initialdistance: f64 = parse_typed(gs, "groundinitialdistance", 0.15);
+        let ground_maxdistance: f64 = parse_typed(gs, "groundmaxdistance", 2.5);
         Ok(Self {
-            batch: gs.get("batch").unwrap() == "1",
+            batch: gs.get("batch").unwrap_or("0") == "1",
             processes,
+            retries,
+            stale_timeout,
             experimental_use_in_memory_fs,
             vegeonly,
             cliffsonly,
@@ -339,21 +590,33 @@ impl Config {
             pnorthlineswidth,
             lazfolder,
             batchoutfolder,
+            workfolder,
             savetempfolders,
+            qareport,
+            temp_resolution,
             savetempfiles,
             scalefactor,
             vege_bitmode,
+            geotiff,
+            crs,
             zoff,
             thinfactor,
+            thinning,
             skipknolldetection,
-            vegemode,
+            vege_height,
+            vegetation_raster,
             xfactor,
             yfactor,
             zfactor,
+            xyz_delimiter,
+            xyz_columns,
             contour_interval,
             basemapcontours,
             detectbuildings,
             water_class,
+            interpolation,
+            idw_power,
+            nodata_distance,
             inidotknolls,
             smoothing,
             curviness,
@@ -366,6 +629,9 @@ impl Config {
             steep_factor,
             flat_place,
             no_small_ciffs,
+            cliff_join_distance,
+            cliff_min_length,
+            cliff_simplify,
             zones,
             thresholds,
             greenshades,
@@ -394,6 +660,8 @@ impl Config {
             buildings,
             waterele,
             buildingcolor,
+            nodata_hatch_spacing,
+            nodata_hatch_color,
             vectorconf,
             mtkskiplayers,
             cliffdebug,
@@ -403,19 +671,1023 @@ impl Config {
             gaplength,
             minimumgap,
             label_depressions,
+            ground_cellsize,
+            ground_maxwindow,
+            ground_slope,
+            ground_initialdistance,
+            ground_maxdistance,
         })
     }
 }
 
+/// Parses the delimiter of text point files, `None` to detect it and a space for any whitespace.
+fn parse_delimiter(value: &str) -> Result<Option<char>, String> {
+    Ok(match value.trim() {
+        "" | "auto" => None,
+        "space" | "whitespace" => Some(' '),
+        "tab" => Some('\t'),
+        "comma" => Some(','),
+        "semicolon" => Some(';'),
+        d if d.chars().count() == 1 => d.chars().next(),
+        d => return Err(format!("Invalid xyzdelimiter {:?}", d)),
+    })
+}
+
+/// Parses the comma separated columns of text point files, empty to detect them.
+fn parse_columns(value: &str) -> Result<Vec<TextColumn>, String> {
+    value
+        .split(',')
+        .filter(|c| !c.trim().is_empty())
+        .map(|c| {
+            TextColumn::from_name(c).ok_or_else(|| format!("Invalid xyzcolumns column {:?}", c))
+        })
+        .collect()
+}
+
+/// Parses numbers separated by `|`, like `greenshades`.
+fn parse_numbers(value: &str) -> Result<Vec<f64>, String> {
+    value
+        .split('|')
+        .map(|v| {
+            v.trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite())
+                .ok_or_else(|| format!("{:?} is not a number", v.trim()))
+        })
+        .collect()
+}
+
+/// Parses the value of `key` as exactly `N` numbers separated by `|`, like the zones.
+fn parse_tuple<const N: usize>(key: &str, value: &str) -> Result<[f64; N], String> {
+    parse_numbers(value)
+        .map_err(|e| format!("Invalid {} {:?}, {}", key, value, e))?
+        .try_into()
+        .map_err(|_| {
+            format!(
+                "Invalid {} {:?}, expected {} numbers separated by |",
+                key, value, N
+            )
+        })
+}
+
+/// Parses the value of `key` as at least two numbers separated by `|`.
+fn parse_list(key: &str, value: &str) -> Result<Vec<f64>, String> {
+    let numbers =
+        parse_numbers(value).map_err(|e| format!("Invalid {} {:?}, {}", key, value, e))?;
+    if numbers.len() < 2 {
+        return Err(format!(
+            "Invalid {} {:?}, expected at least 2 numbers separated by |",
+            key, value
+        ));
+    }
+    Ok(numbers)
+}
+
+/// Where the value of a config key was set.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    /// A line of a config file, counted from 1.
+    Line(PathBuf, usize),
+    /// An environment variable.
+    Env(String),
+    /// A built-in preset.
+    Preset(&'static str),
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Line(path, line) => write!(f, "{}:{}", path.display(), line),
+            Location::Env(name) => write!(f, "environment variable {}", name),
+            Location::Preset(name) => write!(f, "preset {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The config works, but probably not as intended.
+    Warning,
+    /// The config cannot be used.
+    Error,
+}
+
+/// A problem found in the config by [`RawConfig::check`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{}: ", location)?;
+        }
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
+
+/// The keys of the config as read from the files and the environment, with where each one was
+/// set, before they are parsed into a [`Config`].
+#[derive(Default)]
+pub struct RawConfig {
+    ini: Ini,
+    locations: HashMap<String, Location>,
+    files: Vec<PathBuf>,
+    /// Problems found while reading, like keys in sections.
+    issues: Vec<Issue>,
+}
+
+impl RawConfig {
+    /// Reads the stack of config files and the environment overrides, see [`Config::load`].
+    pub fn read(paths: &[PathBuf], create_default: bool) -> Result<Self> {
+        let mut paths = paths.to_vec();
+        if paths.is_empty() {
+            paths = env_config_paths();
+        }
+        if paths.is_empty() {
+            let path = Path::new(DEFAULT_CONFIG_FILE);
+            let create_default =
+                create_default && std::env::var(NO_CREATE_CONFIG_ENV).map_or(true, |v| v != "1");
+            if !path.exists() {
+                if !create_default {
+                    return Err(format!(
+                        "No config file {} in the current directory, give one with --config",
+                        DEFAULT_CONFIG_FILE
+                    )
+                    .into());
+                }
+                // populate the default if no file was found
+                info!("Writing the default config to {}", DEFAULT_CONFIG_FILE);
+                std::fs::write(path, include_bytes!("../pullauta.default.ini"))?;
+            }
+            paths.push(path.to_path_buf());
+        }
+
+        let mut raw = Self::default();
+        for path in &paths {
+            raw.add_file(path)?;
+        }
+        raw.apply_env(std::env::vars_os());
+        raw.resolve_preset();
+        Ok(raw)
+    }
+
+    /// The config files read, in order.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Where a key was last set.
+    pub fn location(&self, key: &str) -> Option<&Location> {
+        self.locations.get(key)
+    }
+
+    /// Reads an ini file, its keys replacing the ones already set.
+    pub fn add_file(&mut self, path: &Path) -> Result<()> {
+        debug!("Reading config file {}", path.display());
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config file {}: {}", path.display(), e))?;
+        self.add_str(&text, path)
+    }
+
+    /// Adds the keys of the ini text of the file `path`, replacing the ones already set.
+    pub fn add_str(&mut self, text: &str, path: &Path) -> Result<()> {
+        let ini = Ini::load_from_str(text)
+            .map_err(|e| format!("Could not read config file {}: {}", path.display(), e))?;
+        self.files.push(path.to_path_buf());
+
+        let values: Vec<(String, &str)> = ini
+            .general_section()
+            .iter()
+            .map(|(key, value)| (canonical_key(key), value))
+            .collect();
+        // the ini parser does not tell the lines, find the keys of the general section
+        let mut in_section = false;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let location = Location::Line(path.to_path_buf(), i + 1);
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name.trim_end_matches(']').trim();
+                if ini.section(Some(name)).is_some_and(|s| !s.is_empty()) {
+                    self.issues.push(Issue {
+                        severity: Severity::Warning,
+                        location: Some(location),
+                        message: format!(
+                            "The keys of section [{}] are ignored, they must be before any section",
+                            name
+                        ),
+                    });
+                }
+                in_section = true;
+                continue;
+            }
+            if in_section || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(end) = line.find(['=', ':']) {
+                self.locations
+                    .insert(canonical_key(line[..end].trim()), location);
+            }
+        }
+
+        let gs = self.ini.general_section_mut();
+        for (key, value) in values {
+            gs.insert(key, value);
+        }
+        Ok(())
+    }
+
+    /// Sets the values of the preset named by the `preset` key, see [`RawConfig::apply_preset`].
+    pub fn resolve_preset(&mut self) {
+        let Some(preset) = self
+            .ini
+            .general_section()
+            .get("preset")
+            .and_then(Preset::find)
+        else {
+            return;
+        };
+        self.apply_preset(preset);
+    }
+
+    /// Sets the values of a preset as a base layer under the config files and the environment:
+    /// the keys they set override the preset, and are reported as a warning by
+    /// [`RawConfig::check`] when their value differs. Numbered lists are taken as a whole, only
+    /// if none of their keys are set.
+    pub fn apply_preset(&mut self, preset: &'static Preset) {
+        let Ok(ini) = Ini::load_from_str(preset.ini) else {
+            return;
+        };
+        debug!("Applying preset {}", preset.name);
+
+        let mut shadowed: Vec<String> = Vec::new();
+        for prefix in LISTS {
+            let list = |ini: &Ini| {
+                let mut list = ini
+                    .general_section()
+                    .iter()
+                    .filter(|(key, _)| numbered(key, prefix).is_some())
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect::<Vec<_>>();
+                list.sort();
+                list
+            };
+            let preset_list = list(&ini);
+            if preset_list.is_empty() {
+                continue;
+            }
+            let current = list(&self.ini);
+            if !current.is_empty() {
+                if current != preset_list {
+                    shadowed.push(format!("{}*", prefix));
+                }
+                continue;
+            }
+            for (key, value) in preset_list {
+                self.locations
+                    .insert(key.clone(), Location::Preset(preset.name));
+                self.ini.general_section_mut().insert(key, value);
+            }
+        }
+        for (key, value) in ini.general_section().iter() {
+            if LISTS.iter().any(|prefix| numbered(key, prefix).is_some()) {
+                continue;
+            }
+            match self.ini.general_section().get(key) {
+                Some(current) if current != value => shadowed.push(key.to_string()),
+                Some(_) => {}
+                None => {
+                    self.locations
+                        .insert(key.to_string(), Location::Preset(preset.name));
+                    self.ini.general_section_mut().insert(key, value);
+                }
+            }
+        }
+
+        if !shadowed.is_empty() {
+            self.issues.push(Issue {
+                severity: Severity::Warning,
+                location: None,
+                message: format!(
+                    "The config sets {} over the values of preset {}",
+                    shadowed
+                        .iter()
+                        .map(|key| format!("`{}`", key))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    preset.name
+                ),
+            });
+        }
+    }
+
+    /// Sets the keys given by `PULLAUTA_*` environment variables. Variables that do not name a
+    /// known key are warned about and ignored.
+    pub fn apply_env(&mut self, vars: impl IntoIterator<Item = (OsString, OsString)>) {
+        for (name, value) in vars {
+            let (Some(name), Some(value)) = (name.to_str(), value.to_str()) else {
+                continue;
+            };
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if name == CONFIG_ENV || name == NO_CREATE_CONFIG_ENV || key.is_empty() {
+                continue;
+            }
+            let key = canonical_key(&key.to_lowercase());
+            if key_kind(&key).is_none() {
+                match suggest_key(&key) {
+                    Some(known) => warn!(
+                        "Ignoring {}, `{}` is not a config key, did you mean `{}`?",
+                        name, key, known
+                    ),
+                    None => warn!("Ignoring {}, `{}` is not a config key", name, key),
+                }
+                continue;
+            }
+            info!("Setting {}={} from {}", key, value, name);
+            self.locations
+                .insert(key.clone(), Location::Env(name.to_string()));
+            self.ini.general_section_mut().insert(key, value);
+        }
+    }
+
+    /// Checks the config for unknown keys, values that do not parse or are out of range and
+    /// inconsistent combinations of values.
+    pub fn check(&self) -> Vec<Issue> {
+        let mut issues = self.issues.clone();
+        let gs = self.ini.general_section();
+        let issue = |severity, key: &str, message| Issue {
+            severity,
+            location: self.locations.get(key).cloned(),
+            message,
+        };
+
+        for (key, value) in gs.iter() {
+            let Some(kind) = key_kind(key) else {
+                let message = match suggest_key(key) {
+                    Some(known) => format!("Unknown key `{}`, did you mean `{}`?", key, known),
+                    None => format!("Unknown key `{}`", key),
+                };
+                issues.push(issue(Severity::Warning, key, message));
+                continue;
+            };
+            if value.trim().is_empty() && !matches!(kind, Kind::Text | Kind::Parsed(_) | Kind::List)
+            {
+                let message = format!("`{}` has no value, the default is used", key);
+                issues.push(issue(Severity::Warning, key, message));
+                continue;
+            }
+            if let Err(e) = check_value(key, kind, value) {
+                issues.push(issue(Severity::Error, key, e));
+            }
+        }
+
+        let only: Vec<&str> = ["vegeonly", "cliffsonly", "contoursonly"]
+            .into_iter()
+            .filter(|&key| gs.get(key).is_some_and(|v| v.trim() == "1"))
+            .collect();
+        if only.len() > 1 {
+            let message = format!(
+                "Only one of vegeonly, cliffsonly, or contoursonly can be set, not {}",
+                only.join(" and ")
+            );
+            issues.push(issue(Severity::Error, only[1], message));
+        }
+
+        let number = |key: &str| gs.get(key).and_then(|v| v.trim().parse::<f64>().ok());
+        let thinfactor = number("thinfactor").unwrap_or(1.0);
+        if gs.get("thinmode").is_some_and(|m| m.trim() == "grid")
+            && ![0.0, 1.0].contains(&thinfactor)
+        {
+            let message =
+                "`thinfactor` is not used by grid thinning, the cells set the density".to_string();
+            issues.push(issue(Severity::Warning, "thinfactor", message));
+        }
+        let (c1_limit, c2_limit) = (
+            number("cliff1").unwrap_or(1.0),
+            number("cliff2").unwrap_or(1.0),
+        );
+        if c1_limit > c2_limit {
+            let key = if gs.contains_key("cliff1") {
+                "cliff1"
+            } else {
+                "cliff2"
+            };
+            let message = format!(
+                "`cliff1` ({}) is higher than `cliff2` ({}), impassable cliffs must be the steepest",
+                c1_limit, c2_limit
+            );
+            issues.push(issue(Severity::Error, key, message));
+        }
+
+        // the zones and thresholds are read until the first missing number
+        for (prefix, what) in [("zone", "low|high"), ("thresold", "roof low|roof high")] {
+            let mut numbers: Vec<u32> =
+                gs.iter().filter_map(|(k, _)| numbered(k, prefix)).collect();
+            numbers.sort_unstable();
+            let mut next = 1;
+            for n in numbers {
+                let key = format!("{}{}", prefix, n);
+                if n != next {
+                    let message = format!(
+                        "`{}` is ignored because there is no `{}{}`",
+                        key, prefix, next
+                    );
+                    issues.push(issue(Severity::Warning, &key, message));
+                    continue;
+                }
+                next += 1;
+                if let Ok(numbers) = parse_numbers(gs.get(&key).unwrap_or("")) {
+                    if numbers.len() >= 2 && numbers[0] >= numbers[1] {
+                        let message =
+                            format!("`{}` is an empty range, {} must be increasing", key, what);
+                        issues.push(issue(Severity::Error, &key, message));
+                    }
+                }
+            }
+        }
+
+        // in the order of the files and lines
+        issues.sort_by_key(|issue| match &issue.location {
+            Some(Location::Line(path, line)) => {
+                (0, self.files.iter().rposition(|f| f == path), *line)
+            }
+            Some(Location::Env(_)) => (1, None, 0),
+            Some(Location::Preset(_)) => (2, None, 0),
+            None => (3, None, 0),
+        });
+        issues
+    }
+}
+
+/// Key read in place of `key`, accepting `threshold` for the historical spelling `thresold`.
+fn canonical_key(key: &str) -> String {
+    if key == "yellowthreshold" {
+        return "yellowthresold".to_string();
+    }
+    if let Some(n) = numbered(key, "threshold") {
+        return format!("thresold{}", n);
+    }
+    key.to_string()
+}
+
+/// Prefixes of the numbered keys read as a list, until the first missing number.
+const LISTS: [&str; 2] = ["zone", "thresold"];
+
+/// The number of a numbered key like `zone3`, counted from 1.
+fn numbered(key: &str, prefix: &str) -> Option<u32> {
+    let n = key.strip_prefix(prefix)?;
+    if n.is_empty() || !n.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    n.parse().ok().filter(|&n| n > 0)
+}
+
+/// How the value of a config key is checked.
+#[derive(Clone, Copy)]
+enum Kind {
+    /// 0 or 1.
+    Flag,
+    /// A whole number between the bounds.
+    Integer(i64, i64),
+    /// A number between the bounds.
+    Number(f64, f64),
+    /// A number greater than 0.
+    Positive,
+    /// An RGB color like `255,0,0`.
+    Color,
+    /// This many numbers separated by `|`.
+    Tuple(usize),
+    /// At least two numbers separated by `|`.
+    List,
+    /// Text checked by a parser.
+    Parsed(fn(&str) -> Result<(), String>),
+    /// Any text.
+    Text,
+}
+
+const INF: f64 = f64::INFINITY;
+
+/// All the keys of the config, but the numbered `zone` and `thresold` keys.
+const KEYS: &[(&str, Kind)] = &[
+    ("basemapinterval", Kind::Number(0.0, INF)),
+    ("batch", Kind::Flag),
+    ("batchoutfolder", Kind::Text),
+    ("buildingcolor", Kind::Color),
+    ("buildingsclass", Kind::Integer(0, 255)),
+    ("cliff1", Kind::Number(0.0, INF)),
+    ("cliff2", Kind::Number(0.0, INF)),
+    ("cliffdebug", Kind::Flag),
+    ("cliffflatplace", Kind::Number(0.0, INF)),
+    ("cliffgeneralizejoin", Kind::Number(0.0, INF)),
+    ("cliffgeneralizeminlength", Kind::Number(0.0, INF)),
+    ("cliffgeneralizesimplify", Kind::Number(0.0, INF)),
+    ("cliffnosmallciffs", Kind::Number(0.0, INF)),
+    ("cliffsonly", Kind::Flag),
+    ("cliffsteepfactor", Kind::Number(0.0, INF)),
+    ("cliffthin", Kind::Number(0.0, 1.0)),
+    ("contour_interval", Kind::Positive),
+    ("contoursonly", Kind::Flag),
+    ("coordxfactor", Kind::Number(-INF, INF)),
+    ("coordyfactor", Kind::Number(-INF, INF)),
+    ("coordzfactor", Kind::Number(-INF, INF)),
+    ("crs", Kind::Parsed(check_crs)),
+    ("curviness", Kind::Number(0.0, INF)),
+    ("dashlength", Kind::Number(0.0, INF)),
+    ("depression_length", Kind::Integer(0, i64::MAX)),
+    ("detectbuildings", Kind::Flag),
+    ("experimental_use_in_memory_fs", Kind::Flag),
+    ("firstandlastreturnasground", Kind::Integer(0, i64::MAX)),
+    ("firstandlastreturnfactor", Kind::Number(-INF, INF)),
+    ("formline", Kind::Number(0.0, 2.0)),
+    ("formlineaddition", Kind::Number(0.0, INF)),
+    ("formlinesteepness", Kind::Number(0.0, INF)),
+    ("gaplength", Kind::Number(0.0, INF)),
+    ("geotiff", Kind::Flag),
+    ("greendetectsize", Kind::Positive),
+    (
+        "greendotsize",
+        Kind::Integer(i32::MIN as i64, i32::MAX as i64),
+    ),
+    ("greenground", Kind::Number(-INF, INF)),
+    ("greenhigh", Kind::Number(-INF, INF)),
+    ("greenshades", Kind::List),
+    ("groundboxsize", Kind::Integer(0, i64::MAX)),
+    ("groundcellsize", Kind::Positive),
+    ("groundinitialdistance", Kind::Number(0.0, INF)),
+    ("groundmaxdistance", Kind::Number(0.0, INF)),
+    ("groundmaxwindow", Kind::Positive),
+    ("groundslope", Kind::Number(0.0, INF)),
+    ("idwpower", Kind::Positive),
+    ("indexcontours", Kind::Number(0.0, INF)),
+    ("interpolation", Kind::Parsed(check_interpolation)),
+    ("knolls", Kind::Number(0.0, 1.0)),
+    ("label_formlines_depressions", Kind::Flag),
+    ("lastreturnfactor", Kind::Number(-INF, INF)),
+    ("lazfolder", Kind::Text),
+    ("lightgreentone", Kind::Number(0.0, 255.0)),
+    ("medianboxsize", Kind::Integer(0, u32::MAX as i64)),
+    ("medianboxsize2", Kind::Integer(0, u32::MAX as i64)),
+    ("minimumgap", Kind::Integer(0, u32::MAX as i64)),
+    ("mtkskiplayers", Kind::Text),
+    ("nodatadistance", Kind::Number(0.0, INF)),
+    ("nodatahatchcolor", Kind::Color),
+    ("nodatahatchspacing", Kind::Number(0.0, INF)),
+    ("northlinesangle", Kind::Number(-INF, INF)),
+    ("northlineswidth", Kind::Integer(0, i64::MAX)),
+    ("pointvolumeexponent", Kind::Number(-INF, INF)),
+    ("pointvolumefactor", Kind::Number(-INF, INF)),
+    ("preset", Kind::Parsed(check_preset)),
+    ("processes", Kind::Integer(1, i64::MAX)),
+    ("qareport", Kind::Flag),
+    ("retries", Kind::Integer(0, u32::MAX as i64)),
+    ("savetempfiles", Kind::Flag),
+    ("savetempfolders", Kind::Flag),
+    ("scalefactor", Kind::Positive),
+    ("skipknolldetection", Kind::Flag),
+    ("smoothing", Kind::Number(0.0, INF)),
+    ("staletimeout", Kind::Integer(1, i64::MAX)),
+    ("tempresolution", Kind::Number(0.0, 1.0)),
+    ("thincellsize", Kind::Positive),
+    ("thinfactor", Kind::Number(0.0, 1.0)),
+    ("thinkeep", Kind::Parsed(check_thinkeep)),
+    ("thinmode", Kind::Parsed(check_thinmode)),
+    ("thinseed", Kind::Integer(0, i64::MAX)),
+    ("topweight", Kind::Number(-INF, INF)),
+    ("undergrowth", Kind::Number(-INF, INF)),
+    ("undergrowth2", Kind::Number(-INF, INF)),
+    ("vectorconf", Kind::Text),
+    ("vege_bitmode", Kind::Flag),
+    ("vegemode", Kind::Parsed(check_vegemode)),
+    ("vegeonly", Kind::Flag),
+    ("vegetationraster", Kind::Text),
+    ("vegethin", Kind::Integer(0, u32::MAX as i64)),
+    ("vegezoffset", Kind::Number(-INF, INF)),
+    ("waterclass", Kind::Integer(0, 255)),
+    ("waterelevation", Kind::Number(-INF, INF)),
+    ("workfolder", Kind::Text),
+    ("xyzcolumns", Kind::Parsed(check_columns)),
+    ("xyzdelimiter", Kind::Parsed(check_delimiter)),
+    ("yellow_smoothing", Kind::Flag),
+    ("yellowfirstlast", Kind::Integer(0, i64::MAX)),
+    ("yellowheight", Kind::Number(-INF, INF)),
+    ("yellowthresold", Kind::Number(-INF, INF)),
+    ("zoffset", Kind::Number(-INF, INF)),
+];
+
+fn check_crs(value: &str) -> Result<(), String> {
+    match value.trim() {
+        "" => Ok(()),
+        crs => Crs::parse(crs).map(|_| ()),
+    }
+}
+
+fn check_preset(value: &str) -> Result<(), String> {
+    let name = value.trim();
+    if name.is_empty() || Preset::find(name).is_some() {
+        return Ok(());
+    }
+    Err(match suggest(name, PRESETS.iter().map(|p| p.name)) {
+        Some(preset) => format!("Unknown preset {:?}, did you mean {}?", name, preset),
+        None => format!(
+            "Unknown preset {:?}, run 'pullauta presets' for the list",
+            name
+        ),
+    })
+}
+
+fn check_thinmode(value: &str) -> Result<(), String> {
+    match value.trim() {
+        "" | "seeded" | "random" | "grid" => Ok(()),
+        mode => Err(format!(
+            "Invalid thinmode {:?}, expected seeded, random or grid",
+            mode
+        )),
+    }
+}
+
+fn check_thinkeep(value: &str) -> Result<(), String> {
+    GridKeep::from_name(value).map(|_| ()).ok_or_else(|| {
+        format!(
+            "Invalid thinkeep {:?}, expected lowest, highest or first",
+            value
+        )
+    })
+}
+
+fn check_vegemode(value: &str) -> Result<(), String> {
+    match value.trim() {
+        "" | "0" | "2" => Ok(()),
+        "1" => Err(String::from(
+            "vegemode=1 of the Perl version is not implemented, use vegemode=2 for the vegetation \
+             height raster mode of this version",
+        )),
+        mode => Err(format!("Invalid vegemode {:?}, expected 0 or 2", mode)),
+    }
+}
+
+fn check_interpolation(value: &str) -> Result<(), String> {
+    Interpolation::from_name(value).map(|_| ()).ok_or_else(|| {
+        format!(
+            "Invalid interpolation {:?}, expected linear, tin, idw or naturalneighbour",
+            value
+        )
+    })
+}
+
+fn check_delimiter(value: &str) -> Result<(), String> {
+    parse_delimiter(value).map(|_| ())
+}
+
+fn check_columns(value: &str) -> Result<(), String> {
+    parse_columns(value).map(|_| ())
+}
+
+fn key_kind(key: &str) -> Option<Kind> {
+    if let Some(&(_, kind)) = KEYS.iter().find(|(k, _)| *k == key) {
+        return Some(kind);
+    }
+    if numbered(key, "zone").is_some() {
+        return Some(Kind::Tuple(4));
+    }
+    if numbered(key, "thresold").is_some() {
+        return Some(Kind::Tuple(3));
+    }
+    None
+}
+
+/// The known key closest to an unknown one, if any is close enough.
+fn suggest_key(key: &str) -> Option<String> {
+    let key = key.to_lowercase();
+    // numbered keys are compared with the same number
+    let digits = key.trim_start_matches(|c: char| !c.is_ascii_digit());
+    let mut candidates: Vec<String> = KEYS.iter().map(|(k, _)| k.to_string()).collect();
+    if !digits.is_empty() {
+        candidates.push(format!("zone{}", digits));
+        candidates.push(format!("thresold{}", digits));
+    }
+    suggest(&key, candidates.iter().map(String::as_str)).map(String::from)
+}
+
+/// Checks a value against the kind of its key, returning what is wrong with it.
+fn check_value(key: &str, kind: Kind, value: &str) -> Result<(), String> {
+    let value = value.trim();
+    let number = || {
+        value
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .ok_or_else(|| format!("`{}` must be a number, not {:?}", key, value))
+    };
+    match kind {
+        Kind::Flag => {
+            if value != "0" && value != "1" {
+                return Err(format!("`{}` must be 0 or 1, not {:?}", key, value));
+            }
+        }
+        Kind::Integer(min, max) => {
+            let v = value
+                .parse::<i64>()
+                .map_err(|_| format!("`{}` must be a whole number, not {:?}", key, value))?;
+            if v < min || v > max {
+                return Err(if max == i64::MAX {
+                    format!("`{}` is {}, it must be at least {}", key, v, min)
+                } else {
+                    format!("`{}` is {}, it must be between {} and {}", key, v, min, max)
+                });
+            }
+        }
+        Kind::Number(min, max) => {
+            let v = number()?;
+            if v < min || v > max {
+                return Err(if max == INF {
+                    format!("`{}` is {}, it must be at least {}", key, v, min)
+                } else {
+                    format!("`{}` is {}, it must be between {} and {}", key, v, min, max)
+                });
+            }
+        }
+        Kind::Positive => {
+            let v = number()?;
+            if v <= 0.0 {
+                return Err(format!("`{}` is {}, it must be greater than 0", key, v));
+            }
+        }
+        Kind::Color => {
+            let components: Vec<&str> = value.split(',').collect();
+            if components.len() != 3 || components.iter().any(|c| c.trim().parse::<u8>().is_err()) {
+                return Err(format!(
+                    "`{}` must be a color like 255,0,0, not {:?}",
+                    key, value
+                ));
+            }
+        }
+        Kind::Tuple(n) => {
+            let numbers =
+                parse_numbers(value).map_err(|e| format!("Invalid {} {:?}, {}", key, value, e))?;
+            if numbers.len() != n {
+                return Err(format!(
+                    "Invalid {} {:?}, expected {} numbers separated by |",
+                    key, value, n
+                ));
+            }
+        }
+        Kind::List => {
+            parse_list(key, value)?;
+        }
+        Kind::Parsed(parse) => parse(value)?,
+        Kind::Text => {}
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use std::path::Path;
 
-    use super::Config;
+    use super::*;
 
     #[test]
     fn should_load_config_template_successfully() {
         Config::from_file(Path::new("pullauta.default.ini"))
             .expect("Could not load and parse the default config template");
     }
+
+    #[test]
+    fn test_layered_config() {
+        let mut raw = RawConfig::default();
+        raw.add_str(
+            include_str!("../pullauta.default.ini"),
+            Path::new("pullauta.default.ini"),
+        )
+        .unwrap();
+        raw.add_str("contour_interval=2.5\nbatch=1\n", Path::new("project.ini"))
+            .unwrap();
+        let vars = [
+            ("PULLAUTA_BATCH", "0"),
+            ("PULLAUTA_BUILDINGCOLOR", "10,20,30"),
+            ("PULLAUTA_THINFACTOR", "0.5"),
+            ("PULLAUTA_CONFIG", "other.ini"),
+            ("HOME", "/root"),
+        ]
+        .map(|(k, v)| (OsString::from(k), OsString::from(v)));
+        raw.apply_env(vars);
+
+        let config = Config::from_raw(&raw).unwrap();
+        assert_eq!(config.contour_interval, 2.5);
+        assert!(!config.batch);
+        assert_eq!(config.buildingcolor, (10, 20, 30));
+        assert_eq!(config.thinning, Thinning::Random(0.5));
+        assert_eq!(raw.ini.general_section().get("config"), None);
+        // keys of the base config are kept
+        assert_eq!(config.processes, 2);
+
+        assert_eq!(
+            raw.location("contour_interval"),
+            Some(&Location::Line(PathBuf::from("project.ini"), 1))
+        );
+        assert_eq!(
+            raw.location("batch"),
+            Some(&Location::Env("PULLAUTA_BATCH".to_string()))
+        );
+        assert_eq!(
+            raw.location("cliff1"),
+            Some(&Location::Line(PathBuf::from("pullauta.default.ini"), 100))
+        );
+    }
+
+    #[test]
+    fn test_vegemode() {
+        let config = |ini: &str| {
+            let mut raw = RawConfig::default();
+            raw.add_str(
+                include_str!("../pullauta.default.ini"),
+                Path::new("pullauta.default.ini"),
+            )
+            .unwrap();
+            raw.add_str(ini, Path::new("project.ini")).unwrap();
+            (Config::from_raw(&raw), raw.check().len())
+        };
+        assert!(!config("").0.unwrap().vege_height);
+        assert!(config("vegemode=2").0.unwrap().vege_height);
+        // the vegemode 1 of the Perl version is not the height raster mode of this version
+        let (perl, issues) = config("vegemode=1");
+        assert!(perl.is_err());
+        assert_eq!(issues, 1);
+    }
+
+    #[test]
+    fn test_env_unknown_keys() {
+        let mut raw = RawConfig::default();
+        let vars = [
+            ("PULLAUTA_CONTOUR_INTERVALL", "2.5"),
+            ("PULLAUTA_LOG", "debug"),
+            ("PULLAUTA_ZONE2", "1|2|3|4"),
+        ]
+        .map(|(k, v)| (OsString::from(k), OsString::from(v)));
+        raw.apply_env(vars);
+
+        let keys = raw
+            .ini
+            .general_section()
+            .iter()
+            .map(|(k, _)| k)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["zone2"]);
+    }
+
+    /// A preset for the tests of how presets are applied.
+    static TEST_PRESET: Preset = Preset {
+        name: "test",
+        ini: "# Test data
+crs=EPSG:3067
+cliff1=1.3
+cliff2=2.3
+greenshades=0.2|0.35|0.5|0.7|1.3|2.6|4|99|99|99|99
+zone1=1.0|2.65|99|1
+zone2=2.65|3.4|99|0.1
+thresold1=0.20|3|0.08
+thresold2=3|4|0.08
+",
+    };
+
+    #[test]
+    fn test_presets() {
+        for preset in PRESETS.iter().chain([&TEST_PRESET]) {
+            let mut raw = RawConfig::default();
+            raw.add_str(preset.ini, Path::new(preset.name)).unwrap();
+            assert_eq!(raw.check(), vec![], "preset {}", preset.name);
+            assert!(!preset.about().is_empty());
+
+            // the complete file has the values of the preset
+            let mut dump = RawConfig::default();
+            dump.add_str(&preset.to_ini(), Path::new("dump.ini"))
+                .unwrap();
+            assert_eq!(dump.check(), vec![], "preset {}", preset.name);
+            for (key, value) in raw.ini.general_section().iter() {
+                assert_eq!(dump.ini.general_section().get(key), Some(value));
+            }
+            let zones = |raw: &RawConfig| {
+                let gs = raw.ini.general_section();
+                gs.iter()
+                    .filter(|(k, _)| numbered(k, "zone").is_some())
+                    .count()
+            };
+            assert_eq!(zones(&dump), zones(&raw));
+        }
+
+        // the keys the config files do not set come from the preset
+        let mut raw = RawConfig::default();
+        raw.add_str("cliff2=3\nzone1=1|2|99|1\n", Path::new("project.ini"))
+            .unwrap();
+        raw.apply_preset(&TEST_PRESET);
+        let config = Config::from_raw(&raw).unwrap();
+        assert_eq!(config.c1_limit, 1.3);
+        assert_eq!(config.c2_limit, 3.0);
+        assert_eq!(config.crs.unwrap().epsg, Some(3067));
+        // the zones are set by the user, the thresholds come from the preset
+        assert_eq!(config.zones.len(), 1);
+        assert_eq!(config.thresholds.len(), 2);
+        assert_eq!(raw.location("cliff1"), Some(&Location::Preset("test")));
+        assert_eq!(
+            raw.check()
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>(),
+            ["warning: The config sets `zone*`, `cliff2` over the values of preset test"]
+        );
+
+        // the keys the config files set override the preset, even at their default value
+        let mut raw = RawConfig::default();
+        raw.add_str(
+            include_str!("../pullauta.default.ini"),
+            Path::new("pullauta.ini"),
+        )
+        .unwrap();
+        raw.apply_preset(&TEST_PRESET);
+        let config = Config::from_raw(&raw).unwrap();
+        assert_eq!(config.c1_limit, 1.15);
+        assert_eq!(config.zones.len(), 3);
+        assert_eq!(
+            raw.location("cliff1"),
+            Some(&Location::Line(PathBuf::from("pullauta.ini"), 100))
+        );
+        assert_eq!(
+            raw.check()
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>(),
+            ["warning: The config sets `zone*`, `thresold*`, `crs`, `cliff1`, `cliff2` over the values of preset test"]
+        );
+
+        // stacked files override single keys of the lists
+        let mut raw = RawConfig::default();
+        raw.add_str(
+            include_str!("../pullauta.default.ini"),
+            Path::new("pullauta.ini"),
+        )
+        .unwrap();
+        raw.add_str("zone1=1|2|99|1\n", Path::new("project.ini"))
+            .unwrap();
+        let config = Config::from_raw(&raw).unwrap();
+        assert_eq!(config.zones.len(), 3);
+        assert_eq!(config.zones[0].high, 2.0);
+
+        raw.add_str("preset=fi_mml_2020\n", Path::new("project.ini"))
+            .unwrap();
+        assert_eq!(
+            raw.check()[0].message,
+            "Unknown preset \"fi_mml_2020\", run 'pullauta presets' for the list"
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let mut raw = RawConfig::default();
+        raw.add_str(
+            include_str!("../pullauta.default.ini"),
+            Path::new("pullauta.default.ini"),
+        )
+        .unwrap();
+        assert_eq!(raw.check(), vec![]);
+
+        let ini = "contour_intreval=2.5
+processes=two
+batch=yes
+lightgreentone=300
+cliff1=3
+threshold6=1|2
+zone4=4|2|9|1
+zone6=5|6|9|1
+buildingcolor=
+[extra]
+key=1
+";
+        raw.add_str(ini, Path::new("project.ini")).unwrap();
+        let messages: Vec<String> = raw.check().iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "project.ini:1: warning: Unknown key `contour_intreval`, did you mean `contour_interval`?",
+                "project.ini:2: error: `processes` must be a whole number, not \"two\"",
+                "project.ini:3: error: `batch` must be 0 or 1, not \"yes\"",
+                "project.ini:4: error: `lightgreentone` is 300, it must be between 0 and 255",
+                "project.ini:5: error: `cliff1` (3) is higher than `cliff2` (2), impassable cliffs must be the steepest",
+                "project.ini:6: error: Invalid thresold6 \"1|2\", expected 3 numbers separated by |",
+                "project.ini:7: error: `zone4` is an empty range, low|high must be increasing",
+                "project.ini:8: warning: `zone6` is ignored because there is no `zone5`",
+                "project.ini:9: warning: `buildingcolor` has no value, the default is used",
+                "project.ini:10: warning: The keys of section [extra] are ignored, they must be before any section",
+            ]
+        );
+    }
 }
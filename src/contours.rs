@@ -1,16 +1,41 @@
 use log::info;
 use rustc_hash::FxHashMap as HashMap;
-use std::error::Error;
 use std::io::{BufReader, BufWriter, Write};
 use std::path::Path;
 
 use crate::config::Config;
+use crate::error::{missing, Result, ResultExt};
+use crate::ground::fill_gaps;
+use crate::interpolation::{self, Interpolation};
+use crate::io::bytes::FromToBytes;
 use crate::io::fs::FileSystem;
 use crate::io::heightmap::HeightMap;
 use crate::io::xyz::XyzInternalReader;
-use crate::util::read_lines_no_alloc;
+use crate::util::try_read_lines_no_alloc;
 use crate::vec2d::Vec2D;
 
+/// File of the temp folder with the mask of the heightmap cells that had no ground points.
+pub const INTERPOLATED_FILE: &str = "interpolated.bin";
+
+/// Writes the mask of the interpolated heightmap cells to the temp folder, see
+/// [`xyz2heightmap_with_mask`].
+pub fn write_interpolated(
+    fs: &impl FileSystem,
+    tmpfolder: &Path,
+    interpolated: &Vec2D<bool>,
+) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(fs.create(tmpfolder.join(INTERPOLATED_FILE))?);
+    interpolated.to_bytes(&mut writer)?;
+    writer.flush()
+}
+
+/// Reads the mask of the interpolated heightmap cells of the temp folder. It has the same size as
+/// xyz_03.hmap and xyz2.hmap.
+pub fn read_interpolated(fs: &impl FileSystem, tmpfolder: &Path) -> std::io::Result<Vec2D<bool>> {
+    let mut reader = BufReader::new(fs.open(tmpfolder.join(INTERPOLATED_FILE))?);
+    Vec2D::from_bytes(&mut reader)
+}
+
 /// Create a heightmap from a point cloud file.
 ///
 /// Loads all the points and uses those that are classified as ground or water to create a heightmap using averages.
@@ -19,7 +44,18 @@ pub fn xyz2heightmap(
     config: &Config,
     tmpfolder: &Path,
     xyzfilein: &str, // this should be point cloud in
-) -> Result<HeightMap, Box<dyn Error>> {
+) -> Result<HeightMap> {
+    xyz2heightmap_with_mask(fs, config, tmpfolder, xyzfilein).map(|(hmap, _)| hmap)
+}
+
+/// Like [`xyz2heightmap`], but also returns a mask of the cells that had no points and were
+/// interpolated with the method of the `interpolation` config key.
+pub fn xyz2heightmap_with_mask(
+    fs: &impl FileSystem,
+    config: &Config,
+    tmpfolder: &Path,
+    xyzfilein: &str, // this should be point cloud in
+) -> Result<(HeightMap, Vec2D<bool>)> {
     info!("Generating curves...");
 
     let scalefactor = config.scalefactor;
@@ -108,6 +144,45 @@ pub fn xyz2heightmap(
         }
     }
 
+    // the cells without points are interpolated
+    let mut interpolated = Vec2D::new(w + 1, h + 1, false);
+    for (x, y, z) in avg_alt.iter() {
+        interpolated[(x, y)] = z.is_nan();
+    }
+    if !interpolated.iter().any(|(_, _, i)| !i) {
+        return Err(format!("No ground points in {}", xyz_file_in.display()).into());
+    }
+    match config.interpolation {
+        Interpolation::Linear => fill_linear(&mut avg_alt),
+        method => interpolation::fill(&mut avg_alt, method, config.idw_power),
+    }
+
+    xmin += 1.0;
+    ymin += 1.0;
+
+    // cells the interpolation could not reach are filled from their neighbours
+    if avg_alt.is_any_nan() {
+        fill_gaps(&mut avg_alt);
+    }
+
+    let mut hmap = HeightMap {
+        xoffset: xmin,
+        yoffset: ymin,
+        scale: 2.0 * scalefactor,
+        grid: avg_alt,
+        distance: None,
+    };
+    hmap.set_distance_from_mask(&interpolated);
+
+    Ok((hmap, interpolated))
+}
+
+/// Fills the cells without points by linear interpolation along rows and columns, then with the
+/// average of their neighbours and finally from the cells above and below them.
+fn fill_linear(avg_alt: &mut Vec2D<f64>) {
+    let w = avg_alt.width() - 1;
+    let h = avg_alt.height() - 1;
+
     for x in 0..w + 1 {
         for y in 0..h + 1 {
             if avg_alt[(x, y)].is_nan() {
@@ -200,40 +275,18 @@ pub fn xyz2heightmap(
             }
         }
     }
-
-    xmin += 1.0;
-    ymin += 1.0;
-
-    // make sure we do not have any NaNs
-    for x in 0..avg_alt.width() {
-        for y in 0..avg_alt.height() {
-            if avg_alt[(x, y)].is_nan() {
-                panic!(
-                    "heightmap should not have any nans, found NaN at ({}, {})",
-                    x, y
-                );
-            }
-        }
-    }
-
-    let hmap = HeightMap {
-        xoffset: xmin,
-        yoffset: ymin,
-        scale: 2.0 * scalefactor,
-        grid: avg_alt.clone(),
-    };
-
-    Ok(hmap)
 }
 
-/// Creates contour lines from a heightmap.
+/// Creates contour lines from a heightmap. No contours are drawn across the cells farther than
+/// `nodatadistance` from real points.
 pub fn heightmap2contours(
     fs: &impl FileSystem,
+    config: &Config,
     tmpfolder: &Path,
     cinterval: f64,
     heightmap: &HeightMap,
     dxffile: &str,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<()> {
     let mut avg_alt = heightmap.grid.clone();
     let w = heightmap.grid.width() - 1;
     let h = heightmap.grid.height() - 1;
@@ -272,12 +325,24 @@ pub fn heightmap2contours(
         }
     }
 
+    // cells with a corner in an unmapped area
+    let mut unmapped = Vec2D::new(w + 1, h + 1, false);
+    if config.nodata_distance > 0.0 {
+        for i in 0..w {
+            for j in 0..h {
+                unmapped[(i, j)] = [(i, j), (i, j + 1), (i + 1, j), (i + 1, j + 1)]
+                    .iter()
+                    .any(|&(x, y)| heightmap.is_unmapped(x, y, config.nodata_distance));
+            }
+        }
+    }
+
     let v = cinterval;
 
     let mut level: f64 = (hmin / v).floor() * v;
     let polyline_out = tmpfolder.join("temp_polylines.txt");
 
-    let f = fs.create(&polyline_out).expect("Unable to create file");
+    let f = fs.create(&polyline_out).with_file(&polyline_out)?;
     let mut f = BufWriter::new(f);
 
     loop {
@@ -295,7 +360,8 @@ pub fn heightmap2contours(
                 let mut c = avg_alt[(i + 1, j)];
                 let mut d = avg_alt[(i + 1, j + 1)];
 
-                if a < level && b < level && c < level && d < level
+                if unmapped[(i, j)]
+                    || a < level && b < level && c < level && d < level
                     || a > level && b > level && c > level && d > level
                 {
                     // skip
@@ -438,13 +504,11 @@ pub fn heightmap2contours(
         for k in obj.iter() {
             if curves.contains_key(k) {
                 let (x, y, _) = *k;
-                write!(&mut f, "{},{};", x as f64 / 100.0, y as f64 / 100.0)
-                    .expect("Cannot write to output file");
+                write!(&mut f, "{},{};", x as f64 / 100.0, y as f64 / 100.0)?;
                 let mut res = (x, y);
 
                 let (x, y) = *curves.get(k).unwrap();
-                write!(&mut f, "{},{};", x as f64 / 100.0, y as f64 / 100.0)
-                    .expect("Cannot write to output file");
+                write!(&mut f, "{},{};", x as f64 / 100.0, y as f64 / 100.0)?;
                 curves.remove(k);
 
                 let mut head = (x, y);
@@ -460,8 +524,7 @@ pub fn heightmap2contours(
                         res = head;
 
                         let (x, y) = *curves.get(&(head.0, head.1, 1)).unwrap();
-                        write!(&mut f, "{},{};", x as f64 / 100.0, y as f64 / 100.0)
-                            .expect("Cannot write to output file");
+                        write!(&mut f, "{},{};", x as f64 / 100.0, y as f64 / 100.0)?;
                         curves.remove(&(head.0, head.1, 1));
 
                         head = (x, y);
@@ -475,8 +538,7 @@ pub fn heightmap2contours(
                         res = head;
 
                         let (x, y) = *curves.get(&(head.0, head.1, 2)).unwrap();
-                        write!(&mut f, "{},{};", x as f64 / 100.0, y as f64 / 100.0)
-                            .expect("Cannot write to output file");
+                        write!(&mut f, "{},{};", x as f64 / 100.0, y as f64 / 100.0)?;
                         curves.remove(&(head.0, head.1, 2));
 
                         head = (x, y);
@@ -487,8 +549,7 @@ pub fn heightmap2contours(
                             curves.remove(&(head.0, head.1, 2));
                         }
                     } else {
-                        f.write_all("\r\n".as_bytes())
-                            .expect("Cannot write to output file");
+                        f.write_all("\r\n".as_bytes())?;
                         break;
                     }
                 }
@@ -499,22 +560,20 @@ pub fn heightmap2contours(
     // explicitly flush and drop to close the file
     drop(f);
 
-    let f = fs
-        .create(tmpfolder.join(dxffile))
-        .expect("Unable to create file");
+    let dxf_output = tmpfolder.join(dxffile);
+    let f = fs.create(&dxf_output).with_file(&dxf_output)?;
     let mut f = BufWriter::new(f);
 
     write!(
         &mut f,
         "  0\r\nSECTION\r\n  2\r\nHEADER\r\n  9\r\n$EXTMIN\r\n 10\r\n{}\r\n 20\r\n{}\r\n  9\r\n$EXTMAX\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nENDSEC\r\n  0\r\nSECTION\r\n  2\r\nENTITIES\r\n  0\r\n",
         xmin, ymin, xmax, ymax,
-    ).expect("Cannot write dxf file");
+    )?;
 
-    read_lines_no_alloc(fs, polyline_out, |line| {
+    try_read_lines_no_alloc(fs, polyline_out, |line| {
         let parts = line.trim().split(';');
         let r = parts.collect::<Vec<&str>>();
-        f.write_all("POLYLINE\r\n 66\r\n1\r\n  8\r\ncont\r\n  0\r\n".as_bytes())
-            .expect("Cannot write dxf file");
+        f.write_all("POLYLINE\r\n 66\r\n1\r\n  8\r\ncont\r\n  0\r\n".as_bytes())?;
         for (i, d) in r.iter().enumerate() {
             if d != &"" {
                 let ii = i + 1;
@@ -522,23 +581,20 @@ pub fn heightmap2contours(
                 if ii > 5 && ii < ldata - 5 && ldata > 12 && ii % 2 == 0 {
                     continue;
                 }
-                let mut xy_raw = d.split(',');
-                let x: f64 = xy_raw.next().unwrap().parse::<f64>().unwrap() * size + xmin;
-                let y: f64 = xy_raw.next().unwrap().parse::<f64>().unwrap() * size + ymin;
+                let (x, y) = d.split_once(',').ok_or_else(|| missing("y coordinate"))?;
+                let x: f64 = x.parse::<f64>()? * size + xmin;
+                let y: f64 = y.parse::<f64>()? * size + ymin;
                 write!(
                     &mut f,
                     "VERTEX\r\n  8\r\ncont\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\n",
                     x, y
-                )
-                .expect("Cannot write dxf file");
+                )?;
             }
         }
-        f.write_all("SEQEND\r\n  0\r\n".as_bytes())
-            .expect("Cannot write dxf file");
-    })
-    .expect("Cannot read file");
-    f.write_all("ENDSEC\r\n  0\r\nEOF\r\n".as_bytes())
-        .expect("Cannot write dxf file");
+        f.write_all("SEQEND\r\n  0\r\n".as_bytes())?;
+        Ok(())
+    })?;
+    f.write_all("ENDSEC\r\n  0\r\nEOF\r\n".as_bytes())?;
     info!("Done");
 
     Ok(())
@@ -1,7 +1,7 @@
-use std::error::Error;
 use std::io::{BufWriter, Write};
 use std::path::Path;
 
+use crate::error::{Result, ResultExt};
 use crate::io::fs::FileSystem;
 
 pub fn polylinedxfcrop(
@@ -12,10 +12,8 @@ pub fn polylinedxfcrop(
     miny: f64,
     maxx: f64,
     maxy: f64,
-) -> Result<(), Box<dyn Error>> {
-    let data = fs
-        .read_to_string(input)
-        .expect("Should have been able to read the file");
+) -> Result<()> {
+    let data = fs.read_to_string(input).with_file(input)?;
     let data: Vec<&str> = data.split("POLYLINE").collect();
     let dxfhead = data[0];
     let mut out = String::new();
@@ -81,9 +79,9 @@ pub fn polylinedxfcrop(
     if !out.contains("EOF") {
         out.push_str("ENDSEC\r\n  0\r\nEOF\r\n");
     }
-    let fp = fs.create(output).expect("Unable to create file");
+    let fp = fs.create(&output).with_file(output)?;
     let mut fp = BufWriter::new(fp);
-    fp.write_all(out.as_bytes()).expect("Unable to write file");
+    fp.write_all(out.as_bytes())?;
     Ok(())
 }
 
@@ -95,18 +93,15 @@ pub fn pointdxfcrop(
     miny: f64,
     maxx: f64,
     maxy: f64,
-) -> Result<(), Box<dyn Error>> {
-    let data = fs
-        .read_to_string(input)
-        .expect("Should have been able to read the file");
+) -> Result<()> {
+    let data = fs.read_to_string(input).with_file(input)?;
     let mut data: Vec<&str> = data.split("POINT").collect();
     let dxfhead = data[0];
 
-    let fp = fs.create(output).expect("Unable to create file");
+    let fp = fs.create(&output).with_file(output)?;
     let mut fp = BufWriter::new(fp);
 
-    fp.write_all(dxfhead.as_bytes())
-        .expect("Could not write file");
+    fp.write_all(dxfhead.as_bytes())?;
 
     let (d2, ending) = data[data.len() - 1]
         .split_once("ENDSEC")
@@ -119,10 +114,10 @@ pub fn pointdxfcrop(
             let val4 = val[4].trim().parse::<f64>().unwrap_or(0.0);
             let val6 = val[6].trim().parse::<f64>().unwrap_or(0.0);
             if val4 >= minx && val4 <= maxx && val6 >= miny && val6 <= maxy {
-                write!(fp, "POINT{}", rec).expect("Could not write file");
+                write!(fp, "POINT{}", rec)?;
             }
         }
     }
-    write!(fp, "ENDSEC{}", ending).expect("Could not write file");
+    write!(fp, "ENDSEC{}", ending)?;
     Ok(())
 }
@@ -0,0 +1,260 @@
+//! The error type of the crate, with the tile, stage, file and line an error happened in.
+
+use std::fmt;
+use std::io;
+use std::num::{ParseFloatError, ParseIntError};
+use std::path::{Path, PathBuf};
+
+/// Result with the [`Error`] of the crate by default.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Image(image::ImageError),
+    Las(las::Error),
+    Laz(laz::LasZipError),
+    Tiff(tiff::TiffError),
+    Json(serde_json::Error),
+    Ini(ini::Error),
+    #[cfg(feature = "shapefile")]
+    Shapefile(shapefile::Error),
+    #[cfg(feature = "shapefile")]
+    Zip(zip::result::ZipError),
+    /// A number or other value that could not be parsed.
+    Parse(String),
+    /// Any other failure, described by the message.
+    Invalid(String),
+    /// An error with where it happened, the outermost context first.
+    Context(Context, Box<Error>),
+}
+
+/// Where an [`Error`] happened.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Context {
+    /// The input file of a tile of a batch.
+    Tile(String),
+    /// A stage of a [`Pipeline`](crate::pipeline::Pipeline).
+    Stage(&'static str),
+    File(PathBuf),
+    /// A line of a file, starting from 1.
+    Line(PathBuf, usize),
+}
+
+impl Error {
+    pub fn invalid(message: impl Into<String>) -> Self {
+        Error::Invalid(message.into())
+    }
+
+    pub fn context(self, context: Context) -> Self {
+        Error::Context(context, Box::new(self))
+    }
+
+    /// The contexts of the error, the outermost first.
+    pub fn contexts(&self) -> impl Iterator<Item = &Context> {
+        let mut error = self;
+        std::iter::from_fn(move || match error {
+            Error::Context(context, inner) => {
+                error = inner;
+                Some(context)
+            }
+            _ => None,
+        })
+    }
+
+    /// The error without its contexts.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context(_, inner) => inner.root(),
+            error => error,
+        }
+    }
+
+    /// The tile the error happened in, if known.
+    pub fn tile(&self) -> Option<&str> {
+        self.contexts().find_map(|c| match c {
+            Context::Tile(tile) => Some(tile.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The stage the error happened in, if known.
+    pub fn stage(&self) -> Option<&'static str> {
+        self.contexts().find_map(|c| match c {
+            Context::Stage(stage) => Some(*stage),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Context::Tile(tile) => write!(f, "tile {}", tile),
+            Context::Stage(stage) => write!(f, "stage {}", stage),
+            Context::File(path) => write!(f, "{}", path.display()),
+            Context::Line(path, line) => write!(f, "{}:{}", path.display(), line),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::Image(e) => e.fmt(f),
+            Error::Las(e) => e.fmt(f),
+            Error::Laz(e) => e.fmt(f),
+            Error::Tiff(e) => e.fmt(f),
+            Error::Json(e) => e.fmt(f),
+            Error::Ini(e) => e.fmt(f),
+            #[cfg(feature = "shapefile")]
+            Error::Shapefile(e) => e.fmt(f),
+            #[cfg(feature = "shapefile")]
+            Error::Zip(e) => e.fmt(f),
+            Error::Parse(message) | Error::Invalid(message) => f.write_str(message),
+            Error::Context(context, inner) => write!(f, "{}: {}", context, inner),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Image(e) => Some(e),
+            Error::Las(e) => Some(e),
+            Error::Laz(e) => Some(e),
+            Error::Tiff(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Ini(e) => Some(e),
+            #[cfg(feature = "shapefile")]
+            Error::Shapefile(e) => Some(e),
+            #[cfg(feature = "shapefile")]
+            Error::Zip(e) => Some(e),
+            Error::Parse(_) | Error::Invalid(_) => None,
+            Error::Context(_, inner) => Some(inner.as_ref()),
+        }
+    }
+}
+
+macro_rules! impl_from {
+    ($($variant:ident($error:ty)),* $(,)?) => {
+        $(
+            impl From<$error> for Error {
+                fn from(error: $error) -> Self {
+                    Error::$variant(error)
+                }
+            }
+        )*
+    };
+}
+
+impl_from!(
+    Io(io::Error),
+    Image(image::ImageError),
+    Las(las::Error),
+    Laz(laz::LasZipError),
+    Tiff(tiff::TiffError),
+    Json(serde_json::Error),
+    Ini(ini::Error),
+);
+
+#[cfg(feature = "shapefile")]
+impl_from!(Shapefile(shapefile::Error), Zip(zip::result::ZipError));
+
+impl From<ParseFloatError> for Error {
+    fn from(error: ParseFloatError) -> Self {
+        Error::Parse(error.to_string())
+    }
+}
+
+impl From<ParseIntError> for Error {
+    fn from(error: ParseIntError) -> Self {
+        Error::Parse(error.to_string())
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Invalid(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Invalid(message.to_string())
+    }
+}
+
+/// Adds where an error happened to results.
+pub trait ResultExt<T> {
+    fn context(self, context: Context) -> Result<T>;
+
+    fn with_tile(self, tile: &str) -> Result<T>
+    where
+        Self: Sized,
+    {
+        self.context(Context::Tile(tile.to_string()))
+    }
+
+    fn with_stage(self, stage: &'static str) -> Result<T>
+    where
+        Self: Sized,
+    {
+        self.context(Context::Stage(stage))
+    }
+
+    fn with_file(self, path: impl AsRef<Path>) -> Result<T>
+    where
+        Self: Sized,
+    {
+        self.context(Context::File(path.as_ref().to_path_buf()))
+    }
+
+    /// Adds the line of a file, counting from 1.
+    fn with_line(self, path: impl AsRef<Path>, line: usize) -> Result<T>
+    where
+        Self: Sized,
+    {
+        self.context(Context::Line(path.as_ref().to_path_buf(), line))
+    }
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for std::result::Result<T, E> {
+    fn context(self, context: Context) -> Result<T> {
+        self.map_err(|e| e.into().context(context))
+    }
+}
+
+/// Makes an error of a missing value, for `Option::ok_or_else`.
+pub fn missing(what: &str) -> Error {
+    Error::Parse(format!("missing {}", what))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_context() {
+        fn parse(value: &str) -> Result<f64> {
+            value.parse::<f64>().with_line("temp/pins.txt", 3)
+        }
+        let error = parse("x")
+            .with_stage("knolls")
+            .with_tile("t_0_0.laz")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "tile t_0_0.laz: stage knolls: temp/pins.txt:3: invalid float literal"
+        );
+        assert_eq!(error.tile(), Some("t_0_0.laz"));
+        assert_eq!(error.stage(), Some("knolls"));
+        assert!(matches!(error.root(), Error::Parse(_)));
+
+        // errors can be sent to the thread reporting them
+        fn is_send_sync<T: Send + Sync>(_: &T) {}
+        is_send_sync(&error);
+    }
+}
@@ -0,0 +1,306 @@
+use log::info;
+use std::collections::VecDeque;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::io::crs::Crs;
+use crate::io::fs::FileSystem;
+use crate::io::read_points;
+use crate::io::xyz::XyzInternalWriter;
+use crate::vec2d::Vec2D;
+
+/// Classes of noise points, which are left out of the ground detection.
+const NOISE_CLASSES: [u8; 2] = [7, 18];
+/// Classes of the points that are reclassified.
+const RECLASSIFIED_CLASSES: [u8; 3] = [0, 1, 2];
+
+/// Largest number of cells of the grids of the filter, 1 GB of elevations per grid.
+const MAX_GRID_CELLS: usize = 1 << 27;
+
+/// Detects the ground points of a point cloud with a progressive morphological filter and writes
+/// the reclassified points to `output` in the internal format.
+///
+/// The lowest point of every cell is opened (eroded and dilated) with growing windows. Cells whose
+/// elevation drops more than the slope dependent threshold of the window are objects like trees
+/// and buildings, the remaining cells form the ground surface. Only the never classified (0),
+/// unclassified (1) and ground (2) points are reclassified: those close to that surface become
+/// ground, the others unclassified. Raw point clouds are all class 0 or 1, and the other classes
+/// (buildings, water and so on) come from a classification that is better than this filter.
+pub fn ground(fs: &impl FileSystem, config: &Config, input: &Path, output: &Path) -> Result<()> {
+    info!("Detecting ground points of {}", input.display());
+
+    let &Config {
+        ground_cellsize: cellsize,
+        ground_maxwindow: maxwindow,
+        ground_slope: slope,
+        ground_initialdistance: initialdistance,
+        ground_maxdistance: maxdistance,
+        xyz_delimiter,
+        ..
+    } = config;
+    let xyz_columns = &config.xyz_columns;
+
+    let mut xmin = f64::MAX;
+    let mut ymin = f64::MAX;
+    let mut xmax = f64::MIN;
+    let mut ymax = f64::MIN;
+    let fields = read_points(fs, input, xyz_delimiter, xyz_columns, |r| {
+        xmin = xmin.min(r.x);
+        ymin = ymin.min(r.y);
+        xmax = xmax.max(r.x);
+        ymax = ymax.max(r.y);
+    })?;
+    if xmin > xmax {
+        return Err(format!("No points in {}", input.display()).into());
+    }
+
+    let w = ((xmax - xmin) / cellsize).floor() as usize + 1;
+    let h = ((ymax - ymin) / cellsize).floor() as usize + 1;
+    if w.saturating_mul(h) > MAX_GRID_CELLS {
+        return Err(Error::invalid(format!(
+            "The points of {} span {} x {} cells of groundcellsize {}, remove the outlier points or \
+             use larger cells",
+            input.display(),
+            w,
+            h,
+            cellsize
+        )));
+    }
+    let cell = |x: f64, y: f64| {
+        (
+            ((x - xmin) / cellsize).floor() as usize,
+            ((y - ymin) / cellsize).floor() as usize,
+        )
+    };
+
+    // lowest point of each cell
+    let mut minimum = Vec2D::new(w, h, f64::NAN);
+    read_points(fs, input, xyz_delimiter, xyz_columns, |r| {
+        if NOISE_CLASSES.contains(&r.classification) {
+            return;
+        }
+        let z = &mut minimum[cell(r.x, r.y)];
+        if z.is_nan() || r.z < *z {
+            *z = r.z;
+        }
+    })?;
+
+    let ground = ground_surface(
+        &minimum,
+        cellsize,
+        maxwindow,
+        slope,
+        initialdistance,
+        maxdistance,
+    );
+
+    let mut writer = XyzInternalWriter::compressed(
+        BufWriter::new(fs.create(output)?),
+        fields,
+        config.temp_resolution,
+    );
+    let mut ground_points: u64 = 0;
+    let mut total_points: u64 = 0;
+    let mut write_result = Ok(());
+    read_points(fs, input, xyz_delimiter, xyz_columns, |mut r| {
+        total_points += 1;
+        if RECLASSIFIED_CLASSES.contains(&r.classification) {
+            let elevation = interpolate(&ground, (r.x - xmin) / cellsize, (r.y - ymin) / cellsize);
+            if (r.z - elevation).abs() <= initialdistance {
+                r.classification = 2;
+                ground_points += 1;
+            } else {
+                r.classification = 1;
+            }
+        }
+        if write_result.is_ok() {
+            write_result = writer.write_record(&r);
+        }
+    })?;
+    write_result?;
+    writer.finish()?;
+    let crs = config
+        .crs
+        .clone()
+        .or_else(|| Crs::from_point_file(fs, input));
+    Crs::write_file(crs.as_ref(), fs, Crs::sidecar_path(output))?;
+
+    info!(
+        "Classified {} of {} points as ground",
+        ground_points, total_points
+    );
+    Ok(())
+}
+
+/// Runs the progressive morphological filter on a grid of the lowest elevations of each cell (NaN
+/// for cells without points) and returns the interpolated ground surface.
+fn ground_surface(
+    minimum: &Vec2D<f64>,
+    cellsize: f64,
+    maxwindow: f64,
+    slope: f64,
+    initialdistance: f64,
+    maxdistance: f64,
+) -> Vec2D<f64> {
+    let (w, h) = (minimum.width(), minimum.height());
+    let mut surface = minimum.clone();
+    fill_gaps(&mut surface);
+
+    // open the surface with growing windows, flagging the cells that drop too much
+    let mut is_ground = Vec2D::new(w, h, true);
+    let mut previous_size = 1;
+    let mut radius = 1;
+    while ((2 * radius + 1) as f64) * cellsize <= maxwindow {
+        let size = 2 * radius + 1;
+        let threshold =
+            (slope * (size - previous_size) as f64 * cellsize + initialdistance).min(maxdistance);
+
+        let opened = dilate(&erode(&surface, radius), radius);
+        for (x, y, z) in surface.iter() {
+            if z - opened[(x, y)] > threshold {
+                is_ground[(x, y)] = false;
+            }
+        }
+        surface = opened;
+        previous_size = size;
+        radius *= 2;
+    }
+
+    // the ground surface is made of the lowest points of the ground cells only
+    let mut ground = Vec2D::new(w, h, f64::NAN);
+    for (x, y, z) in minimum.iter() {
+        if is_ground[(x, y)] {
+            ground[(x, y)] = z;
+        }
+    }
+    fill_gaps(&mut ground);
+    ground
+}
+
+/// Fills the NaN cells of a grid with the average of their nearest filled neighbours, growing
+/// outwards from the filled cells.
+pub(crate) fn fill_gaps(grid: &mut Vec2D<f64>) {
+    let (w, h) = (grid.width(), grid.height());
+    let mut queue = VecDeque::new();
+    let mut queued = Vec2D::new(w, h, false);
+    for (x, y, z) in grid.iter() {
+        if !z.is_nan() {
+            queued[(x, y)] = true;
+            queue.push_back((x, y));
+        }
+    }
+
+    while let Some((x, y)) = queue.pop_front() {
+        let mut sum = 0.0;
+        let mut count = 0;
+        for nx in x.saturating_sub(1)..(x + 2).min(w) {
+            for ny in y.saturating_sub(1)..(y + 2).min(h) {
+                let z = grid[(nx, ny)];
+                if !z.is_nan() {
+                    sum += z;
+                    count += 1;
+                } else if !queued[(nx, ny)] {
+                    queued[(nx, ny)] = true;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+        if grid[(x, y)].is_nan() && count > 0 {
+            grid[(x, y)] = sum / count as f64;
+        }
+    }
+}
+
+/// Applies `op` over a square window of the given radius, one axis at a time.
+fn filter(grid: &Vec2D<f64>, radius: usize, op: fn(f64, f64) -> f64) -> Vec2D<f64> {
+    let (w, h) = (grid.width(), grid.height());
+    let mut rows = Vec2D::new(w, h, 0.0);
+    for y in 0..h {
+        for x in 0..w {
+            let mut value = grid[(x, y)];
+            for nx in x.saturating_sub(radius)..(x + radius + 1).min(w) {
+                value = op(value, grid[(nx, y)]);
+            }
+            rows[(x, y)] = value;
+        }
+    }
+    let mut result = Vec2D::new(w, h, 0.0);
+    for y in 0..h {
+        for x in 0..w {
+            let mut value = rows[(x, y)];
+            for ny in y.saturating_sub(radius)..(y + radius + 1).min(h) {
+                value = op(value, rows[(x, ny)]);
+            }
+            result[(x, y)] = value;
+        }
+    }
+    result
+}
+
+fn erode(grid: &Vec2D<f64>, radius: usize) -> Vec2D<f64> {
+    filter(grid, radius, f64::min)
+}
+
+fn dilate(grid: &Vec2D<f64>, radius: usize) -> Vec2D<f64> {
+    filter(grid, radius, f64::max)
+}
+
+/// Bilinear interpolation of the grid at fractional cell coordinates, with cell values located
+/// at the cell centers.
+fn interpolate(grid: &Vec2D<f64>, x: f64, y: f64) -> f64 {
+    let (w, h) = (grid.width(), grid.height());
+    let x = (x - 0.5).clamp(0.0, (w - 1) as f64);
+    let y = (y - 0.5).clamp(0.0, (h - 1) as f64);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+    let (dx, dy) = (x - x0 as f64, y - y0 as f64);
+
+    let top = grid[(x0, y0)] * (1.0 - dx) + grid[(x1, y0)] * dx;
+    let bottom = grid[(x0, y1)] * (1.0 - dx) + grid[(x1, y1)] * dx;
+    top * (1.0 - dy) + bottom * dy
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ground_surface() {
+        // a gentle slope with a 5 m high, 10 m wide building and a cell without points
+        let mut minimum = Vec2D::new(40, 40, 0.0);
+        for (x, _, z) in minimum.iter_mut() {
+            *z = x as f64 * 0.1;
+        }
+        for x in 15..25 {
+            for y in 15..25 {
+                minimum[(x, y)] += 5.0;
+            }
+        }
+        minimum[(5, 5)] = f64::NAN;
+
+        let ground = ground_surface(&minimum, 1.0, 20.0, 0.3, 0.15, 2.5);
+        for (x, y, z) in ground.iter() {
+            // the building is filled from its surroundings, which does not follow the slope exactly
+            let tolerance = if (15..25).contains(&x) && (15..25).contains(&y) {
+                1.0
+            } else {
+                0.1
+            };
+            assert!((z - x as f64 * 0.1).abs() < tolerance, "{} {} {}", x, y, z);
+        }
+    }
+
+    #[test]
+    fn test_outlier_grid() {
+        let fs = crate::io::fs::memory::MemoryFileSystem::new();
+        let config = Config::from_file(Path::new("pullauta.default.ini")).unwrap();
+        // one point far away from the others would need a grid of 10^12 cells
+        let points = [(0.0, 0.0, 0.0), (1.0, 1.0, 0.0), (1e6, 1e6, 0.0)];
+        crate::io::write_test_las(&fs, "in.las", &points, false);
+        let error =
+            ground(&fs, &config, Path::new("in.las"), Path::new("out.xyz.bin")).unwrap_err();
+        assert!(error.to_string().contains("outlier"), "{}", error);
+    }
+}
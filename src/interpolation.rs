@@ -0,0 +1,452 @@
+use crate::ground::fill_gaps;
+use crate::vec2d::Vec2D;
+
+/// Method for filling the cells of a heightmap without points, see the `interpolation` config
+/// key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    /// Linear interpolation along rows and columns followed by averaging, as in the original
+    /// Perl version.
+    #[default]
+    Linear,
+    /// Linear interpolation over a Delaunay triangulation of the cells around the gaps.
+    Tin,
+    /// Inverse distance weighting of the nearest cells around the gaps.
+    Idw,
+    /// Sibson's natural neighbour interpolation of the cells around the gaps.
+    NaturalNeighbour,
+}
+
+impl Interpolation {
+    /// Parses a value of the `interpolation` config key.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase().replace(['_', '-', ' '], "");
+        Some(match name.as_str() {
+            "" | "linear" => Self::Linear,
+            "tin" | "delaunay" => Self::Tin,
+            "idw" => Self::Idw,
+            "naturalneighbour" | "naturalneighbor" | "nn" | "sibson" => Self::NaturalNeighbour,
+            _ => return None,
+        })
+    }
+}
+
+/// Number of nearest cells used by inverse distance weighting.
+const IDW_NEIGHBOURS: usize = 12;
+/// Size in cells of the buckets used for finding the nearest cells.
+const BUCKET_SIZE: i64 = 8;
+/// Marks a missing neighbour of a triangle.
+const NONE: usize = usize::MAX;
+
+/// Fills the NaN cells of a grid with the TIN, IDW or natural neighbour method. The filled cells
+/// next to the gaps are used as samples. Cells the triangulation does not cover are filled with
+/// inverse distance weighting, any remaining ones from their neighbours. The linear method is
+/// implemented in [`crate::contours::xyz2heightmap`], for it only the neighbour filling is done.
+pub fn fill(grid: &mut Vec2D<f64>, method: Interpolation, idw_power: f64) {
+    let (w, h) = (grid.width() as i64, grid.height() as i64);
+    let is_gap = |grid: &Vec2D<f64>, x: i64, y: i64| {
+        x >= 0 && y >= 0 && x < w && y < h && grid[(x as usize, y as usize)].is_nan()
+    };
+
+    // visiting the cells row by row in alternating directions keeps consecutive cells close,
+    // which makes locating them in the triangulation fast
+    let serpentine =
+        (0..h).flat_map(|y| (0..w).map(move |x| if y % 2 == 0 { (x, y) } else { (w - 1 - x, y) }));
+    let mut samples = Vec::new();
+    let mut gaps = Vec::new();
+    for (x, y) in serpentine {
+        if is_gap(grid, x, y) {
+            gaps.push((x, y));
+        } else if (-1..=1).any(|dx| (-1..=1).any(|dy| is_gap(grid, x + dx, y + dy))) {
+            samples.push((x, y, grid[(x as usize, y as usize)]));
+        }
+    }
+    if gaps.is_empty() || samples.is_empty() {
+        fill_gaps(grid);
+        return;
+    }
+
+    let mut triangulation = match method {
+        Interpolation::Tin | Interpolation::NaturalNeighbour => {
+            let mut triangulation = Triangulation::new(w.max(h));
+            for &(x, y, _) in &samples {
+                triangulation.insert((x, y));
+            }
+            Some(triangulation)
+        }
+        _ => None,
+    };
+    let index = SampleIndex::new(&samples, w, h);
+    for (x, y) in gaps {
+        let value = match (&mut triangulation, method) {
+            (Some(t), Interpolation::Tin) => t.linear(&samples, (x, y)),
+            (Some(t), Interpolation::NaturalNeighbour) => t.natural_neighbour(&samples, (x, y)),
+            _ => None,
+        };
+        let value = match (value, method) {
+            (Some(value), _) => value,
+            (None, Interpolation::Linear) => f64::NAN,
+            (None, _) => index.idw(&samples, (x, y), idw_power),
+        };
+        grid[(x as usize, y as usize)] = value;
+    }
+    fill_gaps(grid);
+}
+
+/// Buckets of sample indices for finding the nearest samples of a cell.
+struct SampleIndex {
+    buckets: Vec2D<Vec<usize>>,
+}
+
+impl SampleIndex {
+    fn new(samples: &[(i64, i64, f64)], w: i64, h: i64) -> Self {
+        let bw = (w / BUCKET_SIZE + 1) as usize;
+        let bh = (h / BUCKET_SIZE + 1) as usize;
+        let mut buckets = Vec2D::new(bw, bh, Vec::new());
+        for (i, &(x, y, _)) in samples.iter().enumerate() {
+            buckets[((x / BUCKET_SIZE) as usize, (y / BUCKET_SIZE) as usize)].push(i);
+        }
+        SampleIndex { buckets }
+    }
+
+    /// Inverse distance weighted average of the nearest samples of a cell.
+    fn idw(&self, samples: &[(i64, i64, f64)], (x, y): (i64, i64), power: f64) -> f64 {
+        let (bw, bh) = (self.buckets.width() as i64, self.buckets.height() as i64);
+        let (bx, by) = (x / BUCKET_SIZE, y / BUCKET_SIZE);
+
+        // search rings of buckets until the next ring cannot have closer samples
+        let mut nearest: Vec<(i64, usize)> = Vec::new();
+        for r in 0..bw.max(bh) {
+            if nearest.len() >= IDW_NEIGHBOURS {
+                let reach = (r - 1) * BUCKET_SIZE;
+                if reach * reach > nearest[IDW_NEIGHBOURS - 1].0 {
+                    break;
+                }
+            }
+            for i in bx - r..=bx + r {
+                for j in by - r..=by + r {
+                    let on_ring = (i - bx).abs() == r || (j - by).abs() == r;
+                    if !on_ring || i < 0 || j < 0 || i >= bw || j >= bh {
+                        continue;
+                    }
+                    for &s in &self.buckets[(i as usize, j as usize)] {
+                        let (sx, sy, _) = samples[s];
+                        nearest.push(((sx - x).pow(2) + (sy - y).pow(2), s));
+                    }
+                }
+            }
+            nearest.sort_unstable();
+            nearest.truncate(IDW_NEIGHBOURS);
+        }
+
+        let mut sum = 0.0;
+        let mut weights = 0.0;
+        for &(d2, s) in &nearest {
+            let weight = 1.0 / (d2 as f64).sqrt().powf(power);
+            sum += weight * samples[s].2;
+            weights += weight;
+        }
+        sum / weights
+    }
+}
+
+/// Delaunay triangulation of points with integer coordinates, built incrementally with the
+/// Bowyer-Watson algorithm. The first three points are the corners of a super triangle containing
+/// all others. Integer coordinates make the geometric predicates exact.
+struct Triangulation {
+    points: Vec<(i64, i64)>,
+    /// Vertices in counterclockwise order.
+    triangles: Vec<[usize; 3]>,
+    /// The neighbour across the edge opposite to each vertex.
+    neighbours: Vec<[usize; 3]>,
+    /// Marks the triangles of the cavity being searched, see [`Triangulation::cavity`].
+    stamps: Vec<u32>,
+    stamp: u32,
+    /// Triangle to start locating the next point from.
+    last: usize,
+}
+
+impl Triangulation {
+    /// Creates a triangulation that can contain points with coordinates from 0 to `size`.
+    fn new(size: i64) -> Self {
+        let m = size + 1;
+        Triangulation {
+            points: vec![(-10 * m, -10 * m), (10 * m, -10 * m), (0, 10 * m)],
+            triangles: vec![[0, 1, 2]],
+            neighbours: vec![[NONE; 3]],
+            stamps: vec![0],
+            stamp: 0,
+            last: 0,
+        }
+    }
+
+    fn insert(&mut self, p: (i64, i64)) {
+        let pi = self.points.len();
+        self.points.push(p);
+        let Some(start) = self.locate(p) else {
+            return;
+        };
+        let cavity = self.cavity(p, start);
+
+        // the boundary of the cavity as counterclockwise edges with their outer neighbours
+        let mut edges = Vec::new();
+        for &t in &cavity {
+            for i in 0..3 {
+                let n = self.neighbours[t][i];
+                if n == NONE || self.stamps[n] != self.stamp {
+                    let v = self.triangles[t];
+                    edges.push((v[(i + 1) % 3], v[(i + 2) % 3], n));
+                }
+            }
+        }
+
+        // reuse the slots of the cavity for the new triangles, of which there are two more
+        let mut slots = cavity;
+        while slots.len() < edges.len() {
+            slots.push(self.triangles.len());
+            self.triangles.push([0; 3]);
+            self.neighbours.push([NONE; 3]);
+            self.stamps.push(0);
+        }
+        for (&(a, b, n), &t) in edges.iter().zip(&slots) {
+            self.triangles[t] = [pi, a, b];
+            self.neighbours[t][0] = n;
+            if n != NONE {
+                let j = (0..3)
+                    .find(|&j| self.triangles[n][j] != a && self.triangles[n][j] != b)
+                    .unwrap();
+                self.neighbours[n][j] = t;
+            }
+        }
+        for (&(a, b, _), &t) in edges.iter().zip(&slots) {
+            // the new triangles around p are linked by their shared edges to p
+            for (&(a2, b2, _), &t2) in edges.iter().zip(&slots) {
+                if a2 == b {
+                    self.neighbours[t][1] = t2;
+                }
+                if b2 == a {
+                    self.neighbours[t][2] = t2;
+                }
+            }
+        }
+        self.last = slots[0];
+    }
+
+    /// Finds a triangle containing the point by walking towards it.
+    fn locate(&mut self, p: (i64, i64)) -> Option<usize> {
+        let mut t = self.last;
+        let mut steps = 0;
+        'walk: while steps < self.triangles.len() {
+            steps += 1;
+            let v = self.triangles[t];
+            for i in 0..3 {
+                let (a, b) = (self.points[v[(i + 1) % 3]], self.points[v[(i + 2) % 3]]);
+                if orient(a, b, p) < 0 {
+                    t = self.neighbours[t][i];
+                    if t == NONE {
+                        return None;
+                    }
+                    continue 'walk;
+                }
+            }
+            self.last = t;
+            return Some(t);
+        }
+        // the walk should always arrive, but fall back to checking every triangle
+        (0..self.triangles.len()).find(|&t| {
+            let v = self.triangles[t];
+            (0..3).all(|i| orient(self.points[v[(i + 1) % 3]], self.points[v[(i + 2) % 3]], p) >= 0)
+        })
+    }
+
+    /// Finds the triangles whose circumcircle contains the point, starting from the triangle
+    /// containing it. They are marked with the current stamp.
+    fn cavity(&mut self, p: (i64, i64), start: usize) -> Vec<usize> {
+        self.stamp += 1;
+        self.stamps[start] = self.stamp;
+        let mut cavity = vec![start];
+        let mut i = 0;
+        while i < cavity.len() {
+            let t = cavity[i];
+            i += 1;
+            for n in self.neighbours[t] {
+                if n != NONE && self.stamps[n] != self.stamp {
+                    let [a, b, c] = self.triangles[n].map(|v| self.points[v]);
+                    if incircle(a, b, c, p) > 0 {
+                        self.stamps[n] = self.stamp;
+                        cavity.push(n);
+                    }
+                }
+            }
+        }
+        cavity
+    }
+
+    fn has_super_vertex(&self, t: usize) -> bool {
+        self.triangles[t].iter().any(|&v| v < 3)
+    }
+
+    /// Value of the sample with the given point index.
+    fn value(samples: &[(i64, i64, f64)], v: usize) -> f64 {
+        samples[v - 3].2
+    }
+
+    /// Linear interpolation in the triangle containing the point.
+    fn linear(&mut self, samples: &[(i64, i64, f64)], p: (i64, i64)) -> Option<f64> {
+        let tri = self.locate(p)?;
+        let t = &*self;
+        if t.has_super_vertex(tri) {
+            return None;
+        }
+        let [a, b, c] = t.triangles[tri];
+        let area = orient(t.points[a], t.points[b], t.points[c]) as f64;
+        let wa = orient(t.points[b], t.points[c], p) as f64 / area;
+        let wb = orient(t.points[c], t.points[a], p) as f64 / area;
+        let wc = 1.0 - wa - wb;
+        Some(
+            wa * Self::value(samples, a)
+                + wb * Self::value(samples, b)
+                + wc * Self::value(samples, c),
+        )
+    }
+
+    /// Sibson's natural neighbour interpolation: the samples are weighted by the areas their
+    /// Voronoi cells would lose to the point if it was inserted.
+    fn natural_neighbour(&mut self, samples: &[(i64, i64, f64)], p: (i64, i64)) -> Option<f64> {
+        let start = self.locate(p)?;
+        let cavity = self.cavity(p, start);
+        let t = &*self;
+        if cavity.iter().any(|&c| t.has_super_vertex(c)) {
+            return None;
+        }
+        let in_cavity = |n: usize| n != NONE && t.stamps[n] == t.stamp;
+
+        // the counterclockwise boundary edges of the cavity with their cavity triangle
+        let mut edges = Vec::new();
+        for &c in &cavity {
+            for i in 0..3 {
+                if !in_cavity(t.neighbours[c][i]) {
+                    let v = t.triangles[c];
+                    edges.push((v[(i + 1) % 3], v[(i + 2) % 3], c));
+                }
+            }
+        }
+
+        let pf = (p.0 as f64, p.1 as f64);
+        let point = |v: usize| (t.points[v].0 as f64, t.points[v].1 as f64);
+        let mut sum = 0.0;
+        let mut weights = 0.0;
+        for &(a, b, first) in &edges {
+            let prev = edges.iter().find(|e| e.1 == a)?.0;
+            // the new Voronoi edge between p and a, and the old Voronoi vertices of the
+            // cavity triangles around a
+            let mut polygon = vec![
+                circumcenter(pf, point(prev), point(a)),
+                circumcenter(pf, point(a), point(b)),
+            ];
+            let mut c = first;
+            loop {
+                let [u, v, w] = t.triangles[c].map(point);
+                polygon.push(circumcenter(u, v, w));
+                let k = (0..3).find(|&k| t.triangles[c][k] == a)?;
+                let next = t.neighbours[c][(k + 1) % 3];
+                if !in_cavity(next) || polygon.len() > cavity.len() + 2 {
+                    break;
+                }
+                c = next;
+            }
+            let weight = polygon_area(&polygon);
+            sum += weight * Self::value(samples, a);
+            weights += weight;
+        }
+        if weights > 0.0 {
+            Some(sum / weights)
+        } else {
+            None
+        }
+    }
+}
+
+/// Twice the signed area of the triangle abc, positive if it is counterclockwise.
+fn orient(a: (i64, i64), b: (i64, i64), c: (i64, i64)) -> i128 {
+    (b.0 - a.0) as i128 * (c.1 - a.1) as i128 - (b.1 - a.1) as i128 * (c.0 - a.0) as i128
+}
+
+/// Positive if d is inside the circumcircle of the counterclockwise triangle abc.
+fn incircle(a: (i64, i64), b: (i64, i64), c: (i64, i64), d: (i64, i64)) -> i128 {
+    let (adx, ady) = ((a.0 - d.0) as i128, (a.1 - d.1) as i128);
+    let (bdx, bdy) = ((b.0 - d.0) as i128, (b.1 - d.1) as i128);
+    let (cdx, cdy) = ((c.0 - d.0) as i128, (c.1 - d.1) as i128);
+    (adx * adx + ady * ady) * (bdx * cdy - cdx * bdy)
+        - (bdx * bdx + bdy * bdy) * (adx * cdy - cdx * ady)
+        + (cdx * cdx + cdy * cdy) * (adx * bdy - bdx * ady)
+}
+
+fn circumcenter(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> (f64, f64) {
+    let (bx, by) = (b.0 - a.0, b.1 - a.1);
+    let (cx, cy) = (c.0 - a.0, c.1 - a.1);
+    let d = 2.0 * (bx * cy - by * cx);
+    let b2 = bx * bx + by * by;
+    let c2 = cx * cx + cy * cy;
+    (a.0 + (cy * b2 - by * c2) / d, a.1 + (bx * c2 - cx * b2) / d)
+}
+
+fn polygon_area(polygon: &[(f64, f64)]) -> f64 {
+    let mut area = 0.0;
+    for (i, &(x0, y0)) in polygon.iter().enumerate() {
+        let (x1, y1) = polygon[(i + 1) % polygon.len()];
+        area += x0 * y1 - x1 * y0;
+    }
+    (area / 2.0).abs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A plane with a hole in the middle and a notch at the edge.
+    fn plane_with_gaps() -> Vec2D<f64> {
+        let mut grid = Vec2D::new(30, 20, 0.0);
+        for (x, y, v) in grid.iter_mut() {
+            let inside_hole = (8..20).contains(&x) && (5..14).contains(&y);
+            let notch = x < 4 && y > 16;
+            *v = if inside_hole || notch {
+                f64::NAN
+            } else {
+                2.0 * x as f64 + 3.0 * y as f64
+            };
+        }
+        grid
+    }
+
+    #[test]
+    fn test_linear_precision() {
+        for method in [Interpolation::Tin, Interpolation::NaturalNeighbour] {
+            let mut grid = plane_with_gaps();
+            fill(&mut grid, method, 2.0);
+            for (x, y, v) in grid.iter() {
+                if (8..20).contains(&x) && (5..14).contains(&y) {
+                    let expected = 2.0 * x as f64 + 3.0 * y as f64;
+                    assert!(
+                        (v - expected).abs() < 1e-6,
+                        "{:?} {} {}",
+                        method,
+                        v,
+                        expected
+                    );
+                }
+            }
+            assert!(!grid.is_any_nan());
+        }
+    }
+
+    #[test]
+    fn test_idw() {
+        let mut grid = plane_with_gaps();
+        fill(&mut grid, Interpolation::Idw, 2.0);
+        assert!(!grid.is_any_nan());
+        for (_, _, v) in grid.iter() {
+            assert!((0.0..=2.0 * 29.0 + 3.0 * 19.0).contains(&v));
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::io::Read;
+
 /// Trait defining how to read and write a value from a byte stream.
 pub trait FromToBytes: Sized {
     /// Read a value from a byte stream.
@@ -19,6 +21,18 @@ impl FromToBytes for f64 {
     }
 }
 
+impl FromToBytes for f32 {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut buff = [0; 4];
+        reader.read_exact(&mut buff)?;
+        Ok(f32::from_ne_bytes(buff))
+    }
+
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.to_ne_bytes())
+    }
+}
+
 impl FromToBytes for usize {
     fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
         let mut buff = [0; usize::BITS as usize / 8];
@@ -41,6 +55,108 @@ impl FromToBytes for u64 {
     }
 }
 
+impl FromToBytes for u32 {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut buff = [0; 4];
+        reader.read_exact(&mut buff)?;
+        Ok(u32::from_ne_bytes(buff))
+    }
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.to_ne_bytes())
+    }
+}
+
+impl FromToBytes for u16 {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut buff = [0; 2];
+        reader.read_exact(&mut buff)?;
+        Ok(u16::from_ne_bytes(buff))
+    }
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.to_ne_bytes())
+    }
+}
+
+impl FromToBytes for u8 {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut buff = [0; 1];
+        reader.read_exact(&mut buff)?;
+        Ok(buff[0])
+    }
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&[*self])
+    }
+}
+
+impl FromToBytes for bool {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        Ok(u8::from_bytes(reader)? != 0)
+    }
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        (*self as u8).to_bytes(writer)
+    }
+}
+
+/// Strings are stored as their length in bytes followed by the UTF-8 data.
+impl FromToBytes for String {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let len = u64::from_bytes(reader)?;
+        // the length comes from the file, so the string grows as it is read
+        let mut buff = Vec::new();
+        reader.take(len).read_to_end(&mut buff)?;
+        if buff.len() as u64 != len {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(buff).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        (self.len() as u64).to_bytes(writer)?;
+        writer.write_all(self.as_bytes())
+    }
+}
+
+/// Optional values are stored as a single presence byte followed by the value, if any.
+impl<T: FromToBytes> FromToBytes for Option<T> {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        match u8::from_bytes(reader)? {
+            0 => Ok(None),
+            _ => Ok(Some(T::from_bytes(reader)?)),
+        }
+    }
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        match self {
+            None => 0u8.to_bytes(writer),
+            Some(value) => {
+                1u8.to_bytes(writer)?;
+                value.to_bytes(writer)
+            }
+        }
+    }
+}
+
+/// The most items reserved for a vector before reading them.
+const MAX_RESERVED_ITEMS: usize = 4096;
+
+/// Vectors are stored as the number of items followed by the items themselves.
+impl<T: FromToBytes> FromToBytes for Vec<T> {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let len = u64::from_bytes(reader)? as usize;
+        // the length comes from the file, so only a bounded capacity is reserved up front
+        let mut items = Vec::with_capacity(len.min(MAX_RESERVED_ITEMS));
+        for _ in 0..len {
+            items.push(T::from_bytes(reader)?);
+        }
+        Ok(items)
+    }
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        (self.len() as u64).to_bytes(writer)?;
+        for item in self.iter() {
+            item.to_bytes(writer)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -65,4 +181,33 @@ mod test {
         42usize.to_bytes(&mut buff).unwrap();
         assert_eq!(usize::from_bytes(&mut buff.as_slice()).unwrap(), 42);
     }
+
+    #[test]
+    fn test_string() {
+        let mut buff = Vec::new();
+        String::from("EPSG:3067").to_bytes(&mut buff).unwrap();
+        assert_eq!(
+            String::from_bytes(&mut buff.as_slice()).unwrap(),
+            "EPSG:3067"
+        );
+    }
+
+    #[test]
+    fn test_corrupt_length() {
+        let mut buff = Vec::new();
+        u64::MAX.to_bytes(&mut buff).unwrap();
+        assert!(String::from_bytes(&mut buff.as_slice()).is_err());
+        assert!(Vec::<u32>::from_bytes(&mut buff.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_option_vec() {
+        let mut buff = Vec::new();
+        let value: Vec<Option<u32>> = vec![Some(1), None, Some(3)];
+        value.to_bytes(&mut buff).unwrap();
+        assert_eq!(
+            Vec::<Option<u32>>::from_bytes(&mut buff.as_slice()).unwrap(),
+            value
+        );
+    }
 }
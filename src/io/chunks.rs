@@ -0,0 +1,509 @@
+//! Spatial queries on LAS/LAZ files that only decompress the parts of the file that are needed.
+//!
+//! LAZ files are compressed in chunks (usually of 50 000 points) that can be decompressed
+//! independently, and the chunk table at the end of the file says where each chunk starts. The
+//! bounds of each chunk are not stored in the file though, so they need to be computed once by
+//! scanning the coordinates of all the points. Cloud Optimized Point Clouds (COPC) store an octree hierarchy
+//! where each node is a single LAZ chunk with known bounds, so no scan is needed for those.
+
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use las::{Point, Reader};
+use laz::laszip::ChunkTable;
+use laz::{DecompressionSelection, LasZipDecompressor, LazVlr};
+use log::debug;
+
+use super::bytes::FromToBytes;
+use super::fs::FileSystem;
+use super::tileindex::Bounds;
+use crate::error::{Error, Result};
+
+/// Number of points per chunk used for uncompressed LAS files, which can be seeked freely.
+const LAS_CHUNK_SIZE: u64 = 50_000;
+
+/// User id of the COPC VLRs.
+const COPC_USER_ID: &str = "copc";
+/// Record id of the COPC info VLR.
+const COPC_INFO_RECORD_ID: u16 = 1;
+
+/// A contiguous range of points in a file, together with the bounds of those points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkBounds {
+    pub first_point: u64,
+    pub point_count: u64,
+    pub bounds: Bounds,
+}
+
+impl FromToBytes for ChunkBounds {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        Ok(ChunkBounds {
+            first_point: u64::from_bytes(reader)?,
+            point_count: u64::from_bytes(reader)?,
+            bounds: Bounds::from_bytes(reader)?,
+        })
+    }
+
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.first_point.to_bytes(writer)?;
+        self.point_count.to_bytes(writer)?;
+        self.bounds.to_bytes(writer)
+    }
+}
+
+/// Determine the chunks of a LAS/LAZ/COPC file and their bounds. For COPC files this is read
+/// from the octree hierarchy, for other files the coordinates of the points are scanned once, see
+/// [`scan_chunk_bounds`].
+pub fn read_chunk_bounds(fs: &impl FileSystem, path: &Path) -> Result<Vec<ChunkBounds>> {
+    let reader = Reader::new(BufReader::new(fs.open(path)?))?;
+
+    if let Some(chunks) = copc_chunk_bounds(fs, path, reader.header())? {
+        debug!("Read {} COPC nodes of {}", chunks.len(), path.display());
+        return Ok(chunks);
+    }
+
+    let ranges = chunk_ranges(fs, path, reader.header())?;
+    let chunks = scan_chunk_bounds(fs, path, reader.header(), &ranges)?;
+    debug!("Scanned {} chunks of {}", chunks.len(), path.display());
+    Ok(chunks)
+}
+
+/// Call `callback` for every point of the file that lies inside `bounds`, decompressing only
+/// the chunks that intersect it. If `chunks` is empty, the whole file is read.
+pub fn query_points(
+    fs: &impl FileSystem,
+    path: &Path,
+    chunks: &[ChunkBounds],
+    bounds: &Bounds,
+    mut callback: impl FnMut(Point),
+) -> Result<()> {
+    let mut reader = Reader::new(BufReader::new(fs.open(path)?))?;
+
+    if chunks.is_empty() {
+        for p in reader.points() {
+            let p = p?;
+            if bounds.contains(p.x, p.y) {
+                callback(p);
+            }
+        }
+        return Ok(());
+    }
+
+    let mut points = Vec::new();
+    let mut read_chunks = 0;
+    for chunk in chunks.iter().filter(|c| c.bounds.intersects(bounds)) {
+        reader.seek(chunk.first_point)?;
+        points.clear();
+        reader.read_points_into(chunk.point_count, &mut points)?;
+        for p in points.drain(..) {
+            if bounds.contains(p.x, p.y) {
+                callback(p);
+            }
+        }
+        read_chunks += 1;
+    }
+    debug!(
+        "Decompressed {} of {} chunks of {}",
+        read_chunks,
+        chunks.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Get the (first point, point count) of every chunk of a file, using the chunk table for
+/// compressed files.
+fn chunk_ranges(
+    fs: &impl FileSystem,
+    path: &Path,
+    header: &las::Header,
+) -> Result<Vec<(u64, u64)>> {
+    let total = header.number_of_points();
+
+    let mut counts = Vec::new();
+    if let Some(vlr) = laz_vlr(header)? {
+        let mut file = BufReader::new(fs.open(path)?);
+        let raw_header = las::raw::Header::read_from(&mut file)?;
+        file.seek(SeekFrom::Start(u64::from(raw_header.offset_to_point_data)))?;
+        match ChunkTable::read_from(&mut file, &vlr) {
+            Ok(table) => counts.extend(table.as_ref().iter().map(|e| e.point_count)),
+            Err(e) => debug!("No usable chunk table in {}: {}", path.display(), e),
+        }
+    }
+    if counts.is_empty() {
+        // uncompressed data (or a single chunk), split it into chunks of our own
+        counts.extend(std::iter::repeat_n(
+            LAS_CHUNK_SIZE,
+            total.div_ceil(LAS_CHUNK_SIZE) as usize,
+        ));
+    }
+
+    // the last entry of fixed size chunk tables is not accurate, so clamp it to the total
+    let mut ranges = Vec::with_capacity(counts.len());
+    let mut first_point = 0;
+    for count in counts {
+        let count = count.min(total - first_point);
+        if count == 0 {
+            break;
+        }
+        ranges.push((first_point, count));
+        first_point += count;
+    }
+    Ok(ranges)
+}
+
+/// Reads the next point records of a file into a buffer.
+type ReadRecords = Box<dyn FnMut(&mut [u8]) -> std::io::Result<()>>;
+
+/// Computes the bounds of each range of points from the raw X and Y of the points, without
+/// decoding them into [`Point`]s. LAZ point formats 6 to 10 compress the coordinates in a layer
+/// of their own, so only that layer is decompressed. Formats 0 to 5 compress each point as a
+/// whole, so those files are decompressed once here and never again for their neighbours.
+fn scan_chunk_bounds(
+    fs: &impl FileSystem,
+    path: &Path,
+    header: &las::Header,
+    ranges: &[(u64, u64)],
+) -> Result<Vec<ChunkBounds>> {
+    let mut file = BufReader::new(fs.open(path)?);
+    let raw_header = las::raw::Header::read_from(&mut file)?;
+    file.seek(SeekFrom::Start(u64::from(raw_header.offset_to_point_data)))?;
+    let record_length = usize::from(raw_header.point_data_record_length);
+
+    let mut read_records: ReadRecords = match laz_vlr(header)? {
+        Some(vlr) => {
+            let mut decompressor = LasZipDecompressor::selective(
+                file,
+                vlr,
+                DecompressionSelection::xy_returns_channel(),
+            )?;
+            Box::new(move |records| decompressor.decompress_many(records))
+        }
+        None => Box::new(move |records| file.read_exact(records)),
+    };
+
+    let transforms = header.transforms();
+    let mut chunks = Vec::with_capacity(ranges.len());
+    let mut records = Vec::new();
+    for &(first_point, point_count) in ranges {
+        records.resize(point_count as usize * record_length, 0);
+        read_records(&mut records)?;
+        let mut bounds = Bounds {
+            min_x: f64::MAX,
+            min_y: f64::MAX,
+            max_x: f64::MIN,
+            max_y: f64::MIN,
+        };
+        // every point record starts with the X, Y and Z as scaled 32-bit integers
+        for record in records.chunks_exact(record_length) {
+            let x = transforms
+                .x
+                .direct(i32::from_le_bytes(record[0..4].try_into().unwrap()));
+            let y = transforms
+                .y
+                .direct(i32::from_le_bytes(record[4..8].try_into().unwrap()));
+            bounds.min_x = bounds.min_x.min(x);
+            bounds.min_y = bounds.min_y.min(y);
+            bounds.max_x = bounds.max_x.max(x);
+            bounds.max_y = bounds.max_y.max(y);
+        }
+        chunks.push(ChunkBounds {
+            first_point,
+            point_count,
+            bounds,
+        });
+    }
+    Ok(chunks)
+}
+
+/// The LAZ VLR of a compressed file.
+fn laz_vlr(header: &las::Header) -> Result<Option<LazVlr>> {
+    let Some(vlr) = header
+        .vlrs()
+        .iter()
+        .find(|v| v.user_id == LazVlr::USER_ID && v.record_id == LazVlr::RECORD_ID)
+    else {
+        return Ok(None);
+    };
+    Ok(Some(LazVlr::from_buffer(&vlr.data)?))
+}
+
+/// Reads the chunk bounds from the octree hierarchy of a COPC file, or returns `None` if the file
+/// is not a COPC file.
+fn copc_chunk_bounds(
+    fs: &impl FileSystem,
+    path: &Path,
+    header: &las::Header,
+) -> Result<Option<Vec<ChunkBounds>>> {
+    let Some(info) = header
+        .vlrs()
+        .iter()
+        .find(|v| v.user_id == COPC_USER_ID && v.record_id == COPC_INFO_RECORD_ID)
+    else {
+        return Ok(None);
+    };
+
+    let mut data = info.data.as_slice();
+    let center_x = f64::from_le_reader(&mut data)?;
+    let center_y = f64::from_le_reader(&mut data)?;
+    let _center_z = f64::from_le_reader(&mut data)?;
+    let halfsize = f64::from_le_reader(&mut data)?;
+    let _spacing = f64::from_le_reader(&mut data)?;
+    let root_hier_offset = u64::from_le_reader(&mut data)?;
+    let root_hier_size = u64::from_le_reader(&mut data)?;
+
+    // (offset in file, point count, bounds) of every node with points
+    let mut nodes = Vec::new();
+    let mut pages = vec![(root_hier_offset, root_hier_size)];
+    let mut file = BufReader::new(fs.open(path)?);
+    while let Some((offset, size)) = pages.pop() {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut page = vec![0; size as usize];
+        file.read_exact(&mut page)?;
+
+        let mut page = page.as_slice();
+        while !page.is_empty() {
+            let level = i32::from_le_reader(&mut page)?;
+            let x = i32::from_le_reader(&mut page)?;
+            let y = i32::from_le_reader(&mut page)?;
+            let _z = i32::from_le_reader(&mut page)?;
+            let offset = u64::from_le_reader(&mut page)?;
+            let byte_size = i32::from_le_reader(&mut page)?;
+            let point_count = i32::from_le_reader(&mut page)?;
+
+            if point_count < 0 {
+                // the entry points to another hierarchy page
+                pages.push((offset, byte_size as u64));
+            } else if point_count > 0 {
+                let Some(divisions) = u32::try_from(level).ok().and_then(|l| 1u32.checked_shl(l))
+                else {
+                    return Err(Error::invalid(format!(
+                        "invalid COPC hierarchy level {} in {}",
+                        level,
+                        path.display()
+                    )));
+                };
+                let size = 2.0 * halfsize / f64::from(divisions);
+                let min_x = center_x - halfsize + size * f64::from(x);
+                let min_y = center_y - halfsize + size * f64::from(y);
+                nodes.push((
+                    offset,
+                    point_count as u64,
+                    Bounds {
+                        min_x,
+                        min_y,
+                        max_x: min_x + size,
+                        max_y: min_y + size,
+                    },
+                ));
+            }
+        }
+    }
+
+    // the nodes are stored as consecutive chunks, so their order in the file gives the point
+    // index of their first point
+    nodes.sort_by_key(|n| n.0);
+    let mut first_point = 0;
+    let chunks = nodes
+        .into_iter()
+        .map(|(_, point_count, bounds)| {
+            let chunk = ChunkBounds {
+                first_point,
+                point_count,
+                bounds,
+            };
+            first_point += point_count;
+            chunk
+        })
+        .collect();
+    Ok(Some(chunks))
+}
+
+/// Helper for reading little endian values from the raw VLR data.
+trait FromLeReader: Sized {
+    fn from_le_reader(reader: &mut &[u8]) -> std::io::Result<Self>;
+}
+
+macro_rules! impl_from_le_reader {
+    ($($t:ty),*) => {
+        $(impl FromLeReader for $t {
+            fn from_le_reader(reader: &mut &[u8]) -> std::io::Result<Self> {
+                let mut buff = [0; std::mem::size_of::<$t>()];
+                reader.read_exact(&mut buff)?;
+                Ok(<$t>::from_le_bytes(buff))
+            }
+        })*
+    };
+}
+
+impl_from_le_reader!(f64, u64, i32);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::fs::memory::MemoryFileSystem;
+    use crate::io::test_las;
+    use las::point::Format;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn bounds(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Bounds {
+        Bounds {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        }
+    }
+
+    fn query(fs: &MemoryFileSystem, path: &Path, chunks: &[ChunkBounds], b: &Bounds) -> Vec<f64> {
+        let mut xs = Vec::new();
+        query_points(fs, path, chunks, b, |p| xs.push(p.x)).unwrap();
+        xs
+    }
+
+    /// A COPC file of two level 1 nodes next to each other, the points of the left node first.
+    fn copc_file(level: i32) -> Vec<u8> {
+        let points = [
+            (10.0, 10.0, 0.0),
+            (20.0, 20.0, 0.0),
+            (60.0, 10.0, 0.0),
+            (70.0, 20.0, 0.0),
+            (80.0, 30.0, 0.0),
+        ];
+        // the hierarchy page goes at the end of the file, so its offset is known once the
+        // file is written with a placeholder info VLR of the same size
+        let info = |offset: u64| {
+            let mut data = Vec::new();
+            for value in [50.0f64, 50.0, 50.0, 50.0, 1.0] {
+                data.extend(value.to_le_bytes());
+            }
+            data.extend(offset.to_le_bytes());
+            data.extend(64u64.to_le_bytes());
+            data.resize(160, 0);
+            las::Vlr {
+                user_id: COPC_USER_ID.to_string(),
+                record_id: COPC_INFO_RECORD_ID,
+                description: String::new(),
+                data,
+            }
+        };
+        let format = Format::new(6).unwrap();
+        let offset = test_las(&points, format, true, vec![info(0)]).len() as u64;
+        let mut data = test_las(&points, format, true, vec![info(offset)]);
+        assert_eq!(data.len() as u64, offset);
+
+        for (x, node_offset, point_count) in [(1, 200u64, 3i32), (0, 100, 2)] {
+            for value in [level, x, 0, 0] {
+                data.extend(value.to_le_bytes());
+            }
+            data.extend(node_offset.to_le_bytes());
+            data.extend(0i32.to_le_bytes());
+            data.extend(point_count.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_copc() {
+        let fs = MemoryFileSystem::new();
+        let path = Path::new("tile.copc.laz");
+        fs.create(path).unwrap().write_all(&copc_file(1)).unwrap();
+
+        let chunks = read_chunk_bounds(&fs, path).unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                ChunkBounds {
+                    first_point: 0,
+                    point_count: 2,
+                    bounds: bounds(0.0, 0.0, 50.0, 50.0),
+                },
+                ChunkBounds {
+                    first_point: 2,
+                    point_count: 3,
+                    bounds: bounds(50.0, 0.0, 100.0, 50.0),
+                },
+            ]
+        );
+        assert_eq!(
+            query(&fs, path, &chunks, &bounds(55.0, 0.0, 75.0, 100.0)),
+            vec![60.0, 70.0]
+        );
+    }
+
+    #[test]
+    fn test_copc_invalid_level() {
+        let fs = MemoryFileSystem::new();
+        let path = Path::new("tile.copc.laz");
+        fs.create(path).unwrap().write_all(&copc_file(40)).unwrap();
+        assert!(read_chunk_bounds(&fs, path).is_err());
+    }
+
+    #[test]
+    fn test_las_chunks() {
+        let fs = MemoryFileSystem::new();
+        let path = Path::new("tile.las");
+        let points = (0..120_000)
+            .map(|i| (f64::from(i) * 0.01, 5.0, 0.0))
+            .collect::<Vec<_>>();
+        let data = test_las(&points, Format::new(1).unwrap(), false, vec![]);
+        fs.create(path).unwrap().write_all(&data).unwrap();
+
+        let chunks = read_chunk_bounds(&fs, path).unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].first_point, 50_000);
+        assert_eq!(chunks[1].bounds, bounds(500.0, 5.0, 999.99, 5.0));
+        assert_eq!(chunks[2].point_count, 20_000);
+
+        let b = bounds(999.985, 0.0, 1000.005, 10.0);
+        assert_eq!(query(&fs, path, &chunks, &b), vec![999.99, 1000.0]);
+        assert_eq!(query(&fs, path, &[], &b), vec![999.99, 1000.0]);
+    }
+
+    #[test]
+    fn test_laz_chunks() {
+        let fs = MemoryFileSystem::new();
+        let points = [(1.0, 2.0, 0.0), (3.0, 4.0, 0.0), (2.0, 8.0, 0.0)];
+        for format in [0, 6] {
+            let path = PathBuf::from(format!("tile{}.laz", format));
+            let data = test_las(&points, Format::new(format).unwrap(), true, vec![]);
+            fs.create(&path).unwrap().write_all(&data).unwrap();
+
+            let chunks = read_chunk_bounds(&fs, &path).unwrap();
+            assert_eq!(
+                chunks,
+                vec![ChunkBounds {
+                    first_point: 0,
+                    point_count: 3,
+                    bounds: bounds(1.0, 2.0, 3.0, 8.0),
+                }]
+            );
+            assert_eq!(
+                query(&fs, &path, &chunks, &bounds(0.0, 3.0, 5.0, 10.0)),
+                vec![3.0, 2.0]
+            );
+        }
+    }
+
+    #[test]
+    fn test_bytes() {
+        let chunk = ChunkBounds {
+            first_point: 50_000,
+            point_count: 1234,
+            bounds: Bounds {
+                min_x: 1.0,
+                min_y: 2.0,
+                max_x: 3.0,
+                max_y: 4.0,
+            },
+        };
+
+        let mut bytes = Vec::new();
+        chunk.to_bytes(&mut bytes).unwrap();
+        assert_eq!(
+            ChunkBounds::from_bytes(&mut bytes.as_slice()).unwrap(),
+            chunk
+        );
+    }
+}
@@ -0,0 +1,449 @@
+use las::Header;
+use log::warn;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::bytes::FromToBytes;
+use super::fs::FileSystem;
+
+/// Name of the file in the temp folder holding the coordinate reference system of the points
+/// being processed.
+pub const CRS_FILE: &str = "crs.bin";
+
+/// User id of the VLRs that carry the coordinate reference system of a LAS file.
+const PROJECTION_USER_ID: &str = "LASF_Projection";
+/// Record id of the OGC WKT coordinate system VLR.
+const WKT_RECORD_ID: u16 = 2112;
+/// Record id of the GeoTIFF GeoKeyDirectoryTag VLR.
+const GEOKEY_DIRECTORY_RECORD_ID: u16 = 34735;
+
+/// GeoTIFF key holding the EPSG code of a projected coordinate system.
+const PROJECTED_CS_TYPE_GEOKEY: u16 = 3072;
+/// GeoTIFF key holding the EPSG code of a geographic coordinate system.
+const GEOGRAPHIC_TYPE_GEOKEY: u16 = 2048;
+
+/// The coordinate reference system of a point cloud, as far as it could be determined from the
+/// LAS header. Either an EPSG code (from the GeoTIFF keys) or a WKT definition, or both.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Crs {
+    pub epsg: Option<u32>,
+    pub wkt: Option<String>,
+    /// Whether the coordinates are longitudes and latitudes, as declared by the GeoTIFF keys or
+    /// the WKT definition.
+    pub geographic: bool,
+}
+
+impl Crs {
+    /// Reads the coordinate reference system from the (E)VLRs of a LAS header. Returns `None` if
+    /// the file does not declare any.
+    pub fn from_header(header: &Header) -> Option<Self> {
+        let mut crs = Crs::default();
+
+        for vlr in header.all_vlrs() {
+            if vlr.user_id != PROJECTION_USER_ID {
+                continue;
+            }
+            match vlr.record_id {
+                WKT_RECORD_ID => {
+                    let wkt = String::from_utf8_lossy(&vlr.data)
+                        .trim_end_matches('\0')
+                        .trim()
+                        .to_string();
+                    if !wkt.is_empty() {
+                        crs.geographic = is_geographic_wkt(&wkt);
+                        crs.wkt = Some(wkt);
+                    }
+                }
+                GEOKEY_DIRECTORY_RECORD_ID => {
+                    if let Some((epsg, geographic)) = epsg_from_geokeys(&vlr.data) {
+                        crs.epsg = Some(epsg.into());
+                        crs.geographic |= geographic;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if crs.epsg.is_none() && crs.wkt.is_none() {
+            None
+        } else {
+            Some(crs)
+        }
+    }
+
+    /// Reads the coordinate reference system from the values of a GeoTIFF GeoKeyDirectoryTag.
+    /// Returns `None` if it does not declare an EPSG code.
+    pub fn from_geokey_directory(keys: &[u16]) -> Option<Self> {
+        epsg_from_geokey_directory(keys).map(|(epsg, geographic)| Crs {
+            epsg: Some(epsg.into()),
+            wkt: None,
+            geographic,
+        })
+    }
+
+    /// Reads a coordinate reference system written by [`Crs::write_file`]. Returns `None` if the
+    /// file is missing or unreadable, or if it records an unknown coordinate reference system.
+    pub fn from_file(fs: &impl FileSystem, path: impl AsRef<Path>) -> Option<Self> {
+        let mut reader = BufReader::new(fs.open(path).ok()?);
+        Option::<Crs>::from_bytes(&mut reader).ok().flatten()
+    }
+
+    /// Writes an optional coordinate reference system to a file, see [`Crs::from_file`].
+    pub fn write_file(
+        crs: Option<&Crs>,
+        fs: &impl FileSystem,
+        path: impl AsRef<Path>,
+    ) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(fs.create(path)?);
+        crs.cloned().to_bytes(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Parses a coordinate reference system given as an EPSG code (`EPSG:3067` or `3067`) or as
+    /// a WKT definition.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let code = s
+            .strip_prefix("EPSG:")
+            .or_else(|| s.strip_prefix("epsg:"))
+            .unwrap_or(s);
+        if let Ok(epsg) = code.parse::<u32>() {
+            let wkt = wkt_for_epsg(epsg);
+            if wkt.is_none() {
+                warn!(
+                    "No definition known for EPSG:{}, give the crs as WKT to get .prj files",
+                    epsg
+                );
+            }
+            return Ok(Crs {
+                epsg: Some(epsg),
+                wkt: None,
+                // only known for the codes with a built in definition
+                geographic: wkt.is_some_and(|wkt| is_geographic_wkt(&wkt)),
+            });
+        }
+        let is_wkt = [
+            "PROJCS[",
+            "GEOGCS[",
+            "COMPD_CS[",
+            "PROJCRS[",
+            "GEOGCRS[",
+            "COMPOUNDCRS[",
+        ]
+        .iter()
+        .any(|prefix| s.to_uppercase().starts_with(prefix));
+        if is_wkt {
+            // the EPSG code of the whole definition is the authority at its very end
+            let epsg = s
+                .rsplit_once("AUTHORITY[\"EPSG\",\"")
+                .and_then(|(_, tail)| tail.strip_suffix("\"]]"))
+                .and_then(|code| code.parse::<u32>().ok());
+            Ok(Crs {
+                epsg,
+                wkt: Some(s.to_string()),
+                geographic: is_geographic_wkt(s),
+            })
+        } else {
+            Err(format!("Invalid coordinate reference system {:?}", s))
+        }
+    }
+
+    /// Returns the WKT definition of the coordinate reference system. Without one from the input
+    /// file it is only known for a few common EPSG codes: the UTM zones of WGS 84 and ETRS89,
+    /// ETRS-TM35FIN, SWEREF 99 TM and the WGS 84 and ETRS89 geographic systems. Other codes
+    /// are warned about, once per code.
+    pub fn to_wkt(&self) -> Option<String> {
+        static WARNED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+        if self.wkt.is_some() {
+            return self.wkt.clone();
+        }
+        let epsg = self.epsg?;
+        let wkt = wkt_for_epsg(epsg);
+        if wkt.is_none() {
+            let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+            if !warned.contains(&epsg) {
+                warned.push(epsg);
+                warn!(
+                    "No definition known for EPSG:{}, no .prj files are written",
+                    epsg
+                );
+            }
+        }
+        wkt
+    }
+
+    /// Writes the WKT definition of a coordinate reference system to the .prj file of `path`, see
+    /// [`Crs::prj_path`]. Nothing is written if the definition is unknown.
+    pub fn write_prj(crs: Option<&Crs>, fs: &impl FileSystem, path: &Path) -> std::io::Result<()> {
+        let Some(wkt) = crs.and_then(Crs::to_wkt) else {
+            return Ok(());
+        };
+        fs.create(Crs::prj_path(path))?.write_all(wkt.as_bytes())
+    }
+
+    /// Path of the .prj file of an output. For rasters and their world files the extension is
+    /// replaced, `map.png` and `map.pgw` get `map.prj` as GIS applications expect, for other
+    /// files like DXF it is appended, `map.dxf.prj`, so they never share one with a raster.
+    pub fn prj_path(path: &Path) -> PathBuf {
+        const RASTER_EXTENSIONS: [&str; 7] = ["png", "pgw", "jpg", "jgw", "tif", "tfw", "asc"];
+
+        let extension = path.extension().unwrap_or_default().to_ascii_lowercase();
+        if RASTER_EXTENSIONS.iter().any(|e| extension == *e) {
+            path.with_extension("prj")
+        } else {
+            let mut prj = path.as_os_str().to_owned();
+            prj.push(".prj");
+            PathBuf::from(prj)
+        }
+    }
+
+    /// Writes .prj files for the world files and DXF files among `paths`, see [`Crs::write_prj`].
+    pub fn write_prj_sidecars(
+        crs: Option<&Crs>,
+        fs: &impl FileSystem,
+        paths: &[PathBuf],
+    ) -> std::io::Result<()> {
+        if crs.and_then(Crs::to_wkt).is_none() {
+            return Ok(());
+        }
+        for path in paths {
+            let extension = path.extension().unwrap_or_default().to_ascii_lowercase();
+            if extension == "pgw" || extension == "dxf" {
+                Crs::write_prj(crs, fs, path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the coordinate reference system of a point file: from the header of a LAS/LAZ file
+    /// or from the sidecar file of an internal point file.
+    pub fn from_point_file(fs: &impl FileSystem, path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_ascii_lowercase();
+        if extension == "las" || extension == "laz" {
+            let reader = las::Reader::new(BufReader::new(fs.open(path).ok()?)).ok()?;
+            Crs::from_header(reader.header())
+        } else {
+            Crs::from_file(fs, Crs::sidecar_path(path))
+        }
+    }
+
+    /// Path of the file holding the coordinate reference system of an internal point file, as
+    /// they do not store it themselves.
+    pub fn sidecar_path(points: &Path) -> PathBuf {
+        let mut path = points.as_os_str().to_owned();
+        path.push(".crs");
+        PathBuf::from(path)
+    }
+}
+
+impl std::fmt::Display for Crs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.epsg, &self.wkt) {
+            (Some(epsg), _) => write!(f, "EPSG:{}", epsg),
+            (None, Some(wkt)) => {
+                // the name of the coordinate system is the first quoted string of the WKT
+                let name = wkt.split('"').nth(1).unwrap_or(wkt);
+                write!(f, "{}", name)
+            }
+            (None, None) => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Whether a WKT definition is of a geographic coordinate system.
+fn is_geographic_wkt(wkt: &str) -> bool {
+    let wkt = wkt.trim_start().to_uppercase();
+    wkt.starts_with("GEOGCS[") || wkt.starts_with("GEOGCRS[")
+}
+
+/// Extracts the EPSG code from the raw data of a GeoKeyDirectoryTag VLR, and whether it is the
+/// code of a geographic coordinate system.
+fn epsg_from_geokeys(data: &[u8]) -> Option<(u16, bool)> {
+    let shorts = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect::<Vec<_>>();
+    epsg_from_geokey_directory(&shorts)
+}
+
+/// Extracts the EPSG code from the values of a GeoKeyDirectoryTag, and whether it is the code of
+/// a geographic coordinate system.
+fn epsg_from_geokey_directory(shorts: &[u16]) -> Option<(u16, bool)> {
+    // header is: version, revision, minor revision, number of keys
+    let number_of_keys = *shorts.get(3)? as usize;
+    let mut geographic = None;
+    for key in shorts[4..].chunks_exact(4).take(number_of_keys) {
+        let (key_id, location, value) = (key[0], key[1], key[3]);
+        // a location of 0 means the value is stored directly in the entry
+        if location != 0 || value == 0 || value == 32767 {
+            continue;
+        }
+        match key_id {
+            PROJECTED_CS_TYPE_GEOKEY => return Some((value, false)),
+            GEOGRAPHIC_TYPE_GEOKEY => geographic = Some((value, true)),
+            _ => {}
+        }
+    }
+    geographic
+}
+
+/// Builds the WKT definition of some common coordinate reference systems.
+fn wkt_for_epsg(epsg: u32) -> Option<String> {
+    const WGS84: &str = "GEOGCS[\"WGS 84\",DATUM[\"WGS_1984\",SPHEROID[\"WGS 84\",6378137,298.257223563]],PRIMEM[\"Greenwich\",0],UNIT[\"degree\",0.0174532925199433]]";
+    const ETRS89: &str = "GEOGCS[\"ETRS89\",DATUM[\"European_Terrestrial_Reference_System_1989\",SPHEROID[\"GRS 1980\",6378137,298.257222101]],PRIMEM[\"Greenwich\",0],UNIT[\"degree\",0.0174532925199433]]";
+    const SWEREF99: &str = "GEOGCS[\"SWEREF99\",DATUM[\"SWEREF99\",SPHEROID[\"GRS 1980\",6378137,298.257222101]],PRIMEM[\"Greenwich\",0],UNIT[\"degree\",0.0174532925199433]]";
+
+    let transverse_mercator = |name: String,
+                               geogcs: &str,
+                               central_meridian: i32,
+                               false_northing| {
+        format!(
+            "PROJCS[\"{}\",{},PROJECTION[\"Transverse_Mercator\"],PARAMETER[\"latitude_of_origin\",0],PARAMETER[\"central_meridian\",{}],PARAMETER[\"scale_factor\",0.9996],PARAMETER[\"false_easting\",500000],PARAMETER[\"false_northing\",{}],UNIT[\"metre\",1],AUTHORITY[\"EPSG\",\"{}\"]]",
+            name, geogcs, central_meridian, false_northing, epsg
+        )
+    };
+    let utm_meridian = |zone: u32| zone as i32 * 6 - 183;
+    let with_authority = |geogcs: &str| {
+        format!(
+            "{},AUTHORITY[\"EPSG\",\"{}\"]]",
+            &geogcs[..geogcs.len() - 1],
+            epsg
+        )
+    };
+
+    Some(match epsg {
+        4326 => with_authority(WGS84),
+        4258 => with_authority(ETRS89),
+        3067 => transverse_mercator("ETRS89 / TM35FIN(E,N)".to_string(), ETRS89, 27, 0),
+        3006 => transverse_mercator("SWEREF99 TM".to_string(), SWEREF99, 15, 0),
+        25828..=25838 => {
+            let zone = epsg - 25800;
+            let name = format!("ETRS89 / UTM zone {}N", zone);
+            transverse_mercator(name, ETRS89, utm_meridian(zone), 0)
+        }
+        32601..=32660 => {
+            let zone = epsg - 32600;
+            let name = format!("WGS 84 / UTM zone {}N", zone);
+            transverse_mercator(name, WGS84, utm_meridian(zone), 0)
+        }
+        32701..=32760 => {
+            let zone = epsg - 32700;
+            let name = format!("WGS 84 / UTM zone {}S", zone);
+            transverse_mercator(name, WGS84, utm_meridian(zone), 10000000)
+        }
+        _ => return None,
+    })
+}
+
+impl FromToBytes for Crs {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let epsg = Option::<u32>::from_bytes(reader)?;
+        let wkt = Option::<String>::from_bytes(reader)?;
+        // files written by older versions end after the wkt
+        let geographic = match bool::from_bytes(reader) {
+            Ok(geographic) => geographic,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => false,
+            Err(e) => return Err(e),
+        };
+        Ok(Crs {
+            epsg,
+            wkt,
+            geographic,
+        })
+    }
+
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.epsg.to_bytes(writer)?;
+        self.wkt.to_bytes(writer)?;
+        self.geographic.to_bytes(writer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_epsg_from_geokeys() {
+        // directory header followed by GTModelTypeGeoKey=1 and ProjectedCSTypeGeoKey=3067
+        let shorts: [u16; 12] = [1, 1, 0, 2, 1024, 0, 1, 1, 3072, 0, 1, 3067];
+        let data = shorts
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<u8>>();
+        assert_eq!(epsg_from_geokeys(&data), Some((3067, false)));
+
+        // GTModelTypeGeoKey=2 and GeographicTypeGeoKey=4258
+        let shorts: [u16; 12] = [1, 1, 0, 2, 1024, 0, 1, 2, 2048, 0, 1, 4258];
+        assert_eq!(epsg_from_geokey_directory(&shorts), Some((4258, true)));
+    }
+
+    #[test]
+    fn test_display() {
+        let crs = Crs {
+            epsg: None,
+            wkt: Some("PROJCS[\"ETRS89 / TM35FIN(E,N)\",GEOGCS[...]]".to_string()),
+            geographic: false,
+        };
+        assert_eq!(crs.to_string(), "ETRS89 / TM35FIN(E,N)");
+    }
+
+    #[test]
+    fn test_parse_and_wkt() {
+        let crs = Crs::parse("EPSG:25832").unwrap();
+        assert_eq!(crs.epsg, Some(25832));
+        let wkt = crs.to_wkt().unwrap();
+        assert!(
+            wkt.starts_with("PROJCS[\"ETRS89 / UTM zone 32N\""),
+            "{}",
+            wkt
+        );
+        assert!(wkt.contains("PARAMETER[\"central_meridian\",9]"), "{}", wkt);
+        assert!(wkt.ends_with("AUTHORITY[\"EPSG\",\"25832\"]]"), "{}", wkt);
+
+        let crs = Crs::parse("GEOGCS[\"WGS 84\"]").unwrap();
+        assert_eq!(crs.to_wkt().as_deref(), Some("GEOGCS[\"WGS 84\"]"));
+        assert!(crs.geographic);
+        assert!(Crs::parse("EPSG:4326").unwrap().geographic);
+        assert!(!Crs::parse("EPSG:3067").unwrap().geographic);
+        assert_eq!(Crs::parse("EPSG:9999").unwrap().to_wkt(), None);
+        assert!(Crs::parse("TM35FIN").is_err());
+
+        // EPSG codes above 65535 are valid, e.g. the GDA2020 systems
+        let crs = Crs::parse("EPSG:106001").unwrap();
+        assert_eq!(crs.epsg, Some(106001));
+        let mut bytes = Vec::new();
+        crs.to_bytes(&mut bytes).unwrap();
+        assert_eq!(Crs::from_bytes(&mut bytes.as_slice()).unwrap(), crs);
+    }
+
+    #[test]
+    fn test_prj_path() {
+        assert_eq!(
+            Crs::prj_path(Path::new("a/map.pgw")),
+            Path::new("a/map.prj")
+        );
+        assert_eq!(Crs::prj_path(Path::new("map.png")), Path::new("map.prj"));
+        assert_eq!(
+            Crs::prj_path(Path::new("a/map.dxf")),
+            Path::new("a/map.dxf.prj")
+        );
+    }
+
+    #[test]
+    fn test_prj_sidecars() {
+        let fs = crate::io::fs::memory::MemoryFileSystem::new();
+        let crs = Crs::parse("EPSG:3067").unwrap();
+        // only the files given get a .prj, not the other tiles sharing the prefix
+        for path in ["t1.pgw", "t1.dxf", "t1.png", "t10.pgw"] {
+            fs.create(path).unwrap();
+        }
+        let outputs = [PathBuf::from("t1.pgw"), PathBuf::from("t1.dxf")];
+        Crs::write_prj_sidecars(Some(&crs), &fs, &outputs).unwrap();
+        assert!(fs.exists("t1.prj"));
+        assert!(fs.exists("t1.dxf.prj"));
+        assert!(!fs.exists("t10.prj"));
+    }
+}
@@ -0,0 +1,180 @@
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use super::fs::FileSystem;
+use crate::error::{missing, Result};
+
+/// A polyline read from a DXF file, together with the layer it is on.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Polyline {
+    pub layer: String,
+    pub points: Vec<(f64, f64)>,
+}
+
+/// Reads the POLYLINE, LWPOLYLINE and LINE entities of a DXF file as polylines. Other entities
+/// are skipped.
+pub fn read_polylines(fs: &impl FileSystem, path: &Path) -> Result<Vec<Polyline>> {
+    let data = fs.read_to_string(path)?;
+    parse_polylines(&data)
+}
+
+/// Parses the polylines of the contents of a DXF file, see [`read_polylines`].
+pub fn parse_polylines(data: &str) -> Result<Vec<Polyline>> {
+    let mut lines = data.lines().map(str::trim);
+
+    let mut polylines = Vec::new();
+    // the entity being read and the polyline being built from it
+    let mut entity = String::new();
+    let mut current: Option<Polyline> = None;
+    let mut x = None;
+    while let Some(code) = lines.next() {
+        let value = lines
+            .next()
+            .ok_or_else(|| format!("Missing value for group code {} in DXF file", code))?;
+        let code = code
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid group code {:?} in DXF file", code))?;
+
+        match (code, entity.as_str()) {
+            (0, _) => {
+                match value {
+                    // the vertices of a POLYLINE are separate entities until SEQEND
+                    "VERTEX" if entity == "POLYLINE" || entity == "VERTEX" => {
+                        entity = value.to_string();
+                        continue;
+                    }
+                    "SEQEND" => {}
+                    _ if entity == "POLYLINE" || entity == "VERTEX" => continue,
+                    _ => {}
+                }
+                if let Some(polyline) = current.take() {
+                    if !polyline.points.is_empty() {
+                        polylines.push(polyline);
+                    }
+                }
+                entity = value.to_string();
+                if matches!(value, "POLYLINE" | "LWPOLYLINE" | "LINE") {
+                    current = Some(Polyline::default());
+                }
+            }
+            (8, "POLYLINE" | "LWPOLYLINE" | "LINE") => {
+                if let Some(polyline) = current.as_mut() {
+                    polyline.layer = value.to_string();
+                }
+            }
+            (10 | 11, "VERTEX" | "LWPOLYLINE" | "LINE") => x = Some(parse_value(value)?),
+            (20 | 21, "VERTEX" | "LWPOLYLINE" | "LINE") => {
+                let y = parse_value(value)?;
+                if let (Some(polyline), Some(x)) = (current.as_mut(), x.take()) {
+                    polyline.points.push((x, y));
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(polyline) = current.take() {
+        if !polyline.points.is_empty() {
+            polylines.push(polyline);
+        }
+    }
+    Ok(polylines)
+}
+
+/// Writes polylines to a DXF file, each on its own layer.
+pub fn write_polylines(fs: &impl FileSystem, path: &Path, polylines: &[Polyline]) -> Result<()> {
+    let mut xmin = f64::MAX;
+    let mut ymin = f64::MAX;
+    let mut xmax = f64::MIN;
+    let mut ymax = f64::MIN;
+    for &(x, y) in polylines.iter().flat_map(|p| p.points.iter()) {
+        xmin = xmin.min(x);
+        ymin = ymin.min(y);
+        xmax = xmax.max(x);
+        ymax = ymax.max(y);
+    }
+    if polylines.iter().all(|p| p.points.is_empty()) {
+        (xmin, ymin, xmax, ymax) = (0.0, 0.0, 0.0, 0.0);
+    }
+
+    let mut f = BufWriter::new(fs.create(path)?);
+    write!(
+        f,
+        "  0\r\nSECTION\r\n  2\r\nHEADER\r\n  9\r\n$EXTMIN\r\n 10\r\n{}\r\n 20\r\n{}\r\n  9\r\n$EXTMAX\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nENDSEC\r\n  0\r\nSECTION\r\n  2\r\nENTITIES\r\n  0\r\n",
+        xmin, ymin, xmax, ymax
+    )?;
+    for polyline in polylines {
+        write!(
+            f,
+            "POLYLINE\r\n 66\r\n1\r\n  8\r\n{}\r\n  0\r\n",
+            polyline.layer
+        )?;
+        for (x, y) in polyline.points.iter() {
+            write!(
+                f,
+                "VERTEX\r\n  8\r\n{}\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\n",
+                polyline.layer, x, y
+            )?;
+        }
+        f.write_all(b"SEQEND\r\n  0\r\n")?;
+    }
+    f.write_all(b"ENDSEC\r\n  0\r\nEOF\r\n")?;
+    f.flush()?;
+    Ok(())
+}
+
+fn parse_value(value: &str) -> Result<f64> {
+    value
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid coordinate {:?} in DXF file", value).into())
+}
+
+/// Parses the coordinate at `index` of the lines of a VERTEX entity.
+pub fn vertex_coordinate(val: &[&str], index: usize) -> Result<f64> {
+    let value = val.get(index).ok_or_else(|| missing("vertex coordinate"))?;
+    Ok(value.trim().parse::<f64>()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_polylines() {
+        let data = "  0\r\nSECTION\r\n  2\r\nHEADER\r\n  9\r\n$EXTMIN\r\n 10\r\n0\r\n 20\r\n0\r\n  0\r\nENDSEC\r\n  0\r\nSECTION\r\n  2\r\nENTITIES\r\n  0\r\n\
+            POLYLINE\r\n 66\r\n1\r\n  8\r\ncliff2\r\n  0\r\n\
+            VERTEX\r\n  8\r\ncliff2\r\n 10\r\n1.5\r\n 20\r\n2.5\r\n  0\r\n\
+            VERTEX\r\n  8\r\ncliff2\r\n 10\r\n3\r\n 20\r\n4\r\n  0\r\nSEQEND\r\n  0\r\n\
+            LINE\r\n  8\r\nroad\r\n 10\r\n5\r\n 20\r\n6\r\n 11\r\n7\r\n 21\r\n8\r\n  0\r\n\
+            ENDSEC\r\n  0\r\nEOF\r\n";
+
+        let polylines = parse_polylines(data).unwrap();
+        assert_eq!(
+            polylines,
+            vec![
+                Polyline {
+                    layer: "cliff2".to_string(),
+                    points: vec![(1.5, 2.5), (3.0, 4.0)],
+                },
+                Polyline {
+                    layer: "road".to_string(),
+                    points: vec![(5.0, 6.0), (7.0, 8.0)],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_read_polylines() {
+        let fs = crate::io::fs::memory::MemoryFileSystem::new();
+        let polylines = vec![Polyline {
+            layer: "cliff3".to_string(),
+            points: vec![(1.0, 2.0), (3.0, 4.0), (5.0, 6.5)],
+        }];
+
+        write_polylines(&fs, Path::new("test.dxf"), &polylines).unwrap();
+        assert_eq!(
+            read_polylines(&fs, Path::new("test.dxf")).unwrap(),
+            polylines
+        );
+    }
+}
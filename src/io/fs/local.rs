@@ -1,5 +1,6 @@
 use std::io::{self, Read, Seek, Write};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use super::FileSystem;
 
@@ -38,17 +39,33 @@ impl FileSystem for LocalFileSystem {
         std::fs::File::create(path)
     }
 
+    fn create_new(&self, path: impl AsRef<Path>) -> Result<impl Write + Seek, io::Error> {
+        std::fs::File::create_new(path)
+    }
+
     fn remove_file(&self, path: impl AsRef<Path>) -> Result<(), io::Error> {
         std::fs::remove_file(path)
     }
 
+    fn remove_dir_all(&self, path: impl AsRef<Path>) -> Result<(), io::Error> {
+        std::fs::remove_dir_all(path)
+    }
+
     fn file_size(&self, path: impl AsRef<Path>) -> Result<u64, io::Error> {
         let metadata = std::fs::metadata(path)?;
         Ok(metadata.len())
     }
 
+    fn modified(&self, path: impl AsRef<Path>) -> Result<SystemTime, io::Error> {
+        std::fs::metadata(path)?.modified()
+    }
+
     fn copy(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<(), io::Error> {
         std::fs::copy(from, to)?;
         Ok(())
     }
+
+    fn rename(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<(), io::Error> {
+        std::fs::rename(from, to)
+    }
 }
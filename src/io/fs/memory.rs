@@ -5,6 +5,7 @@ use core::str;
 use std::io::{self, Read, Seek, Write};
 use std::path::{Component, Path, PathBuf};
 use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
 
 /// An in-memory implementation of [`FileSystem`] for use whenever there is no access to a local
 /// file system (such as on WASM), or to speed up the processing when there is a lot of RAM available.
@@ -41,6 +42,36 @@ impl MemoryFileSystem {
         writer.write_all(&bytes)?;
         Ok(())
     }
+    /// Open a file for writing, creating it if needed. With `new` set the file must not exist.
+    fn create_file(&self, path: &Path, new: bool) -> Result<WritableFile, io::Error> {
+        let mut root = self.root.write().expect("root lock poisoned");
+
+        let parent = file_parent(path)?;
+
+        // find the parent directory
+        let dir = root.get_directory_mut(parent)?;
+
+        // get file name
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        if new && dir.files.contains_key(&name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "file already exists",
+            ));
+        }
+
+        // open or create new file
+        let file = dir.files.entry(name).or_insert(FileEntry::new());
+
+        // now we replace the arc with a new one which we will write to. This way existing readers
+        // will continue to read the old data, while we start filling up some new data)
+        let writer = WritableFile {
+            data: io::Cursor::new(Vec::new()),
+            data_link: file.data.clone(), // linked to the place where the data is stored
+        };
+        Ok(writer)
+    }
+
     /// Write the contents of a  file in the memory file system to the local file system.
     pub fn save_to_disk(
         &self,
@@ -195,17 +226,18 @@ impl Drop for WritableFile {
     fn drop(&mut self) {
         let data = core::mem::replace(&mut self.data, io::Cursor::new(Vec::new()));
         let mut data_link = self.data_link.write().expect("file data lock poisoned");
-        *data_link = FileData(Arc::new(data.into_inner()));
+        *data_link = FileData(Arc::new(data.into_inner()), SystemTime::now());
     }
 }
 
-/// Holds the data of a file. Cheap to clone because the data is behind an [`Arc`].
+/// Holds the data of a file and the time it was last written. Cheap to clone because the data is
+/// behind an [`Arc`].
 #[derive(Clone)]
-struct FileData(Arc<Vec<u8>>);
+struct FileData(Arc<Vec<u8>>, SystemTime);
 
 impl FileData {
     fn new() -> Self {
-        Self(Arc::new(Vec::new()))
+        Self(Arc::new(Vec::new()), SystemTime::now())
     }
 }
 
@@ -292,27 +324,11 @@ impl FileSystem for MemoryFileSystem {
     }
 
     fn create(&self, path: impl AsRef<Path>) -> Result<impl Write + Seek, io::Error> {
-        let mut root = self.root.write().expect("root lock poisoned");
-        let path = path.as_ref();
-
-        let parent = file_parent(path)?;
-
-        // find the parent directory
-        let dir = root.get_directory_mut(parent)?;
-
-        // get file name
-        let name = path.file_name().unwrap().to_string_lossy().to_string();
-
-        // open or create new file
-        let file = dir.files.entry(name).or_insert(FileEntry::new());
+        self.create_file(path.as_ref(), false)
+    }
 
-        // now we replace the arc with a new one which we will write to. This way existing readers
-        // will continue to read the old data, while we start filling up some new data)
-        let writer = WritableFile {
-            data: io::Cursor::new(Vec::new()),
-            data_link: file.data.clone(), // linked to the place where the data is stored
-        };
-        Ok(writer)
+    fn create_new(&self, path: impl AsRef<Path>) -> Result<impl Write + Seek, io::Error> {
+        self.create_file(path.as_ref(), true)
     }
 
     fn read_to_string(&self, path: impl AsRef<Path>) -> Result<String, io::Error> {
@@ -364,6 +380,23 @@ impl FileSystem for MemoryFileSystem {
         Ok(())
     }
 
+    fn remove_dir_all(&self, path: impl AsRef<Path>) -> Result<(), io::Error> {
+        let mut root = self.root.write().expect("root lock poisoned");
+        let path = path.as_ref();
+
+        let parent = file_parent(path)?;
+        let dir = root.get_directory_mut(parent)?;
+
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+
+        // the subdirectories and files go with the directory
+        dir.subdirs
+            .remove(&name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "directory not found"))?;
+
+        Ok(())
+    }
+
     fn file_size(&self, path: impl AsRef<Path>) -> Result<u64, io::Error> {
         let root = self.root.read().expect("root lock poisoned");
         let path = path.as_ref();
@@ -386,6 +419,21 @@ impl FileSystem for MemoryFileSystem {
         Ok(data.0.len() as u64)
     }
 
+    fn modified(&self, path: impl AsRef<Path>) -> Result<SystemTime, io::Error> {
+        let root = self.root.read().expect("root lock poisoned");
+        let path = path.as_ref();
+
+        let dir = root.get_directory(file_parent(path)?)?;
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let file = dir
+            .files
+            .get(&name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "file not found"))?;
+
+        let data = file.data.read().expect("file data lock poisoned");
+        Ok(data.1)
+    }
+
     fn copy(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<(), io::Error> {
         let mut root = self.root.write().expect("root lock poisoned");
         let from = from.as_ref();
@@ -421,6 +469,32 @@ impl FileSystem for MemoryFileSystem {
 
         Ok(())
     }
+
+    fn rename(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<(), io::Error> {
+        let mut root = self.root.write().expect("root lock poisoned");
+        let from = from.as_ref();
+        let to = to.as_ref();
+
+        let from_parent = file_parent(from)?;
+        let to_parent = file_parent(to)?;
+
+        // make sure the to directory exists before the file is taken out of the from directory
+        root.get_directory(to_parent)?;
+
+        // move the file entry, replacing any existing file
+        let from_name = from.file_name().unwrap().to_string_lossy().to_string();
+        let file = root
+            .get_directory_mut(from_parent)?
+            .files
+            .remove(&from_name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "file not found"))?;
+        let to_name = to.file_name().unwrap().to_string_lossy().to_string();
+        root.get_directory_mut(to_parent)?
+            .files
+            .insert(to_name, file);
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -586,6 +660,35 @@ mod test {
         };
     }
 
+    #[test]
+    fn test_create_new() {
+        let fs = super::MemoryFileSystem::new();
+        let path = "file.lock";
+
+        fs.create_new(path).unwrap().write_all(b"first").unwrap();
+        match fs.create_new(path) {
+            Ok(_) => panic!("file should exist"),
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+        };
+        assert_eq!(fs.read_to_string(path).unwrap(), "first");
+    }
+
+    #[test]
+    fn test_rename() {
+        let fs = super::MemoryFileSystem::new();
+        fs.create("a.txt").unwrap().write_all(b"new").unwrap();
+        fs.create("b.txt").unwrap().write_all(b"old").unwrap();
+
+        fs.rename("a.txt", "b.txt").unwrap();
+        assert!(!fs.exists("a.txt"));
+        assert_eq!(fs.read_to_string("b.txt").unwrap(), "new");
+
+        match fs.rename("a.txt", "c.txt") {
+            Ok(_) => panic!("file should not exist"),
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+        };
+    }
+
     #[test]
     fn test_file_does_not_exist() {
         let fs = super::MemoryFileSystem::new();
@@ -637,6 +740,24 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_remove_dir_all() {
+        let fs = super::MemoryFileSystem::new();
+        fs.create_dir_all("work/a/temp").unwrap();
+        fs.create("work/a/temp/test.txt").unwrap();
+        fs.create("work/b.txt").unwrap();
+
+        fs.remove_dir_all("work/a").unwrap();
+
+        assert!(!fs.exists("work/a/temp/test.txt"));
+        assert!(!fs.exists("work/a"));
+        assert!(fs.exists("work/b.txt"));
+        assert_eq!(
+            fs.remove_dir_all("work/a").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
     #[test]
     fn test_create_and_list_files_and_folders() {
         let fs = super::MemoryFileSystem::new();
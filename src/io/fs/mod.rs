@@ -1,8 +1,11 @@
 use std::{
     io::{self, Read, Seek, Write},
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
+use crate::error::ResultExt;
+
 pub mod local;
 pub mod memory;
 
@@ -23,27 +26,37 @@ pub trait FileSystem: std::fmt::Debug {
     /// Open a file for writing.
     fn create(&self, path: impl AsRef<Path>) -> Result<impl Write + Seek, io::Error>;
 
+    /// Create a new file for writing, failing with [`io::ErrorKind::AlreadyExists`] if it exists.
+    /// Creating the file is atomic, so it can be used to claim work.
+    fn create_new(&self, path: impl AsRef<Path>) -> Result<impl Write + Seek, io::Error>;
+
     /// Read a file into a String.
     fn read_to_string(&self, path: impl AsRef<Path>) -> Result<String, io::Error>;
 
     /// Remove a file.
     fn remove_file(&self, path: impl AsRef<Path>) -> Result<(), io::Error>;
 
+    /// Remove a directory with all its contents.
+    fn remove_dir_all(&self, path: impl AsRef<Path>) -> Result<(), io::Error>;
+
     /// Get the size of a file in bytes.
     fn file_size(&self, path: impl AsRef<Path>) -> Result<u64, io::Error>;
 
+    /// Get the time a file was last modified.
+    fn modified(&self, path: impl AsRef<Path>) -> Result<SystemTime, io::Error>;
+
     /// Copy a file.
     fn copy(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<(), io::Error>;
 
+    /// Rename a file, replacing `to` if it exists.
+    fn rename(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<(), io::Error>;
+
     /// Read an image in PNG format.
-    fn read_image_png(
-        &self,
-        path: impl AsRef<Path>,
-    ) -> Result<image::DynamicImage, image::error::ImageError> {
-        let mut reader = image::ImageReader::new(std::io::BufReader::new(
-            self.open(path).expect("Could not open file"),
-        ));
+    fn read_image_png(&self, path: impl AsRef<Path>) -> crate::error::Result<image::DynamicImage> {
+        let path = path.as_ref();
+        let mut reader =
+            image::ImageReader::new(std::io::BufReader::new(self.open(path).with_file(path)?));
         reader.set_format(image::ImageFormat::Png);
-        reader.decode()
+        reader.decode().with_file(path)
     }
 }
@@ -0,0 +1,265 @@
+use image::DynamicImage;
+use std::io::{Cursor, Write};
+use std::path::Path;
+use tiff::encoder::{colortype, compression::Deflate, TiffEncoder, TiffKindStandard};
+use tiff::tags::Tag;
+
+use super::crs::Crs;
+use super::fs::FileSystem;
+use super::heightmap::HeightMap;
+use crate::error::Result;
+
+/// GeoTIFF keys, see the GeoTIFF specification.
+const GT_MODEL_TYPE_GEOKEY: u16 = 1024;
+const GT_RASTER_TYPE_GEOKEY: u16 = 1025;
+const GT_CITATION_GEOKEY: u16 = 1026;
+const GEOGRAPHIC_TYPE_GEOKEY: u16 = 2048;
+const PROJECTED_CS_TYPE_GEOKEY: u16 = 3072;
+
+const MODEL_TYPE_PROJECTED: u16 = 1;
+const MODEL_TYPE_GEOGRAPHIC: u16 = 2;
+const RASTER_PIXEL_IS_AREA: u16 = 1;
+const USER_DEFINED: u16 = 32767;
+
+/// Value written for cells without data.
+pub const NODATA: f64 = -9999.0;
+
+/// Position and pixel size of a raster in world coordinates, as in a world file (.pgw).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoTransform {
+    pub pixel_width: f64,
+    /// Negative for images with north up.
+    pub pixel_height: f64,
+    /// Coordinates of the center of the top left pixel.
+    pub x: f64,
+    pub y: f64,
+}
+
+impl GeoTransform {
+    /// Parses the six lines of a world file. Rotated rasters are not supported.
+    pub fn from_world_file(data: &str) -> Result<Self> {
+        let values = data
+            .split_whitespace()
+            .map(|v| v.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid world file: {}", e))?;
+        let [pixel_width, rotation1, rotation2, pixel_height, x, y] = values[..] else {
+            return Err(format!("Invalid world file with {} values", values.len()).into());
+        };
+        if rotation1 != 0.0 || rotation2 != 0.0 {
+            return Err("Rotated world files are not supported".into());
+        }
+        Ok(GeoTransform {
+            pixel_width,
+            pixel_height,
+            x,
+            y,
+        })
+    }
+
+    /// The position of a heightmap, with one pixel per cell and north up.
+    pub fn from_heightmap(hmap: &HeightMap) -> Self {
+        GeoTransform {
+            pixel_width: hmap.scale,
+            pixel_height: -hmap.scale,
+            x: hmap.minx(),
+            y: hmap.maxy(),
+        }
+    }
+
+    /// Formats the six lines of a world file.
+    pub fn to_world_file(&self) -> String {
+        format!(
+            "{}\r\n0.0\r\n0.0\r\n{}\r\n{}\r\n{}\r\n",
+            self.pixel_width, self.pixel_height, self.x, self.y
+        )
+    }
+}
+
+/// Writes a heightmap as a deflate compressed 32-bit float GeoTIFF with one pixel per cell.
+/// Cells without data are written as [`NODATA`], which is declared in the GDAL nodata tag.
+pub fn write_heightmap_geotiff(
+    fs: &impl FileSystem,
+    path: &Path,
+    hmap: &HeightMap,
+    crs: Option<&Crs>,
+) -> Result<()> {
+    let (width, height) = (hmap.grid.width(), hmap.grid.height());
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let z = hmap.grid[(x, y)];
+            pixels.push(if z.is_nan() { NODATA } else { z } as f32);
+        }
+    }
+
+    let mut data = Cursor::new(Vec::new());
+    let mut encoder = TiffEncoder::new(&mut data)?;
+    let mut image = encoder.new_image_with_compression::<colortype::Gray32Float, _>(
+        width as u32,
+        height as u32,
+        Deflate::default(),
+    )?;
+    write_geotags(image.encoder(), &GeoTransform::from_heightmap(hmap), crs)?;
+    image
+        .encoder()
+        .write_tag(Tag::GdalNodata, NODATA.to_string().as_str())?;
+    image.write_data(&pixels)?;
+
+    fs.create(path)?.write_all(data.get_ref())?;
+    Ok(())
+}
+
+/// Writes an image as a deflate compressed GeoTIFF with the given position and, if known, the
+/// coordinate reference system. Grayscale images are written as such, other images as RGB or
+/// RGBA.
+pub fn write_geotiff(
+    fs: &impl FileSystem,
+    path: &Path,
+    img: &DynamicImage,
+    transform: &GeoTransform,
+    crs: Option<&Crs>,
+) -> Result<()> {
+    let (width, height) = (img.width(), img.height());
+    let mut data = Cursor::new(Vec::new());
+    let mut encoder = TiffEncoder::new(&mut data)?;
+
+    macro_rules! write_image {
+        ($color:ty, $pixels:expr) => {{
+            let mut image = encoder.new_image_with_compression::<$color, _>(
+                width,
+                height,
+                Deflate::default(),
+            )?;
+            write_geotags(image.encoder(), transform, crs)?;
+            image.write_data(&$pixels)?;
+        }};
+    }
+    match img {
+        DynamicImage::ImageLuma8(gray) => write_image!(colortype::Gray8, gray.as_raw()),
+        DynamicImage::ImageRgb8(rgb) => write_image!(colortype::RGB8, rgb.as_raw()),
+        DynamicImage::ImageRgba8(rgba) => write_image!(colortype::RGBA8, rgba.as_raw()),
+        img if img.color().has_alpha() => write_image!(colortype::RGBA8, img.to_rgba8()),
+        img => write_image!(colortype::RGB8, img.to_rgb8()),
+    }
+
+    fs.create(path)?.write_all(data.get_ref())?;
+    Ok(())
+}
+
+/// Writes a GeoTIFF next to a png file and its world file, with the extension changed to .tif.
+pub fn png_to_geotiff(fs: &impl FileSystem, png: &Path, crs: Option<&Crs>) -> Result<()> {
+    let img = fs.read_image_png(png)?;
+    let transform = GeoTransform::from_world_file(&fs.read_to_string(png.with_extension("pgw"))?)?;
+    write_geotiff(fs, &png.with_extension("tif"), &img, &transform, crs)
+}
+
+fn write_geotags<W: Write + std::io::Seek>(
+    encoder: &mut tiff::encoder::DirectoryEncoder<W, TiffKindStandard>,
+    transform: &GeoTransform,
+    crs: Option<&Crs>,
+) -> Result<()> {
+    encoder.write_tag(
+        Tag::ModelPixelScaleTag,
+        &[transform.pixel_width, -transform.pixel_height, 0.0][..],
+    )?;
+    // the world file refers to the center of the top left pixel, the tie point to its corner
+    encoder.write_tag(
+        Tag::ModelTiepointTag,
+        &[
+            0.0,
+            0.0,
+            0.0,
+            transform.x - transform.pixel_width / 2.0,
+            transform.y - transform.pixel_height / 2.0,
+            0.0,
+        ][..],
+    )?;
+
+    let (keys, ascii) = geokeys(crs);
+    encoder.write_tag(Tag::GeoKeyDirectoryTag, &keys[..])?;
+    if !ascii.is_empty() {
+        encoder.write_tag(Tag::GeoAsciiParamsTag, ascii.as_str())?;
+    }
+    Ok(())
+}
+
+/// Builds the GeoKeyDirectoryTag values and the GeoAsciiParamsTag string for a coordinate
+/// reference system. An EPSG code is written as such, a WKT definition as an ESRI PE string
+/// citation, which GDAL based applications understand.
+fn geokeys(crs: Option<&Crs>) -> (Vec<u16>, String) {
+    let mut keys: Vec<[u16; 4]> = vec![[GT_RASTER_TYPE_GEOKEY, 0, 1, RASTER_PIXEL_IS_AREA]];
+    let mut ascii = String::new();
+
+    if let Some(crs) = crs {
+        if let Some(wkt) = &crs.wkt {
+            ascii = format!("ESRI PE String = {}|", wkt);
+            keys.push([
+                GT_CITATION_GEOKEY,
+                Tag::GeoAsciiParamsTag.to_u16(),
+                ascii.len() as u16,
+                0,
+            ]);
+        }
+        // EPSG codes that do not fit the 16 bits of a geokey are written as user defined
+        match crs.epsg.and_then(|epsg| u16::try_from(epsg).ok()) {
+            Some(epsg) if crs.geographic => {
+                keys.push([GT_MODEL_TYPE_GEOKEY, 0, 1, MODEL_TYPE_GEOGRAPHIC]);
+                keys.push([GEOGRAPHIC_TYPE_GEOKEY, 0, 1, epsg]);
+            }
+            Some(epsg) => {
+                keys.push([GT_MODEL_TYPE_GEOKEY, 0, 1, MODEL_TYPE_PROJECTED]);
+                keys.push([PROJECTED_CS_TYPE_GEOKEY, 0, 1, epsg]);
+            }
+            None => {
+                keys.push([GT_MODEL_TYPE_GEOKEY, 0, 1, MODEL_TYPE_PROJECTED]);
+                keys.push([PROJECTED_CS_TYPE_GEOKEY, 0, 1, USER_DEFINED]);
+            }
+        }
+    }
+    keys.sort_by_key(|k| k[0]);
+
+    let mut directory = vec![1, 1, 0, keys.len() as u16];
+    directory.extend(keys.iter().flatten());
+    (directory, ascii)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::fs::memory::MemoryFileSystem;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_write_geotiff() {
+        let fs = MemoryFileSystem::new();
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(3, 2, Rgb([10, 20, 30])));
+        let transform =
+            GeoTransform::from_world_file("0.5\r\n0.0\r\n0.0\r\n-0.5\r\n1000.25\r\n2000.75\r\n")
+                .unwrap();
+        let crs = Crs {
+            epsg: Some(3067),
+            wkt: None,
+            geographic: false,
+        };
+        write_geotiff(&fs, Path::new("test.tif"), &img, &transform, Some(&crs)).unwrap();
+
+        let mut data = Vec::new();
+        std::io::Read::read_to_end(&mut fs.open("test.tif").unwrap(), &mut data).unwrap();
+        let mut decoder = tiff::decoder::Decoder::new(Cursor::new(data)).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (3, 2));
+        assert_eq!(
+            decoder
+                .get_tag_f64_vec(Tag::ModelTiepointTag)
+                .unwrap()
+                .as_slice(),
+            &[0.0, 0.0, 0.0, 1000.0, 2001.0, 0.0]
+        );
+        let keys = decoder.get_tag_u16_vec(Tag::GeoKeyDirectoryTag).unwrap();
+        assert!(keys.chunks(4).any(|k| k == [3072, 0, 1, 3067]));
+        match decoder.read_image().unwrap() {
+            tiff::decoder::DecodingResult::U8(pixels) => assert_eq!(&pixels[..3], &[10, 20, 30]),
+            _ => panic!("unexpected sample type"),
+        }
+    }
+}
@@ -14,6 +14,10 @@ pub struct HeightMap {
 
     /// The actual grid data
     pub grid: Vec2D<f64>,
+
+    /// Distance in meters from each cell to the nearest cell with real points, or `None` if every
+    /// cell is backed by real points (or the heightmap was written by an older version).
+    pub distance: Option<Vec2D<f32>>,
 }
 
 impl HeightMap {
@@ -33,6 +37,60 @@ impl HeightMap {
         self.yoffset + self.scale * (self.grid.height().saturating_sub(1)) as f64
     }
 
+    /// Get the bilinearly interpolated height at a world coordinate, or `None` if the coordinate
+    /// is outside the heightmap or the heightmap is empty.
+    pub fn sample(&self, x: f64, y: f64) -> Option<f64> {
+        let gx = (x - self.xoffset) / self.scale;
+        let gy = (y - self.yoffset) / self.scale;
+        let (w, h) = (self.grid.width(), self.grid.height());
+        if w == 0 || h == 0 || gx < 0.0 || gy < 0.0 || gx > (w - 1) as f64 || gy > (h - 1) as f64 {
+            return None;
+        }
+
+        let (x0, y0) = (gx.floor() as usize, gy.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+        let (dx, dy) = (gx - x0 as f64, gy - y0 as f64);
+        let bottom = self.grid[(x0, y0)] * (1.0 - dx) + self.grid[(x1, y0)] * dx;
+        let top = self.grid[(x0, y1)] * (1.0 - dx) + self.grid[(x1, y1)] * dx;
+        Some(bottom * (1.0 - dy) + top * dy)
+    }
+
+    /// Set the distance layer from a mask of the cells that have no real points.
+    pub fn set_distance_from_mask(&mut self, interpolated: &Vec2D<bool>) {
+        let mut distance = distance_transform(interpolated);
+        for (_, _, d) in distance.iter_mut() {
+            *d *= self.scale as f32;
+        }
+        self.distance = Some(distance);
+    }
+
+    /// Get the distance in meters from the cell to the nearest cell with real points, 0 if the
+    /// distance is not known.
+    pub fn cell_distance(&self, x: usize, y: usize) -> f64 {
+        self.distance.as_ref().map_or(0.0, |d| d[(x, y)] as f64)
+    }
+
+    /// Whether the cell is farther than `limit` meters from real points. A `limit` of 0 disables
+    /// the check.
+    pub fn is_unmapped(&self, x: usize, y: usize, limit: f64) -> bool {
+        limit > 0.0 && self.cell_distance(x, y) > limit
+    }
+
+    /// Whether the cell nearest to a world coordinate is farther than `limit` meters from real
+    /// points. Coordinates outside the heightmap are never unmapped.
+    pub fn is_unmapped_at(&self, x: f64, y: f64, limit: f64) -> bool {
+        if limit <= 0.0 || self.distance.is_none() {
+            return false;
+        }
+        let gx = ((x - self.xoffset) / self.scale).round();
+        let gy = ((y - self.yoffset) / self.scale).round();
+        if gx < 0.0 || gy < 0.0 || gx >= self.grid.width() as f64 || gy >= self.grid.height() as f64
+        {
+            return false;
+        }
+        self.is_unmapped(gx as usize, gy as usize, limit)
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (f64, f64, f64)> + '_ {
         self.grid.iter().map(|(x, y, v)| {
             (
@@ -73,12 +131,20 @@ impl FromToBytes for HeightMap {
         let yoffset = f64::from_bytes(reader)?;
         let scale = f64::from_bytes(reader)?;
         let data = Vec2D::from_bytes(reader)?;
+        // heightmaps written by older versions end after the grid
+        let distance = match u8::from_bytes(reader) {
+            Ok(0) => None,
+            Ok(_) => Some(Vec2D::from_bytes(reader)?),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => None,
+            Err(e) => return Err(e),
+        };
 
         Ok(HeightMap {
             xoffset,
             yoffset,
             scale,
             grid: data,
+            distance,
         })
     }
 
@@ -86,8 +152,85 @@ impl FromToBytes for HeightMap {
         self.xoffset.to_bytes(writer)?;
         self.yoffset.to_bytes(writer)?;
         self.scale.to_bytes(writer)?;
-        self.grid.to_bytes(writer)
+        self.grid.to_bytes(writer)?;
+        self.distance.to_bytes(writer)
+    }
+}
+
+/// Exact euclidean distance transform (Felzenszwalb & Huttenlocher) giving, for every cell, the
+/// distance in cells to the nearest cell where `mask` is false.
+fn distance_transform(mask: &Vec2D<bool>) -> Vec2D<f32> {
+    let (w, h) = (mask.width(), mask.height());
+    let mut squared = Vec2D::new(w, h, f64::INFINITY);
+    for (x, y, masked) in mask.iter() {
+        if !masked {
+            squared[(x, y)] = 0.0;
+        }
+    }
+
+    let mut column = vec![0.0; h];
+    for x in 0..w {
+        for (y, c) in column.iter_mut().enumerate() {
+            *c = squared[(x, y)];
+        }
+        for (y, d) in distance_1d(&column).into_iter().enumerate() {
+            squared[(x, y)] = d;
+        }
     }
+    let mut row = vec![0.0; w];
+    let mut distance = Vec2D::new(w, h, 0.0f32);
+    for y in 0..h {
+        for (x, r) in row.iter_mut().enumerate() {
+            *r = squared[(x, y)];
+        }
+        for (x, d) in distance_1d(&row).into_iter().enumerate() {
+            distance[(x, y)] = d.sqrt() as f32;
+        }
+    }
+    distance
+}
+
+/// One dimensional squared distance transform of a sampled function, the lower envelope of the
+/// parabolas rooted at each sample.
+fn distance_1d(f: &[f64]) -> Vec<f64> {
+    let n = f.len();
+    let mut result = vec![f64::INFINITY; n];
+    // parabola roots and the boundaries between them in the lower envelope
+    let mut roots: Vec<usize> = Vec::with_capacity(n);
+    let mut bounds: Vec<f64> = Vec::with_capacity(n);
+    for q in 0..n {
+        if f[q].is_infinite() {
+            continue;
+        }
+        while let Some(&v) = roots.last() {
+            let s = ((f[q] + (q * q) as f64) - (f[v] + (v * v) as f64)) / (2 * (q - v)) as f64;
+            if s <= *bounds.last().unwrap() {
+                roots.pop();
+                bounds.pop();
+            } else {
+                roots.push(q);
+                bounds.push(s);
+                break;
+            }
+        }
+        if roots.is_empty() {
+            roots.push(q);
+            bounds.push(f64::NEG_INFINITY);
+        }
+    }
+    if roots.is_empty() {
+        return result;
+    }
+
+    let mut k = 0;
+    for (q, r) in result.iter_mut().enumerate() {
+        while k + 1 < roots.len() && bounds[k + 1] < q as f64 {
+            k += 1;
+        }
+        let v = roots[k];
+        *r = (q as f64 - v as f64).powi(2) + f[v];
+    }
+    result
 }
 
 #[cfg(test)]
@@ -107,6 +250,7 @@ mod test {
             yoffset: -5.0,
             scale: 1.5,
             grid: data,
+            distance: None,
         };
 
         let mut bytes = Vec::new();
@@ -115,4 +259,69 @@ mod test {
 
         assert_eq!(heightmap, heightmap2);
     }
+
+    #[test]
+    fn test_distance() {
+        let mut mask = Vec2D::new(5, 4, true);
+        mask[(0, 0)] = false;
+        mask[(4, 3)] = false;
+
+        let mut heightmap = super::HeightMap {
+            xoffset: 0.0,
+            yoffset: 0.0,
+            scale: 2.0,
+            grid: Vec2D::new(5, 4, 0.0),
+            distance: None,
+        };
+        heightmap.set_distance_from_mask(&mask);
+
+        assert_eq!(heightmap.cell_distance(0, 0), 0.0);
+        assert_eq!(heightmap.cell_distance(1, 0), 2.0);
+        assert_eq!(heightmap.cell_distance(1, 1), 8f64.sqrt() as f32 as f64);
+        assert_eq!(heightmap.cell_distance(3, 3), 2.0);
+        assert!(heightmap.is_unmapped(2, 1, 4.0));
+        assert!(!heightmap.is_unmapped(2, 1, 0.0));
+        assert!(!heightmap.is_unmapped_at(0.4, 0.4, 1.0));
+
+        // the distance layer is persisted, and heightmaps without it can still be read
+        let mut bytes = Vec::new();
+        heightmap.to_bytes(&mut bytes).unwrap();
+        assert_eq!(
+            HeightMap::from_bytes(&mut bytes.as_slice()).unwrap(),
+            heightmap
+        );
+
+        heightmap.distance = None;
+        let mut bytes = Vec::new();
+        heightmap.to_bytes(&mut bytes).unwrap();
+        bytes.pop();
+        assert_eq!(
+            HeightMap::from_bytes(&mut bytes.as_slice()).unwrap(),
+            heightmap
+        );
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut data = Vec2D::new(2, 2, 0.0);
+        data[(1, 0)] = 2.0;
+        data[(0, 1)] = 4.0;
+        data[(1, 1)] = 6.0;
+
+        let mut heightmap = super::HeightMap {
+            xoffset: 10.0,
+            yoffset: 20.0,
+            scale: 2.0,
+            grid: data,
+            distance: None,
+        };
+
+        assert_eq!(heightmap.sample(10.0, 20.0), Some(0.0));
+        assert_eq!(heightmap.sample(11.0, 21.0), Some(3.0));
+        assert_eq!(heightmap.sample(12.0, 22.0), Some(6.0));
+        assert_eq!(heightmap.sample(9.0, 20.0), None);
+
+        heightmap.grid = Vec2D::new(0, 0, 0.0);
+        assert_eq!(heightmap.sample(10.0, 20.0), None);
+    }
 }
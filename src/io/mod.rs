@@ -1,18 +1,124 @@
+use image::{
+    DynamicImage, EncodableLayout, ImageBuffer, ImageFormat, ImageReader, ImageResult,
+    PixelWithColorType,
+};
 use std::{
-    io::{BufReader, BufWriter, Write},
+    io::{BufRead, BufReader, BufWriter, Seek, Write},
+    ops::Deref,
     path::Path,
 };
 
 use fs::FileSystem;
 use heightmap::HeightMap;
+use xyz::{XyzFields, XyzRecord};
+
+use crate::error::{missing, Result, ResultExt};
 
 pub mod bytes;
+pub mod chunks;
+pub mod crs;
+pub mod dxf;
 pub mod fs;
+pub mod geotiff;
 pub mod heightmap;
+pub mod raster;
+pub mod text;
+pub mod tileindex;
 pub mod xyz;
 
+/// Helper function to read the points of a LAS/LAZ file, a text .xyz file or an internal .xyz.bin
+/// file. Returns the optional fields that the points of the file have.
+///
+/// The layout of text files is detected with the given delimiter and columns, see
+/// [`text::TextLayout::detect`]. If classification and return numbers are missing, the points are
+/// read as unclassified single returns.
+pub fn read_points(
+    fs: &impl FileSystem,
+    path: &Path,
+    xyz_delimiter: Option<char>,
+    xyz_columns: &[text::TextColumn],
+    mut callback: impl FnMut(XyzRecord),
+) -> Result<XyzFields> {
+    let filename = path.to_string_lossy().to_lowercase();
+
+    if filename.ends_with(".laz") || filename.ends_with(".las") {
+        let mut reader = las::Reader::new(BufReader::new(fs.open(path)?))?;
+        let fields = XyzFields::from_las_format(reader.header().point_format());
+        for point in reader.points() {
+            callback(XyzRecord::from_las_point(&point?));
+        }
+        Ok(fields)
+    } else if filename.ends_with(".xyz.bin") {
+        let mut reader = xyz::XyzInternalReader::new(BufReader::new(fs.open(path)?))?;
+        let fields = reader.fields();
+        while let Some(record) = reader.next()? {
+            callback(record);
+        }
+        Ok(fields)
+    } else if filename.ends_with(".xyz") {
+        let layout = text::TextLayout::detect(fs, path, xyz_delimiter, xyz_columns)?;
+        text::read_text_points(fs, path, &layout, callback)?;
+        Ok(layout.fields())
+    } else {
+        Err(format!("Unsupported point file: {}", path.display()).into())
+    }
+}
+
+/// Reads a png image, without limits on its size.
+pub fn read_png(fs: &impl FileSystem, path: impl AsRef<Path>) -> Result<DynamicImage> {
+    let path = path.as_ref();
+    let mut reader = ImageReader::new(BufReader::new(fs.open(path).with_file(path)?));
+    reader.set_format(ImageFormat::Png);
+    reader.no_limits();
+    reader.decode().with_file(path)
+}
+
+/// An image that [`write_png`] can write.
+pub trait PngImage {
+    fn write_png_to(&self, writer: &mut (impl Write + Seek)) -> ImageResult<()>;
+}
+
+impl<P, C> PngImage for ImageBuffer<P, C>
+where
+    P: PixelWithColorType,
+    [P::Subpixel]: EncodableLayout,
+    C: Deref<Target = [P::Subpixel]>,
+{
+    fn write_png_to(&self, writer: &mut (impl Write + Seek)) -> ImageResult<()> {
+        self.write_to(writer, ImageFormat::Png)
+    }
+}
+
+impl PngImage for DynamicImage {
+    fn write_png_to(&self, writer: &mut (impl Write + Seek)) -> ImageResult<()> {
+        self.write_to(writer, ImageFormat::Png)
+    }
+}
+
+/// Writes an image as png.
+pub fn write_png(fs: &impl FileSystem, img: &impl PngImage, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    img.write_png_to(&mut BufWriter::new(fs.create(path).with_file(path)?))
+        .with_file(path)
+}
+
+/// Reads the six values of a world file (.pgw), one per line.
+pub fn read_world_file(fs: &impl FileSystem, path: impl AsRef<Path>) -> Result<[f64; 6]> {
+    let path = path.as_ref();
+    let mut lines = BufReader::new(fs.open(path).with_file(path)?).lines();
+    let mut values = [0.0; 6];
+    for (i, value) in values.iter_mut().enumerate() {
+        let line = match lines.next() {
+            Some(line) => line.with_line(path, i + 1)?,
+            None => return Err(missing("line")).with_line(path, i + 1),
+        };
+        *value = line.trim().parse::<f64>().with_line(path, i + 1)?;
+    }
+    Ok(values)
+}
+
 /// Helper function to convert an internal xyz file to a regular xyz file.
-pub fn internal2xyz(fs: &impl FileSystem, input: &str, output: &str) -> std::io::Result<()> {
+pub fn internal2xyz(fs: &impl FileSystem, input: &str, output: &str) -> Result<()> {
     if input.ends_with(".xyz.bin") {
         let mut reader = xyz::XyzInternalReader::new(BufReader::new(fs.open(Path::new(input))?))?;
         let mut writer = BufWriter::new(fs.create(output)?);
@@ -37,8 +143,53 @@ pub fn internal2xyz(fs: &impl FileSystem, input: &str, output: &str) -> std::io:
             writeln!(writer, "{} {} {}", x, y, h)?;
         }
     } else {
-        panic!("Unknown internal file format: {}", input);
+        return Err(format!("Unknown internal file format: {}", input).into());
     }
 
     Ok(())
 }
+
+/// Writes a small LAS/LAZ file of unclassified points, for the tests of the readers.
+#[cfg(test)]
+pub(crate) fn write_test_las(
+    fs: &impl FileSystem,
+    path: impl AsRef<Path>,
+    points: &[(f64, f64, f64)],
+    compressed: bool,
+) {
+    let data = test_las(
+        points,
+        las::point::Format::new(0).unwrap(),
+        compressed,
+        vec![],
+    );
+    fs.create(path).unwrap().write_all(&data).unwrap();
+}
+
+/// The bytes of a LAS/LAZ file of unclassified points in the given point format, with extra VLRs.
+#[cfg(test)]
+pub(crate) fn test_las(
+    points: &[(f64, f64, f64)],
+    mut format: las::point::Format,
+    compressed: bool,
+    vlrs: Vec<las::Vlr>,
+) -> Vec<u8> {
+    format.is_compressed = compressed;
+    let mut builder = las::Builder::from((1, 4));
+    builder.point_format = format;
+    builder.vlrs = vlrs;
+    let header = builder.into_header().unwrap();
+    let mut writer = las::Writer::new(std::io::Cursor::new(Vec::new()), header).unwrap();
+    for &(x, y, z) in points {
+        writer
+            .write_point(las::Point {
+                x,
+                y,
+                z,
+                gps_time: format.has_gps_time.then_some(0.0),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    writer.into_inner().unwrap().into_inner()
+}
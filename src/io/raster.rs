@@ -0,0 +1,502 @@
+use image::{DynamicImage, GrayImage, Luma};
+use log::info;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use tiff::decoder::{Decoder, DecodingResult, Limits};
+use tiff::tags::Tag;
+
+use super::crs::{Crs, CRS_FILE};
+use super::fs::FileSystem;
+use super::geotiff::{write_geotiff, write_heightmap_geotiff, GeoTransform, NODATA};
+use super::heightmap::HeightMap;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::ground::fill_gaps;
+use crate::vec2d::Vec2D;
+
+const GT_RASTER_TYPE_GEOKEY: u16 = 1025;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+const PLANAR_CONFIGURATION_PLANAR: u16 = 2;
+
+/// Direction of the light of hillshades, in degrees clockwise from north.
+const HILLSHADE_AZIMUTH: f64 = 315.0;
+/// Angle of the light of hillshades above the horizon, in degrees.
+const HILLSHADE_ALTITUDE: f64 = 45.0;
+
+/// Returns true if the file is an elevation raster supported by [`read_raster`].
+pub fn is_raster(path: &Path) -> bool {
+    let filename = path.to_string_lossy().to_lowercase();
+    filename.ends_with(".tif") || filename.ends_with(".tiff") || filename.ends_with(".asc")
+}
+
+/// Reads a single band elevation raster, a GeoTIFF (.tif, .tiff) or an ESRI ASCII grid (.asc),
+/// into a heightmap with one cell per pixel. Cells without data are NaN. Also returns the
+/// coordinate reference system of the raster, from the GeoTIFF keys or from the .prj file next
+/// to an ASCII grid.
+pub fn read_raster(fs: &impl FileSystem, path: &Path) -> Result<(HeightMap, Option<Crs>)> {
+    let filename = path.to_string_lossy().to_lowercase();
+    let result = if filename.ends_with(".asc") {
+        let hmap = read_ascii_grid(&fs.read_to_string(path)?)?;
+        let prj = path.with_extension("prj");
+        let crs = if fs.exists(&prj) {
+            Crs::parse(&fs.read_to_string(&prj)?).ok()
+        } else {
+            None
+        };
+        (hmap, crs)
+    } else {
+        let mut data = Vec::new();
+        BufReader::new(fs.open(path)?).read_to_end(&mut data)?;
+        read_geotiff(&data)?
+    };
+    Ok(result)
+}
+
+/// Resamples a raster bilinearly to a heightmap with the given cell size, starting from the
+/// center of its lower left cell and extending one cell beyond it on every side, like the
+/// heightmaps made from point clouds. Cells without data are filled from their neighbours, they
+/// are marked in the returned mask.
+pub fn resample(raster: &HeightMap, scale: f64) -> Result<(HeightMap, Vec2D<bool>)> {
+    if raster.grid.iter().all(|(_, _, z)| z.is_nan()) {
+        return Err("The raster has no data".into());
+    }
+    let w = ((raster.maxx() - raster.minx()) / scale).ceil() as usize + 2;
+    let h = ((raster.maxy() - raster.miny()) / scale).ceil() as usize + 2;
+    let mut grid = Vec2D::new(w, h, f64::NAN);
+    for (x, y, z) in grid.iter_mut() {
+        let wx = raster.xoffset + x as f64 * scale;
+        let wy = raster.yoffset + y as f64 * scale;
+        *z = raster.sample(wx, wy).unwrap_or(f64::NAN);
+    }
+    let mut interpolated = Vec2D::new(w, h, false);
+    for (x, y, z) in grid.iter() {
+        interpolated[(x, y)] = z.is_nan();
+    }
+    fill_gaps(&mut grid);
+    let mut hmap = HeightMap {
+        xoffset: raster.xoffset,
+        yoffset: raster.yoffset,
+        scale,
+        grid,
+        distance: None,
+    };
+    hmap.set_distance_from_mask(&interpolated);
+    Ok((hmap, interpolated))
+}
+
+/// Exports a heightmap file of a temp folder, like xyz_03.hmap, xyz2.hmap or xyz_knolls.hmap,
+/// see [`write_heightmap`]. The coordinate reference system is the one of the config or of the
+/// temp folder.
+pub fn export_heightmap(
+    fs: &impl FileSystem,
+    config: &Config,
+    input: &Path,
+    output: &Path,
+    shade: bool,
+) -> Result<()> {
+    info!("Exporting {} to {}", input.display(), output.display());
+    let hmap = HeightMap::from_file(fs, input)?;
+    let crs = config.crs.clone().or_else(|| {
+        let folder = input.parent().unwrap_or(Path::new(""));
+        Crs::from_file(fs, folder.join(CRS_FILE))
+    });
+    write_heightmap(fs, &hmap, output, shade, crs.as_ref())
+}
+
+/// Writes a heightmap to a file, chosen by the extension of `output`: a 32-bit float GeoTIFF
+/// (.tif, .tiff), an ESRI ASCII grid (.asc) or, for .png files or with `shade` set, an 8-bit
+/// hillshade image, which is an error for .asc files. Png files get a world file, and all but
+/// GeoTIFFs a .prj file if the coordinate reference system is known.
+pub fn write_heightmap(
+    fs: &impl FileSystem,
+    hmap: &HeightMap,
+    output: &Path,
+    shade: bool,
+    crs: Option<&Crs>,
+) -> Result<()> {
+    let filename = output.to_string_lossy().to_lowercase();
+    let is_tiff = filename.ends_with(".tif") || filename.ends_with(".tiff");
+    if filename.ends_with(".png") || (shade && is_tiff) {
+        let img = DynamicImage::ImageLuma8(hillshade(hmap));
+        let transform = GeoTransform::from_heightmap(hmap);
+        if is_tiff {
+            write_geotiff(fs, output, &img, &transform, crs)?;
+        } else {
+            img.write_to(
+                &mut BufWriter::new(fs.create(output)?),
+                image::ImageFormat::Png,
+            )?;
+            fs.create(output.with_extension("pgw"))?
+                .write_all(transform.to_world_file().as_bytes())?;
+            Crs::write_prj(crs, fs, output)?;
+        }
+    } else if is_tiff {
+        write_heightmap_geotiff(fs, output, hmap, crs)?;
+    } else if filename.ends_with(".asc") {
+        if shade {
+            return Err(Error::invalid(format!(
+                "A hillshade cannot be written as an ESRI ASCII grid, use .tif or .png for {}",
+                output.display()
+            )));
+        }
+        write_ascii_grid(fs, output, hmap)?;
+        Crs::write_prj(crs, fs, output)?;
+    } else {
+        return Err(format!("Unsupported output file: {}", output.display()).into());
+    }
+    Ok(())
+}
+
+/// Writes a heightmap as an ESRI ASCII grid, with the position given by the center of the lower
+/// left cell. Cells without data are written as [`NODATA`].
+pub fn write_ascii_grid(fs: &impl FileSystem, path: &Path, hmap: &HeightMap) -> Result<()> {
+    let (w, h) = (hmap.grid.width(), hmap.grid.height());
+    let mut writer = BufWriter::new(fs.create(path)?);
+    write!(
+        writer,
+        "ncols {}\nnrows {}\nxllcenter {}\nyllcenter {}\ncellsize {}\nNODATA_value {}\n",
+        w, h, hmap.xoffset, hmap.yoffset, hmap.scale, NODATA
+    )?;
+    for y in (0..h).rev() {
+        for x in 0..w {
+            let z = hmap.grid[(x, y)];
+            let separator = if x + 1 < w { ' ' } else { '\n' };
+            if z.is_nan() {
+                write!(writer, "{}{}", NODATA, separator)?;
+            } else {
+                write!(writer, "{:.3}{}", z, separator)?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Renders a heightmap as an 8-bit hillshade lit from the northwest at 45 degrees, with the
+/// slopes computed with Horn's method. Cells without data are black.
+pub fn hillshade(hmap: &HeightMap) -> GrayImage {
+    let (w, h) = (hmap.grid.width(), hmap.grid.height());
+    let azimuth = HILLSHADE_AZIMUTH.to_radians();
+    let altitude = HILLSHADE_ALTITUDE.to_radians();
+    let light = [
+        azimuth.sin() * altitude.cos(),
+        azimuth.cos() * altitude.cos(),
+        altitude.sin(),
+    ];
+
+    let mut img = GrayImage::new(w as u32, h as u32);
+    for (x, y, z) in hmap.grid.iter() {
+        if z.is_nan() {
+            continue;
+        }
+        // neighbours outside the grid or without data take the value of the cell
+        let at = |dx: isize, dy: isize| {
+            let (nx, ny) = (x as isize + dx, y as isize + dy);
+            if nx < 0 || ny < 0 || nx >= w as isize || ny >= h as isize {
+                return z;
+            }
+            let v = hmap.grid[(nx as usize, ny as usize)];
+            if v.is_nan() {
+                z
+            } else {
+                v
+            }
+        };
+        let dzdx = ((at(1, 1) + 2.0 * at(1, 0) + at(1, -1))
+            - (at(-1, 1) + 2.0 * at(-1, 0) + at(-1, -1)))
+            / (8.0 * hmap.scale);
+        let dzdy = ((at(-1, 1) + 2.0 * at(0, 1) + at(1, 1))
+            - (at(-1, -1) + 2.0 * at(0, -1) + at(1, -1)))
+            / (8.0 * hmap.scale);
+        let length = (dzdx * dzdx + dzdy * dzdy + 1.0).sqrt();
+        let shade = (-dzdx * light[0] - dzdy * light[1] + light[2]) / length;
+        img.put_pixel(
+            x as u32,
+            (h - 1 - y) as u32,
+            Luma([(shade.max(0.0) * 255.0).round() as u8]),
+        );
+    }
+    img
+}
+
+/// Parses an ESRI ASCII grid. The header gives the size, the lower left corner or center and
+/// the cell size, the values follow row by row from the north.
+fn read_ascii_grid(data: &str) -> Result<HeightMap> {
+    let mut tokens = data.split_whitespace().peekable();
+    let (mut ncols, mut nrows, mut cellsize) = (None, None, None);
+    let (mut xll, mut yll) = (None, None);
+    // whether the coordinates are of the center of the lower left cell instead of its corner
+    let (mut xcenter, mut ycenter) = (false, false);
+    let mut nodata = None;
+
+    while let Some(key) = tokens.next_if(|t| t.starts_with(|c: char| c.is_ascii_alphabetic())) {
+        let value = tokens
+            .next()
+            .ok_or_else(|| format!("Missing value for {} in ASCII grid", key))?;
+        let number = value
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid value for {} in ASCII grid: {}", key, value))?;
+        match key.to_lowercase().as_str() {
+            "ncols" => ncols = Some(number as usize),
+            "nrows" => nrows = Some(number as usize),
+            "xllcorner" => xll = Some(number),
+            "yllcorner" => yll = Some(number),
+            "xllcenter" => {
+                xll = Some(number);
+                xcenter = true;
+            }
+            "yllcenter" => {
+                yll = Some(number);
+                ycenter = true;
+            }
+            "cellsize" => cellsize = Some(number),
+            "nodata_value" => nodata = Some(number),
+            _ => return Err(format!("Unknown ASCII grid header {}", key).into()),
+        }
+    }
+    let (Some(w), Some(h), Some(xll), Some(yll), Some(cellsize)) =
+        (ncols, nrows, xll, yll, cellsize)
+    else {
+        return Err("Incomplete ASCII grid header".into());
+    };
+
+    let mut grid = Vec2D::new(w, h, f64::NAN);
+    for row in 0..h {
+        for x in 0..w {
+            let value = tokens
+                .next()
+                .ok_or("ASCII grid has fewer values than its header declares")?;
+            let z = value
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid value in ASCII grid: {}", value))?;
+            if Some(z) != nodata {
+                grid[(x, h - 1 - row)] = z;
+            }
+        }
+    }
+
+    let offset = |center| if center { 0.0 } else { cellsize / 2.0 };
+    Ok(HeightMap {
+        xoffset: xll + offset(xcenter),
+        yoffset: yll + offset(ycenter),
+        scale: cellsize,
+        grid,
+        distance: None,
+    })
+}
+
+/// Reads the first band of a GeoTIFF.
+fn read_geotiff(data: &[u8]) -> Result<(HeightMap, Option<Crs>)> {
+    let mut decoder = Decoder::new(std::io::Cursor::new(data))?.with_limits(Limits::unlimited());
+    let (w, h) = decoder.dimensions()?;
+    let (w, h) = (w as usize, h as usize);
+
+    let scale = decoder
+        .find_tag(Tag::ModelPixelScaleTag)?
+        .ok_or("GeoTIFF has no pixel scale")?
+        .into_f64_vec()?;
+    let tiepoint = decoder
+        .find_tag(Tag::ModelTiepointTag)?
+        .ok_or("GeoTIFF has no tie point")?
+        .into_f64_vec()?;
+    let (&[sx, sy, ..], &[i, j, _, x, y, ..]) = (&scale[..], &tiepoint[..]) else {
+        return Err("Invalid GeoTIFF pixel scale or tie point".into());
+    };
+    if (sx - sy).abs() > 1e-9 * sx.abs() {
+        return Err(format!("GeoTIFF pixels are not square: {} x {}", sx, sy).into());
+    }
+
+    let keys = decoder
+        .find_tag(Tag::GeoKeyDirectoryTag)?
+        .map(|v| v.into_u16_vec())
+        .transpose()?
+        .unwrap_or_default();
+    let pixel_is_point = keys
+        .get(4..)
+        .unwrap_or_default()
+        .chunks_exact(4)
+        .any(|k| k[0] == GT_RASTER_TYPE_GEOKEY && k[3] == RASTER_PIXEL_IS_POINT);
+    let crs = Crs::from_geokey_directory(&keys);
+
+    let nodata = decoder
+        .find_tag(Tag::GdalNodata)?
+        .map(|v| v.into_string())
+        .transpose()?
+        .and_then(|s| {
+            s.trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                .parse::<f64>()
+                .ok()
+        });
+    let samples = decoder
+        .find_tag_unsigned::<u16>(Tag::SamplesPerPixel)?
+        .unwrap_or(1) as usize;
+    let planar = decoder.find_tag_unsigned::<u16>(Tag::PlanarConfiguration)?;
+    // only the first band is read, which comes first in planar images
+    let stride = if planar == Some(PLANAR_CONFIGURATION_PLANAR) {
+        1
+    } else {
+        samples
+    };
+
+    macro_rules! values {
+        ($($variant:ident),*) => {
+            match decoder.read_image()? {
+                $(DecodingResult::$variant(v) => {
+                    v.into_iter().step_by(stride).take(w * h).map(|v| v as f64).collect::<Vec<_>>()
+                })*
+            }
+        };
+    }
+    let values = values!(U8, U16, U32, U64, F32, F64, I8, I16, I32, I64);
+    if values.len() < w * h {
+        return Err("GeoTIFF has fewer pixels than its size".into());
+    }
+
+    let mut grid = Vec2D::new(w, h, f64::NAN);
+    for (k, &z) in values.iter().enumerate() {
+        if !z.is_nan() && Some(z) != nodata {
+            grid[(k % w, h - 1 - k / w)] = z;
+        }
+    }
+
+    // world coordinates of the center of the top left pixel
+    let offset = if pixel_is_point { 0.0 } else { 0.5 };
+    let left = x + (offset - i) * sx;
+    let top = y - (offset - j) * sy;
+    Ok((
+        HeightMap {
+            xoffset: left,
+            yoffset: top - (h - 1) as f64 * sy,
+            scale: sx,
+            grid,
+            distance: None,
+        },
+        crs,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::fs::memory::MemoryFileSystem;
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    #[test]
+    fn test_read_ascii_grid() {
+        let fs = MemoryFileSystem::new();
+        std::io::Write::write_all(
+            &mut fs.create("dem.asc").unwrap(),
+            b"ncols 3\nnrows 2\nxllcorner 1000\nyllcorner 2000\ncellsize 2\nNODATA_value -9999\n\
+              1 2 3\n4 -9999 6\n",
+        )
+        .unwrap();
+
+        let (hmap, crs) = read_raster(&fs, Path::new("dem.asc")).unwrap();
+        assert_eq!(crs, None);
+        assert_eq!(
+            (hmap.xoffset, hmap.yoffset, hmap.scale),
+            (1001.0, 2001.0, 2.0)
+        );
+        assert_eq!((hmap.grid.width(), hmap.grid.height()), (3, 2));
+        assert_eq!(hmap.grid[(0, 1)], 1.0);
+        assert_eq!(hmap.grid[(2, 0)], 6.0);
+        assert!(hmap.grid[(1, 0)].is_nan());
+
+        let hmap = read_ascii_grid("ncols 1\nnrows 1\nxllcorner 10\nyllcenter 20\ncellsize 2\n5\n")
+            .unwrap();
+        assert_eq!((hmap.xoffset, hmap.yoffset), (11.0, 20.0));
+    }
+
+    #[test]
+    fn test_read_geotiff() {
+        let mut data = std::io::Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut data).unwrap();
+        let mut image = encoder.new_image::<colortype::Gray32Float>(2, 2).unwrap();
+        image
+            .encoder()
+            .write_tag(Tag::ModelPixelScaleTag, &[0.5, 0.5, 0.0][..])
+            .unwrap();
+        image
+            .encoder()
+            .write_tag(
+                Tag::ModelTiepointTag,
+                &[0.0, 0.0, 0.0, 500.0, 700.0, 0.0][..],
+            )
+            .unwrap();
+        image
+            .encoder()
+            .write_tag(
+                Tag::GeoKeyDirectoryTag,
+                &[1u16, 1, 0, 2, 1025, 0, 1, 1, 3072, 0, 1, 3067][..],
+            )
+            .unwrap();
+        image.encoder().write_tag(Tag::GdalNodata, "-1").unwrap();
+        image.write_data(&[10.0, 11.0, -1.0, 13.0]).unwrap();
+
+        let (hmap, crs) = read_geotiff(data.get_ref()).unwrap();
+        assert_eq!(crs.and_then(|c| c.epsg), Some(3067));
+        assert_eq!(
+            (hmap.xoffset, hmap.yoffset, hmap.scale),
+            (500.25, 699.25, 0.5)
+        );
+        assert_eq!(hmap.grid[(0, 1)], 10.0);
+        assert_eq!(hmap.grid[(1, 1)], 11.0);
+        assert!(hmap.grid[(0, 0)].is_nan());
+        assert_eq!(hmap.grid[(1, 0)], 13.0);
+    }
+
+    #[test]
+    fn test_export_roundtrip() {
+        let fs = MemoryFileSystem::new();
+        let mut grid = Vec2D::new(3, 2, 0.0);
+        for (x, y, v) in grid.iter_mut() {
+            *v = 100.0 + x as f64 + 10.0 * y as f64;
+        }
+        grid[(1, 1)] = f64::NAN;
+        let hmap = HeightMap {
+            xoffset: 1000.0,
+            yoffset: 2000.0,
+            scale: 2.0,
+            grid,
+            distance: None,
+        };
+        let crs = Crs::parse("EPSG:3067").unwrap();
+
+        for output in ["out.tif", "out.asc"] {
+            write_heightmap(&fs, &hmap, Path::new(output), false, Some(&crs)).unwrap();
+            let (read, read_crs) = read_raster(&fs, Path::new(output)).unwrap();
+            assert_eq!(read_crs.and_then(|c| c.epsg), Some(3067));
+            assert_eq!(
+                (read.xoffset, read.yoffset, read.scale),
+                (1000.0, 2000.0, 2.0)
+            );
+            assert_eq!(read.grid[(2, 0)], 102.0);
+            assert_eq!(read.grid[(0, 1)], 110.0);
+            assert!(read.grid[(1, 1)].is_nan());
+        }
+        assert!(write_heightmap(&fs, &hmap, Path::new("shade.asc"), true, None).is_err());
+        assert!(!fs.exists("shade.asc"));
+    }
+
+    #[test]
+    fn test_hillshade() {
+        // a flat surface is lit at the altitude of the light, a slope facing the light brighter
+        let mut grid = Vec2D::new(4, 4, 0.0);
+        for (x, _, v) in grid.iter_mut() {
+            *v = x as f64;
+        }
+        let flat = HeightMap {
+            xoffset: 0.0,
+            yoffset: 0.0,
+            scale: 1.0,
+            grid: Vec2D::new(4, 4, 5.0),
+            distance: None,
+        };
+        let west = HeightMap {
+            grid,
+            ..flat.clone()
+        };
+        assert_eq!(hillshade(&flat).get_pixel(1, 1)[0], 180);
+        assert!(hillshade(&west).get_pixel(1, 1)[0] > 180);
+    }
+}
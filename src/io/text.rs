@@ -0,0 +1,416 @@
+use log::info;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use super::fs::FileSystem;
+use super::xyz::{XyzFields, XyzRecord};
+use crate::config::Config;
+use crate::error::{Result, ResultExt};
+use crate::util::read_lines_no_alloc;
+
+/// Number of data lines looked at to detect the layout of a text point file.
+const SAMPLE_LINES: usize = 100;
+
+/// Delimiters that are tried, in order, before falling back to whitespace.
+const DELIMITERS: [char; 4] = [',', ';', '\t', '|'];
+
+/// The meaning of a column of a text point file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextColumn {
+    X,
+    Y,
+    Z,
+    Classification,
+    NumberOfReturns,
+    ReturnNumber,
+    Intensity,
+    /// A column that is not used.
+    Skip,
+}
+
+impl TextColumn {
+    /// Parses a column name of a header line or of the `xyzcolumns` config key.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().trim_matches('"');
+        // before the spaces and dashes of the names are normalized to underscores
+        if matches!(name, "skip" | "_" | "-" | "") {
+            return Some(Self::Skip);
+        }
+        let name = name.to_lowercase().replace([' ', '-'], "_");
+        Some(match name.as_str() {
+            "x" | "easting" | "east" | "e" | "lon" | "longitude" => Self::X,
+            "y" | "northing" | "north" | "n" | "lat" | "latitude" => Self::Y,
+            "z" | "elevation" | "height" | "h" | "alt" | "altitude" => Self::Z,
+            "class" | "classification" | "c" => Self::Classification,
+            "number_of_returns" | "numberofreturns" | "num_returns" | "returns" | "nr" => {
+                Self::NumberOfReturns
+            }
+            "return_number" | "returnnumber" | "return" | "rn" => Self::ReturnNumber,
+            "intensity" | "i" => Self::Intensity,
+            "skip" => Self::Skip,
+            _ => return None,
+        })
+    }
+}
+
+/// The delimiter and columns of a text point file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextLayout {
+    /// The delimiter between the columns, `None` for any whitespace.
+    pub delimiter: Option<char>,
+    pub columns: Vec<TextColumn>,
+    /// Line number of the header line, if the file has one.
+    pub header_line: Option<usize>,
+}
+
+impl TextLayout {
+    /// Detects the layout of a text point file. The given delimiter and columns (from the
+    /// `xyzdelimiter` and `xyzcolumns` config keys) are used as they are, with a space delimiter
+    /// standing for any whitespace, the rest is detected from the first lines of the file.
+    ///
+    /// Column names are taken from a header line if there is one. Otherwise the columns are
+    /// x, y and z followed by classification, number of returns and return number as long as
+    /// their values are small integers.
+    pub fn detect(
+        fs: &impl FileSystem,
+        path: &Path,
+        delimiter: Option<char>,
+        columns: &[TextColumn],
+    ) -> Result<Self> {
+        let mut sample = Vec::new();
+        let mut line_number = 0;
+        let mut reader = BufReader::new(fs.open(path).with_file(path)?);
+        let mut line = String::new();
+        while sample.len() < SAMPLE_LINES && reader.read_line(&mut line).with_file(path)? > 0 {
+            line_number += 1;
+            if !is_comment(&line) {
+                sample.push((line_number, line.trim_end().to_string()));
+            }
+            line.clear();
+        }
+        Self::detect_lines(&sample, delimiter, columns)
+            .map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    fn detect_lines(
+        sample: &[(usize, String)],
+        delimiter: Option<char>,
+        columns: &[TextColumn],
+    ) -> Result<Self, String> {
+        let Some((first_line, first)) = sample.first() else {
+            return Err("no points".to_string());
+        };
+
+        let delimiter = match delimiter {
+            Some(' ') => None,
+            Some(d) => Some(d),
+            None => DELIMITERS.into_iter().find(|&d| {
+                let counts = sample.iter().map(|(_, l)| l.split(d).count());
+                counts.clone().all(|c| c >= 3) && counts.clone().min() == counts.max()
+            }),
+        };
+
+        let header = split(first, delimiter)
+            .iter()
+            .any(|f| f.parse::<f64>().is_err());
+        let data = &sample[if header { 1 } else { 0 }..];
+
+        let columns = if !columns.is_empty() {
+            columns.to_vec()
+        } else if header {
+            split(first, delimiter)
+                .iter()
+                .map(|name| TextColumn::from_name(name).unwrap_or(TextColumn::Skip))
+                .collect()
+        } else {
+            let width = data
+                .iter()
+                .map(|(_, l)| split(l, delimiter).len())
+                .min()
+                .unwrap_or(0);
+            let is_small_integer = |i: usize| {
+                data.iter().all(|(_, l)| {
+                    split(l, delimiter)[i]
+                        .parse::<f64>()
+                        .is_ok_and(|v| v.fract() == 0.0 && (0.0..=255.0).contains(&v))
+                })
+            };
+            let mut columns = vec![TextColumn::X, TextColumn::Y, TextColumn::Z];
+            for (i, column) in [
+                TextColumn::Classification,
+                TextColumn::NumberOfReturns,
+                TextColumn::ReturnNumber,
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                if 3 + i >= width || !is_small_integer(3 + i) {
+                    break;
+                }
+                columns.push(column);
+            }
+            columns
+        };
+
+        for required in [TextColumn::X, TextColumn::Y, TextColumn::Z] {
+            if !columns.contains(&required) {
+                return Err(format!("no {:?} column found", required));
+            }
+        }
+
+        Ok(Self {
+            delimiter,
+            columns,
+            header_line: header.then_some(*first_line),
+        })
+    }
+
+    /// Returns the optional fields of the points read with this layout.
+    pub fn fields(&self) -> XyzFields {
+        if self.columns.contains(&TextColumn::Intensity) {
+            XyzFields::INTENSITY
+        } else {
+            XyzFields::NONE
+        }
+    }
+
+    /// Parses one data line. Missing classification and return numbers default to an
+    /// unclassified single return, and the number of returns is raised to the return number.
+    fn parse(&self, line: &str) -> Result<XyzRecord, String> {
+        let fields = split(line, self.delimiter);
+        let mut record = XyzRecord {
+            classification: 1,
+            number_of_returns: 1,
+            return_number: 1,
+            ..Default::default()
+        };
+        for (i, &column) in self.columns.iter().enumerate() {
+            if column == TextColumn::Skip {
+                continue;
+            }
+            let Some(value) = fields.get(i) else {
+                if matches!(column, TextColumn::X | TextColumn::Y | TextColumn::Z) {
+                    return Err(format!(
+                        "expected at least {} columns, found {}",
+                        i + 1,
+                        fields.len()
+                    ));
+                }
+                continue;
+            };
+            let v = value
+                .parse::<f64>()
+                .map_err(|_| format!("invalid value {:?} for {:?}", value, column))?;
+            let small = || {
+                if v.fract() == 0.0 && (0.0..=255.0).contains(&v) {
+                    Ok(v as u8)
+                } else {
+                    Err(format!("invalid value {:?} for {:?}", value, column))
+                }
+            };
+            match column {
+                TextColumn::X => record.x = v,
+                TextColumn::Y => record.y = v,
+                TextColumn::Z => record.z = v,
+                TextColumn::Classification => record.classification = small()?,
+                TextColumn::NumberOfReturns => record.number_of_returns = small()?,
+                TextColumn::ReturnNumber => record.return_number = small()?,
+                TextColumn::Intensity => record.intensity = v.clamp(0.0, u16::MAX as f64) as u16,
+                TextColumn::Skip => {}
+            }
+        }
+        record.return_number = record.return_number.max(1);
+        record.number_of_returns = record.number_of_returns.max(record.return_number);
+        Ok(record)
+    }
+}
+
+/// Reads the points of a text point file with the given layout. Empty lines and lines starting
+/// with `#` or `//` are skipped, any other line that can not be parsed is an error that names
+/// the line.
+pub fn read_text_points(
+    fs: &impl FileSystem,
+    path: &Path,
+    layout: &TextLayout,
+    mut callback: impl FnMut(XyzRecord),
+) -> Result<()> {
+    let mut error = None;
+    let mut line_number = 0;
+    read_lines_no_alloc(fs, path, |line| {
+        line_number += 1;
+        if error.is_some() || is_comment(line) || layout.header_line == Some(line_number) {
+            return;
+        }
+        match layout.parse(line) {
+            Ok(record) => callback(record),
+            Err(e) => {
+                error = Some(format!(
+                    "{} line {}: {}: {}",
+                    path.display(),
+                    line_number,
+                    e,
+                    line
+                ))
+            }
+        }
+    })?;
+    match error {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// Rewrites a text point file of any supported layout as the space separated
+/// `x y z classification number_of_returns return_number` format.
+pub fn xyzfixer(fs: &impl FileSystem, config: &Config, input: &Path, output: &Path) -> Result<()> {
+    let layout = TextLayout::detect(fs, input, config.xyz_delimiter, &config.xyz_columns)?;
+    info!(
+        "Fixing {} with columns {:?} separated by {}",
+        input.display(),
+        layout.columns,
+        layout
+            .delimiter
+            .map_or("whitespace".to_string(), |d| format!("{:?}", d))
+    );
+
+    let mut f = BufWriter::new(fs.create(output)?);
+    let mut count: u64 = 0;
+    let mut result = Ok(());
+    read_text_points(fs, input, &layout, |r| {
+        if result.is_ok() {
+            result = writeln!(
+                f,
+                "{} {} {} {} {} {}",
+                r.x, r.y, r.z, r.classification, r.number_of_returns, r.return_number
+            );
+            count += 1;
+        }
+    })?;
+    result?;
+    f.flush()?;
+
+    info!("Wrote {} points to {}", count, output.display());
+    Ok(())
+}
+
+fn is_comment(line: &str) -> bool {
+    let line = line.trim_start();
+    line.is_empty() || line.starts_with('#') || line.starts_with("//")
+}
+
+fn split(line: &str, delimiter: Option<char>) -> Vec<&str> {
+    match delimiter {
+        Some(d) => line.split(d).map(|f| f.trim().trim_matches('"')).collect(),
+        None => line.split_whitespace().collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(data: &str) -> Vec<(usize, String)> {
+        data.lines()
+            .enumerate()
+            .filter(|(_, l)| !is_comment(l))
+            .map(|(i, l)| (i + 1, l.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_header_and_delimiter() {
+        let sample = lines("# exported\nE;N;Intensity;Z\n1.5;2.5;100;3\n4;5;200;6\n");
+        let layout = TextLayout::detect_lines(&sample, None, &[]).unwrap();
+        assert_eq!(layout.delimiter, Some(';'));
+        assert_eq!(layout.header_line, Some(2));
+        assert_eq!(
+            layout.columns,
+            vec![
+                TextColumn::X,
+                TextColumn::Y,
+                TextColumn::Intensity,
+                TextColumn::Z
+            ]
+        );
+
+        let r = layout.parse("1.5;2.5;100;3").unwrap();
+        assert_eq!((r.x, r.y, r.z, r.intensity), (1.5, 2.5, 3.0, 100));
+        assert_eq!(
+            (r.classification, r.number_of_returns, r.return_number),
+            (1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn test_detect_without_header() {
+        let sample = lines("1.0\t2.0\t3.0\t2\t1\t1\n4.0\t5.0\t6.0\t1\t2\t2\n");
+        let layout = TextLayout::detect_lines(&sample, None, &[]).unwrap();
+        assert_eq!(layout.delimiter, Some('\t'));
+        assert_eq!(layout.columns.len(), 6);
+
+        // an intensity column is not mistaken for the classification
+        let sample = lines("1.0 2.0 3.0 1234.5\n4.0 5.0 6.0 99\n");
+        let layout = TextLayout::detect_lines(&sample, None, &[]).unwrap();
+        assert_eq!(layout.delimiter, None);
+        assert_eq!(
+            layout.columns,
+            vec![TextColumn::X, TextColumn::Y, TextColumn::Z]
+        );
+    }
+
+    #[test]
+    fn test_forced_whitespace() {
+        // a space stands for any whitespace, also tabs and repeated spaces
+        let sample = lines("1\t2  3\n4 5\t6\n");
+        let layout = TextLayout::detect_lines(&sample, Some(' '), &[]).unwrap();
+        assert_eq!(layout.delimiter, None);
+        assert_eq!(layout.parse("4 5\t6").unwrap().z, 6.0);
+    }
+
+    #[test]
+    fn test_column_names() {
+        assert_eq!(TextColumn::from_name("-"), Some(TextColumn::Skip));
+        assert_eq!(TextColumn::from_name("_"), Some(TextColumn::Skip));
+        assert_eq!(TextColumn::from_name(" \"skip\" "), Some(TextColumn::Skip));
+        assert_eq!(
+            TextColumn::from_name("Return-Number"),
+            Some(TextColumn::ReturnNumber)
+        );
+        assert_eq!(
+            TextColumn::from_name("number of returns"),
+            Some(TextColumn::NumberOfReturns)
+        );
+        assert_eq!(TextColumn::from_name("--"), None);
+    }
+
+    #[test]
+    fn test_detect_reads_sample_only() {
+        let fs = crate::io::fs::memory::MemoryFileSystem::new();
+        let mut data = "1 2 3\n".repeat(SAMPLE_LINES).into_bytes();
+        // not valid UTF-8, so reading this far would fail
+        data.extend([0xff, 0xfe, b'\n']);
+        fs.create("test.xyz").unwrap().write_all(&data).unwrap();
+
+        let layout = TextLayout::detect(&fs, Path::new("test.xyz"), None, &[]).unwrap();
+        assert_eq!(layout.columns.len(), 3);
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let fs = crate::io::fs::memory::MemoryFileSystem::new();
+        fs.create("test.xyz")
+            .unwrap()
+            .write_all(b"1 2 3 2 1 1\n\n4 5 6 2 1 3\n7 8\n")
+            .unwrap();
+        let layout =
+            TextLayout::detect_lines(&[(1, "1 2 3 2 1 1".to_string())], None, &[]).unwrap();
+
+        let mut records = Vec::new();
+        let err =
+            read_text_points(&fs, Path::new("test.xyz"), &layout, |r| records.push(r)).unwrap_err();
+        assert!(err.to_string().contains("line 4"), "{}", err);
+        assert_eq!(records.len(), 2);
+        // the number of returns is fixed to be at least the return number
+        assert_eq!(records[1].number_of_returns, 3);
+    }
+}
@@ -0,0 +1,486 @@
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use las::Reader;
+use log::{debug, info, warn};
+
+use super::bytes::FromToBytes;
+use super::chunks::{read_chunk_bounds, ChunkBounds};
+use super::crs::Crs;
+use super::fs::FileSystem;
+use crate::error::{Result, ResultExt};
+
+/// Name of the tile index file, stored next to the input files.
+pub const TILE_INDEX_FILE: &str = "pullauta.tileindex";
+
+/// The magic number that identifies a valid tile index file.
+const TILE_INDEX_MAGIC: &[u8] = b"PTIX";
+/// Version of the tile index format, bumped whenever the layout changes.
+const TILE_INDEX_VERSION: u32 = 5;
+
+/// An axis aligned bounding box in world coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl Bounds {
+    /// Grow the bounds by `buffer` in every direction.
+    pub fn expand(&self, buffer: f64) -> Bounds {
+        Bounds {
+            min_x: self.min_x - buffer,
+            min_y: self.min_y - buffer,
+            max_x: self.max_x + buffer,
+            max_y: self.max_y + buffer,
+        }
+    }
+
+    /// Check if two bounds overlap.
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        self.max_x > other.min_x
+            && self.min_x < other.max_x
+            && self.max_y > other.min_y
+            && self.min_y < other.max_y
+    }
+
+    /// Check if a point lies strictly inside the bounds.
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x > self.min_x && x < self.max_x && y > self.min_y && y < self.max_y
+    }
+}
+
+impl FromToBytes for Bounds {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        Ok(Bounds {
+            min_x: f64::from_bytes(reader)?,
+            min_y: f64::from_bytes(reader)?,
+            max_x: f64::from_bytes(reader)?,
+            max_y: f64::from_bytes(reader)?,
+        })
+    }
+
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.min_x.to_bytes(writer)?;
+        self.min_y.to_bytes(writer)?;
+        self.max_x.to_bytes(writer)?;
+        self.max_y.to_bytes(writer)
+    }
+}
+
+/// Information about a single input file, as read from its header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileIndexEntry {
+    /// File name, relative to the indexed folder.
+    pub filename: String,
+    /// Size of the file when it was indexed, used to detect changed files.
+    pub file_size: u64,
+    /// Modification time of the file when it was indexed, in nanoseconds since the Unix epoch.
+    pub modified: u64,
+    pub bounds: Bounds,
+    pub point_count: u64,
+    /// The LAS point format number, which tells the optional fields of the points.
+    pub point_format: u8,
+    pub crs: Option<Crs>,
+    /// Bounds of the individually decompressible chunks of the file, used to only read the
+    /// needed parts of neighbouring tiles.
+    pub chunks: Vec<ChunkBounds>,
+}
+
+impl TileIndexEntry {
+    /// Read the header and the chunk bounds of a LAS/LAZ file and create an entry for it.
+    pub fn from_file(fs: &impl FileSystem, path: &Path) -> Result<Self> {
+        let filename = path
+            .file_name()
+            .ok_or_else(|| format!("No file name for {}", path.display()))?
+            .to_string_lossy()
+            .to_string();
+        let file_size = fs.file_size(path).with_file(path)?;
+        let modified = modified_time(fs, path)?;
+        let reader = Reader::new(BufReader::new(fs.open(path)?))?;
+        let header = reader.header();
+        let bounds = header.bounds();
+        let crs = Crs::from_header(header);
+        let point_count = header.number_of_points();
+        let point_format = header.point_format().to_u8()?;
+
+        Ok(TileIndexEntry {
+            filename,
+            file_size,
+            modified,
+            bounds: Bounds {
+                min_x: bounds.min.x,
+                min_y: bounds.min.y,
+                max_x: bounds.max.x,
+                max_y: bounds.max.y,
+            },
+            point_count,
+            point_format,
+            crs,
+            chunks: read_chunk_bounds(fs, path)?,
+        })
+    }
+}
+
+impl FromToBytes for TileIndexEntry {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        Ok(TileIndexEntry {
+            filename: String::from_bytes(reader)?,
+            file_size: u64::from_bytes(reader)?,
+            modified: u64::from_bytes(reader)?,
+            bounds: Bounds::from_bytes(reader)?,
+            point_count: u64::from_bytes(reader)?,
+            point_format: u8::from_bytes(reader)?,
+            crs: Option::<Crs>::from_bytes(reader)?,
+            chunks: Vec::from_bytes(reader)?,
+        })
+    }
+
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.filename.to_bytes(writer)?;
+        self.file_size.to_bytes(writer)?;
+        self.modified.to_bytes(writer)?;
+        self.bounds.to_bytes(writer)?;
+        self.point_count.to_bytes(writer)?;
+        self.point_format.to_bytes(writer)?;
+        self.crs.to_bytes(writer)?;
+        self.chunks.to_bytes(writer)
+    }
+}
+
+/// The modification time of a file in nanoseconds since the Unix epoch.
+fn modified_time(fs: &impl FileSystem, path: &Path) -> Result<u64> {
+    let modified = fs.modified(path).with_file(path)?;
+    Ok(modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64))
+}
+
+/// Spatial index over the LAS/LAZ files of a folder. Built once from the file headers and
+/// persisted next to the input files, so batch processing does not need to re-read every header
+/// for every tile.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TileIndex {
+    pub entries: Vec<TileIndexEntry>,
+}
+
+impl TileIndex {
+    /// Load the index of `folder` and bring it up to date with the files currently in it. Headers
+    /// are only read for new or changed files, and the index is only written back if anything
+    /// changed.
+    pub fn update(fs: &impl FileSystem, folder: impl AsRef<Path>) -> Result<Self> {
+        let folder = folder.as_ref();
+        let index_file = folder.join(TILE_INDEX_FILE);
+
+        let old = if fs.exists(&index_file) {
+            match TileIndex::from_file(fs, &index_file) {
+                Ok(index) => index,
+                Err(e) => {
+                    info!("Rebuilding unreadable tile index {:?}: {}", index_file, e);
+                    TileIndex::default()
+                }
+            }
+        } else {
+            TileIndex::default()
+        };
+
+        let mut files = fs
+            .list(folder)?
+            .into_iter()
+            .filter(|p| {
+                p.extension()
+                    .is_some_and(|e| e == "laz" || e == "las" || e == "LAZ" || e == "LAS")
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+
+        let mut changed = false;
+        let mut entries = Vec::with_capacity(files.len());
+        for path in files.iter() {
+            let filename = path
+                .file_name()
+                .ok_or_else(|| format!("No file name for {}", path.display()))?
+                .to_string_lossy();
+            let file_size = fs.file_size(path).with_file(path)?;
+            let modified = modified_time(fs, path)?;
+            match old.get(&filename) {
+                // a file exported again may well have the same size
+                Some(entry) if entry.file_size == file_size && entry.modified == modified => {
+                    entries.push(entry.clone())
+                }
+                _ => {
+                    debug!("Indexing {}", path.display());
+                    // an unreadable file is left out, it fails when its tile is processed
+                    match TileIndexEntry::from_file(fs, path) {
+                        Ok(entry) => {
+                            entries.push(entry);
+                            changed = true;
+                        }
+                        Err(e) => warn!("Could not index {}: {}", path.display(), e),
+                    }
+                }
+            }
+        }
+
+        changed |= entries.len() != old.entries.len();
+        let index = TileIndex { entries };
+        if changed {
+            info!(
+                "Writing tile index of {} files to {:?}",
+                index.entries.len(),
+                index_file
+            );
+            index.to_file(fs, &index_file)?;
+        }
+        Ok(index)
+    }
+
+    /// Find the entry of a file by its name.
+    pub fn get(&self, filename: &str) -> Option<&TileIndexEntry> {
+        self.entries.iter().find(|e| e.filename == filename)
+    }
+
+    /// Iterate over all the files whose bounds intersect the given bounds.
+    pub fn intersecting<'a>(
+        &'a self,
+        bounds: &'a Bounds,
+    ) -> impl Iterator<Item = &'a TileIndexEntry> + 'a {
+        self.entries
+            .iter()
+            .filter(move |e| e.bounds.intersects(bounds))
+    }
+
+    /// Groups the files by their coordinate reference system, in order of first appearance.
+    /// Returns a single group when all the files agree.
+    pub fn crs_groups(&self) -> Vec<(Option<&Crs>, Vec<&str>)> {
+        let mut groups: Vec<(Option<&Crs>, Vec<&str>)> = Vec::new();
+        for entry in self.entries.iter() {
+            let crs = entry.crs.as_ref();
+            match groups.iter_mut().find(|(c, _)| *c == crs) {
+                Some((_, files)) => files.push(&entry.filename),
+                None => groups.push((crs, vec![&entry.filename])),
+            }
+        }
+        groups
+    }
+
+    /// Logs a warning if the files do not all have the same coordinate reference system, as the
+    /// tiles are then not in the same coordinates.
+    pub fn warn_on_crs_mismatch(&self) {
+        let groups = self.crs_groups();
+        if groups.len() < 2 {
+            return;
+        }
+        warn!("The input files have different coordinate reference systems:");
+        for (crs, files) in groups {
+            warn!(
+                "  {}: {}",
+                crs.map_or("unknown".to_string(), |c| c.to_string()),
+                files.join(", ")
+            );
+        }
+    }
+
+    /// Helper for easily reading a TileIndex from a file
+    pub fn from_file<P: AsRef<Path>>(fs: &impl FileSystem, path: P) -> std::io::Result<Self> {
+        let mut reader = BufReader::new(fs.open(path)?);
+        TileIndex::from_bytes(&mut reader)
+    }
+
+    /// Helper for easily writing a TileIndex to a file. The index is written to a temporary file
+    /// that replaces the old one, so other processes never read a partly written index.
+    pub fn to_file<P: AsRef<Path>>(&self, fs: &impl FileSystem, path: P) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".{}.tmp", std::process::id()));
+        let tmp = PathBuf::from(tmp);
+        let written = (|| {
+            let mut writer = BufWriter::new(fs.create(&tmp)?);
+            self.to_bytes(&mut writer)?;
+            writer.flush()?;
+            drop(writer);
+            fs.rename(&tmp, path)
+        })();
+        if written.is_err() && fs.exists(&tmp) {
+            let _ = fs.remove_file(&tmp);
+        }
+        written
+    }
+}
+
+impl FromToBytes for TileIndex {
+    fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut buff = [0; TILE_INDEX_MAGIC.len()];
+        reader.read_exact(&mut buff)?;
+        if buff != TILE_INDEX_MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid magic number",
+            ));
+        }
+        let version = u32::from_bytes(reader)?;
+        if version != TILE_INDEX_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unsupported tile index version {}", version),
+            ));
+        }
+
+        Ok(TileIndex {
+            entries: Vec::from_bytes(reader)?,
+        })
+    }
+
+    fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(TILE_INDEX_MAGIC)?;
+        TILE_INDEX_VERSION.to_bytes(writer)?;
+        self.entries.to_bytes(writer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(filename: &str, min_x: f64, min_y: f64) -> TileIndexEntry {
+        TileIndexEntry {
+            filename: filename.to_string(),
+            file_size: 1234,
+            modified: 0,
+            bounds: Bounds {
+                min_x,
+                min_y,
+                max_x: min_x + 1000.0,
+                max_y: min_y + 1000.0,
+            },
+            point_count: 42,
+            point_format: 1,
+            crs: Some(Crs {
+                epsg: Some(3067),
+                wkt: None,
+                geographic: false,
+            }),
+            chunks: vec![ChunkBounds {
+                first_point: 0,
+                point_count: 42,
+                bounds: Bounds {
+                    min_x,
+                    min_y,
+                    max_x: min_x + 1000.0,
+                    max_y: min_y + 1000.0,
+                },
+            }],
+        }
+    }
+
+    #[test]
+    fn test_bytes() {
+        let index = TileIndex {
+            entries: vec![entry("a.laz", 0.0, 0.0), entry("b.laz", 1000.0, 0.0)],
+        };
+
+        let mut bytes = Vec::new();
+        index.to_bytes(&mut bytes).unwrap();
+        let index2 = TileIndex::from_bytes(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(index, index2);
+    }
+
+    #[test]
+    fn test_intersecting() {
+        let index = TileIndex {
+            entries: vec![
+                entry("a.laz", 0.0, 0.0),
+                entry("b.laz", 1000.0, 0.0),
+                entry("c.laz", 3000.0, 0.0),
+            ],
+        };
+
+        let bounds = index.get("a.laz").unwrap().bounds.expand(127.0);
+        let names = index
+            .intersecting(&bounds)
+            .map(|e| e.filename.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a.laz", "b.laz"]);
+    }
+
+    #[test]
+    fn test_crs_groups() {
+        let mut index = TileIndex {
+            entries: vec![
+                entry("a.laz", 0.0, 0.0),
+                entry("b.laz", 1000.0, 0.0),
+                entry("c.laz", 3000.0, 0.0),
+            ],
+        };
+        assert_eq!(index.crs_groups().len(), 1);
+
+        index.entries[1].crs = None;
+        let groups = index.crs_groups();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].1, vec!["a.laz", "c.laz"]);
+        assert_eq!(groups[1], (None, vec!["b.laz"]));
+    }
+
+    #[test]
+    fn test_update_skips_unreadable() {
+        let fs = crate::io::fs::memory::MemoryFileSystem::new();
+        fs.create_dir_all("in").unwrap();
+        fs.create("in/broken.laz")
+            .unwrap()
+            .write_all(b"not a laz file")
+            .unwrap();
+
+        let index = TileIndex::update(&fs, "in").unwrap();
+        assert!(index.entries.is_empty());
+    }
+
+    #[test]
+    fn test_update_reindexes_modified() {
+        let fs = crate::io::fs::memory::MemoryFileSystem::new();
+        fs.create_dir_all("in").unwrap();
+        crate::io::write_test_las(&fs, "in/a.las", &[(0.0, 0.0, 0.0), (1.0, 1.0, 1.0)], false);
+        let index = TileIndex::update(&fs, "in").unwrap();
+        assert_eq!(index.entries[0].bounds.max_x, 1.0);
+
+        // the same number of points gives a file of the same size
+        crate::io::write_test_las(&fs, "in/a.las", &[(0.0, 0.0, 0.0), (5.0, 5.0, 1.0)], false);
+        let index = TileIndex::update(&fs, "in").unwrap();
+        assert_eq!(index.entries[0].bounds.max_x, 5.0);
+    }
+
+    #[test]
+    fn test_to_file_replaces() {
+        // the memory file system swaps in whole files, so this needs the local one
+        let fs = crate::io::fs::local::LocalFileSystem;
+        let dir = std::env::temp_dir().join(format!("pullauta-tileindex-{}", std::process::id()));
+        fs.create_dir_all(&dir).unwrap();
+        let path = dir.join("index.bin");
+        let index = TileIndex {
+            entries: (0..1000)
+                .map(|i| entry(&format!("{}.laz", i), i as f64 * 1000.0, 0.0))
+                .collect(),
+        };
+        index.to_file(&fs, &path).unwrap();
+
+        // the index being rewritten is never seen half written by another process
+        let done = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            let reader = scope.spawn(|| {
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    assert_eq!(TileIndex::from_file(&fs, &path).unwrap(), index);
+                }
+            });
+            for _ in 0..50 {
+                index.to_file(&fs, &path).unwrap();
+            }
+            done.store(true, std::sync::atomic::Ordering::Relaxed);
+            reader.join().unwrap();
+        });
+        assert_eq!(fs.list(&dir).unwrap(), vec![path]);
+        fs.remove_dir_all(&dir).unwrap();
+    }
+}
@@ -6,11 +6,66 @@ use std::{
 
 use log::debug;
 
-/// The magic number that identifies a valid XYZ binary file.
-const XYZ_MAGIC: &[u8] = b"XYZB";
+/// The magic number that identifies a versioned XYZ binary file.
+const XYZ_MAGIC: &[u8] = b"XYZV";
+/// The magic number of the original, unversioned XYZ binary files that only contain the base
+/// fields. These can still be read.
+const XYZ_MAGIC_LEGACY: &[u8] = b"XYZB";
+/// Version of the XYZ binary format, bumped whenever the layout changes.
+const XYZ_VERSION: u32 = 1;
+/// The magic number of the compressed variant of the versioned format, where the records are
+/// stored in chunks of delta encoded integers.
+const XYZ_MAGIC_COMPRESSED: &[u8] = b"XYZC";
+/// Size of the header before the number of records: magic, version and fields.
+const XYZ_HEADER_LEN: u64 = (XYZ_MAGIC.len() + 4 + 4) as u64;
+
+/// Number of records in each chunk of a compressed file. The delta encoding restarts at the
+/// start of every chunk.
+const COMPRESSED_CHUNK_SIZE: usize = 65_536;
+/// Default resolution of the coordinates in compressed files, in meters.
+pub const COMPRESSED_RESOLUTION: f64 = 0.001;
+
+/// The optional fields stored for every record of an XYZ binary file, in addition to the base
+/// fields (coordinates, classification and return numbers).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct XyzFields(u32);
+
+impl XyzFields {
+    pub const NONE: XyzFields = XyzFields(0);
+    pub const INTENSITY: XyzFields = XyzFields(1);
+    pub const RGB: XyzFields = XyzFields(1 << 1);
+    pub const GPS_TIME: XyzFields = XyzFields(1 << 2);
+    pub const SCAN_ANGLE: XyzFields = XyzFields(1 << 3);
+    pub const POINT_SOURCE_ID: XyzFields = XyzFields(1 << 4);
+    pub const ALL: XyzFields = XyzFields((1 << 5) - 1);
+
+    /// The fields that are available in LAS files of the given point format.
+    pub fn from_las_format(format: &las::point::Format) -> Self {
+        let mut fields = Self::INTENSITY | Self::SCAN_ANGLE | Self::POINT_SOURCE_ID;
+        if format.has_color {
+            fields = fields | Self::RGB;
+        }
+        if format.has_gps_time {
+            fields = fields | Self::GPS_TIME;
+        }
+        fields
+    }
+
+    pub fn contains(&self, other: XyzFields) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for XyzFields {
+    type Output = XyzFields;
+
+    fn bitor(self, rhs: XyzFields) -> XyzFields {
+        XyzFields(self.0 | rhs.0)
+    }
+}
 
 /// A single record of an observed laser data point needed by the algorithms.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct XyzRecord {
     pub x: f64,
     pub y: f64,
@@ -18,9 +73,93 @@ pub struct XyzRecord {
     pub classification: u8,
     pub number_of_returns: u8,
     pub return_number: u8,
+    /// Optional fields, zero if not present in the file (see [`XyzFields`]).
+    pub intensity: u16,
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub gps_time: f64,
+    pub scan_angle: f32,
+    pub point_source_id: u16,
+}
+
+impl XyzRecord {
+    /// Create a record from a LAS point, keeping all the fields it has.
+    pub fn from_las_point(pt: &las::Point) -> Self {
+        let color = pt.color.unwrap_or_default();
+        Self {
+            x: pt.x,
+            y: pt.y,
+            z: pt.z,
+            classification: u8::from(pt.classification),
+            number_of_returns: pt.number_of_returns,
+            return_number: pt.return_number,
+            intensity: pt.intensity,
+            red: color.red,
+            green: color.green,
+            blue: color.blue,
+            gps_time: pt.gps_time.unwrap_or_default(),
+            scan_angle: pt.scan_angle,
+            point_source_id: pt.point_source_id,
+        }
+    }
+
+    /// Read a record containing the base fields followed by the given optional fields.
+    pub fn from_bytes_with_fields<R: std::io::Read>(
+        reader: &mut R,
+        fields: XyzFields,
+    ) -> std::io::Result<Self> {
+        let mut record = Self::from_bytes(reader)?;
+        if fields.contains(XyzFields::INTENSITY) {
+            record.intensity = u16::from_bytes(reader)?;
+        }
+        if fields.contains(XyzFields::RGB) {
+            record.red = u16::from_bytes(reader)?;
+            record.green = u16::from_bytes(reader)?;
+            record.blue = u16::from_bytes(reader)?;
+        }
+        if fields.contains(XyzFields::GPS_TIME) {
+            record.gps_time = f64::from_bytes(reader)?;
+        }
+        if fields.contains(XyzFields::SCAN_ANGLE) {
+            record.scan_angle = f32::from_bytes(reader)?;
+        }
+        if fields.contains(XyzFields::POINT_SOURCE_ID) {
+            record.point_source_id = u16::from_bytes(reader)?;
+        }
+        Ok(record)
+    }
+
+    /// Write the base fields followed by the given optional fields.
+    pub fn to_bytes_with_fields<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        fields: XyzFields,
+    ) -> std::io::Result<()> {
+        self.to_bytes(writer)?;
+        if fields.contains(XyzFields::INTENSITY) {
+            self.intensity.to_bytes(writer)?;
+        }
+        if fields.contains(XyzFields::RGB) {
+            self.red.to_bytes(writer)?;
+            self.green.to_bytes(writer)?;
+            self.blue.to_bytes(writer)?;
+        }
+        if fields.contains(XyzFields::GPS_TIME) {
+            self.gps_time.to_bytes(writer)?;
+        }
+        if fields.contains(XyzFields::SCAN_ANGLE) {
+            self.scan_angle.to_bytes(writer)?;
+        }
+        if fields.contains(XyzFields::POINT_SOURCE_ID) {
+            self.point_source_id.to_bytes(writer)?;
+        }
+        Ok(())
+    }
 }
 
 impl FromToBytes for XyzRecord {
+    /// Reads only the base fields, the optional fields are left as zero.
     fn from_bytes<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
         let x = f64::from_bytes(reader)?;
         let y = f64::from_bytes(reader)?;
@@ -38,16 +177,18 @@ impl FromToBytes for XyzRecord {
             classification,
             number_of_returns,
             return_number,
+            ..Default::default()
         })
     }
 
+    /// Writes only the base fields.
     fn to_bytes<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
         // write the x, y, z coordinates
         self.x.to_bytes(writer)?;
         self.y.to_bytes(writer)?;
         self.z.to_bytes(writer)?;
 
-        // write the classification, number of returns, return number, and intensity
+        // write the classification, number of returns and return number
         writer.write_all(&[
             self.classification,
             self.number_of_returns,
@@ -56,54 +197,352 @@ impl FromToBytes for XyzRecord {
     }
 }
 
+/// Delta encodes records into a chunk of zigzag varints. Every field of a record is turned into
+/// an integer "column" (coordinates are quantized to the resolution, or use their bit pattern
+/// like the other floats if the resolution is 0), and only the difference to the previous record
+/// of the chunk is stored. Consecutive laser points are close to each other, so most values fit
+/// in one or two bytes.
+struct ChunkEncoder {
+    fields: XyzFields,
+    resolution: f64,
+    previous: Vec<i64>,
+    columns: Vec<i64>,
+    data: Vec<u8>,
+    n_records: u32,
+}
+
+impl ChunkEncoder {
+    fn new(fields: XyzFields, resolution: f64) -> Self {
+        Self {
+            fields,
+            resolution,
+            previous: Vec::new(),
+            columns: Vec::new(),
+            data: Vec::new(),
+            n_records: 0,
+        }
+    }
+
+    fn push(&mut self, record: &XyzRecord) {
+        self.columns.clear();
+        record_to_columns(record, self.fields, self.resolution, &mut self.columns);
+        if self.previous.is_empty() {
+            self.previous.resize(self.columns.len(), 0);
+        }
+        for (value, previous) in self.columns.iter().zip(self.previous.iter_mut()) {
+            write_varint(zigzag(value.wrapping_sub(*previous)), &mut self.data);
+            *previous = *value;
+        }
+        self.n_records += 1;
+    }
+
+    /// Write the chunk (record count, byte length and data) and reset the encoder.
+    fn flush<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        if self.n_records == 0 {
+            return Ok(());
+        }
+        self.n_records.to_bytes(writer)?;
+        (self.data.len() as u32).to_bytes(writer)?;
+        writer.write_all(&self.data)?;
+
+        self.previous.clear();
+        self.data.clear();
+        self.n_records = 0;
+        Ok(())
+    }
+}
+
+/// Decodes the chunks written by [`ChunkEncoder`].
+struct ChunkDecoder {
+    fields: XyzFields,
+    resolution: f64,
+    previous: Vec<i64>,
+    data: Vec<u8>,
+    position: usize,
+    remaining: u32,
+}
+
+impl ChunkDecoder {
+    fn new(fields: XyzFields, resolution: f64) -> Self {
+        Self {
+            fields,
+            resolution,
+            previous: Vec::new(),
+            data: Vec::new(),
+            position: 0,
+            remaining: 0,
+        }
+    }
+
+    fn next<R: Read>(&mut self, reader: &mut R) -> std::io::Result<XyzRecord> {
+        if self.remaining == 0 {
+            // read the whole next chunk at once
+            let count = u32::from_bytes(reader)?;
+            let len = u32::from_bytes(reader)? as usize;
+            // a varint takes at most 10 bytes
+            let max_len = count as usize * number_of_columns(self.fields) * 10;
+            if count == 0 || count as usize > COMPRESSED_CHUNK_SIZE || len > max_len {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid chunk of {} records in {} bytes", count, len),
+                ));
+            }
+            self.remaining = count;
+            self.data.resize(len, 0);
+            reader.read_exact(&mut self.data)?;
+            self.position = 0;
+            self.previous.clear();
+            self.previous.resize(number_of_columns(self.fields), 0);
+        }
+
+        for previous in self.previous.iter_mut() {
+            let delta = unzigzag(read_varint(&self.data, &mut self.position)?);
+            *previous = previous.wrapping_add(delta);
+        }
+        self.remaining -= 1;
+        Ok(record_from_columns(
+            &self.previous,
+            self.fields,
+            self.resolution,
+        ))
+    }
+}
+
+/// Number of integer columns used for a record with the given optional fields.
+fn number_of_columns(fields: XyzFields) -> usize {
+    let mut n = 6;
+    if fields.contains(XyzFields::INTENSITY) {
+        n += 1;
+    }
+    if fields.contains(XyzFields::RGB) {
+        n += 3;
+    }
+    if fields.contains(XyzFields::GPS_TIME) {
+        n += 1;
+    }
+    if fields.contains(XyzFields::SCAN_ANGLE) {
+        n += 1;
+    }
+    if fields.contains(XyzFields::POINT_SOURCE_ID) {
+        n += 1;
+    }
+    n
+}
+
+fn record_to_columns(record: &XyzRecord, fields: XyzFields, resolution: f64, out: &mut Vec<i64>) {
+    for value in [record.x, record.y, record.z] {
+        if resolution == 0.0 {
+            out.push(value.to_bits() as i64);
+        } else {
+            out.push((value / resolution).round() as i64);
+        }
+    }
+    out.push(i64::from(record.classification));
+    out.push(i64::from(record.number_of_returns));
+    out.push(i64::from(record.return_number));
+    if fields.contains(XyzFields::INTENSITY) {
+        out.push(i64::from(record.intensity));
+    }
+    if fields.contains(XyzFields::RGB) {
+        out.push(i64::from(record.red));
+        out.push(i64::from(record.green));
+        out.push(i64::from(record.blue));
+    }
+    if fields.contains(XyzFields::GPS_TIME) {
+        out.push(record.gps_time.to_bits() as i64);
+    }
+    if fields.contains(XyzFields::SCAN_ANGLE) {
+        out.push(i64::from(record.scan_angle.to_bits()));
+    }
+    if fields.contains(XyzFields::POINT_SOURCE_ID) {
+        out.push(i64::from(record.point_source_id));
+    }
+}
+
+fn record_from_columns(columns: &[i64], fields: XyzFields, resolution: f64) -> XyzRecord {
+    let mut columns = columns.iter().copied();
+    let mut next = || columns.next().unwrap_or_default();
+    // dividing by the inverse gives the closest float to the decimal value, unlike multiplying,
+    // but only resolutions like 0.001 have an integer inverse
+    let inverse = (1.0 / resolution).round();
+    let exact_inverse = 1.0 / inverse == resolution;
+    let mut coordinate = || {
+        if resolution == 0.0 {
+            f64::from_bits(next() as u64)
+        } else if exact_inverse {
+            next() as f64 / inverse
+        } else {
+            next() as f64 * resolution
+        }
+    };
+    let (x, y, z) = (coordinate(), coordinate(), coordinate());
+    let mut record = XyzRecord {
+        x,
+        y,
+        z,
+        classification: next() as u8,
+        number_of_returns: next() as u8,
+        return_number: next() as u8,
+        ..Default::default()
+    };
+    if fields.contains(XyzFields::INTENSITY) {
+        record.intensity = next() as u16;
+    }
+    if fields.contains(XyzFields::RGB) {
+        record.red = next() as u16;
+        record.green = next() as u16;
+        record.blue = next() as u16;
+    }
+    if fields.contains(XyzFields::GPS_TIME) {
+        record.gps_time = f64::from_bits(next() as u64);
+    }
+    if fields.contains(XyzFields::SCAN_ANGLE) {
+        record.scan_angle = f32::from_bits(next() as u32);
+    }
+    if fields.contains(XyzFields::POINT_SOURCE_ID) {
+        record.point_source_id = next() as u16;
+    }
+    record
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> std::io::Result<u64> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*position).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "truncated chunk")
+        })?;
+        *position += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+        if shift >= 64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid varint",
+            ));
+        }
+    }
+}
+
 pub struct XyzInternalWriter<W: Write + Seek> {
     inner: Option<W>,
+    fields: XyzFields,
+    encoder: Option<ChunkEncoder>,
     records_written: u64,
     // for stats
     start: Option<Instant>,
 }
 
 impl<W: Write + Seek> XyzInternalWriter<W> {
+    /// Create a writer that only stores the base fields of the records.
     pub fn new(inner: W) -> Self {
+        Self::with_fields(inner, XyzFields::NONE)
+    }
+
+    /// Create a writer that stores the given optional fields in addition to the base fields.
+    pub fn with_fields(inner: W, fields: XyzFields) -> Self {
         Self {
             inner: Some(inner),
+            fields,
+            encoder: None,
             records_written: 0,
             start: None,
         }
     }
 
+    /// Create a writer for the compressed variant of the format. The coordinates are rounded to
+    /// `resolution` meters, usually [`COMPRESSED_RESOLUTION`] which is well below the precision
+    /// of the laser data, or stored exactly if it is 0.
+    pub fn compressed(inner: W, fields: XyzFields, resolution: f64) -> Self {
+        let mut writer = Self::with_fields(inner, fields);
+        writer.encoder = Some(ChunkEncoder::new(fields, resolution));
+        writer
+    }
+
+    /// Write the header, with a temporary number of records of all FF.
+    fn write_header(
+        inner: &mut W,
+        fields: XyzFields,
+        encoder: Option<&ChunkEncoder>,
+    ) -> std::io::Result<()> {
+        match encoder {
+            Some(encoder) => {
+                inner.write_all(XYZ_MAGIC_COMPRESSED)?;
+                XYZ_VERSION.to_bytes(inner)?;
+                fields.0.to_bytes(inner)?;
+                encoder.resolution.to_bytes(inner)?;
+            }
+            None => {
+                inner.write_all(XYZ_MAGIC)?;
+                XYZ_VERSION.to_bytes(inner)?;
+                fields.0.to_bytes(inner)?;
+            }
+        }
+        u64::MAX.to_bytes(inner)
+    }
+
     pub fn write_record(&mut self, record: &XyzRecord) -> std::io::Result<()> {
-        let inner = self.inner.as_mut().ok_or_else(|| {
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "writer has already been finished",
-            )
-        })?;
+        let inner = self
+            .inner
+            .as_mut()
+            .ok_or_else(|| std::io::Error::other("writer has already been finished"))?;
 
-        // write the header (format + length) on the first write
+        // write the header (format + version + fields + length) on the first write
         if self.records_written == 0 {
             self.start = Some(Instant::now());
-
-            inner.write_all(XYZ_MAGIC)?;
-            // Write the temporary number of records as all FF
-            u64::MAX.to_bytes(inner)?;
+            Self::write_header(inner, self.fields, self.encoder.as_ref())?;
         }
 
-        record.to_bytes(inner)?;
+        match self.encoder.as_mut() {
+            Some(encoder) => {
+                encoder.push(record);
+                if encoder.n_records as usize >= COMPRESSED_CHUNK_SIZE {
+                    encoder.flush(inner)?;
+                }
+            }
+            None => record.to_bytes_with_fields(inner, self.fields)?,
+        }
         self.records_written += 1;
         Ok(())
     }
 
     pub fn finish(&mut self) -> std::io::Result<W> {
-        let mut inner = self.inner.take().ok_or_else(|| {
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "writer has already been finished",
-            )
-        })?;
+        let mut inner = self
+            .inner
+            .take()
+            .ok_or_else(|| std::io::Error::other("writer has already been finished"))?;
+
+        // make sure there is a header even if no records were written
+        if self.records_written == 0 {
+            Self::write_header(&mut inner, self.fields, self.encoder.as_ref())?;
+        }
+        let mut count_offset = XYZ_HEADER_LEN;
+        if let Some(encoder) = self.encoder.as_mut() {
+            encoder.flush(&mut inner)?;
+            count_offset += 8;
+        }
 
-        // seek to the beginning of the file and write the number of records
-        inner.seek(std::io::SeekFrom::Start(XYZ_MAGIC.len() as u64))?;
+        // seek to the end of the header and write the number of records
+        inner.seek(std::io::SeekFrom::Start(count_offset))?;
         self.records_written.to_bytes(&mut inner)?;
 
         // log statistics about the written records
@@ -130,6 +569,8 @@ impl<W: Write + Seek> Drop for XyzInternalWriter<W> {
 
 pub struct XyzInternalReader<R: Read> {
     inner: R,
+    fields: XyzFields,
+    decoder: Option<ChunkDecoder>,
     n_records: u64,
     records_read: u64,
     // for stats
@@ -137,27 +578,60 @@ pub struct XyzInternalReader<R: Read> {
 }
 
 impl<R: Read> XyzInternalReader<R> {
+    /// Open a file in any of the internal formats: legacy, versioned or compressed.
     pub fn new(mut inner: R) -> std::io::Result<Self> {
         // read and check the magic number
         let mut buff = [0; XYZ_MAGIC.len()];
         inner.read_exact(&mut buff)?;
-        if buff != XYZ_MAGIC {
+        let (fields, decoder) = if buff == XYZ_MAGIC || buff == XYZ_MAGIC_COMPRESSED {
+            let version = u32::from_bytes(&mut inner)?;
+            if version != XYZ_VERSION {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("unsupported xyz format version {}", version),
+                ));
+            }
+            let fields = u32::from_bytes(&mut inner)?;
+            if fields & !XyzFields::ALL.0 != 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("unknown xyz fields {:#x}", fields),
+                ));
+            }
+            let fields = XyzFields(fields);
+            let decoder = if buff == XYZ_MAGIC_COMPRESSED {
+                let resolution = f64::from_bytes(&mut inner)?;
+                Some(ChunkDecoder::new(fields, resolution))
+            } else {
+                None
+            };
+            (fields, decoder)
+        } else if buff == XYZ_MAGIC_LEGACY {
+            (XyzFields::NONE, None)
+        } else {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 "invalid magic number",
             ));
-        }
+        };
 
         // read the number of records, defined by the first u64
         let n_records = u64::from_bytes(&mut inner)?;
         Ok(Self {
             inner,
+            fields,
+            decoder,
             n_records,
             records_read: 0,
             start: None,
         })
     }
 
+    /// The optional fields present in the file. Fields that are not present are read as zero.
+    pub fn fields(&self) -> XyzFields {
+        self.fields
+    }
+
     #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> std::io::Result<Option<XyzRecord>> {
         if self.records_read >= self.n_records {
@@ -179,7 +653,10 @@ impl<R: Read> XyzInternalReader<R> {
             self.start = Some(Instant::now());
         }
 
-        let record = XyzRecord::from_bytes(&mut self.inner)?;
+        let record = match self.decoder.as_mut() {
+            Some(decoder) => decoder.next(&mut self.inner)?,
+            None => XyzRecord::from_bytes_with_fields(&mut self.inner, self.fields)?,
+        };
         self.records_read += 1;
         Ok(Some(record))
     }
@@ -202,6 +679,7 @@ mod test {
             classification: 4,
             number_of_returns: 5,
             return_number: 6,
+            ..Default::default()
         };
 
         let mut buff = Vec::new();
@@ -223,6 +701,7 @@ mod test {
             classification: 4,
             number_of_returns: 5,
             return_number: 6,
+            ..Default::default()
         };
 
         writer.write_record(&record).unwrap();
@@ -238,4 +717,196 @@ mod test {
         assert_eq!(reader.next().unwrap().unwrap(), record);
         assert_eq!(reader.next().unwrap(), None);
     }
+
+    #[test]
+    fn test_writer_reader_fields() {
+        let record = XyzRecord {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+            classification: 4,
+            number_of_returns: 5,
+            return_number: 6,
+            intensity: 7,
+            red: 8,
+            green: 9,
+            blue: 10,
+            gps_time: 11.0,
+            scan_angle: 12.0,
+            point_source_id: 13,
+        };
+
+        let mut writer = XyzInternalWriter::with_fields(
+            Cursor::new(Vec::new()),
+            XyzFields::INTENSITY | XyzFields::RGB | XyzFields::POINT_SOURCE_ID,
+        );
+        writer.write_record(&record).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+
+        let mut reader = XyzInternalReader::new(Cursor::new(data)).unwrap();
+        assert!(!reader.fields().contains(XyzFields::GPS_TIME));
+        // the fields that were not written are read as zero
+        let expected = XyzRecord {
+            gps_time: 0.0,
+            scan_angle: 0.0,
+            ..record
+        };
+        assert_eq!(reader.next().unwrap().unwrap(), expected);
+        assert_eq!(reader.next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_reader_legacy() {
+        let record = XyzRecord {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+            classification: 4,
+            number_of_returns: 5,
+            return_number: 6,
+            ..Default::default()
+        };
+
+        // files written before the format was versioned only have the magic and the count
+        let mut data = XYZ_MAGIC_LEGACY.to_vec();
+        1u64.to_bytes(&mut data).unwrap();
+        record.to_bytes(&mut data).unwrap();
+
+        let mut reader = XyzInternalReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(reader.fields(), XyzFields::NONE);
+        assert_eq!(reader.next().unwrap().unwrap(), record);
+        assert_eq!(reader.next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, -1, 63, -64, 64, 1_000_000, i64::MAX, i64::MIN] {
+            let mut data = Vec::new();
+            write_varint(zigzag(value), &mut data);
+            let mut position = 0;
+            assert_eq!(unzigzag(read_varint(&data, &mut position).unwrap()), value);
+            assert_eq!(position, data.len());
+        }
+    }
+
+    #[test]
+    fn test_writer_reader_compressed() {
+        // more records than fit in a single chunk
+        let records = (0..COMPRESSED_CHUNK_SIZE + 10)
+            .map(|i| XyzRecord {
+                x: 500_000.0 + i as f64 * 0.01,
+                y: 7_000_000.0 - i as f64 * 0.37,
+                z: 100.0 + (i % 100) as f64 * 0.05,
+                classification: (i % 3) as u8,
+                number_of_returns: 2,
+                return_number: 1 + (i % 2) as u8,
+                intensity: (i % 1000) as u16,
+                gps_time: 1000.0 + i as f64 * 1e-5,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let mut writer = XyzInternalWriter::compressed(
+            Cursor::new(Vec::new()),
+            XyzFields::INTENSITY | XyzFields::GPS_TIME,
+            COMPRESSED_RESOLUTION,
+        );
+        for record in records.iter() {
+            writer.write_record(record).unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+        // 27 bytes for the base fields, 10 for intensity and GPS time
+        assert!(data.len() < records.len() * 37 / 3);
+
+        let mut reader = XyzInternalReader::new(Cursor::new(data)).unwrap();
+        for record in records.iter() {
+            let read = reader.next().unwrap().unwrap();
+            assert!((read.x - record.x).abs() < COMPRESSED_RESOLUTION);
+            assert!((read.y - record.y).abs() < COMPRESSED_RESOLUTION);
+            assert!((read.z - record.z).abs() < COMPRESSED_RESOLUTION);
+            assert_eq!(
+                XyzRecord {
+                    x: record.x,
+                    y: record.y,
+                    z: record.z,
+                    ..read
+                },
+                *record
+            );
+        }
+        assert_eq!(reader.next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_writer_reader_exact() {
+        let records = (0..1000)
+            .map(|i| XyzRecord {
+                x: 500_000.0 + i as f64 * 0.0123 * 1.7,
+                y: 7_000_000.0 - i as f64 / 3.0,
+                z: 100.0 + (i % 100) as f64 * 1e-6,
+                classification: 2,
+                number_of_returns: 1,
+                return_number: 1,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let mut writer =
+            XyzInternalWriter::compressed(Cursor::new(Vec::new()), XyzFields::NONE, 0.0);
+        for record in records.iter() {
+            writer.write_record(record).unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+
+        let mut reader = XyzInternalReader::new(Cursor::new(data)).unwrap();
+        for record in records.iter() {
+            assert_eq!(reader.next().unwrap().unwrap(), *record);
+        }
+        assert_eq!(reader.next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_writer_reader_resolution() {
+        // resolutions without an integer inverse
+        for resolution in [0.3, 0.003, 0.7] {
+            let record = XyzRecord {
+                x: 500_000.0,
+                y: 7_000_000.0,
+                z: 123.4,
+                ..Default::default()
+            };
+            let mut writer =
+                XyzInternalWriter::compressed(Cursor::new(Vec::new()), XyzFields::NONE, resolution);
+            writer.write_record(&record).unwrap();
+            let data = writer.finish().unwrap().into_inner();
+
+            let mut reader = XyzInternalReader::new(Cursor::new(data)).unwrap();
+            let read = reader.next().unwrap().unwrap();
+            assert!((read.x - record.x).abs() <= resolution / 2.0);
+            assert!((read.y - record.y).abs() <= resolution / 2.0);
+            assert!((read.z - record.z).abs() <= resolution / 2.0);
+        }
+    }
+
+    #[test]
+    fn test_reader_corrupt_chunk() {
+        let mut writer = XyzInternalWriter::compressed(
+            Cursor::new(Vec::new()),
+            XyzFields::NONE,
+            COMPRESSED_RESOLUTION,
+        );
+        writer.write_record(&XyzRecord::default()).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        // the chunk follows the resolution and the record count
+        let chunk = XYZ_HEADER_LEN as usize + 8 + 8;
+
+        // a chunk of no records, and one longer than its records can be
+        for (count, len) in [(0u32, 6u32), (1, u32::MAX)] {
+            let mut corrupt = data.clone();
+            corrupt[chunk..chunk + 4].copy_from_slice(&count.to_ne_bytes());
+            corrupt[chunk + 4..chunk + 8].copy_from_slice(&len.to_ne_bytes());
+            let mut reader = XyzInternalReader::new(Cursor::new(corrupt)).unwrap();
+            assert!(reader.next().is_err());
+        }
+    }
 }
@@ -0,0 +1,815 @@
+//! The state of the tiles of a batch. It is kept in files next to the outputs of the tiles in the
+//! output folder, so a batch can be stopped and started again:
+//!
+//! - `<tile>.inprogress` marks a tile a worker is processing. The worker keeps updating it, so the
+//!   marker of a worker that was killed becomes stale and the tile is taken over.
+//! - `<tile>.takeover` is held by the worker taking over a stale tile, so only one does.
+//! - `<tile>.failed` holds the error of a tile that failed all its attempts.
+//! - `<tile>.png` marks a tile that is done.
+//!
+//! [`FAILURE_REPORT`] lists the failed tiles of the batch. The workers of a process take the tiles
+//! from a [`JobQueue`], and each of them processes its tiles in a workspace of its own, see
+//! [`worker_workspace`].
+
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use std::any::Any;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::error::{Error, Result, ResultExt};
+use crate::io::fs::FileSystem;
+use crate::report::REPORT_FOLDER;
+use crate::workspace::Workspace;
+
+/// The report of the failed tiles of a batch, in the output folder.
+pub const FAILURE_REPORT: &str = "failed_tiles.json";
+
+const MARKER_EXTENSION: &str = "inprogress";
+const TAKEOVER_EXTENSION: &str = "takeover";
+const FAILURE_EXTENSION: &str = "failed";
+
+/// Set when the batch is interrupted, see [`interrupt`].
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The output folders and names of the tiles claimed by this process.
+static ACTIVE: Mutex<Vec<(PathBuf, String)>> = Mutex::new(Vec::new());
+
+/// A process working on a batch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Worker {
+    pub host: String,
+    pub pid: u32,
+    /// Unix time the process started at, to tell apart processes with the same id.
+    pub started: u64,
+}
+
+impl Worker {
+    /// This process.
+    pub fn current() -> Self {
+        static STARTED: OnceLock<u64> = OnceLock::new();
+        Self {
+            host: hostname().to_string(),
+            pid: std::process::id(),
+            started: *STARTED.get_or_init(unix_time),
+        }
+    }
+
+    /// Whether the worker is a process of this host that is not running anymore. This is only
+    /// known on Linux, elsewhere the markers of stopped workers become stale with age.
+    fn is_gone(&self) -> bool {
+        self.host == hostname()
+            && self.pid != std::process::id()
+            && Path::new("/proc/self").exists()
+            && !Path::new("/proc").join(self.pid.to_string()).exists()
+    }
+}
+
+fn hostname() -> &'static str {
+    static HOSTNAME: OnceLock<String> = OnceLock::new();
+    HOSTNAME.get_or_init(|| {
+        std::fs::read_to_string("/etc/hostname")
+            .ok()
+            .or_else(|| std::env::var("COMPUTERNAME").ok())
+            .or_else(|| std::env::var("HOSTNAME").ok())
+            .map(|host| host.trim().replace(' ', "_"))
+            .filter(|host| !host.is_empty())
+            .unwrap_or_else(|| String::from("localhost"))
+    })
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The in-progress marker of a tile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    pub worker: Worker,
+    /// Unix time of the last sign of life of the worker.
+    pub updated: u64,
+}
+
+impl Marker {
+    fn parse(contents: &str) -> Option<Self> {
+        let mut fields = contents.split_whitespace();
+        let host = fields.next()?.to_string();
+        let mut number = || fields.next()?.parse::<u64>().ok();
+        Some(Self {
+            worker: Worker {
+                host,
+                pid: u32::try_from(number()?).ok()?,
+                started: number()?,
+            },
+            updated: number()?,
+        })
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{} {} {} {}\n",
+            self.worker.host, self.worker.pid, self.worker.started, self.updated
+        )
+    }
+
+    /// Whether the worker has stopped, because it is gone or has not updated the marker for
+    /// `timeout` seconds.
+    pub fn is_stale(&self, timeout: u64) -> bool {
+        self.worker != Worker::current()
+            && (self.worker.is_gone() || unix_time().saturating_sub(self.updated) > timeout)
+    }
+}
+
+/// A tile that failed all its attempts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub tile: String,
+    pub worker: Worker,
+    pub attempts: u32,
+    /// The stage of the processing the tile failed in, if known.
+    pub stage: Option<String>,
+    pub error: String,
+}
+
+impl Failure {
+    fn to_json(&self) -> Value {
+        json!({
+            "tile": self.tile,
+            "host": self.worker.host,
+            "pid": self.worker.pid,
+            "started": self.worker.started,
+            "attempts": self.attempts,
+            "stage": self.stage,
+            "error": self.error,
+        })
+    }
+
+    fn from_json(json: &Value) -> Option<Self> {
+        Some(Self {
+            tile: json["tile"].as_str()?.to_string(),
+            worker: Worker {
+                host: json["host"].as_str()?.to_string(),
+                pid: u32::try_from(json["pid"].as_u64()?).ok()?,
+                started: json["started"].as_u64()?,
+            },
+            attempts: u32::try_from(json["attempts"].as_u64()?).ok()?,
+            stage: json["stage"].as_str().map(String::from),
+            error: json["error"].as_str()?.to_string(),
+        })
+    }
+}
+
+/// The state of a tile of a batch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TileState {
+    /// Not processed yet.
+    Todo,
+    Done,
+    /// Being processed by a worker.
+    InProgress,
+    /// Claimed by a worker that has stopped, with the contents of its marker.
+    Stale(String),
+    Failed(Failure),
+}
+
+fn marker_path(folder: &Path, tile: &str) -> PathBuf {
+    folder.join(format!("{}.{}", tile, MARKER_EXTENSION))
+}
+
+fn takeover_path(folder: &Path, tile: &str) -> PathBuf {
+    folder.join(format!("{}.{}", tile, TAKEOVER_EXTENSION))
+}
+
+/// Whether a file has not been modified for `timeout` seconds.
+fn is_older_than(fs: &impl FileSystem, path: &Path, timeout: u64) -> bool {
+    fs.modified(path)
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > Duration::from_secs(timeout))
+}
+
+fn failure_path(folder: &Path, tile: &str) -> PathBuf {
+    folder.join(format!("{}.{}", tile, FAILURE_EXTENSION))
+}
+
+/// The state of a tile in the output folder of the config.
+pub fn tile_state(fs: &impl FileSystem, config: &Config, tile: &str) -> TileState {
+    let folder = Path::new(&config.batchoutfolder);
+
+    let marker = marker_path(folder, tile);
+    if fs.exists(&marker) {
+        // a marker that cannot be read yet is being written by the worker that claimed the tile,
+        // unless it has not been modified for longer than a worker takes to update it
+        let contents = fs.read_to_string(&marker).unwrap_or_default();
+        let stale = match Marker::parse(&contents) {
+            Some(m) => m.is_stale(config.stale_timeout),
+            None => is_older_than(fs, &marker, config.stale_timeout),
+        };
+        return if stale {
+            TileState::Stale(contents)
+        } else {
+            TileState::InProgress
+        };
+    }
+
+    let failure = failure_path(folder, tile);
+    if fs.exists(&failure) {
+        let failure = fs
+            .read_to_string(&failure)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .and_then(|json| Failure::from_json(&json));
+        if let Some(failure) = failure {
+            return TileState::Failed(failure);
+        }
+    }
+
+    if fs.exists(folder.join(format!("{}.png", tile))) {
+        TileState::Done
+    } else {
+        TileState::Todo
+    }
+}
+
+/// A tile claimed by this process with an in-progress marker. The claim ends with
+/// [`Claim::finish`] or [`Claim::fail`].
+#[derive(Debug)]
+pub struct Claim {
+    folder: PathBuf,
+    tile: String,
+}
+
+impl Claim {
+    /// Claims a tile that is not done and that no running worker has claimed. Tiles that failed
+    /// in an earlier run are tried again, and the tiles of stopped workers are taken over.
+    pub fn acquire(fs: &impl FileSystem, config: &Config, tile: &str) -> Result<Option<Self>> {
+        let folder = Path::new(&config.batchoutfolder);
+        match tile_state(fs, config, tile) {
+            TileState::Todo => {}
+            TileState::Done => {
+                info!("Skipping {}.png it exists already in output folder.", tile);
+                return Ok(None);
+            }
+            TileState::InProgress => {
+                debug!("Skipping {}, another worker is processing it", tile);
+                return Ok(None);
+            }
+            TileState::Failed(failure) if failure.worker == Worker::current() => {
+                return Ok(None);
+            }
+            TileState::Failed(failure) => {
+                info!("Trying {} again, it failed: {}", tile, failure.error);
+            }
+            TileState::Stale(contents) => {
+                if !take_over(fs, config, tile, &contents)? {
+                    return Ok(None);
+                }
+            }
+        }
+
+        let marker = marker_path(folder, tile);
+        let mut file = match fs.create_new(&marker) {
+            Ok(file) => file,
+            // another worker claimed the tile first
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(None),
+            Err(e) => return Err(e).with_file(&marker),
+        };
+        let line = Marker {
+            worker: Worker::current(),
+            updated: unix_time(),
+        }
+        .to_line();
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            drop(file);
+            let _ = fs.remove_file(&marker);
+            return Err(e).with_file(&marker);
+        }
+
+        let claim = Self {
+            folder: folder.to_path_buf(),
+            tile: tile.to_string(),
+        };
+        ACTIVE
+            .lock()
+            .expect("active tiles lock poisoned")
+            .push((claim.folder.clone(), claim.tile.clone()));
+        Ok(Some(claim))
+    }
+
+    pub fn tile(&self) -> &str {
+        &self.tile
+    }
+
+    /// Updates the marker, to show the worker is still running.
+    pub fn refresh(&self, fs: &impl FileSystem) -> Result<()> {
+        let marker = marker_path(&self.folder, &self.tile);
+        // the marker is replaced at once, so it is never seen half written
+        let tmp = marker.with_extension(format!("{}.tmp", MARKER_EXTENSION));
+        let line = Marker {
+            worker: Worker::current(),
+            updated: unix_time(),
+        }
+        .to_line();
+        fs.create(&tmp)
+            .and_then(|mut f| f.write_all(line.as_bytes()))
+            .with_file(&tmp)?;
+        fs.rename(&tmp, &marker).with_file(&marker)
+    }
+
+    /// Runs `work` while refreshing the marker every `interval`.
+    pub fn with_heartbeat<F: FileSystem + Sync, T>(
+        &self,
+        fs: &F,
+        interval: Duration,
+        work: impl FnOnce() -> T,
+    ) -> T {
+        let (stop, stopped) = mpsc::channel::<()>();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    if let Err(e) = self.refresh(fs) {
+                        warn!("Could not update the marker of {}: {}", self.tile, e);
+                    }
+                }
+            });
+            let result = work();
+            drop(stop);
+            result
+        })
+    }
+
+    /// Removes the outputs written so far, to try the tile again.
+    pub fn remove_outputs(&self, fs: &impl FileSystem) -> Result<()> {
+        remove_outputs(fs, &self.folder, &self.tile)
+    }
+
+    /// Marks the tile done. The empty `<tile>.png` of the modes that do not render the map marks
+    /// it done. The claim is released even if that fails.
+    pub fn finish(self, fs: &impl FileSystem) -> Result<()> {
+        let result = (|| {
+            let png = self.folder.join(format!("{}.png", self.tile));
+            if !fs.exists(&png) {
+                fs.create(&png).with_file(&png)?;
+            }
+            let failure = failure_path(&self.folder, &self.tile);
+            if fs.exists(&failure) {
+                fs.remove_file(&failure).with_file(&failure)?;
+            }
+            Ok(())
+        })();
+        let released = self.release(fs);
+        result.and(released)
+    }
+
+    /// Marks the tile failed with the error of its last attempt, and removes its outputs. The
+    /// claim is released even if that fails.
+    pub fn fail(self, fs: &impl FileSystem, error: &Error, attempts: u32) -> Result<()> {
+        let result = (|| {
+            self.remove_outputs(fs)?;
+            let failure = Failure {
+                tile: self.tile.clone(),
+                worker: Worker::current(),
+                attempts,
+                stage: error.stage().map(String::from),
+                error: error.to_string(),
+            };
+            let path = failure_path(&self.folder, &self.tile);
+            let mut f = fs.create(&path).with_file(&path)?;
+            serde_json::to_writer_pretty(&mut f, &failure.to_json()).with_file(&path)
+        })();
+        let released = self.release(fs);
+        result.and(released)
+    }
+
+    fn release(self, fs: &impl FileSystem) -> Result<()> {
+        ACTIVE
+            .lock()
+            .expect("active tiles lock poisoned")
+            .retain(|(folder, tile)| *folder != self.folder || *tile != self.tile);
+        let marker = marker_path(&self.folder, &self.tile);
+        fs.remove_file(&marker).with_file(&marker)
+    }
+}
+
+/// Removes the stale marker of a tile, and its outputs, so that it can be claimed again. Only one
+/// of the workers taking over a tile at the same time does it, the one that creates its takeover
+/// file. Returns whether this worker did.
+fn take_over(fs: &impl FileSystem, config: &Config, tile: &str, stale: &str) -> Result<bool> {
+    let folder = Path::new(&config.batchoutfolder);
+    let takeover = takeover_path(folder, tile);
+    if let Err(e) = fs.create_new(&takeover) {
+        if e.kind() != io::ErrorKind::AlreadyExists {
+            return Err(e).with_file(&takeover);
+        }
+        // a worker stopped during its takeover, the tile is taken over again in the next run
+        if is_older_than(fs, &takeover, config.stale_timeout) {
+            fs.remove_file(&takeover).with_file(&takeover)?;
+        }
+        return Ok(false);
+    }
+
+    // another worker may have taken the tile over after its state was read
+    let marker = marker_path(folder, tile);
+    let result = if fs.read_to_string(&marker).ok().as_deref() != Some(stale) {
+        Ok(false)
+    } else {
+        warn!("Taking over {} from a worker that has stopped", tile);
+        remove_outputs(fs, folder, tile)
+            .and_then(|_| fs.remove_file(&marker).with_file(&marker))
+            .map(|_| true)
+    };
+    fs.remove_file(&takeover).with_file(&takeover)?;
+    result
+}
+
+/// Removes the outputs of a tile from the output folder and its report folder: the files named
+/// after the tile, except its marker, takeover and failure files.
+fn remove_outputs(fs: &impl FileSystem, folder: &Path, tile: &str) -> Result<()> {
+    let keep = [
+        format!("{}.{}", tile, MARKER_EXTENSION),
+        format!("{}.{}", tile, TAKEOVER_EXTENSION),
+        format!("{}.{}", tile, FAILURE_EXTENSION),
+    ];
+    for folder in [folder.to_path_buf(), folder.join(REPORT_FOLDER)] {
+        if !fs.exists(&folder) {
+            continue;
+        }
+        for path in fs.list(&folder).with_file(&folder)? {
+            let Some(name) = path.file_name().and_then(|f| f.to_str()) else {
+                continue;
+            };
+            let is_output = name
+                .strip_prefix(tile)
+                .is_some_and(|rest| rest.starts_with('.') || rest.starts_with('_'));
+            if is_output && !keep.iter().any(|k| k == name) && fs.file_size(&path).is_ok() {
+                debug!("Removing {}", path.display());
+                fs.remove_file(&path).with_file(&path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs `work`, turning a panic into an error so that it only fails the tile.
+pub fn catch_panic<T>(work: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(work)).unwrap_or_else(|payload| {
+        Err(Error::invalid(format!(
+            "panicked: {}",
+            panic_message(payload.as_ref())
+        )))
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown error")
+}
+
+/// The tiles of a batch, shared by the workers of a process. Each tile is handed out once, and
+/// only if it can be claimed, so the tiles claimed by the workers of other processes and hosts
+/// sharing the output folder are skipped.
+#[derive(Debug)]
+pub struct JobQueue {
+    tiles: Vec<String>,
+    next: AtomicUsize,
+}
+
+impl JobQueue {
+    pub fn new(tiles: Vec<String>) -> Self {
+        Self {
+            tiles,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    /// Claims the next tile that is not done and that no other worker is processing, or returns
+    /// `None` when the queue is empty.
+    pub fn next(&self, fs: &impl FileSystem, config: &Config) -> Result<Option<Claim>> {
+        loop {
+            let i = self.next.fetch_add(1, Ordering::SeqCst);
+            let Some(tile) = self.tiles.get(i) else {
+                return Ok(None);
+            };
+            if let Some(claim) = Claim::acquire(fs, config, tile)? {
+                return Ok(Some(claim));
+            }
+        }
+    }
+}
+
+/// The workspace of a worker of this process in the work folder of the config, named after the
+/// host and the process, see [`remove_abandoned_workspaces`].
+pub fn worker_workspace(config: &Config, name: &str) -> Workspace {
+    let worker = Worker::current();
+    Workspace::isolated(
+        Path::new(&config.workfolder).join(format!("{}-{}-{}", worker.host, worker.pid, name)),
+    )
+}
+
+/// Removes the workspaces that the workers of this host that are not running anymore left behind,
+/// for example when a batch was killed. Returns how many were removed.
+pub fn remove_abandoned_workspaces(fs: &impl FileSystem, config: &Config) -> Result<usize> {
+    let folder = Path::new(&config.workfolder);
+    if !fs.exists(folder) {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for path in fs.list(folder).with_file(folder)? {
+        let Some(name) = path.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
+        // the host name may have dashes of its own
+        let mut parts = name.rsplitn(3, '-');
+        let (Some(_), Some(pid), Some(host)) = (parts.next(), parts.next(), parts.next()) else {
+            continue;
+        };
+        let Ok(pid) = pid.parse() else {
+            continue;
+        };
+        let worker = Worker {
+            host: host.to_string(),
+            pid,
+            started: 0,
+        };
+        if worker.is_gone() {
+            debug!("Removing the abandoned workspace {}", path.display());
+            fs.remove_dir_all(&path).with_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Whether the batch has been interrupted, so no more tiles should be started.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Interrupts the batch, when the user presses Ctrl-C: no more tiles are started, and the
+/// outputs and markers of the tiles being processed are removed. Returns the number of those.
+pub fn interrupt(fs: &impl FileSystem) -> usize {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    let active = std::mem::take(&mut *ACTIVE.lock().expect("active tiles lock poisoned"));
+    for (folder, tile) in &active {
+        let marker = marker_path(folder, tile);
+        if let Err(e) = remove_outputs(fs, folder, tile)
+            .and_then(|_| fs.remove_file(&marker).with_file(&marker))
+        {
+            warn!("Could not clean up {}: {}", tile, e);
+        }
+    }
+    active.len()
+}
+
+/// Writes [`FAILURE_REPORT`] listing the failed tiles of the output folder, or removes it when
+/// none have failed. Returns the number of failed tiles.
+pub fn write_failure_report(fs: &impl FileSystem, config: &Config) -> Result<usize> {
+    let folder = Path::new(&config.batchoutfolder);
+    let report = folder.join(FAILURE_REPORT);
+
+    let mut failures = vec![];
+    for path in fs.list(folder).with_file(folder)? {
+        if path.extension().is_some_and(|e| e == FAILURE_EXTENSION) {
+            let contents = fs.read_to_string(&path).with_file(&path)?;
+            let json: Value = serde_json::from_str(&contents).with_file(&path)?;
+            failures.push(json);
+        }
+    }
+    failures.sort_by(|a, b| a["tile"].as_str().cmp(&b["tile"].as_str()));
+
+    if failures.is_empty() {
+        if fs.exists(&report) {
+            fs.remove_file(&report).with_file(&report)?;
+        }
+        return Ok(0);
+    }
+    let mut f = fs.create(&report).with_file(&report)?;
+    serde_json::to_writer_pretty(&mut f, &Value::Array(failures.clone())).with_file(&report)?;
+    Ok(failures.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::fs::memory::MemoryFileSystem;
+
+    fn config() -> Config {
+        let mut config = Config::from_file(Path::new("pullauta.default.ini")).unwrap();
+        config.batchoutfolder = String::from("out");
+        config
+    }
+
+    fn write(fs: &MemoryFileSystem, path: &str, contents: &str) {
+        fs.create(path)
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_claim() {
+        let fs = MemoryFileSystem::new();
+        let config = config();
+        fs.create_dir_all("out/report").unwrap();
+
+        let claim = Claim::acquire(&fs, &config, "a.laz").unwrap().unwrap();
+        assert_eq!(tile_state(&fs, &config, "a.laz"), TileState::InProgress);
+        assert!(Claim::acquire(&fs, &config, "a.laz").unwrap().is_none());
+
+        claim.refresh(&fs).unwrap();
+        write(&fs, "out/a.laz_depr.png", "partial");
+        write(&fs, "out/a.laz2.png", "another tile");
+        let error = Error::invalid("no points").context(crate::error::Context::Stage("points"));
+        claim.fail(&fs, &error, 2).unwrap();
+
+        assert!(!fs.exists("out/a.laz_depr.png"));
+        assert!(fs.exists("out/a.laz2.png"));
+        let TileState::Failed(failure) = tile_state(&fs, &config, "a.laz") else {
+            panic!("tile should have failed");
+        };
+        assert_eq!(failure.attempts, 2);
+        assert_eq!(failure.stage.as_deref(), Some("points"));
+        assert_eq!(failure.error, "stage points: no points");
+
+        // failed tiles are tried again in the next run, not in this one
+        assert!(Claim::acquire(&fs, &config, "a.laz").unwrap().is_none());
+        assert_eq!(write_failure_report(&fs, &config).unwrap(), 1);
+        let report = fs.read_to_string("out/failed_tiles.json").unwrap();
+        assert!(report.contains("\"tile\": \"a.laz\""));
+
+        let claim = Claim::acquire(&fs, &config, "b.laz").unwrap().unwrap();
+        claim.finish(&fs).unwrap();
+        assert_eq!(tile_state(&fs, &config, "b.laz"), TileState::Done);
+        assert!(!fs.exists("out/b.laz.inprogress"));
+    }
+
+    #[test]
+    fn test_stale() {
+        let fs = MemoryFileSystem::new();
+        let config = config();
+        fs.create_dir_all("out").unwrap();
+
+        // a worker of another host that has not updated its marker for an hour
+        let marker = format!("otherhost 123 1 {}\n", unix_time() - 3600);
+        write(&fs, "out/a.laz.inprogress", &marker);
+        write(&fs, "out/a.laz.png", "partial");
+        assert_eq!(
+            tile_state(&fs, &config, "a.laz"),
+            TileState::Stale(marker.clone())
+        );
+
+        let claim = Claim::acquire(&fs, &config, "a.laz").unwrap().unwrap();
+        assert!(!fs.exists("out/a.laz.png"));
+        let marker = fs.read_to_string("out/a.laz.inprogress").unwrap();
+        assert_eq!(Marker::parse(&marker).unwrap().worker, Worker::current());
+        claim.finish(&fs).unwrap();
+
+        // a worker of another host that is still running
+        let marker = format!("otherhost 123 1 {}\n", unix_time());
+        write(&fs, "out/b.laz.inprogress", &marker);
+        assert_eq!(tile_state(&fs, &config, "b.laz"), TileState::InProgress);
+    }
+
+    #[test]
+    fn test_competing_takeovers() {
+        let config = config();
+        for _ in 0..20 {
+            let fs = MemoryFileSystem::new();
+            fs.create_dir_all("out").unwrap();
+            write(
+                &fs,
+                "out/a.laz.inprogress",
+                &format!("otherhost 123 1 {}\n", unix_time() - 3600),
+            );
+
+            let start = std::sync::Barrier::new(4);
+            let claims = Mutex::new(vec![]);
+            thread::scope(|scope| {
+                for _ in 0..4 {
+                    scope.spawn(|| {
+                        start.wait();
+                        if let Some(claim) = Claim::acquire(&fs, &config, "a.laz").unwrap() {
+                            claims.lock().unwrap().push(claim);
+                        }
+                    });
+                }
+            });
+
+            let claims = claims.into_inner().unwrap();
+            assert_eq!(claims.len(), 1);
+            assert!(!fs.exists("out/a.laz.takeover"));
+            for claim in claims {
+                claim.finish(&fs).unwrap();
+            }
+        }
+
+        // a worker that finds another one in the middle of the takeover leaves the tile to it
+        let fs = MemoryFileSystem::new();
+        fs.create_dir_all("out").unwrap();
+        let marker = format!("otherhost 123 1 {}\n", unix_time() - 3600);
+        write(&fs, "out/a.laz.inprogress", &marker);
+        write(&fs, "out/a.laz.takeover", "");
+        assert!(Claim::acquire(&fs, &config, "a.laz").unwrap().is_none());
+        assert_eq!(fs.read_to_string("out/a.laz.inprogress").unwrap(), marker);
+        assert!(fs.exists("out/a.laz.takeover"));
+    }
+
+    #[test]
+    fn test_unreadable_marker() {
+        let fs = MemoryFileSystem::new();
+        let mut config = config();
+        fs.create_dir_all("out").unwrap();
+        write(&fs, "out/a.laz.inprogress", "garbage");
+
+        // a worker may still be writing it
+        assert_eq!(tile_state(&fs, &config, "a.laz"), TileState::InProgress);
+
+        // but not after the stale timeout
+        config.stale_timeout = 0;
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(
+            tile_state(&fs, &config, "a.laz"),
+            TileState::Stale(String::from("garbage"))
+        );
+        let claim = Claim::acquire(&fs, &config, "a.laz").unwrap().unwrap();
+        claim.finish(&fs).unwrap();
+    }
+
+    #[test]
+    fn test_queue() {
+        let fs = MemoryFileSystem::new();
+        let config = config();
+        fs.create_dir_all("out").unwrap();
+        write(&fs, "out/b.laz.png", "done");
+        write(
+            &fs,
+            "out/c.laz.inprogress",
+            &format!("otherhost 123 1 {}\n", unix_time()),
+        );
+
+        let tiles = ["a.laz", "b.laz", "c.laz", "d.laz", "e.laz"].map(String::from);
+        let queue = JobQueue::new(tiles.to_vec());
+        let claimed = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| {
+                    while let Some(claim) = queue.next(&fs, &config).unwrap() {
+                        claimed.lock().unwrap().push(claim.tile().to_string());
+                        claim.finish(&fs).unwrap();
+                    }
+                });
+            }
+        });
+
+        let mut claimed = claimed.into_inner().unwrap();
+        claimed.sort();
+        assert_eq!(claimed, ["a.laz", "d.laz", "e.laz"]);
+        assert!(queue.next(&fs, &config).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_worker_workspace() {
+        let config = config();
+        let workspace = worker_workspace(&config, "2");
+        let worker = Worker::current();
+        assert_eq!(
+            workspace.dir(),
+            Path::new(&config.workfolder).join(format!("{}-{}-2", worker.host, worker.pid))
+        );
+
+        // the workspace of a running worker is kept
+        let fs = MemoryFileSystem::new();
+        workspace.clear(&fs).unwrap();
+        assert_eq!(remove_abandoned_workspaces(&fs, &config).unwrap(), 0);
+        assert!(fs.exists(workspace.dir()));
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        let result: Result<()> = catch_panic(|| panic!("index out of bounds"));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "panicked: index out of bounds"
+        );
+    }
+}
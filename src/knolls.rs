@@ -2,21 +2,18 @@ use image::{GrayImage, Luma};
 use imageproc::drawing::draw_line_segment_mut;
 use log::info;
 use rustc_hash::FxHashMap as HashMap;
-use std::error::Error;
 use std::io::{BufReader, BufWriter, Write};
 use std::path::Path;
 
 use crate::config::Config;
+use crate::error::{missing, Result, ResultExt};
 use crate::io::bytes::FromToBytes;
+use crate::io::dxf::vertex_coordinate;
 use crate::io::fs::FileSystem;
 use crate::io::heightmap::HeightMap;
-use crate::util::read_lines_no_alloc;
+use crate::util::try_read_lines_no_alloc;
 
-pub fn dotknolls(
-    fs: &impl FileSystem,
-    config: &Config,
-    tmpfolder: &Path,
-) -> Result<(), Box<dyn Error>> {
+pub fn dotknolls(fs: &impl FileSystem, config: &Config, tmpfolder: &Path) -> Result<()> {
     info!("Identifying dotknolls...");
 
     let scalefactor = config.scalefactor;
@@ -40,17 +37,16 @@ pub fn dotknolls(
         Luma([0xff]),
     );
 
-    let f = fs
-        .create(tmpfolder.join("dotknolls.dxf"))
-        .expect("Unable to create file");
+    let output = tmpfolder.join("dotknolls.dxf");
+    let f = fs.create(&output).with_file(&output)?;
     let mut f = BufWriter::new(f);
     write!(&mut f,
         "  0\r\nSECTION\r\n  2\r\nHEADER\r\n  9\r\n$EXTMIN\r\n 10\r\n{}\r\n 20\r\n{}\r\n  9\r\n$EXTMAX\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nENDSEC\r\n  0\r\nSECTION\r\n  2\r\nENTITIES\r\n  0\r\n",
         xstart, ystart, xmax * size + xstart, ymax * size + ystart
-    ).expect("Cannot write dxf file");
+    )?;
 
     let input = tmpfolder.join("out2.dxf");
-    let data = fs.read_to_string(input).expect("Can not read input file");
+    let data = fs.read_to_string(&input).with_file(&input)?;
     let data: Vec<&str> = data.split("POLYLINE").collect();
 
     for (j, rec) in data.iter().enumerate() {
@@ -60,7 +56,10 @@ pub fn dotknolls(
         let mut yline = 0;
         if j > 0 {
             let r = rec.split("VERTEX").collect::<Vec<&str>>();
-            let apu = r[1];
+            let apu = r
+                .get(1)
+                .ok_or_else(|| missing("VERTEX"))
+                .with_file(&input)?;
             let val = apu.split('\n').collect::<Vec<&str>>();
             for (i, v) in val.iter().enumerate() {
                 let vt = v.trim_end();
@@ -74,8 +73,8 @@ pub fn dotknolls(
             for (i, v) in r.iter().enumerate() {
                 if i > 0 {
                     let val = v.trim_end().split('\n').collect::<Vec<&str>>();
-                    x.push(val[xline].trim().parse::<f64>().unwrap());
-                    y.push(val[yline].trim().parse::<f64>().unwrap());
+                    x.push(vertex_coordinate(&val, xline).with_file(&input)?);
+                    y.push(vertex_coordinate(&val, yline).with_file(&input)?);
                 }
             }
         }
@@ -96,13 +95,16 @@ pub fn dotknolls(
     }
 
     let input = tmpfolder.join("dotknolls.txt");
-    read_lines_no_alloc(fs, input, |line| {
+    try_read_lines_no_alloc(fs, input, |line| {
         let parts = line.split(' ');
         let r = parts.collect::<Vec<&str>>();
         if r.len() >= 3 {
             let depression: bool = r[0] == "1";
-            let x: f64 = r[1].parse::<f64>().unwrap();
-            let y: f64 = r[2].parse::<f64>().unwrap();
+            let x: f64 = r[1].parse::<f64>()?;
+            let y: f64 = r[2].parse::<f64>()?;
+            if hmap.is_unmapped_at(x, y, config.nodata_distance) {
+                return Ok(());
+            }
             let mut ok = true;
             let mut i = (x - xstart) / scalefactor - 3.0;
             while i < (x - xstart) / scalefactor + 4.0 && ok {
@@ -133,22 +135,16 @@ pub fn dotknolls(
                 &mut f,
                 "POINT\r\n  8\r\n{}\r\n 10\r\n{}\r\n 20\r\n{}\r\n 50\r\n0\r\n  0\r\n",
                 layer, x, y
-            )
-            .expect("Can not write to file");
+            )?;
         }
-    })
-    .expect("Could not read file");
+        Ok(())
+    })?;
 
-    f.write_all("ENDSEC\r\n  0\r\nEOF\r\n".as_bytes())
-        .expect("Can not write to file");
+    f.write_all("ENDSEC\r\n  0\r\nEOF\r\n".as_bytes())?;
     info!("Done");
     Ok(())
 }
-pub fn knolldetector(
-    fs: &impl FileSystem,
-    config: &Config,
-    tmpfolder: &Path,
-) -> Result<(), Box<dyn Error>> {
+pub fn knolldetector(fs: &impl FileSystem, config: &Config, tmpfolder: &Path) -> Result<()> {
     info!("Detecting knolls...");
     let scalefactor = config.scalefactor;
     let contour_interval = config.contour_interval;
@@ -177,18 +173,16 @@ pub fn knolldetector(
         xyz.insert((x as u64, y as u64), h);
     }
 
-    let data = fs
-        .read_to_string(tmpfolder.join("contours03.dxf"))
-        .expect("Should have been able to read the file");
+    let input = tmpfolder.join("contours03.dxf");
+    let data = fs.read_to_string(&input).with_file(&input)?;
     let data: Vec<&str> = data.split("POLYLINE").collect();
-    let f = fs
-        .create(tmpfolder.join("detected.dxf"))
-        .expect("Unable to create file");
+    let output = tmpfolder.join("detected.dxf");
+    let f = fs.create(&output).with_file(&output)?;
     let mut f = BufWriter::new(f);
     write!(&mut f,
         "  0\r\nSECTION\r\n  2\r\nHEADER\r\n  9\r\n$EXTMIN\r\n 10\r\n{}\r\n 20\r\n{}\r\n  9\r\n$EXTMAX\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\nENDSEC\r\n  0\r\nSECTION\r\n  2\r\nENTITIES\r\n  0\r\n",
         xmin, ymin, xmax, ymax
-    ).expect("Cannot write dxf file");
+    )?;
 
     let mut heads1: HashMap<String, usize> = HashMap::default();
     let mut heads2: HashMap<String, usize> = HashMap::default();
@@ -207,7 +201,10 @@ pub fn knolldetector(
         let mut yline = 0;
         if j > 0 {
             let r = rec.split("VERTEX").collect::<Vec<&str>>();
-            let apu = r[1];
+            let apu = r
+                .get(1)
+                .ok_or_else(|| missing("VERTEX"))
+                .with_file(&input)?;
             let val = apu.split('\n').collect::<Vec<&str>>();
             for (i, v) in val.iter().enumerate() {
                 let vt = v.trim_end();
@@ -222,15 +219,13 @@ pub fn knolldetector(
                 for (i, v) in r.iter().enumerate() {
                     if i > 0 {
                         let val = v.trim_end().split('\n').collect::<Vec<&str>>();
-                        x.push(val[xline].trim().parse::<f64>().unwrap());
-                        y.push(val[yline].trim().parse::<f64>().unwrap());
+                        x.push(vertex_coordinate(&val, xline).with_file(&input)?);
+                        y.push(vertex_coordinate(&val, yline).with_file(&input)?);
                     }
                 }
-                let x0 = x.first().unwrap();
-                let xl = x.last().unwrap();
-
-                let y0 = y.first().unwrap();
-                let yl = y.last().unwrap();
+                // there is at least one vertex
+                let (x0, xl) = (x[0], x[x.len() - 1]);
+                let (y0, yl) = (y[0], y[y.len() - 1]);
 
                 let head = format!("{}x{}", x0, y0);
                 let tail = format!("{}x{}", xl, yl);
@@ -386,10 +381,10 @@ pub fn knolldetector(
                 && el_x[l].first() == el_x[l].last()
                 && el_y[l].first() == el_y[l].last()
             {
-                let tailx = *el_x[l].first().unwrap();
+                let tailx = *el_x[l].first().ok_or_else(|| missing("contour vertex"))?;
                 let mut xl = el_x[l].to_vec();
                 xl.push(tailx);
-                let taily = *el_y[l].first().unwrap();
+                let taily = *el_y[l].first().ok_or_else(|| missing("contour vertex"))?;
                 let mut yl = el_y[l].to_vec();
                 yl.push(taily);
                 let mut mm = ((el_x_len as f64 / 3.0).floor() - 1.0) as i32;
@@ -506,6 +501,12 @@ pub fn knolldetector(
             }
         }
     }
+    let elevation_of = |id: u64| {
+        elevation
+            .get(&id)
+            .copied()
+            .ok_or_else(|| missing("contour elevation"))
+    };
     struct Top {
         id: u64,
         xtest: f64,
@@ -523,11 +524,11 @@ pub fn knolldetector(
         let mut skip = false;
         if !el_x[l].is_empty() {
             let mut x = el_x[l].to_vec();
-            let tailx = *el_x[l].first().unwrap();
+            let tailx = *el_x[l].first().ok_or_else(|| missing("contour vertex"))?;
             x.push(tailx);
 
             let mut y = el_y[l].to_vec();
-            let taily = *el_y[l].first().unwrap();
+            let taily = *el_y[l].first().ok_or_else(|| missing("contour vertex"))?;
             y.push(taily);
 
             let mut minx = f64::MAX;
@@ -563,7 +564,7 @@ pub fn knolldetector(
                 let &Head { id, xtest, ytest } = head;
 
                 if !skip
-                    && *elevation.get(&id).unwrap() > *elevation.get(&(l as u64)).unwrap()
+                    && elevation_of(id)? > elevation_of(l as u64)?
                     && id != (l as u64)
                     && xtest < maxx
                     && xtest > minx
@@ -614,11 +615,11 @@ pub fn knolldetector(
         let mut skip = true;
         if !el_x[l].is_empty() {
             let mut x = el_x[l].to_vec();
-            let tailx = *el_x[l].first().unwrap();
+            let tailx = *el_x[l].first().ok_or_else(|| missing("contour vertex"))?;
             x.push(tailx);
 
             let mut y = el_y[l].to_vec();
-            let taily = *el_y[l].first().unwrap();
+            let taily = *el_y[l].first().ok_or_else(|| missing("contour vertex"))?;
             y.push(taily);
 
             let &BoundingBox {
@@ -626,15 +627,15 @@ pub fn knolldetector(
                 maxx,
                 miny,
                 maxy,
-            } = bb.get(&l).unwrap();
+            } = bb.get(&l).ok_or_else(|| missing("contour bounds"))?;
 
             let mut topid = 0;
             for head in tops.iter() {
                 let &Top { id, xtest, ytest } = head;
                 let ll = l as u64;
 
-                if *elevation.get(&ll).unwrap() < (*elevation.get(&id).unwrap() - 0.1)
-                    && *elevation.get(&ll).unwrap() > (*elevation.get(&id).unwrap() - 4.6)
+                if elevation_of(ll)? < (elevation_of(id)? - 0.1)
+                    && elevation_of(ll)? > (elevation_of(id)? - 4.6)
                     && skip
                     && xtest < maxx
                     && xtest > minx
@@ -686,20 +687,19 @@ pub fn knolldetector(
 
     for head in canditates.iter() {
         let &Candidate { id, topid, .. } = head;
-        let el = *elevation.get(&id).unwrap();
+        let el = elevation_of(id)?;
         let test = (el / halfinterval + 1.0).floor() * halfinterval - el;
 
         if !best.contains_key(&topid) {
             best.insert(topid, id);
             mov.insert(id, test);
         } else {
-            let tid = *best.get(&topid).unwrap();
-            if *mov.get(&tid).unwrap() < 1.75
-                && (*elevation.get(&topid).unwrap() - *elevation.get(&tid).unwrap() - 0.6).abs()
-                    < 0.2
+            let tid = *best.get(&topid).ok_or_else(|| missing("knoll candidate"))?;
+            if *mov.get(&tid).ok_or_else(|| missing("knoll candidate"))? < 1.75
+                && (elevation_of(topid)? - elevation_of(tid)? - 0.6).abs() < 0.2
             {
                 // no action
-            } else if *mov.get(&tid).unwrap() > test {
+            } else if *mov.get(&tid).ok_or_else(|| missing("knoll candidate"))? > test {
                 best.insert(topid, id);
                 mov.insert(id, test);
             }
@@ -715,12 +715,10 @@ pub fn knolldetector(
         } = head;
 
         let x = el_x[id as usize].to_vec();
-        if *best.get(&topid).unwrap() == id
+        if *best.get(&topid).ok_or_else(|| missing("knoll candidate"))? == id
             && (x.len() < 13
-                || (*elevation.get(&topid).unwrap() > (*elevation.get(&id).unwrap() + 0.45)
-                    || (*elevation.get(&id).unwrap()
-                        - 2.5 * (*elevation.get(&id).unwrap() / 2.5).floor())
-                        > 0.45))
+                || (elevation_of(topid)? > (elevation_of(id)? + 0.45)
+                    || (elevation_of(id)? - 2.5 * (elevation_of(id)? / 2.5).floor()) > 0.45))
         {
             new_candidates.push(Candidate {
                 id,
@@ -736,9 +734,8 @@ pub fn knolldetector(
 
     let canditates = new_candidates;
 
-    let file_pins = fs
-        .create(tmpfolder.join("pins.txt"))
-        .expect("Unable to create file");
+    let pins_output = tmpfolder.join("pins.txt");
+    let file_pins = fs.create(&pins_output).with_file(&pins_output)?;
     let mut file_pins = BufWriter::new(file_pins);
 
     for l in 0..data.len() {
@@ -747,11 +744,11 @@ pub fn knolldetector(
         let mut ltopid = 0;
         if !el_x[l].is_empty() {
             let mut x = el_x[l].to_vec();
-            let tailx = *el_x[l].first().unwrap();
+            let tailx = *el_x[l].first().ok_or_else(|| missing("contour vertex"))?;
             x.push(tailx);
 
             let mut y = el_y[l].to_vec();
-            let taily = *el_y[l].first().unwrap();
+            let taily = *el_y[l].first().ok_or_else(|| missing("contour vertex"))?;
             y.push(taily);
 
             let &BoundingBox {
@@ -759,7 +756,7 @@ pub fn knolldetector(
                 maxx,
                 miny,
                 maxy,
-            } = bb.get(&l).unwrap();
+            } = bb.get(&l).ok_or_else(|| missing("contour bounds"))?;
 
             for head in canditates.iter() {
                 let &Candidate {
@@ -799,8 +796,7 @@ pub fn knolldetector(
             }
 
             if !skip {
-                f.write_all("POLYLINE\r\n 66\r\n1\r\n  8\r\n1010\r\n  0\r\n".as_bytes())
-                    .expect("Can not write to file");
+                f.write_all("POLYLINE\r\n 66\r\n1\r\n  8\r\n1010\r\n  0\r\n".as_bytes())?;
                 let mut xa = 0.0;
                 let mut ya = 0.0;
                 for k in 0..x.len() {
@@ -816,10 +812,10 @@ pub fn knolldetector(
                     "{},{},{},{},{},{},{},{}\r\n",
                     x[0],
                     y[0],
-                    *elevation.get(&ll).unwrap(),
+                    elevation_of(ll)?,
                     xa,
                     ya,
-                    *elevation.get(&ltopid).unwrap(),
+                    elevation_of(ltopid)?,
                     x.iter()
                         .map(|x| x.to_string())
                         .collect::<Vec<_>>()
@@ -828,37 +824,29 @@ pub fn knolldetector(
                         .map(|x| x.to_string())
                         .collect::<Vec<_>>()
                         .join(" ")
-                )
-                .expect("Could not write to file");
+                )?;
 
                 for k in 0..x.len() {
                     write!(
                         &mut f,
                         "VERTEX\r\n  8\r\n1010\r\n 10\r\n{}\r\n 20\r\n{}\r\n  0\r\n",
                         x[k], y[k]
-                    )
-                    .expect("Can not write to file");
+                    )?;
                 }
-                f.write_all("SEQEND\r\n  0\r\n".as_bytes())
-                    .expect("Can not write to file");
+                f.write_all("SEQEND\r\n  0\r\n".as_bytes())?;
             } else {
                 el_x[l].clear();
                 el_y[l].clear();
             }
         }
     }
-    f.write_all("ENDSEC\r\n  0\r\nEOF\r\n".as_bytes())
-        .expect("Can not write to file");
+    f.write_all("ENDSEC\r\n  0\r\nEOF\r\n".as_bytes())?;
 
     info!("Done");
     Ok(())
 }
 
-pub fn xyzknolls(
-    fs: &impl FileSystem,
-    config: &Config,
-    tmpfolder: &Path,
-) -> Result<(), Box<dyn Error>> {
+pub fn xyzknolls(fs: &impl FileSystem, config: &Config, tmpfolder: &Path) -> Result<()> {
     info!("Identifying knolls...");
     let scalefactor = config.scalefactor;
     let contour_interval = config.contour_interval;
@@ -919,22 +907,23 @@ pub fn xyzknolls(
 
     let pins_file_in = tmpfolder.join("pins.txt");
     if fs.exists(&pins_file_in) {
-        read_lines_no_alloc(fs, pins_file_in, |line| {
-            let mut r = line.trim().split(',');
-            let ele = r.nth(2).unwrap().parse::<f64>().unwrap();
-            let xx = r.next().unwrap().parse::<f64>().unwrap();
-            let yy = r.next().unwrap().parse::<f64>().unwrap();
-            let ele2 = r.next().unwrap().parse::<f64>().unwrap();
-            let xlist = r.next().unwrap();
-            let ylist = r.next().unwrap();
-            let mut x: Vec<f64> = xlist
+        try_read_lines_no_alloc(fs, pins_file_in, |line| {
+            let r = line.trim().split(',').collect::<Vec<&str>>();
+            let [_, _, ele, xx, yy, ele2, xlist, ylist, ..] = r[..] else {
+                return Err(missing("pin fields"));
+            };
+            let ele = ele.parse::<f64>()?;
+            let xx = xx.parse::<f64>()?;
+            let yy = yy.parse::<f64>()?;
+            let ele2 = ele2.parse::<f64>()?;
+            let mut x = xlist
                 .split(' ')
-                .map(|s| s.parse::<f64>().unwrap())
-                .collect();
-            let mut y: Vec<f64> = ylist
+                .map(|s| s.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()?;
+            let mut y = ylist
                 .split(' ')
-                .map(|s| s.parse::<f64>().unwrap())
-                .collect();
+                .map(|s| s.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()?;
             x.push(x[0]);
             y.push(y[0]);
 
@@ -946,8 +935,8 @@ pub fn xyzknolls(
                 xlist: x,
                 ylist: y,
             });
-        })
-        .expect("could not read pins file");
+            Ok(())
+        })?;
     }
 
     // compute closest distance from each pin to another pin
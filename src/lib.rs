@@ -4,18 +4,28 @@
 #![deny(clippy::undocumented_unsafe_blocks)]
 
 pub mod blocks;
+pub mod cli;
 pub mod cliffs;
 pub mod config;
 pub mod contours;
 pub mod crop;
+pub mod error;
+pub mod ground;
+pub mod interpolation;
 pub mod io;
+pub mod jobs;
 pub mod knolls;
 pub mod merge;
+pub mod pipeline;
 pub mod process;
+pub mod profile;
 pub mod render;
+pub mod report;
+pub mod thinning;
 pub mod util;
 pub mod vec2d;
 pub mod vegetation;
+pub mod workspace;
 
 #[cfg(feature = "shapefile")]
 pub mod shapefile;
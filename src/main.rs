@@ -1,14 +1,22 @@
 use log::debug;
+use log::error;
 use log::info;
-use pullauta::config::Config;
+use pullauta::cli::{self, Cli, Command};
+use pullauta::config::{Config, Preset, RawConfig, Severity, PRESETS};
 use pullauta::io::fs::memory::MemoryFileSystem;
 use pullauta::io::fs::FileSystem;
+use pullauta::process::Batch;
+use pullauta::util::suggest;
+use pullauta::workspace::Workspace;
 use std::env;
+use std::error::Error;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
+use std::process;
 use std::sync::Arc;
-use std::{thread, time};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() {
     // setup and configure logging, default to INFO when RUST_LOG is not set
@@ -30,36 +38,156 @@ fn main() {
         })
         .init();
 
-    let mut thread: String = String::new();
+    let cli = match Cli::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            match e.command().and_then(cli::command_help) {
+                Some(help) => eprintln!("\n{}", help),
+                None => eprintln!("\nRun 'pullauta --help' for the commands and options."),
+            }
+            process::exit(2);
+        }
+    };
 
-    let config =
-        Arc::new(Config::load_or_create_default().expect("Could not open or create config file"));
+    // the help does not need the config
+    match &cli.command {
+        Command::Help { command: None } => {
+            println!("{}", cli::help());
+            return;
+        }
+        Command::Help {
+            command: Some(name),
+        } => match cli::command_help(name) {
+            Some(help) => {
+                println!("{}", help);
+                return;
+            }
+            None => {
+                eprintln!("error: unknown command '{}'", name);
+                process::exit(2);
+            }
+        },
+        Command::Version => {
+            println!("Karttapullautin v{}", VERSION);
+            return;
+        }
+        Command::Presets { name: None } => {
+            if PRESETS.is_empty() {
+                println!("No presets are built in.");
+            }
+            for preset in PRESETS {
+                println!("{:<20}{}", preset.name, preset.about());
+            }
+            return;
+        }
+        Command::Presets { name: Some(name) } => match Preset::find(name) {
+            Some(preset) => {
+                print!("{}", preset.to_ini());
+                return;
+            }
+            None => {
+                match suggest(name, PRESETS.iter().map(|p| p.name)) {
+                    Some(preset) => {
+                        eprintln!(
+                            "error: unknown preset '{}', did you mean '{}'?",
+                            name, preset
+                        )
+                    }
+                    None => eprintln!("error: unknown preset '{}'", name),
+                }
+                eprintln!("\nRun 'pullauta presets' for the list of presets.");
+                process::exit(2);
+            }
+        },
+        _ => {}
+    }
 
-    let fs = pullauta::io::fs::local::LocalFileSystem;
+    // the config paths, also the ones of PULLAUTA_CONFIG, are relative to where pullauta is run,
+    // not to the work directory
+    let config_paths = if cli.config.is_empty() {
+        pullauta::config::env_config_paths()
+    } else {
+        cli.config.clone()
+    };
+    let config_paths: Vec<PathBuf> = config_paths
+        .iter()
+        .map(|path| env::current_dir().unwrap_or_default().join(path))
+        .collect();
+    if let Some(workdir) = &cli.workdir {
+        if let Err(e) = env::set_current_dir(workdir) {
+            eprintln!(
+                "error: could not change to the work directory {}: {}",
+                workdir.display(),
+                e
+            );
+            process::exit(1);
+        }
+    }
 
-    let mut args: Vec<String> = env::args().collect();
+    if cli.command == Command::CheckConfig {
+        process::exit(check_config(&config_paths));
+    }
 
-    args.remove(0); // program name
+    let config = match Config::load(&config_paths, !cli.no_create_config) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
+    };
 
-    if !args.is_empty() && args[0].trim().parse::<usize>().is_ok() {
-        thread = args.remove(0);
+    if let Err(e) = run(cli, config) {
+        error!("{}", e);
+        process::exit(1);
     }
+}
 
-    let command = if !args.is_empty() {
-        args.remove(0)
-    } else {
-        String::new()
+/// Prints the problems found in the config, returns the exit code.
+fn check_config(config_paths: &[PathBuf]) -> i32 {
+    let raw = match RawConfig::read(config_paths, false) {
+        Ok(raw) => raw,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        }
     };
+    let issues = raw.check();
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    let errors = issues
+        .iter()
+        .filter(|i| i.severity == Severity::Error)
+        .count();
+    let files: Vec<String> = raw
+        .files()
+        .iter()
+        .map(|f| f.display().to_string())
+        .collect();
+    println!(
+        "Checked {}: {} error{}, {} warning{}",
+        files.join(", "),
+        errors,
+        if errors == 1 { "" } else { "s" },
+        issues.len() - errors,
+        if issues.len() - errors == 1 { "" } else { "s" }
+    );
+    if errors > 0 {
+        1
+    } else {
+        0
+    }
+}
 
-    let command_lowercase = command.to_lowercase();
+fn run(cli: Cli, config: Arc<Config>) -> Result<(), Box<dyn Error>> {
+    let Cli {
+        thread, command, ..
+    } = cli;
 
-    if command.is_empty()
-        || command_lowercase.ends_with(".las")
-        || command_lowercase.ends_with(".laz")
-        || command_lowercase.ends_with(".xyz")
-        || command_lowercase.ends_with(".xyz.bin")
-    {
-        const VERSION: &str = env!("CARGO_PKG_VERSION");
+    let fs = pullauta::io::fs::local::LocalFileSystem;
+
+    if matches!(command, Command::Default | Command::Process { .. }) {
         println!(
             "Karttapullautin v{}\nThere is no warranty. Use it at your own risk!\n",
             VERSION
@@ -68,348 +196,286 @@ fn main() {
 
     let batch: bool = config.batch;
 
-    let tmpfolder = PathBuf::from(format!("temp{}", thread));
-    fs::create_dir_all(&tmpfolder).expect("Could not create tmp folder");
+    let workspace = Workspace::thread(&thread);
+    let tmpfolder = workspace.tmpfolder();
+    fs::create_dir_all(&tmpfolder)
+        .map_err(|e| format!("Could not create tmp folder {}: {}", tmpfolder.display(), e))?;
 
     let pnorthlinesangle = config.pnorthlinesangle;
     let pnorthlineswidth = config.pnorthlineswidth;
 
-    if command.is_empty() && fs.exists(tmpfolder.join("vegetation.png")) && !batch {
-        info!("Rendering png map with depressions");
-        pullauta::render::render(
-            &fs,
-            &config,
-            &thread,
-            &tmpfolder,
-            pnorthlinesangle,
-            pnorthlineswidth,
-            false,
-        )
-        .unwrap();
-        info!("Rendering png map without depressions");
-        pullauta::render::render(
-            &fs,
-            &config,
-            &thread,
-            &tmpfolder,
-            pnorthlinesangle,
-            pnorthlineswidth,
-            true,
-        )
-        .unwrap();
-        info!("\nAll done!");
-        return;
-    }
-
-    if command.is_empty() && !batch {
-        println!("USAGE:\npullauta [parameter 1] [parameter 2] [parameter 3] ... [parameter n]\nSee README.MD for more details");
-        return;
-    }
-
-    if command == "cliffgeneralize" {
-        info!("Not implemented in this version, use the perl version");
-        return;
-    }
-
-    if command == "ground" {
-        info!("Not implemented in this version, use the perl version");
-        return;
-    }
-
-    if command == "ground2" {
-        info!("Not implemented in this version, use the perl version");
-        return;
-    }
-
-    if command == "groundfix" {
-        info!("Not implemented in this version, use the perl version");
-        return;
-    }
-
-    if command == "profile" {
-        info!("Not implemented in this version, use the perl version");
-        return;
-    }
-
-    if command == "makecliffsold" {
-        info!("Not implemented in this version, use the perl version");
-        return;
-    }
-
-    if command == "makeheight" {
-        info!("Not implemented in this version, use the perl version");
-        return;
-    }
-
-    if command == "xyzfixer" {
-        info!("Not implemented in this version, use the perl version");
-        return;
-    }
-
-    if command == "vege" {
-        info!("Not implemented in this version, use the perl version");
-        return;
-    }
-
-    if command == "internal2xyz" {
-        if args.len() < 2 {
-            info!("USAGE: internal2xyz [input file] [output file]");
-            return;
+    match command {
+        Command::Default if !batch => {
+            if !fs.exists(tmpfolder.join("vegetation.png")) {
+                println!("{}", cli::help());
+                return Ok(());
+            }
+            info!("Rendering png map with depressions");
+            pullauta::render::render(
+                &fs,
+                &config,
+                &workspace,
+                pnorthlinesangle,
+                pnorthlineswidth,
+                false,
+            )?;
+            info!("Rendering png map without depressions");
+            pullauta::render::render(
+                &fs,
+                &config,
+                &workspace,
+                pnorthlinesangle,
+                pnorthlineswidth,
+                true,
+            )?;
+            info!("\nAll done!");
         }
-
-        let input = &args[0];
-        let output = &args[1];
-        pullauta::io::internal2xyz(&fs, input, output).unwrap();
-        return;
-    }
-
-    if command == "blocks" {
-        pullauta::blocks::blocks(&fs, &tmpfolder).unwrap();
-        return;
-    }
-
-    if command == "dotknolls" {
-        pullauta::knolls::dotknolls(&fs, &config, &tmpfolder).unwrap();
-        return;
-    }
-
-    if command == "dxfmerge" || command == "merge" {
-        pullauta::merge::dxfmerge(&fs, &config).unwrap();
-        if command == "merge" {
-            let mut scale = 1.0;
-            if !args.is_empty() {
-                scale = args[0].parse::<f64>().unwrap();
+        Command::Default => batch_process(&fs, &config)?,
+        Command::StartThread { thread } => {
+            if !batch {
+                return Err("startthread needs batch=1 in the config".into());
             }
-            pullauta::merge::pngmergevege(&fs, &config, scale).unwrap();
+            let batch = open_batch(&fs, &config)?;
+            handle_interrupt(fs.clone());
+            let workspace = pullauta::jobs::worker_workspace(&config, &thread);
+            let failed = batch.work(&fs, &config, &workspace)?;
+            report_failed(&fs, &config, failed)?;
         }
-        return;
-    }
-
-    if command == "knolldetector" {
-        pullauta::knolls::knolldetector(&fs, &config, &tmpfolder).unwrap();
-        return;
-    }
-
-    if command == "makecliffs" {
-        pullauta::cliffs::makecliffs(&fs, &config, &tmpfolder).unwrap();
-        return;
-    }
-
-    if command == "makevege" {
-        pullauta::vegetation::makevege(&fs, &config, &tmpfolder).unwrap();
-    }
-
-    if command == "pngmerge" || command == "pngmergedepr" {
-        let mut scale = 4.0;
-        if !args.is_empty() {
-            scale = args[0].parse::<f64>().unwrap();
+        Command::Process { input, norender } => {
+            process_file(&fs, &config, &workspace, &input, norender)?
         }
-        pullauta::merge::pngmerge(&fs, &config, scale, command == "pngmergedepr").unwrap();
-        return;
-    }
-
-    if command == "pngmergevege" {
-        let mut scale = 1.0;
-        if !args.is_empty() {
-            scale = args[0].parse::<f64>().unwrap();
+        Command::Zip { files } => pullauta::process::process_zip(&fs, &config, &workspace, &files)?,
+        Command::Blocks => pullauta::blocks::blocks(&fs, &tmpfolder)?,
+        Command::CliffGeneralize => pullauta::cliffs::cliffgeneralize(&fs, &config, &tmpfolder)?,
+        Command::DotKnolls => pullauta::knolls::dotknolls(&fs, &config, &tmpfolder)?,
+        Command::DxfMerge { vegetation_scale } => {
+            pullauta::merge::dxfmerge(&fs, &config)?;
+            if let Some(scale) = vegetation_scale {
+                pullauta::merge::pngmergevege(&fs, &config, scale)?;
+            }
         }
-        pullauta::merge::pngmergevege(&fs, &config, scale).unwrap();
-        return;
-    }
-
-    if command == "polylinedxfcrop" {
-        let dxffilein = Path::new(&args[0]);
-        let dxffileout = Path::new(&args[1]);
-        let minx = args[2].parse::<f64>().unwrap();
-        let miny = args[3].parse::<f64>().unwrap();
-        let maxx = args[4].parse::<f64>().unwrap();
-        let maxy = args[5].parse::<f64>().unwrap();
-        pullauta::crop::polylinedxfcrop(&fs, dxffilein, dxffileout, minx, miny, maxx, maxy)
-            .unwrap();
-        return;
-    }
-
-    if command == "pointdxfcrop" {
-        let dxffilein = Path::new(&args[0]);
-        let dxffileout = Path::new(&args[1]);
-        let minx = args[2].parse::<f64>().unwrap();
-        let miny = args[3].parse::<f64>().unwrap();
-        let maxx = args[4].parse::<f64>().unwrap();
-        let maxy = args[5].parse::<f64>().unwrap();
-        pullauta::crop::pointdxfcrop(&fs, dxffilein, dxffileout, minx, miny, maxx, maxy).unwrap();
-        return;
-    }
-
-    if command == "smoothjoin" {
-        pullauta::merge::smoothjoin(&fs, &config, &tmpfolder).unwrap();
-    }
-
-    if command == "xyzknolls" {
-        pullauta::knolls::xyzknolls(&fs, &config, &tmpfolder).unwrap();
-    }
-
-    #[cfg(feature = "shapefile")]
-    if command == "unzipmtk" {
-        pullauta::shapefile::unzip_and_render(&fs, &config, &tmpfolder, &args).unwrap();
-    }
-
-    #[cfg(feature = "shapefile")]
-    if command == "mtkshaperender" {
-        pullauta::shapefile::render(&fs, &config, &tmpfolder).unwrap();
-    }
-
-    if command == "xyz2contours" {
-        let cinterval: f64 = args[0].parse::<f64>().unwrap();
-        let xyzfilein = args[1].clone();
-        let xyzfileout = args[2].clone();
-        let dxffile = args[3].clone();
-        let hmap = pullauta::contours::xyz2heightmap(&fs, &config, &tmpfolder, &xyzfilein).unwrap();
-
-        if xyzfileout != "null" && !xyzfileout.is_empty() {
-            hmap.to_file(&fs, xyzfileout).unwrap();
+        Command::DxfCrop {
+            input,
+            output,
+            bounds: [minx, miny, maxx, maxy],
+            points,
+        } => {
+            if points {
+                pullauta::crop::pointdxfcrop(&fs, &input, &output, minx, miny, maxx, maxy)?;
+            } else {
+                pullauta::crop::polylinedxfcrop(&fs, &input, &output, minx, miny, maxx, maxy)?;
+            }
         }
-
-        pullauta::contours::heightmap2contours(&fs, &tmpfolder, cinterval, &hmap, &dxffile)
-            .unwrap();
-        return;
-    }
-
-    if command == "render" {
-        let angle: f64 = args[0].parse::<f64>().unwrap();
-        let nwidth: usize = args[1].parse::<usize>().unwrap();
-        let nodepressions: bool = args.len() > 2 && args[2] == "nodepressions";
-        pullauta::render::render(
-            &fs,
-            &config,
-            &thread,
-            &tmpfolder,
+        Command::ExportHeightmap {
+            input,
+            output,
+            hillshade,
+        } => pullauta::io::raster::export_heightmap(&fs, &config, &input, &output, hillshade)?,
+        Command::Ground { input, output } => {
+            pullauta::ground::ground(&fs, &config, &input, &output)?
+        }
+        Command::Internal2Xyz { input, output } => {
+            pullauta::io::internal2xyz(&fs, &input, &output)?
+        }
+        Command::KnollDetector => pullauta::knolls::knolldetector(&fs, &config, &tmpfolder)?,
+        Command::MakeCliffs => pullauta::cliffs::makecliffs(&fs, &config, &tmpfolder)?,
+        Command::MakeHeight => pullauta::vegetation::makeheight(&fs, &config, &tmpfolder)?,
+        Command::MakeVege => pullauta::vegetation::makevege(&fs, &config, &tmpfolder)?,
+        Command::NotImplemented { .. } => {
+            info!("Not implemented in this version, use the perl version")
+        }
+        Command::PngMerge { scale, depressions } => {
+            pullauta::merge::pngmerge(&fs, &config, scale, depressions)?
+        }
+        Command::PngMergeVege { scale } => pullauta::merge::pngmergevege(&fs, &config, scale)?,
+        Command::Profile { line, output, step } => {
+            pullauta::profile::profile(&fs, &config, &tmpfolder, &line, &output, step)?
+        }
+        Command::Render {
             angle,
             nwidth,
             nodepressions,
-        )
-        .unwrap();
-        return;
-    }
-    let proc = config.processes;
-    if command.is_empty() && batch && proc > 1 {
-        // inner function to reduce code duplication
-        fn launch_threads<F: FileSystem + Send + Clone + 'static>(
-            fs: F,
-            proc: u64,
-            config: &Arc<Config>,
-        ) {
-            // do the processing
-            let mut handles: Vec<thread::JoinHandle<()>> = Vec::with_capacity((proc + 1) as usize);
-            for i in 0..proc {
-                let config = config.clone();
-                let fs = fs.clone();
-                let handle = thread::spawn(move || {
-                    info!("Starting thread");
-                    pullauta::process::batch_process(&config, &fs, &format!("{}", i + 1));
-                    info!("Thread complete");
-                });
-                thread::sleep(time::Duration::from_millis(100));
-                handles.push(handle);
-            }
-            for handle in handles {
-                handle.join().unwrap();
+        } => pullauta::render::render(&fs, &config, &workspace, angle, nwidth, nodepressions)?,
+        Command::SmoothJoin => pullauta::merge::smoothjoin(&fs, &config, &tmpfolder)?,
+        #[cfg(feature = "shapefile")]
+        Command::UnzipMtk { files } => {
+            pullauta::shapefile::unzip_and_render(&fs, &config, &tmpfolder, &files)?
+        }
+        #[cfg(feature = "shapefile")]
+        Command::MtkShapeRender => pullauta::shapefile::render(&fs, &config, &tmpfolder)?,
+        #[cfg(not(feature = "shapefile"))]
+        Command::UnzipMtk { .. } | Command::MtkShapeRender => {
+            return Err("This version of pullauta is built without shape file support".into())
+        }
+        Command::Vege => pullauta::vegetation::makevege(&fs, &config, &tmpfolder)?,
+        Command::Xyz2Contours {
+            interval,
+            input,
+            heightmap,
+            dxf,
+        } => {
+            let hmap = pullauta::contours::xyz2heightmap(&fs, &config, &tmpfolder, &input)?;
+            if let Some(heightmap) = heightmap {
+                hmap.to_file(&fs, heightmap)?;
             }
+            pullauta::contours::heightmap2contours(
+                &fs, &config, &tmpfolder, interval, &hmap, &dxf,
+            )?;
         }
+        Command::XyzFixer { input, output } => {
+            pullauta::io::text::xyzfixer(&fs, &config, &input, &output)?
+        }
+        Command::XyzKnolls => pullauta::knolls::xyzknolls(&fs, &config, &tmpfolder)?,
+        // handled before the config is loaded
+        Command::Help { .. }
+        | Command::Version
+        | Command::CheckConfig
+        | Command::Presets { .. } => {}
+    }
+    Ok(())
+}
 
-        if config.experimental_use_in_memory_fs {
-            // copy all the input files into the memory file system
-            let fs = pullauta::io::fs::memory::MemoryFileSystem::new();
-            fs.create_dir_all(&config.lazfolder).unwrap();
-            for file in fs::read_dir(&config.lazfolder).unwrap() {
-                let file = file.unwrap();
-                let path = file.path();
-                println!("Copying {} into memory fs", path.display());
-                fs.load_from_disk(&path, &path).unwrap();
-            }
+/// Processes all the files of the input folder, in as many threads as configured.
+fn batch_process(
+    fs: &pullauta::io::fs::local::LocalFileSystem,
+    config: &Arc<Config>,
+) -> Result<(), Box<dyn Error>> {
+    let batch = open_batch(fs, config)?;
+    let workers = config.processes.max(1) as usize;
+
+    if config.experimental_use_in_memory_fs {
+        // copy all the input files into the memory file system
+        let fs = MemoryFileSystem::new();
+        fs.create_dir_all(&config.lazfolder)?;
+        fs.create_dir_all(&config.batchoutfolder)?;
+        for file in fs::read_dir(&config.lazfolder)? {
+            let path = file?.path();
+            println!("Copying {} into memory fs", path.display());
+            fs.load_from_disk(&path, &path)?;
+        }
 
-            launch_threads(fs.clone(), proc, &config);
+        let result = batch
+            .run(&fs, config, workers)
+            .map_err(Box::from)
+            .and_then(|failed| report_failed(&fs, config, failed));
 
-            // copy the output files back to disk
-            std::fs::create_dir_all(&config.batchoutfolder).unwrap();
-            for path in fs.list(&config.batchoutfolder).unwrap() {
-                info!("Copying {} from memory fs to disk", path.display());
-                fs.save_to_disk(&path, &path).unwrap();
-            }
-        } else {
-            launch_threads(fs, proc, &config);
+        // copy the output files back to disk
+        std::fs::create_dir_all(&config.batchoutfolder)?;
+        for path in fs.list(&config.batchoutfolder)? {
+            info!("Copying {} from memory fs to disk", path.display());
+            fs.save_to_disk(&path, &path)?;
         }
-        return;
+        result
+    } else {
+        handle_interrupt(fs.clone());
+        let failed = batch.run(fs, config, workers)?;
+        report_failed(fs, config, failed)
     }
+}
 
-    if (command.is_empty() && batch && proc < 2) || (command == "startthread" && batch) {
-        thread = String::from("0");
-        if !args.is_empty() {
-            thread.clone_from(&args[0]);
-        }
-        if thread == "0" {
-            thread = String::from("");
-        }
-        pullauta::process::batch_process(&config, &fs, &thread)
+/// Lists the tiles of the input folder of a batch and updates their index.
+fn open_batch(
+    fs: &pullauta::io::fs::local::LocalFileSystem,
+    config: &Config,
+) -> Result<Batch, Box<dyn Error>> {
+    let batch = Batch::open(fs, config).map_err(|e| {
+        format!(
+            "Could not index the input folder {}: {}",
+            config.lazfolder, e
+        )
+    })?;
+    if config.crs.is_none() {
+        batch.index().warn_on_crs_mismatch();
     }
+    Ok(batch)
+}
 
-    if command_lowercase.ends_with(".zip") {
-        let mut zips: Vec<String> = vec![command];
-        zips.extend(args);
-        pullauta::process::process_zip(&fs, &config, &thread, &tmpfolder, &zips).unwrap();
-        return;
+/// On Ctrl-C, removes the partial outputs and markers of the tiles being processed before
+/// exiting, so the next run starts them again right away.
+fn handle_interrupt<F: FileSystem + Send + 'static>(fs: F) {
+    let result = ctrlc::set_handler(move || {
+        let active = pullauta::jobs::interrupt(&fs);
+        error!(
+            "Interrupted, removed the partial outputs of {} tile(s)",
+            active
+        );
+        process::exit(130);
+    });
+    if let Err(e) = result {
+        debug!("Could not set the Ctrl-C handler: {}", e);
     }
+}
 
-    if command_lowercase.ends_with(".las")
-        || command_lowercase.ends_with(".laz")
-        || command_lowercase.ends_with(".xyz")
-        || command_lowercase.ends_with(".xyz.bin")
-    {
-        let mut norender: bool = false;
-        if args.len() > 1 {
-            norender = args[1].clone() == "norender";
-        }
-
-        if config.experimental_use_in_memory_fs {
-            let fs = pullauta::io::fs::memory::MemoryFileSystem::new();
-
-            debug!("Copying input file into memory fs: {}", command);
-            // copy the input file into the memory file system
-            fs.load_from_disk(Path::new(&command), Path::new("input.laz"))
-                .expect("Could not copy input file into memory fs");
-
-            debug!("Done");
+/// Writes the report of the failed tiles of the output folder and the index of the QA reports,
+/// and fails the batch when any of its tiles failed.
+fn report_failed(
+    fs: &impl FileSystem,
+    config: &Config,
+    failed: usize,
+) -> Result<(), Box<dyn Error>> {
+    pullauta::report::write_report_index(fs, config)?;
+    let reported = pullauta::jobs::write_failure_report(fs, config)?;
+    if reported > 0 {
+        info!(
+            "{} failed tile(s) are listed in {}/{}",
+            reported,
+            config.batchoutfolder,
+            pullauta::jobs::FAILURE_REPORT
+        );
+    }
+    check_failed(failed)
+}
 
-            pullauta::process::process_tile(
-                &fs,
-                &config,
-                &thread,
-                &tmpfolder,
-                // Path::new(&command),
-                Path::new("input.laz"),
-                norender,
-            )
-            .unwrap();
+/// Fails a batch when any of its tiles failed, after the others have been processed.
+fn check_failed(failed: usize) -> Result<(), Box<dyn Error>> {
+    if failed > 0 {
+        return Err(format!("{} tile(s) failed, see the errors above", failed).into());
+    }
+    Ok(())
+}
 
-            // now write the output files to disk
-            fn copy(fs: &MemoryFileSystem, name: &str) {
-                if fs.exists(name) {
-                    info!("Copying {} from memory fs to disk", name);
-                    fs.save_to_disk(name, name)
-                        .expect("Could not copy from memory fs to disk");
-                }
-            }
-            copy(&fs, "pullautus.png");
-            copy(&fs, "pullautus_depr.png");
-        } else {
-            pullauta::process::process_tile(
-                &fs,
-                &config,
-                &thread,
-                &tmpfolder,
-                Path::new(&command),
-                norender,
-            )
-            .unwrap();
+/// Processes a single point cloud or elevation raster.
+fn process_file(
+    fs: &pullauta::io::fs::local::LocalFileSystem,
+    config: &Config,
+    workspace: &Workspace,
+    input: &Path,
+    norender: bool,
+) -> Result<(), Box<dyn Error>> {
+    if !config.experimental_use_in_memory_fs {
+        pullauta::process::process_tile(fs, config, workspace, input, norender)?;
+        return Ok(());
+    }
+
+    let fs = MemoryFileSystem::new();
+
+    debug!("Copying input file into memory fs: {}", input.display());
+    // copy the input file into the memory file system, keeping its name so that the
+    // format can be told from the extension
+    let name = input
+        .file_name()
+        .map(PathBuf::from)
+        .ok_or("No file name for input file")?;
+    fs.load_from_disk(input, &name)?;
+    // the coordinate reference system of ASCII grids is in a .prj file next to them
+    let prj = input.with_extension("prj");
+    if prj.exists() {
+        fs.load_from_disk(&prj, name.with_extension("prj"))?;
+    }
+    if let Some(vegetation_raster) = &config.vegetation_raster {
+        fs.load_from_disk(vegetation_raster, vegetation_raster)?;
+    }
+
+    debug!("Done");
+
+    pullauta::process::process_tile(&fs, config, workspace, &name, norender)?;
+
+    // now write the output files to disk
+    for name in [workspace.map(true, "png"), workspace.map(false, "png")] {
+        if fs.exists(&name) {
+            info!("Copying {} from memory fs to disk", name.display());
+            fs.save_to_disk(&name, &name)?;
         }
     }
+    Ok(())
 }
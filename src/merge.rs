@@ -1,14 +1,19 @@
 use image::{Rgb, RgbImage};
-use log::info;
+use log::{info, warn};
 use rustc_hash::FxHashMap as HashMap;
-use std::error::Error;
 use std::io::{BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
 
 use crate::config::Config;
+use crate::error::{missing, Result, ResultExt};
 use crate::io::bytes::FromToBytes;
+use crate::io::crs::Crs;
+use crate::io::dxf::vertex_coordinate;
 use crate::io::fs::FileSystem;
+use crate::io::geotiff::png_to_geotiff;
 use crate::io::heightmap::HeightMap;
+use crate::io::tileindex::{TileIndex, TILE_INDEX_FILE};
+use crate::io::{read_world_file, write_png};
 use crate::vec2d::Vec2D;
 
 fn merge_png(
@@ -17,7 +22,7 @@ fn merge_png(
     png_files: Vec<PathBuf>,
     outfilename: &str,
     scale: f64,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<()> {
     let batchoutfolder = &config.batchoutfolder;
 
     let mut xmin = f64::MAX;
@@ -26,22 +31,22 @@ fn merge_png(
     let mut ymax = f64::MIN;
     let mut res = f64::NAN;
     for png in png_files.iter() {
-        let filename = png.as_path().file_name().unwrap().to_str().unwrap();
+        let Some(filename) = png.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
         let full_filename = format!("{}/{}", batchoutfolder, filename);
-        let img = fs
-            .read_image_png(&full_filename)
-            .expect("Opening image failed");
+        // empty files are placeholders of tiles that are not done
+        if fs.file_size(&full_filename).with_file(&full_filename)? == 0 {
+            continue;
+        }
+        let img = fs.read_image_png(&full_filename)?;
 
         let width = img.width() as f64;
         let height = img.height() as f64;
         let pgw = full_filename.replace(".png", ".pgw");
         let input = Path::new(&pgw);
         if fs.exists(input) {
-            let data = fs.read_to_string(input).expect("Can not read input file");
-            let d: Vec<&str> = data.split('\n').collect();
-            let tfw0 = d[0].trim().parse::<f64>().unwrap();
-            let tfw4 = d[4].trim().parse::<f64>().unwrap();
-            let tfw5 = d[5].trim().parse::<f64>().unwrap();
+            let [tfw0, _, _, _, tfw4, tfw5] = read_world_file(fs, input)?;
 
             if res.is_nan() {
                 res = tfw0;
@@ -66,21 +71,19 @@ fn merge_png(
         Rgb([255, 255, 255]),
     );
     for png in png_files.iter() {
-        let filename = png.as_path().file_name().unwrap().to_str().unwrap();
+        let Some(filename) = png.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
         let png = format!("{}/{}", batchoutfolder, filename);
         let pgw = png.replace(".png", ".pgw");
         let png = Path::new(&png);
         let pgw = Path::new(&pgw);
-        let filesize = fs.file_size(png).unwrap();
-        if fs.exists(png) && fs.exists(pgw) && filesize > 0 {
-            let img = fs.read_image_png(png).expect("Opening image failed");
+        if fs.exists(png) && fs.exists(pgw) && fs.file_size(png).with_file(png)? > 0 {
+            let img = fs.read_image_png(png)?;
             let width = img.width() as f64;
             let height = img.height() as f64;
 
-            let data = fs.read_to_string(pgw).expect("Can not read input file");
-            let d: Vec<&str> = data.split('\n').collect();
-            let tfw4 = d[4].trim().parse::<f64>().unwrap();
-            let tfw5 = d[5].trim().parse::<f64>().unwrap();
+            let [_, _, _, _, tfw4, tfw5] = read_world_file(fs, pgw)?;
 
             let img2 = image::imageops::thumbnail(
                 &img.to_rgb8(),
@@ -96,27 +99,17 @@ fn merge_png(
         }
     }
 
+    let jpg = format!("{}.jpg", outfilename);
     im.write_to(
-        &mut BufWriter::new(
-            fs.create(format!("{}.jpg", outfilename))
-                .expect("could not save output jpg"),
-        ),
+        &mut BufWriter::new(fs.create(&jpg).with_file(&jpg)?),
         image::ImageFormat::Jpeg,
     )
-    .expect("could not save output jpg");
+    .with_file(&jpg)?;
 
-    im.write_to(
-        &mut BufWriter::new(
-            fs.create(format!("{}.png", outfilename))
-                .expect("could not save output png"),
-        ),
-        image::ImageFormat::Png,
-    )
-    .expect("could not save output Png");
+    write_png(fs, &im, format!("{}.png", outfilename))?;
 
-    let tfw_file = fs
-        .create(format!("{}.pgw", outfilename))
-        .expect("Unable to create file");
+    let pgw = format!("{}.pgw", outfilename);
+    let tfw_file = fs.create(&pgw).with_file(&pgw)?;
     let mut tfw_out = BufWriter::new(tfw_file);
     write!(
         &mut tfw_out,
@@ -125,27 +118,57 @@ fn merge_png(
         -res * scale,
         xmin,
         ymax
-    )
-    .expect("Could not write to file");
-    tfw_out.flush().expect("Cannot flush");
+    )?;
+    tfw_out.flush()?;
+    drop(tfw_out);
     fs.copy(
         Path::new(&format!("{}.pgw", outfilename)),
         Path::new(&format!("{}.jgw", outfilename)),
-    )
-    .expect("Could not copy file");
+    )?;
+
+    let crs = merged_crs(fs, config);
+    Crs::write_prj(crs.as_ref(), fs, Path::new(&format!("{}.pgw", outfilename)))?;
+    if config.geotiff {
+        png_to_geotiff(fs, Path::new(&format!("{}.png", outfilename)), crs.as_ref())?;
+    }
     Ok(())
 }
 
-pub fn pngmergevege(
-    fs: &impl FileSystem,
-    config: &Config,
-    scale: f64,
-) -> Result<(), Box<dyn Error>> {
+/// Returns the coordinate reference system of the merged outputs: the one set in the config, or
+/// else the first one of the indexed input files. Warns if the input files disagree.
+fn merged_crs(fs: &impl FileSystem, config: &Config) -> Option<Crs> {
+    if let Some(crs) = &config.crs {
+        return Some(crs.clone());
+    }
+    let index_file = Path::new(&config.lazfolder).join(TILE_INDEX_FILE);
+    let index = TileIndex::from_file(fs, index_file).ok()?;
+    let mut crs = None;
+    for entry in index.entries {
+        let Some(entry_crs) = entry.crs else {
+            continue;
+        };
+        match &crs {
+            None => crs = Some(entry_crs),
+            Some(first) if *first != entry_crs => {
+                warn!(
+                    "{} is in {} but the merged outputs use {}, set crs in the config to choose",
+                    entry.filename, entry_crs, first
+                );
+            }
+            Some(_) => {}
+        }
+    }
+    crs
+}
+
+pub fn pngmergevege(fs: &impl FileSystem, config: &Config, scale: f64) -> Result<()> {
     let batchoutfolder = &config.batchoutfolder;
 
     let mut png_files: Vec<PathBuf> = Vec::new();
-    for path in fs.list(batchoutfolder).unwrap() {
-        let filename = path.file_name().unwrap().to_str().unwrap();
+    for path in fs.list(batchoutfolder).with_file(batchoutfolder)? {
+        let Some(filename) = path.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
         if filename.ends_with("_vege.png") {
             png_files.push(path);
         }
@@ -154,21 +177,18 @@ pub fn pngmergevege(
         info!("No _vege.png files found in output directory");
         return Ok(());
     }
-    merge_png(fs, config, png_files, "merged_vege", scale).unwrap();
+    merge_png(fs, config, png_files, "merged_vege", scale)?;
     Ok(())
 }
 
-pub fn pngmerge(
-    fs: &impl FileSystem,
-    config: &Config,
-    scale: f64,
-    depr: bool,
-) -> Result<(), Box<dyn Error>> {
+pub fn pngmerge(fs: &impl FileSystem, config: &Config, scale: f64, depr: bool) -> Result<()> {
     let batchoutfolder = &config.batchoutfolder;
 
     let mut png_files: Vec<PathBuf> = Vec::new();
-    for path in fs.list(batchoutfolder).unwrap() {
-        let filename = path.file_name().unwrap().to_str().unwrap();
+    for path in fs.list(batchoutfolder).with_file(batchoutfolder)? {
+        let Some(filename) = path.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
         if filename.ends_with(".png")
             && !filename.ends_with("_undergrowth.png")
             && !filename.ends_with("_undergrowth_bit.png")
@@ -189,15 +209,15 @@ pub fn pngmerge(
     if depr {
         outfilename = "merged_depr";
     }
-    merge_png(fs, config, png_files, outfilename, scale).unwrap();
+    merge_png(fs, config, png_files, outfilename, scale)?;
     Ok(())
 }
 
-pub fn dxfmerge(fs: &impl FileSystem, config: &Config) -> Result<(), Box<dyn Error>> {
+pub fn dxfmerge(fs: &impl FileSystem, config: &Config) -> Result<()> {
     let batchoutfolder = &config.batchoutfolder;
 
     let mut dxf_files: Vec<PathBuf> = Vec::new();
-    for path in fs.list(batchoutfolder).unwrap() {
+    for path in fs.list(batchoutfolder).with_file(batchoutfolder)? {
         if let Some(extension) = path.extension() {
             if extension == "dxf" {
                 dxf_files.push(path);
@@ -210,11 +230,11 @@ pub fn dxfmerge(fs: &impl FileSystem, config: &Config) -> Result<(), Box<dyn Err
         return Ok(());
     }
 
-    let out2_file = fs.create("merged.dxf").expect("Unable to create file");
+    let out2_file = fs.create("merged.dxf").with_file("merged.dxf")?;
     let mut out2 = BufWriter::new(out2_file);
     let out_file = fs
         .create("merged_contours.dxf")
-        .expect("Unable to create file");
+        .with_file("merged_contours.dxf")?;
     let mut out = BufWriter::new(out_file);
 
     let mut headprinted = false;
@@ -222,123 +242,113 @@ pub fn dxfmerge(fs: &impl FileSystem, config: &Config) -> Result<(), Box<dyn Err
     let mut headout = String::new();
 
     for dx in dxf_files.iter() {
-        let dxf = dx.as_path().file_name().unwrap().to_str().unwrap();
+        let Some(dxf) = dx.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
         let dxf_filename = format!("{}/{}", batchoutfolder, dxf);
         let input = Path::new(&dxf_filename);
         if fs.exists(input) && dxf_filename.ends_with("contours.dxf") {
-            let data = fs.read_to_string(input).expect("Can not read input file");
+            let data = fs.read_to_string(input).with_file(input)?;
             if data.contains("POLYLINE") {
                 let d: Vec<&str> = data.splitn(2, "POLYLINE").collect();
                 let head = d[0];
                 let body = d[1];
                 let d: Vec<&str> = body.splitn(2, "ENDSEC").collect();
                 let body = d[0];
-                footer = String::from(d[1]);
+                footer = String::from(*d.get(1).ok_or_else(|| missing("ENDSEC")).with_file(input)?);
 
                 if !headprinted {
                     headout = String::from(head);
-                    out.write_all(head.as_bytes())
-                        .expect("Could not write to file");
-                    out2.write_all(head.as_bytes())
-                        .expect("Could not write to file");
+                    out.write_all(head.as_bytes())?;
+                    out2.write_all(head.as_bytes())?;
                     headprinted = true;
                 }
 
-                out.write_all("POLYLINE".as_bytes())
-                    .expect("Could not write to file");
-                out.write_all(body.as_bytes())
-                    .expect("Could not write to file");
+                out.write_all("POLYLINE".as_bytes())?;
+                out.write_all(body.as_bytes())?;
 
                 let plines: Vec<&str> = body.split("POLYLINE").collect();
                 for pl in plines.iter() {
                     if !pl.contains("_intermed") {
-                        out2.write_all("POLYLINE".as_bytes())
-                            .expect("Could not write to file");
-                        out2.write_all(pl.as_bytes())
-                            .expect("Could not write to file");
+                        out2.write_all("POLYLINE".as_bytes())?;
+                        out2.write_all(pl.as_bytes())?;
                     }
                 }
             }
         }
     }
-    write!(&mut out, "ENDSEC{}", &footer).expect("Could not write to file");
+    write!(&mut out, "ENDSEC{}", &footer)?;
 
     headprinted = false;
 
-    let out_file = fs.create("merged_c2f.dxf").expect("Unable to create file");
+    let out_file = fs.create("merged_c2f.dxf").with_file("merged_c2f.dxf")?;
     let mut out = BufWriter::new(out_file);
 
     for dx in dxf_files.iter() {
-        let dxf = dx.as_path().file_name().unwrap().to_str().unwrap();
+        let Some(dxf) = dx.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
         let dxf_filename = format!("{}/{}", batchoutfolder, dxf);
         let input = Path::new(&dxf_filename);
         if fs.exists(input) && dxf_filename.ends_with("_c2f.dxf") {
-            let data = fs.read_to_string(input).expect("Can not read input file");
+            let data = fs.read_to_string(input).with_file(input)?;
             if data.contains("POLYLINE") {
                 let d: Vec<&str> = data.splitn(2, "POLYLINE").collect();
                 let body = d[1];
                 let d: Vec<&str> = body.splitn(2, "ENDSEC").collect();
                 let body = d[0];
-                footer = String::from(d[1]);
+                footer = String::from(*d.get(1).ok_or_else(|| missing("ENDSEC")).with_file(input)?);
 
                 if !headprinted {
-                    out.write_all(headout.as_bytes())
-                        .expect("Could not write to file");
+                    out.write_all(headout.as_bytes())?;
                     headprinted = true;
                 }
 
-                out.write_all("POLYLINE".as_bytes())
-                    .expect("Could not write to file");
-                out.write_all(body.as_bytes())
-                    .expect("Could not write to file");
+                out.write_all("POLYLINE".as_bytes())?;
+                out.write_all(body.as_bytes())?;
 
-                out2.write_all("POLYLINE".as_bytes())
-                    .expect("Could not write to file");
-                out2.write_all(body.as_bytes())
-                    .expect("Could not write to file");
+                out2.write_all("POLYLINE".as_bytes())?;
+                out2.write_all(body.as_bytes())?;
             }
         }
     }
-    write!(&mut out, "ENDSEC{}", &footer).expect("Could not write to file");
+    write!(&mut out, "ENDSEC{}", &footer)?;
 
     headprinted = false;
 
-    let out_file = fs.create("merged_c2.dxf").expect("Unable to create file");
+    let out_file = fs.create("merged_c2.dxf").with_file("merged_c2.dxf")?;
     let mut out = BufWriter::new(out_file);
 
     for dx in dxf_files.iter() {
-        let dxf = dx.as_path().file_name().unwrap().to_str().unwrap();
+        let Some(dxf) = dx.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
         let dxf_filename = format!("{}/{}", batchoutfolder, dxf);
         let input = Path::new(&dxf_filename);
         if fs.exists(input) && dxf_filename.ends_with("_c2g.dxf") {
-            let data = fs.read_to_string(input).expect("Can not read input file");
+            let data = fs.read_to_string(input).with_file(input)?;
             if data.contains("POLYLINE") {
                 let d: Vec<&str> = data.splitn(2, "POLYLINE").collect();
                 let body = d[1];
                 let d: Vec<&str> = body.splitn(2, "ENDSEC").collect();
                 let body = d[0];
-                footer = String::from(d[1]);
+                footer = String::from(*d.get(1).ok_or_else(|| missing("ENDSEC")).with_file(input)?);
 
                 if !headprinted {
-                    out.write_all(headout.as_bytes())
-                        .expect("Could not write to file");
+                    out.write_all(headout.as_bytes())?;
                     headprinted = true;
                 }
 
-                out.write_all("POLYLINE".as_bytes())
-                    .expect("Could not write to file");
-                out.write_all(body.as_bytes())
-                    .expect("Could not write to file");
+                out.write_all("POLYLINE".as_bytes())?;
+                out.write_all(body.as_bytes())?;
 
-                out2.write_all("POLYLINE".as_bytes())
-                    .expect("Could not write to file");
-                out2.write_all(body.as_bytes())
-                    .expect("Could not write to file");
+                out2.write_all("POLYLINE".as_bytes())?;
+                out2.write_all(body.as_bytes())?;
             }
         }
     }
 
-    write!(&mut out, "ENDSEC{}", &footer).expect("Could not write to file");
+    write!(&mut out, "ENDSEC{}", &footer)?;
 
     headprinted = false;
 
@@ -347,203 +357,205 @@ pub fn dxfmerge(fs: &impl FileSystem, config: &Config) -> Result<(), Box<dyn Err
     if basemapcontours > 0.0 {
         let out_file = fs
             .create("merged_basemap.dxf")
-            .expect("Unable to create file");
+            .with_file("merged_basemap.dxf")?;
         let mut out = BufWriter::new(out_file);
 
         for dx in dxf_files.iter() {
-            let dxf = dx.as_path().file_name().unwrap().to_str().unwrap();
+            let Some(dxf) = dx.file_name().and_then(|f| f.to_str()) else {
+                continue;
+            };
             let dxf_filename = format!("{}/{}", batchoutfolder, dxf);
             let input = Path::new(&dxf_filename);
             if fs.exists(input) && dxf_filename.ends_with("_basemap.dxf") {
-                let data = fs.read_to_string(input).expect("Can not read input file");
+                let data = fs.read_to_string(input).with_file(input)?;
                 if data.contains("POLYLINE") {
                     let d: Vec<&str> = data.splitn(2, "POLYLINE").collect();
                     let body = d[1];
                     let d: Vec<&str> = body.splitn(2, "ENDSEC").collect();
                     let body = d[0];
-                    footer = String::from(d[1]);
+                    footer =
+                        String::from(*d.get(1).ok_or_else(|| missing("ENDSEC")).with_file(input)?);
 
                     if !headprinted {
-                        out.write_all(headout.as_bytes())
-                            .expect("Could not write to file");
+                        out.write_all(headout.as_bytes())?;
                         headprinted = true;
                     }
 
-                    out.write_all("POLYLINE".as_bytes())
-                        .expect("Could not write to file");
-                    out.write_all(body.as_bytes())
-                        .expect("Could not write to file");
+                    out.write_all("POLYLINE".as_bytes())?;
+                    out.write_all(body.as_bytes())?;
 
-                    out2.write_all("POLYLINE".as_bytes())
-                        .expect("Could not write to file");
-                    out2.write_all(body.as_bytes())
-                        .expect("Could not write to file");
+                    out2.write_all("POLYLINE".as_bytes())?;
+                    out2.write_all(body.as_bytes())?;
                 }
             }
         }
-        write!(&mut out, "ENDSEC{}", &footer).expect("Could not write to file");
+        write!(&mut out, "ENDSEC{}", &footer)?;
 
         headprinted = false;
     }
 
-    let out_file = fs.create("merged_c3.dxf").expect("Unable to create file");
+    let out_file = fs.create("merged_c3.dxf").with_file("merged_c3.dxf")?;
     let mut out = BufWriter::new(out_file);
 
     for dx in dxf_files.iter() {
-        let dxf = dx.as_path().file_name().unwrap().to_str().unwrap();
+        let Some(dxf) = dx.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
         let dxf_filename = format!("{}/{}", batchoutfolder, dxf);
         let input = Path::new(&dxf_filename);
         if fs.exists(input) && dxf_filename.ends_with("_c3g.dxf") {
-            let data = fs.read_to_string(input).expect("Can not read input file");
+            let data = fs.read_to_string(input).with_file(input)?;
             if data.contains("POLYLINE") {
                 let d: Vec<&str> = data.splitn(2, "POLYLINE").collect();
                 let body = d[1];
                 let d: Vec<&str> = body.splitn(2, "ENDSEC").collect();
                 let body = d[0];
-                footer = String::from(d[1]);
+                footer = String::from(*d.get(1).ok_or_else(|| missing("ENDSEC")).with_file(input)?);
 
                 if !headprinted {
-                    out.write_all(headout.as_bytes())
-                        .expect("Could not write to file");
+                    out.write_all(headout.as_bytes())?;
                     headprinted = true;
                 }
 
-                out.write_all("POLYLINE".as_bytes())
-                    .expect("Could not write to file");
-                out.write_all(body.as_bytes())
-                    .expect("Could not write to file");
+                out.write_all("POLYLINE".as_bytes())?;
+                out.write_all(body.as_bytes())?;
 
-                out2.write_all("POLYLINE".as_bytes())
-                    .expect("Could not write to file");
-                out2.write_all(body.as_bytes())
-                    .expect("Could not write to file");
+                out2.write_all("POLYLINE".as_bytes())?;
+                out2.write_all(body.as_bytes())?;
             }
         }
     }
-    write!(&mut out, "ENDSEC{}", &footer).expect("Could not write to file");
+    write!(&mut out, "ENDSEC{}", &footer)?;
 
     headprinted = false;
 
-    let out_file = fs.create("formlines.dxf").expect("Unable to create file");
+    let out_file = fs.create("formlines.dxf").with_file("formlines.dxf")?;
     let mut out = BufWriter::new(out_file);
 
     for dx in dxf_files.iter() {
-        let dxf = dx.as_path().file_name().unwrap().to_str().unwrap();
+        let Some(dxf) = dx.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
         let dxf_filename = format!("{}/{}", batchoutfolder, dxf);
         let input = Path::new(&dxf_filename);
         if fs.exists(input) && dxf_filename.ends_with("_formlines.dxf") {
-            let data = fs.read_to_string(input).expect("Can not read input file");
+            let data = fs.read_to_string(input).with_file(input)?;
             if data.contains("POLYLINE") {
                 let d: Vec<&str> = data.splitn(2, "POLYLINE").collect();
                 let body = d[1];
                 let d: Vec<&str> = body.splitn(2, "ENDSEC").collect();
                 let body = d[0];
-                footer = String::from(d[1]);
+                footer = String::from(*d.get(1).ok_or_else(|| missing("ENDSEC")).with_file(input)?);
 
                 if !headprinted {
-                    out.write_all(headout.as_bytes())
-                        .expect("Could not write to file");
+                    out.write_all(headout.as_bytes())?;
                     headprinted = true;
                 }
 
-                out.write_all("POLYLINE".as_bytes())
-                    .expect("Could not write to file");
-                out.write_all(body.as_bytes())
-                    .expect("Could not write to file");
+                out.write_all("POLYLINE".as_bytes())?;
+                out.write_all(body.as_bytes())?;
 
-                out2.write_all("POLYLINE".as_bytes())
-                    .expect("Could not write to file");
-                out2.write_all(body.as_bytes())
-                    .expect("Could not write to file");
+                out2.write_all("POLYLINE".as_bytes())?;
+                out2.write_all(body.as_bytes())?;
             }
         }
     }
-    write!(&mut out, "ENDSEC{}", &footer).expect("Could not write to file");
+    write!(&mut out, "ENDSEC{}", &footer)?;
 
     headprinted = false;
 
     let out_file = fs
         .create("merged_dotknolls.dxf")
-        .expect("Unable to create file");
+        .with_file("merged_dotknolls.dxf")?;
     let mut out = BufWriter::new(out_file);
 
     for dx in dxf_files.iter() {
-        let dxf = dx.as_path().file_name().unwrap().to_str().unwrap();
+        let Some(dxf) = dx.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
         let dxf_filename = format!("{}/{}", batchoutfolder, dxf);
         let input = Path::new(&dxf_filename);
         if fs.exists(input) && dxf_filename.ends_with("_dotknolls.dxf") {
-            let data = fs.read_to_string(input).expect("Can not read input file");
+            let data = fs.read_to_string(input).with_file(input)?;
             if data.contains("POINT") {
                 let d: Vec<&str> = data.splitn(2, "POINT").collect();
                 let body = d[1];
                 let d: Vec<&str> = body.splitn(2, "ENDSEC").collect();
                 let body = d[0];
-                footer = String::from(d[1]);
+                footer = String::from(*d.get(1).ok_or_else(|| missing("ENDSEC")).with_file(input)?);
 
                 if !headprinted {
-                    out.write_all(headout.as_bytes())
-                        .expect("Could not write to file");
+                    out.write_all(headout.as_bytes())?;
                     headprinted = true;
                 }
 
-                out.write_all("POINT".as_bytes())
-                    .expect("Could not write to file");
-                out.write_all(body.as_bytes())
-                    .expect("Could not write to file");
+                out.write_all("POINT".as_bytes())?;
+                out.write_all(body.as_bytes())?;
 
-                out2.write_all("POINT".as_bytes())
-                    .expect("Could not write to file");
-                out2.write_all(body.as_bytes())
-                    .expect("Could not write to file");
+                out2.write_all("POINT".as_bytes())?;
+                out2.write_all(body.as_bytes())?;
             }
         }
     }
-    write!(&mut out, "ENDSEC{}", &footer).expect("Could not write to file");
+    write!(&mut out, "ENDSEC{}", &footer)?;
 
     headprinted = false;
 
     let out_file = fs
         .create("merged_detected.dxf")
-        .expect("Unable to create file");
+        .with_file("merged_detected.dxf")?;
     let mut out = BufWriter::new(out_file);
 
     for dx in dxf_files.iter() {
-        let dxf = dx.as_path().file_name().unwrap().to_str().unwrap();
+        let Some(dxf) = dx.file_name().and_then(|f| f.to_str()) else {
+            continue;
+        };
         let dxf_filename = format!("{}/{}", batchoutfolder, dxf);
         let input = Path::new(&dxf_filename);
         if fs.exists(input) && dxf_filename.ends_with("_detected.dxf") {
-            let data = fs.read_to_string(input).expect("Can not read input file");
+            let data = fs.read_to_string(input).with_file(input)?;
             if data.contains("POLYLINE") {
                 let d: Vec<&str> = data.splitn(2, "POLYLINE").collect();
                 let body = d[1];
                 let d: Vec<&str> = body.splitn(2, "ENDSEC").collect();
                 let body = d[0];
-                footer = String::from(d[1]);
+                footer = String::from(*d.get(1).ok_or_else(|| missing("ENDSEC")).with_file(input)?);
 
                 if !headprinted {
-                    out.write_all(headout.as_bytes())
-                        .expect("Could not write to file");
+                    out.write_all(headout.as_bytes())?;
                     headprinted = true;
                 }
 
-                out.write_all("POLYLINE".as_bytes())
-                    .expect("Could not write to file");
-                out.write_all(body.as_bytes())
-                    .expect("Could not write to file");
+                out.write_all("POLYLINE".as_bytes())?;
+                out.write_all(body.as_bytes())?;
             }
         }
     }
-    write!(&mut out, "ENDSEC{}", &footer).expect("Could not write to file");
-    write!(&mut out2, "ENDSEC{}", &footer).expect("Could not write to file");
+    write!(&mut out, "ENDSEC{}", &footer)?;
+    write!(&mut out2, "ENDSEC{}", &footer)?;
+
+    let crs = merged_crs(fs, config);
+    let outputs: Vec<PathBuf> = [
+        "merged.dxf",
+        "merged_contours.dxf",
+        "merged_c2f.dxf",
+        "merged_c2.dxf",
+        "merged_basemap.dxf",
+        "merged_c3.dxf",
+        "formlines.dxf",
+        "merged_dotknolls.dxf",
+        "merged_detected.dxf",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .filter(|path| fs.exists(path))
+    .collect();
+    Crs::write_prj_sidecars(crs.as_ref(), fs, &outputs)?;
 
     Ok(())
 }
 
-pub fn smoothjoin(
-    fs: &impl FileSystem,
-    config: &Config,
-    tmpfolder: &Path,
-) -> Result<(), Box<dyn Error>> {
+pub fn smoothjoin(fs: &impl FileSystem, config: &Config, tmpfolder: &Path) -> Result<()> {
     info!("Smooth curves...");
 
     let &Config {
@@ -598,7 +610,7 @@ pub fn smoothjoin(
         }
     }
     let input = tmpfolder.join("out.dxf");
-    let data = fs.read_to_string(input).expect("Can not read input file");
+    let data = fs.read_to_string(&input).with_file(&input)?;
     let data: Vec<&str> = data.split("POLYLINE").collect();
     let mut dxfheadtmp = data[0];
     dxfheadtmp = dxfheadtmp.split("ENDSEC").collect::<Vec<&str>>()[0];
@@ -606,26 +618,23 @@ pub fn smoothjoin(
     let dxfhead = &format!("HEADER{}ENDSEC", dxfheadtmp);
 
     let output = tmpfolder.join("out2.dxf");
-    let fp = fs.create(output).expect("Unable to create file");
+    let fp = fs.create(&output).with_file(&output)?;
     let mut fp = BufWriter::new(fp);
 
-    fp.write_all(b"  0\r\nSECTION\r\n  2\r\n")
-        .expect("Could not write file");
-    fp.write_all(dxfhead.as_bytes())
-        .expect("Could not write file");
-    fp.write_all(b"\r\n  0\r\nSECTION\r\n  2\r\nENTITIES\r\n  0\r\n")
-        .expect("Could not write file");
+    fp.write_all(b"  0\r\nSECTION\r\n  2\r\n")?;
+    fp.write_all(dxfhead.as_bytes())?;
+    fp.write_all(b"\r\n  0\r\nSECTION\r\n  2\r\nENTITIES\r\n  0\r\n")?;
 
     let depr_output = tmpfolder.join("depressions.txt");
-    let depr_fp = fs.create(depr_output).expect("Unable to create file");
+    let depr_fp = fs.create(&depr_output).with_file(&depr_output)?;
     let mut depr_fp = BufWriter::new(depr_fp);
 
     let dotknoll_output = tmpfolder.join("dotknolls.txt");
-    let dotknoll_fp = fs.create(dotknoll_output).expect("Unable to create file");
+    let dotknoll_fp = fs.create(&dotknoll_output).with_file(&dotknoll_output)?;
     let mut dotknoll_fp = BufWriter::new(dotknoll_fp);
 
     let knollhead_output = tmpfolder.join("knollheads.txt");
-    let knollhead_fp = fs.create(knollhead_output).expect("Unable to create file");
+    let knollhead_fp = fs.create(&knollhead_output).with_file(&knollhead_output)?;
     let mut knollhead_fp = BufWriter::new(knollhead_fp);
 
     let mut heads1: HashMap<String, usize> = HashMap::default();
@@ -645,7 +654,10 @@ pub fn smoothjoin(
         let mut yline = 0;
         if j > 0 {
             let r = rec.split("VERTEX").collect::<Vec<&str>>();
-            let apu = r[1];
+            let apu = r
+                .get(1)
+                .ok_or_else(|| missing("VERTEX"))
+                .with_file(&input)?;
             let val = apu.split('\n').collect::<Vec<&str>>();
             for (i, v) in val.iter().enumerate() {
                 let vt = v.trim_end();
@@ -659,14 +671,13 @@ pub fn smoothjoin(
             for (i, v) in r.iter().enumerate() {
                 if i > 0 {
                     let val = v.trim_end().split('\n').collect::<Vec<&str>>();
-                    x.push(val[xline].trim().parse::<f64>().unwrap());
-                    y.push(val[yline].trim().parse::<f64>().unwrap());
+                    x.push(vertex_coordinate(&val, xline).with_file(&input)?);
+                    y.push(vertex_coordinate(&val, yline).with_file(&input)?);
                 }
             }
-            let x0 = x.first().unwrap();
-            let xl = x.last().unwrap();
-            let y0 = y.first().unwrap();
-            let yl = y.last().unwrap();
+            // there is at least one vertex
+            let (x0, xl) = (x[0], x[x.len() - 1]);
+            let (y0, yl) = (y[0], y[y.len() - 1]);
             let head = format!("{}x{}", x0, y0);
             let tail = format!("{}x{}", xl, yl);
 
@@ -872,13 +883,11 @@ pub fn smoothjoin(
                 depression = 1;
                 if (h_center < h && hit % 2 == 1) || (h_center > h && hit % 2 != 1) {
                     depression = -1;
-                    write!(&mut depr_fp, "{},{}", el_x[l][0], el_y[l][0])
-                        .expect("Unable to write file");
+                    write!(&mut depr_fp, "{},{}", el_x[l][0], el_y[l][0])?;
                     for k in 1..el_x[l].len() {
-                        write!(&mut depr_fp, "|{},{}", el_x[l][k], el_y[l][k])
-                            .expect("Unable to write file");
+                        write!(&mut depr_fp, "|{},{}", el_x[l][k], el_y[l][k])?;
                     }
-                    writeln!(&mut depr_fp).expect("Unable to write file");
+                    writeln!(&mut depr_fp)?;
                 }
                 if !skip {
                     // Check if knoll is distinct enough
@@ -933,15 +942,13 @@ pub fn smoothjoin(
                 }
                 x_avg /= (el_x_len - 1) as f64;
                 y_avg /= (el_x_len - 1) as f64;
-                write!(&mut dotknoll_fp, "{} {} {}\r\n", depression, x_avg, y_avg)
-                    .expect("Unable to write to file");
+                write!(&mut dotknoll_fp, "{} {} {}\r\n", depression, x_avg, y_avg)?;
                 skip = true;
             }
 
             if !skip {
                 // not skipped, lets save first coordinate pair for later form line knoll PIP analysis
-                write!(&mut knollhead_fp, "{} {}\r\n", el_x[l][0], el_y[l][0])
-                    .expect("Unable to write to file");
+                write!(&mut knollhead_fp, "{} {}\r\n", el_x[l][0], el_y[l][0])?;
                 // adaptive generalization
                 if el_x_len > 101 {
                     let mut newx: Vec<f64> = vec![];
@@ -1124,24 +1131,20 @@ pub fn smoothjoin(
                     fp,
                     "POLYLINE\r\n 66\r\n1\r\n  8\r\n{}\r\n 38\r\n{}\r\n  0\r\n",
                     layer, h
-                )
-                .expect("Unable to write file");
+                )?;
 
                 for k in 0..el_x_len {
                     write!(
                         fp,
                         "VERTEX\r\n  8\r\n{}\r\n 10\r\n{}\r\n 20\r\n{}\r\n 30\r\n{}\r\n  0\r\n",
                         layer, el_x[l][k], el_y[l][k], h
-                    )
-                    .expect("Unable to write file");
+                    )?;
                 }
-                fp.write_all(b"SEQEND\r\n  0\r\n")
-                    .expect("Unable to write file");
+                fp.write_all(b"SEQEND\r\n  0\r\n")?;
             } // -- if not dotkoll
         }
     }
-    fp.write_all(b"ENDSEC\r\n  0\r\nEOF\r\n")
-        .expect("Unable to write file");
+    fp.write_all(b"ENDSEC\r\n  0\r\nEOF\r\n")?;
     info!("Done");
     Ok(())
 }
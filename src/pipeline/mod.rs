@@ -0,0 +1,548 @@
+//! The processing of a tile as a pipeline of stages, each of them reading and writing artifacts
+//! of the temp folder.
+//!
+//! [`Pipeline::builder`] starts from the standard stages of the config. Stages can be replaced or
+//! removed and new ones added, and only the stages needed for the requested target artifacts are
+//! run. The artifacts are files of the [`FileSystem`] the pipeline runs on, so running on a
+//! [`MemoryFileSystem`](crate::io::fs::memory::MemoryFileSystem) keeps all of them in memory.
+
+use log::info;
+use rustc_hash::FxHashSet as HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::error::{Result, ResultExt};
+use crate::io::fs::FileSystem;
+use crate::io::heightmap::HeightMap;
+use crate::util::Timing;
+use crate::workspace::Workspace;
+
+mod stages;
+
+pub use stages::{standard_stages, standard_targets};
+
+/// Data passed between the stages of a pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Artifact {
+    /// The points of the input, and their coordinate reference system.
+    Points,
+    /// The heightmap of the ground, and the mask of its interpolated cells.
+    Heightmap,
+    /// Contours with a 0.3 m interval, used to detect knolls.
+    RawContours,
+    /// Contours of the `basemapinterval` config key.
+    BasemapContours,
+    /// Knolls detected from the raw contours.
+    Knolls,
+    /// The heightmap with the detected knolls raised.
+    KnollHeightmap,
+    /// Contours of half the contour interval.
+    Contours,
+    /// The smoothed contours, with the depressions, knolls and knoll heads found on them.
+    SmoothContours,
+    /// Knolls too small to be drawn with contours.
+    DotKnolls,
+    /// The vegetation and undergrowth images, and the water mask.
+    Vegetation,
+    /// Small and impassable cliffs.
+    Cliffs,
+    /// The image of the detected buildings.
+    Blocks,
+    /// The rendered map with and without depressions, written to the working directory.
+    Map,
+    /// The formlines, written when `formline=2`.
+    Formlines,
+}
+
+impl Artifact {
+    pub const ALL: [Artifact; 14] = [
+        Artifact::Points,
+        Artifact::Heightmap,
+        Artifact::RawContours,
+        Artifact::BasemapContours,
+        Artifact::Knolls,
+        Artifact::KnollHeightmap,
+        Artifact::Contours,
+        Artifact::SmoothContours,
+        Artifact::DotKnolls,
+        Artifact::Vegetation,
+        Artifact::Cliffs,
+        Artifact::Blocks,
+        Artifact::Map,
+        Artifact::Formlines,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Artifact::Points => "points",
+            Artifact::Heightmap => "heightmap",
+            Artifact::RawContours => "rawcontours",
+            Artifact::BasemapContours => "basemapcontours",
+            Artifact::Knolls => "knolls",
+            Artifact::KnollHeightmap => "knollheightmap",
+            Artifact::Contours => "contours",
+            Artifact::SmoothContours => "smoothcontours",
+            Artifact::DotKnolls => "dotknolls",
+            Artifact::Vegetation => "vegetation",
+            Artifact::Cliffs => "cliffs",
+            Artifact::Blocks => "blocks",
+            Artifact::Map => "map",
+            Artifact::Formlines => "formlines",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.name() == name)
+    }
+
+    /// Names of the files of the temp folder that make up the artifact. The map is written to
+    /// the working directory instead, see [`Artifact::paths`].
+    pub fn files(&self) -> &'static [&'static str] {
+        match self {
+            Artifact::Points => &["xyztemp.xyz.bin", crate::io::crs::CRS_FILE],
+            Artifact::Heightmap => &["xyz_03.hmap", "xyz2.hmap", "interpolated.bin"],
+            Artifact::RawContours => &["contours03.dxf"],
+            Artifact::BasemapContours => &["basemap.dxf"],
+            Artifact::Knolls => &["pins.txt", "detected.dxf"],
+            Artifact::KnollHeightmap => &["xyz_knolls.hmap"],
+            Artifact::Contours => &["out.dxf"],
+            Artifact::SmoothContours => &[
+                "out2.dxf",
+                "depressions.txt",
+                "dotknolls.txt",
+                "knollheads.txt",
+            ],
+            Artifact::DotKnolls => &["dotknolls.dxf"],
+            Artifact::Vegetation => &[
+                "vegetation.png",
+                "vegetation.pgw",
+                "undergrowth.png",
+                "undergrowth.pgw",
+                "blueblack.png",
+            ],
+            Artifact::Cliffs => &["c2g.dxf", "c3g.dxf"],
+            Artifact::Blocks => &["blocks.png"],
+            Artifact::Map => &[],
+            Artifact::Formlines => &["formlines.dxf"],
+        }
+    }
+
+    /// Paths of the files of the artifact in a workspace.
+    pub fn paths(&self, workspace: &Workspace) -> Vec<PathBuf> {
+        match self {
+            Artifact::Map => [true, false]
+                .iter()
+                .flat_map(|&nodepressions| {
+                    [
+                        workspace.map(nodepressions, "png"),
+                        workspace.map(nodepressions, "pgw"),
+                    ]
+                })
+                .collect(),
+            _ => {
+                let tmpfolder = workspace.tmpfolder();
+                self.files().iter().map(|f| tmpfolder.join(f)).collect()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Artifact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What a stage gets to run with.
+pub struct Context<'a, F: FileSystem> {
+    pub fs: &'a F,
+    pub config: &'a Config,
+    pub workspace: &'a Workspace,
+    /// The temp folder of the workspace.
+    pub tmpfolder: &'a Path,
+    /// The input file of the tile, if any. Stages that need it fail without one.
+    pub input: Option<&'a Path>,
+}
+
+impl<F: FileSystem> Context<'_, F> {
+    pub fn input(&self) -> Result<&Path> {
+        self.input.ok_or_else(|| "No input file given".into())
+    }
+}
+
+type StageFn<'a, F> = dyn Fn(&Context<F>) -> Result<()> + 'a;
+
+/// A step of the processing, making its output artifacts from its input artifacts.
+pub struct Stage<'a, F: FileSystem> {
+    name: &'static str,
+    inputs: Vec<Artifact>,
+    outputs: Vec<Artifact>,
+    run: Box<StageFn<'a, F>>,
+}
+
+impl<'a, F: FileSystem> Stage<'a, F> {
+    pub fn new(
+        name: &'static str,
+        inputs: &[Artifact],
+        outputs: &[Artifact],
+        run: impl Fn(&Context<F>) -> Result<()> + 'a,
+    ) -> Self {
+        Self {
+            name,
+            inputs: inputs.to_vec(),
+            outputs: outputs.to_vec(),
+            run: Box::new(run),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn inputs(&self) -> &[Artifact] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[Artifact] {
+        &self.outputs
+    }
+}
+
+/// Builds a [`Pipeline`], see [`Pipeline::builder`].
+pub struct PipelineBuilder<'a, F: FileSystem> {
+    config: &'a Config,
+    input: Option<PathBuf>,
+    workspace: Workspace,
+    stages: Vec<Stage<'a, F>>,
+    targets: Vec<Artifact>,
+    existing: Vec<Artifact>,
+}
+
+impl<'a, F: FileSystem> PipelineBuilder<'a, F> {
+    /// The input file of the tile.
+    pub fn input(mut self, input: impl Into<PathBuf>) -> Self {
+        self.input = Some(input.into());
+        self
+    }
+
+    /// The workspace the artifacts are written to, `temp` and the rendered maps in the working
+    /// directory by default.
+    pub fn workspace(mut self, workspace: Workspace) -> Self {
+        self.workspace = workspace;
+        self
+    }
+
+    /// The artifacts to make, [`standard_targets`] by default. Only the stages needed for them
+    /// are run.
+    pub fn targets(mut self, targets: impl IntoIterator<Item = Artifact>) -> Self {
+        self.targets = targets.into_iter().collect();
+        self
+    }
+
+    /// Artifacts already in the temp folder, for example from an earlier run. The stages making
+    /// them are not run.
+    pub fn existing(mut self, existing: impl IntoIterator<Item = Artifact>) -> Self {
+        self.existing = existing.into_iter().collect();
+        self
+    }
+
+    /// Adds a stage. A stage with the same name is replaced, otherwise the stage is run before
+    /// the first stage using any of its outputs.
+    pub fn stage(mut self, stage: Stage<'a, F>) -> Self {
+        if let Some(i) = self.stages.iter().position(|s| s.name == stage.name) {
+            self.stages[i] = stage;
+        } else if let Some(i) = self
+            .stages
+            .iter()
+            .position(|s| s.inputs.iter().any(|a| stage.outputs.contains(a)))
+        {
+            self.stages.insert(i, stage);
+        } else {
+            self.stages.push(stage);
+        }
+        self
+    }
+
+    /// Removes the stage with the given name.
+    pub fn without(mut self, name: &str) -> Self {
+        self.stages.retain(|s| s.name != name);
+        self
+    }
+
+    /// Picks the stages needed for the targets, and checks that the input of each of them is
+    /// made by an earlier stage or exists already.
+    pub fn build(self) -> Result<Pipeline<'a, F>> {
+        let existing: HashSet<Artifact> = self.existing.iter().copied().collect();
+        let mut needed: HashSet<Artifact> = self
+            .targets
+            .iter()
+            .copied()
+            .filter(|a| !existing.contains(a))
+            .collect();
+
+        // walk back from the last stage, each picked stage needing its inputs from earlier ones
+        let mut picked = vec![false; self.stages.len()];
+        for (i, stage) in self.stages.iter().enumerate().rev() {
+            if stage.outputs.iter().any(|a| needed.contains(a)) {
+                picked[i] = true;
+                for output in &stage.outputs {
+                    needed.remove(output);
+                }
+                needed.extend(stage.inputs.iter().filter(|a| !existing.contains(a)));
+            }
+        }
+
+        let stages: Vec<Stage<'a, F>> = self
+            .stages
+            .into_iter()
+            .zip(picked)
+            .filter_map(|(stage, picked)| picked.then_some(stage))
+            .collect();
+
+        let mut available = existing;
+        for stage in &stages {
+            if let Some(missing) = stage.inputs.iter().find(|a| !available.contains(a)) {
+                return Err(format!(
+                    "Stage `{}` needs `{}`, which no earlier stage makes",
+                    stage.name, missing
+                )
+                .into());
+            }
+            available.extend(stage.outputs.iter().copied());
+        }
+        if let Some(missing) = self.targets.iter().find(|a| !available.contains(a)) {
+            return Err(format!("No stage makes `{}`", missing).into());
+        }
+
+        Ok(Pipeline {
+            config: self.config,
+            input: self.input,
+            tmpfolder: self.workspace.tmpfolder(),
+            workspace: self.workspace,
+            stages,
+        })
+    }
+}
+
+/// The stages needed to make some artifacts, in the order they are run.
+pub struct Pipeline<'a, F: FileSystem> {
+    config: &'a Config,
+    input: Option<PathBuf>,
+    workspace: Workspace,
+    tmpfolder: PathBuf,
+    stages: Vec<Stage<'a, F>>,
+}
+
+impl<'a, F: FileSystem> Pipeline<'a, F> {
+    /// Starts a pipeline with the [`standard_stages`] of the config.
+    pub fn builder(config: &'a Config) -> PipelineBuilder<'a, F> {
+        PipelineBuilder {
+            config,
+            input: None,
+            workspace: Workspace::thread(""),
+            stages: standard_stages(config),
+            targets: standard_targets(config, true),
+            existing: Vec::new(),
+        }
+    }
+
+    /// Names of the stages that are run, in order.
+    pub fn stage_names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|s| s.name).collect()
+    }
+
+    pub fn run(&self, fs: &F) -> Result<()> {
+        self.run_timed(fs, &mut Timing::start_now("pipeline"))
+    }
+
+    /// Runs the stages, each of them in its own timing section.
+    pub fn run_timed(&self, fs: &F, timing: &mut Timing) -> Result<()> {
+        fs.create_dir_all(&self.tmpfolder)?;
+        let context = Context {
+            fs,
+            config: self.config,
+            workspace: &self.workspace,
+            tmpfolder: &self.tmpfolder,
+            input: self.input.as_deref(),
+        };
+        for stage in &self.stages {
+            info!("Stage {}", stage.name);
+            timing.start_section(stage.name);
+            (stage.run)(&context).with_stage(stage.name)?;
+        }
+        timing.end_section();
+        Ok(())
+    }
+
+    /// Paths of the files of an artifact.
+    pub fn paths(&self, artifact: Artifact) -> Vec<PathBuf> {
+        artifact.paths(&self.workspace)
+    }
+
+    /// Reads the heightmap of [`Artifact::Heightmap`] or [`Artifact::KnollHeightmap`].
+    pub fn heightmap(&self, fs: &F, artifact: Artifact) -> Result<HeightMap> {
+        let file = match artifact {
+            Artifact::Heightmap => "xyz_03.hmap",
+            Artifact::KnollHeightmap => "xyz_knolls.hmap",
+            _ => return Err(format!("`{}` is not a heightmap", artifact).into()),
+        };
+        Ok(HeightMap::from_file(fs, self.tmpfolder.join(file))?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::fs::memory::MemoryFileSystem;
+    use std::io::Write;
+
+    fn default_config() -> Config {
+        Config::from_file(Path::new("pullauta.default.ini")).unwrap()
+    }
+
+    /// A stage writing its name to the files of its outputs.
+    fn stage<'a>(
+        name: &'static str,
+        inputs: &[Artifact],
+        outputs: &[Artifact],
+    ) -> Stage<'a, MemoryFileSystem> {
+        let files: Vec<&str> = outputs.iter().map(|a| a.files()[0]).collect();
+        Stage::new(
+            name,
+            inputs,
+            outputs,
+            move |ctx: &Context<MemoryFileSystem>| {
+                for file in &files {
+                    ctx.fs
+                        .create(ctx.tmpfolder.join(file))?
+                        .write_all(name.as_bytes())?;
+                }
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn test_standard_stages() {
+        let config = default_config();
+        let pipeline = Pipeline::<MemoryFileSystem>::builder(&config)
+            .build()
+            .unwrap();
+        assert_eq!(
+            pipeline.stage_names(),
+            [
+                "points",
+                "heightmap",
+                "rawcontours",
+                "knolls",
+                "knollheightmap",
+                "contours",
+                "smoothjoin",
+                "dotknolls",
+                "vegetation",
+                "cliffs",
+                "render",
+            ]
+        );
+
+        let pipeline = Pipeline::<MemoryFileSystem>::builder(&config)
+            .targets([Artifact::Cliffs])
+            .build()
+            .unwrap();
+        assert_eq!(pipeline.stage_names(), ["points", "heightmap", "cliffs"]);
+
+        let pipeline = Pipeline::<MemoryFileSystem>::builder(&config)
+            .targets([Artifact::Cliffs])
+            .existing([Artifact::Points, Artifact::Heightmap])
+            .build()
+            .unwrap();
+        assert_eq!(pipeline.stage_names(), ["cliffs"]);
+
+        let contoursonly = Config {
+            contoursonly: true,
+            ..default_config()
+        };
+        let pipeline = Pipeline::<MemoryFileSystem>::builder(&contoursonly)
+            .build()
+            .unwrap();
+        assert_eq!(
+            pipeline.stage_names(),
+            [
+                "points",
+                "heightmap",
+                "rawcontours",
+                "knolls",
+                "knollheightmap",
+                "contours",
+                "smoothjoin",
+                "dotknolls",
+                "formlines",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_custom_stages() {
+        let config = default_config();
+        let fs = MemoryFileSystem::new();
+        let pipeline = Pipeline::builder(&config)
+            .workspace(Workspace::isolated("work"))
+            .stage(stage("points", &[], &[Artifact::Points]))
+            .stage(stage(
+                "heightmap",
+                &[Artifact::Points],
+                &[Artifact::Heightmap],
+            ))
+            .stage(stage("slope", &[Artifact::Heightmap], &[Artifact::Blocks]))
+            .targets([Artifact::Blocks])
+            .build()
+            .unwrap();
+        // the new stage replaces the blocks stage as the maker of its output
+        assert_eq!(pipeline.stage_names(), ["points", "heightmap", "slope"]);
+        pipeline.run(&fs).unwrap();
+        assert_eq!(
+            fs.read_to_string(&pipeline.paths(Artifact::Blocks)[0])
+                .unwrap(),
+            "slope"
+        );
+        assert_eq!(
+            fs.read_to_string("work/temp/xyz_03.hmap").unwrap(),
+            "heightmap"
+        );
+
+        let error = Pipeline::builder(&config)
+            .stage(stage("points", &[], &[Artifact::Points]))
+            .without("heightmap")
+            .targets([Artifact::Cliffs])
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Stage `cliffs` needs `heightmap`, which no earlier stage makes"
+        );
+
+        let error = Pipeline::<MemoryFileSystem>::builder(&config)
+            .without("cliffs")
+            .targets([Artifact::Cliffs])
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "No stage makes `cliffs`");
+
+        let error = Pipeline::builder(&config)
+            .stage(stage("points", &[], &[]))
+            .stage(Stage::new("fail", &[], &[Artifact::Points], |_| {
+                Err("no points".into())
+            }))
+            .targets([Artifact::Points])
+            .build()
+            .unwrap()
+            .run(&fs)
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "stage fail: no points");
+        assert_eq!(error.stage(), Some("fail"));
+    }
+}
@@ -0,0 +1,204 @@
+use image::{Rgba, RgbaImage};
+use log::info;
+use std::path::Path;
+
+use super::{Artifact, Stage};
+use crate::config::Config;
+use crate::io::fs::FileSystem;
+use crate::io::heightmap::HeightMap;
+use crate::io::raster::{is_raster, read_raster};
+use crate::{blocks, cliffs, contours, knolls, merge, process, render, vegetation};
+
+/// The stages of the processing of a tile, in the order they are run. Which stages read which
+/// artifacts depends on the config.
+pub fn standard_stages<'a, F: FileSystem>(config: &Config) -> Vec<Stage<'a, F>> {
+    use Artifact::*;
+
+    let knoll_heightmap_inputs: &[Artifact] = if config.skipknolldetection {
+        &[Heightmap]
+    } else {
+        &[Heightmap, Knolls]
+    };
+    let (contours_inputs, contours_heightmap): (&[Artifact], &str) = if config.skipknolldetection {
+        (&[Heightmap], "xyz_03.hmap")
+    } else {
+        (&[KnollHeightmap], "xyz_knolls.hmap")
+    };
+    let render_inputs: &[Artifact] = if config.detectbuildings {
+        &[
+            Heightmap,
+            SmoothContours,
+            DotKnolls,
+            Vegetation,
+            Cliffs,
+            Blocks,
+        ]
+    } else {
+        &[Heightmap, SmoothContours, DotKnolls, Vegetation, Cliffs]
+    };
+
+    vec![
+        Stage::new("points", &[], &[Points], |ctx| {
+            process::prepare_points(ctx.fs, ctx.config, ctx.tmpfolder, ctx.input()?)
+        }),
+        Stage::new("heightmap", &[Points], &[Heightmap], |ctx| {
+            process::make_heightmap(ctx.fs, ctx.config, ctx.tmpfolder, ctx.input)
+        }),
+        Stage::new("rawcontours", &[Heightmap], &[RawContours], |ctx| {
+            info!("Knoll detection part 1");
+            let xyz_03 = HeightMap::from_file(ctx.fs, ctx.tmpfolder.join("xyz_03.hmap"))?;
+            contours::heightmap2contours(
+                ctx.fs,
+                ctx.config,
+                ctx.tmpfolder,
+                ctx.config.scalefactor * 0.3,
+                &xyz_03,
+                "contours03.dxf", // dxf curves generated from the heightmap
+            )
+        }),
+        Stage::new("basemap", &[Heightmap], &[BasemapContours], |ctx| {
+            info!("Basemap contours");
+            let xyz2 = HeightMap::from_file(ctx.fs, ctx.tmpfolder.join("xyz2.hmap"))?;
+            contours::heightmap2contours(
+                ctx.fs,
+                ctx.config,
+                ctx.tmpfolder,
+                ctx.config.basemapcontours,
+                &xyz2,
+                "basemap.dxf", // generate dxf contours
+            )
+        }),
+        Stage::new("knolls", &[Heightmap, RawContours], &[Knolls], |ctx| {
+            info!("Knoll detection part 2");
+            knolls::knolldetector(ctx.fs, ctx.config, ctx.tmpfolder)
+        }),
+        Stage::new(
+            "knollheightmap",
+            knoll_heightmap_inputs,
+            &[KnollHeightmap],
+            |ctx| {
+                info!("Contour generation part 1");
+                // modifies the heightmap (but does not change dimensions)
+                knolls::xyzknolls(ctx.fs, ctx.config, ctx.tmpfolder)
+            },
+        ),
+        Stage::new("contours", contours_inputs, &[Contours], move |ctx| {
+            info!("Contour generation part 2");
+            let hmap = HeightMap::from_file(ctx.fs, ctx.tmpfolder.join(contours_heightmap))?;
+            let halfinterval = ctx.config.contour_interval / 2.0 * ctx.config.scalefactor;
+            contours::heightmap2contours(
+                ctx.fs,
+                ctx.config,
+                ctx.tmpfolder,
+                halfinterval,
+                &hmap,
+                "out.dxf", // generates dxf curves
+            )
+        }),
+        Stage::new(
+            "smoothjoin",
+            &[KnollHeightmap, Contours],
+            &[SmoothContours],
+            |ctx| {
+                info!("Contour generation part 3");
+                merge::smoothjoin(ctx.fs, ctx.config, ctx.tmpfolder)
+            },
+        ),
+        Stage::new(
+            "dotknolls",
+            &[KnollHeightmap, SmoothContours],
+            &[DotKnolls],
+            |ctx| {
+                info!("Contour generation part 4");
+                knolls::dotknolls(ctx.fs, ctx.config, ctx.tmpfolder)
+            },
+        ),
+        Stage::new("vegetation", &[Points, Heightmap], &[Vegetation], |ctx| {
+            info!("Vegetation generation");
+            let (fs, config, tmpfolder) = (ctx.fs, ctx.config, ctx.tmpfolder);
+            if !ctx.input.is_some_and(is_raster) {
+                vegetation::makevege(fs, config, tmpfolder)
+            } else if let Some(vegetation_raster) = &config.vegetation_raster {
+                let (chm, _) = read_raster(fs, Path::new(vegetation_raster))?;
+                vegetation::height_from_raster(fs, tmpfolder, &chm)?;
+                vegetation::vege_from_height(fs, config, tmpfolder)
+            } else {
+                vegetation::blank_vegetation(fs, config, tmpfolder)
+            }
+        }),
+        Stage::new("cliffs", &[Points, Heightmap], &[Cliffs], |ctx| {
+            info!("Cliff generation");
+            cliffs::makecliffs(ctx.fs, ctx.config, ctx.tmpfolder)
+        }),
+        Stage::new("blocks", &[Points, Heightmap], &[Blocks], |ctx| {
+            info!("Detecting buildings");
+            blocks::blocks(ctx.fs, ctx.tmpfolder)
+        }),
+        Stage::new("render", render_inputs, &[Map, Formlines], |ctx| {
+            let &Config {
+                pnorthlinesangle,
+                pnorthlineswidth,
+                ..
+            } = ctx.config;
+            info!("Rendering png map with depressions");
+            render::render(
+                ctx.fs,
+                ctx.config,
+                ctx.workspace,
+                pnorthlinesangle,
+                pnorthlineswidth,
+                false,
+            )?;
+            info!("Rendering png map without depressions");
+            render::render(
+                ctx.fs,
+                ctx.config,
+                ctx.workspace,
+                pnorthlinesangle,
+                pnorthlineswidth,
+                true,
+            )
+        }),
+        Stage::new(
+            "formlines",
+            &[Heightmap, SmoothContours],
+            &[Formlines],
+            |ctx| {
+                info!("Rendering formlines");
+                let mut img = RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 0]));
+                render::draw_curves(ctx.fs, ctx.config, &mut img, ctx.tmpfolder, false, false)
+            },
+        ),
+    ]
+}
+
+/// The artifacts made by the processing of a tile with the config, following the `vegeonly`,
+/// `cliffsonly` and `contoursonly` keys. The map is only rendered if `render` is set.
+pub fn standard_targets(config: &Config, render: bool) -> Vec<Artifact> {
+    use Artifact::*;
+
+    if config.vegeonly {
+        return vec![Vegetation];
+    }
+    if config.cliffsonly {
+        return vec![Cliffs];
+    }
+
+    let mut targets = vec![RawContours];
+    if config.basemapcontours != 0.0 {
+        targets.push(BasemapContours);
+    }
+    targets.push(DotKnolls);
+    if config.contoursonly {
+        targets.push(Formlines);
+        return targets;
+    }
+    targets.extend([Vegetation, Cliffs]);
+    if config.detectbuildings {
+        targets.push(Blocks);
+    }
+    if render {
+        targets.push(Map);
+    }
+    targets
+}
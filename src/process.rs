@@ -1,37 +1,39 @@
 use image::{GrayImage, Luma, Rgb, RgbImage, Rgba, RgbaImage};
-use las::{raw::Header, Reader};
-use log::debug;
-use log::info;
-use rand::distributions;
-use rand::prelude::*;
-use std::error::Error;
-use std::io::BufRead;
+use las::Reader;
+use log::{debug, error, info, warn};
 use std::io::BufReader;
 use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
 
-use crate::blocks;
-use crate::cliffs;
 use crate::config::Config;
 use crate::contours;
 use crate::crop;
+use crate::error::{Error, Result, ResultExt};
+use crate::io::chunks::query_points;
+use crate::io::crs::{Crs, CRS_FILE};
 use crate::io::fs::FileSystem;
-use crate::io::heightmap::HeightMap;
-use crate::io::xyz::XyzInternalWriter;
-use crate::knolls;
-use crate::merge;
+use crate::io::geotiff::png_to_geotiff;
+use crate::io::raster::{is_raster, read_raster, resample};
+use crate::io::text::{read_text_points, TextLayout};
+use crate::io::tileindex::{Bounds, TileIndex};
+use crate::io::xyz::{XyzFields, XyzInternalWriter, XyzRecord};
+use crate::io::{read_png, read_world_file, write_png};
+use crate::jobs::{self, JobQueue};
+use crate::pipeline::{standard_targets, Pipeline};
 use crate::render;
-use crate::util::read_lines_no_alloc;
+use crate::report::{self, TileStats};
+use crate::thinning::{Thinner, Thinning};
 use crate::util::Timing;
-use crate::vegetation;
+use crate::workspace::Workspace;
 
 pub fn process_zip(
     fs: &impl FileSystem,
     config: &Config,
-    thread: &String,
-    tmpfolder: &Path,
+    workspace: &Workspace,
     filenames: &[String],
-) -> Result<(), Box<dyn Error>> {
+) -> Result<()> {
     let mut timing = Timing::start_now("process_zip");
     let &Config {
         pnorthlineswidth,
@@ -43,34 +45,32 @@ pub fn process_zip(
     {
         info!("Rendering shape files");
         timing.start_section("unzip and render shape files");
-        crate::shapefile::unzip_and_render(fs, config, tmpfolder, filenames).unwrap();
+        crate::shapefile::unzip_and_render(fs, config, &workspace.tmpfolder(), filenames)?;
     }
+    #[cfg(not(feature = "shapefile"))]
+    let _ = filenames;
 
     info!("Rendering png map with depressions");
     timing.start_section("Rendering png map with depressions");
     render::render(
         fs,
         config,
-        thread,
-        tmpfolder,
+        workspace,
         pnorthlinesangle,
         pnorthlineswidth,
         false,
-    )
-    .unwrap();
+    )?;
 
     info!("Rendering png map without depressions");
     timing.start_section("Rendering png map without depressions");
     render::render(
         fs,
         config,
-        thread,
-        tmpfolder,
+        workspace,
         pnorthlinesangle,
         pnorthlineswidth,
         true,
-    )
-    .unwrap();
+    )?;
 
     Ok(())
 }
@@ -78,23 +78,38 @@ pub fn process_zip(
 pub fn process_tile(
     fs: &impl FileSystem,
     config: &Config,
-    thread: &String,
-    tmpfolder: &Path,
+    workspace: &Workspace,
     input_file: &Path,
     skip_rendering: bool,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<()> {
     let mut timing = Timing::start_now("process_tile");
-    fs.create_dir_all(tmpfolder)
-        .expect("Could not create tmp folder");
 
-    let &Config {
-        pnorthlinesangle,
-        pnorthlineswidth,
-        skipknolldetection,
-        ..
-    } = config;
+    let pipeline = Pipeline::builder(config)
+        .input(input_file)
+        .workspace(workspace.clone())
+        .targets(standard_targets(config, !skip_rendering))
+        .build()?;
+    pipeline.run_timed(fs, &mut timing)?;
+
+    let tmpfolder = &workspace.tmpfolder();
+    let crs = Crs::from_file(fs, tmpfolder.join(CRS_FILE));
+    Crs::write_prj_sidecars(crs.as_ref(), fs, &fs.list(tmpfolder)?)?;
+    if config.qareport {
+        report::write_timing(fs, tmpfolder, &mut timing)?;
+    }
+
+    info!("All done!");
+    Ok(())
+}
 
-    timing.start_section("preparing input file");
+/// Converts the input point cloud or elevation raster to the internal point file
+/// `xyztemp.xyz.bin` of the temp folder, and writes its coordinate reference system.
+pub fn prepare_points(
+    fs: &impl FileSystem,
+    config: &Config,
+    tmpfolder: &Path,
+    input_file: &Path,
+) -> Result<()> {
     info!("Preparing input file");
 
     let filename = input_file
@@ -105,43 +120,53 @@ pub fn process_tile(
 
     let target_file = tmpfolder.join("xyztemp.xyz.bin");
 
-    if filename.ends_with(".xyz") {
-        // if we are here we don't know if the file has at least 6 columns, but we assume that it is in the format
-        // x y z classification number_of_returns return_number
+    let source_crs = if is_raster(input_file) {
+        info!("Reading elevation raster");
+        let (dem, crs) = read_raster(fs, input_file)?;
 
+        // the ground points for cliff detection are the raster cells
+        debug!("Writing records to {:?}", &target_file);
+        let mut writer = XyzInternalWriter::compressed(
+            BufWriter::new(fs.create(&target_file)?),
+            XyzFields::NONE,
+            config.temp_resolution,
+        );
+        for (x, y, z) in dem.iter().filter(|(_, _, z)| !z.is_nan()) {
+            writer.write_record(&XyzRecord {
+                x,
+                y,
+                z,
+                classification: 2,
+                number_of_returns: 1,
+                return_number: 1,
+                ..Default::default()
+            })?;
+        }
+        writer.finish()?;
+        crs
+    } else if filename.ends_with(".xyz") {
         info!("Converting points from .xyz to internal binary format");
+        let layout = TextLayout::detect(fs, input_file, config.xyz_delimiter, &config.xyz_columns)?;
 
         debug!("Writing records to {:?}", &target_file);
-        let mut writer = XyzInternalWriter::new(BufWriter::new(
-            fs.create(&target_file).expect("Could not create writer"),
-        ));
-        read_lines_no_alloc(fs, input_file, |line| {
-            let mut parts = line.split(' ');
-            let x = parts.next().unwrap().parse::<f64>().unwrap();
-            let y = parts.next().unwrap().parse::<f64>().unwrap();
-            let z = parts.next().unwrap().parse::<f64>().unwrap();
-
-            let classification = parts.next().unwrap().parse::<u8>().unwrap();
-            let number_of_returns = parts.next().unwrap().parse::<u8>().unwrap();
-            let return_number = parts.next().unwrap().parse::<u8>().unwrap();
-
-            writer
-                .write_record(&crate::io::xyz::XyzRecord {
-                    x,
-                    y,
-                    z,
-                    classification,
-                    number_of_returns,
-                    return_number,
-                })
-                .expect("Could not write record");
-        })
-        .expect("Could not read file");
-        writer.finish().expect("Unable to finish writing");
+        let mut writer = XyzInternalWriter::compressed(
+            BufWriter::new(fs.create(&target_file)?),
+            layout.fields(),
+            config.temp_resolution,
+        );
+        let mut result = Ok(());
+        read_text_points(fs, input_file, &layout, |r| {
+            if result.is_ok() {
+                result = writer.write_record(&r);
+            }
+        })?;
+        result?;
+        writer.finish()?;
+        None
     } else if filename.ends_with(".laz") || filename.ends_with(".las") {
         info!("Converting points from .laz/laz to internal binary format");
         let &Config {
-            thinfactor,
+            thinning,
             xfactor,
             yfactor,
             zfactor,
@@ -149,214 +174,275 @@ pub fn process_tile(
             ..
         } = config;
 
-        if thinfactor != 1.0 {
-            info!("Using thinning factor {}", thinfactor);
+        if thinning != Thinning::None {
+            info!("Using thinning {:?}", thinning);
         }
-
-        let mut rng = rand::thread_rng();
-        let randdist = distributions::Bernoulli::new(thinfactor).unwrap();
-
-        let mut reader = Reader::new(BufReader::new(
-            fs.open(input_file).expect("Could not open file"),
-        ))
-        .expect("Could not create reader");
+        let mut reader = Reader::new(BufReader::new(fs.open(input_file).with_file(input_file)?))
+            .with_file(input_file)?;
+        let header_bounds = reader.header().bounds();
+        let mut thinner = Thinner::new(
+            thinning,
+            &Bounds {
+                min_x: header_bounds.min.x,
+                min_y: header_bounds.min.y,
+                max_x: header_bounds.max.x,
+                max_y: header_bounds.max.y,
+            },
+        )
+        .with_file(input_file)?;
 
         debug!("Writing records to {:?}", &target_file);
-        let mut writer = XyzInternalWriter::new(BufWriter::new(
-            fs.create(&target_file).expect("Could not create writer"),
-        ));
+        let mut writer = XyzInternalWriter::compressed(
+            BufWriter::new(fs.create(&target_file).with_file(&target_file)?),
+            XyzFields::from_las_format(reader.header().point_format()),
+            config.temp_resolution,
+        );
 
         for ptu in reader.points() {
-            let pt = ptu.unwrap();
-            if thinfactor == 1.0 || rng.sample(randdist) {
-                writer.write_record(&crate::io::xyz::XyzRecord {
-                    x: pt.x * xfactor,
-                    y: pt.y * yfactor,
-                    z: pt.z * zfactor + zoff,
-                    classification: u8::from(pt.classification),
-                    number_of_returns: pt.number_of_returns,
-                    return_number: pt.return_number,
-                })?;
-            }
+            let pt = ptu.with_file(input_file)?;
+            let record = XyzRecord {
+                x: pt.x * xfactor,
+                y: pt.y * yfactor,
+                z: pt.z * zfactor + zoff,
+                ..XyzRecord::from_las_point(&pt)
+            };
+            // thinned by the coordinates of the file, like the tiles of a batch
+            thinner.push((pt.x, pt.y, pt.z), record, &mut writer)?;
         }
-        writer.finish().expect("Unable to finish writing");
+        thinner.finish(&mut writer)?;
+        writer.finish().with_file(&target_file)?;
+        Crs::from_header(reader.header())
     } else if filename.ends_with(".xyz.bin") {
         info!("Copying input file");
-        fs.copy(input_file, target_file)
-            .expect("Could not copy file");
+        fs.copy(input_file, &target_file).with_file(input_file)?;
+        // the coordinate reference system of internal files is kept next to them
+        Crs::from_file(fs, Crs::sidecar_path(input_file))
     } else {
         return Err(format!("Unsupported input file: {}", input_file.display()).into());
+    };
+
+    // the coordinate reference system of the config overrides the one of the input file
+    let crs = config.crs.clone().or(source_crs);
+    match &crs {
+        Some(crs) => info!("Coordinate reference system: {}", crs),
+        None => info!("Coordinate reference system: unknown"),
     }
+    Crs::write_file(crs.as_ref(), fs, tmpfolder.join(CRS_FILE))?;
 
     info!("Done");
+    Ok(())
+}
 
-    info!("Knoll detection part 1");
-    timing.start_section("knoll detection part 1");
-
-    let &Config {
-        scalefactor,
-        vegeonly,
-        cliffsonly,
-        contoursonly,
-        ..
-    } = config;
-
-    let xyz_03 = contours::xyz2heightmap(
-        fs,
-        config,
-        tmpfolder,
-        "xyztemp.xyz.bin", //point cloud in
-    )
-    .expect("contour generation failed");
-    xyz_03.to_file(fs, tmpfolder.join("xyz_03.hmap")).unwrap();
-
-    if vegeonly || cliffsonly {
-    } else {
-        contours::heightmap2contours(
+/// Makes the heightmap `xyz_03.hmap` of the temp folder, and its copy `xyz2.hmap`, from the
+/// points or from the elevation raster given as input.
+pub fn make_heightmap(
+    fs: &impl FileSystem,
+    config: &Config,
+    tmpfolder: &Path,
+    input_file: Option<&Path>,
+) -> Result<()> {
+    info!("Generating the heightmap");
+    let (xyz_03, interpolated) = match input_file.filter(|f| is_raster(f)) {
+        Some(raster) => {
+            let (dem, _) = read_raster(fs, raster)?;
+            resample(&dem, 2.0 * config.scalefactor)?
+        }
+        None => contours::xyz2heightmap_with_mask(
             fs,
+            config,
             tmpfolder,
-            scalefactor * 0.3,
-            &xyz_03,
-            "contours03.dxf", // dxf curves generated from the heightmap
-        )
-        .expect("contour generation failed");
-    }
-    drop(xyz_03);
+            "xyztemp.xyz.bin", //point cloud in
+        )?,
+    };
+    xyz_03.to_file(fs, tmpfolder.join("xyz_03.hmap"))?;
+    contours::write_interpolated(fs, tmpfolder, &interpolated)?;
 
     // copy the generated heightmap
-    fs.copy(tmpfolder.join("xyz_03.hmap"), tmpfolder.join("xyz2.hmap"))
-        .expect("Could not copy file");
+    fs.copy(tmpfolder.join("xyz_03.hmap"), tmpfolder.join("xyz2.hmap"))?;
+    Ok(())
+}
 
-    let &Config {
-        contour_interval,
-        basemapcontours,
-        ..
-    } = config;
-    let halfinterval = contour_interval / 2.0 * scalefactor;
-
-    if !vegeonly && !cliffsonly {
-        if basemapcontours != 0.0 {
-            info!("Basemap contours");
-            let xyz2 = HeightMap::from_file(fs, tmpfolder.join("xyz2.hmap"))
-                .expect("could not read xyz2 heightmap");
-            contours::heightmap2contours(
-                fs,
-                tmpfolder,
-                basemapcontours,
-                &xyz2,
-                "basemap.dxf", // generate dxf contours
-            )
-            .expect("contour generation failed");
-        }
-        if !skipknolldetection {
-            info!("Knoll detection part 2");
-            timing.start_section("knoll detection part 2");
-            knolls::knolldetector(fs, config, tmpfolder).unwrap();
+/// The tiles of the input folder of a batch, with what the workers processing them share.
+pub struct Batch {
+    index: TileIndex,
+    zip_files: Vec<String>,
+    queue: JobQueue,
+}
+
+impl Batch {
+    /// Lists the tiles of the input folder and updates their index.
+    pub fn open(fs: &impl FileSystem, conf: &Config) -> Result<Self> {
+        let Config {
+            lazfolder,
+            batchoutfolder,
+            ..
+        } = conf;
+
+        fs.create_dir_all(batchoutfolder)
+            .with_file(batchoutfolder)?;
+
+        let mut zip_files: Vec<String> = Vec::new();
+        let mut tiles: Vec<String> = Vec::new();
+        for path in fs.list(lazfolder).with_file(lazfolder)? {
+            let is_vegetation_raster = conf
+                .vegetation_raster
+                .as_ref()
+                .is_some_and(|raster| Path::new(raster).file_name() == path.file_name());
+            if is_raster(&path) && !is_vegetation_raster {
+                warn!(
+                    "Skipping {}, elevation rasters are not supported in batch mode, process it on its own",
+                    path.display()
+                );
+                continue;
+            }
+            if let Some(extension) = path.extension() {
+                if extension == "zip" {
+                    zip_files.push(path.to_string_lossy().into_owned());
+                } else if extension == "laz" || extension == "las" {
+                    match path.file_name().and_then(|f| f.to_str()) {
+                        Some(tile) => tiles.push(tile.to_string()),
+                        None => warn!("Skipping {}, its name is not valid UTF-8", path.display()),
+                    }
+                }
+            }
         }
-        info!("Contour generation part 1");
-        timing.start_section("contour generation part 1");
-        knolls::xyzknolls(fs, config, tmpfolder).unwrap(); // modifies the heightmap (but does not change dimensions
-
-        info!("Contour generation part 2");
-        timing.start_section("contour generation part 2");
-        if !skipknolldetection {
-            // contours 2.5
-            let xyz_knolls = HeightMap::from_file(fs, tmpfolder.join("xyz_knolls.hmap"))
-                .expect("could not read xyz_knolls heightmap");
-            contours::heightmap2contours(
-                fs,
-                tmpfolder,
-                halfinterval,
-                &xyz_knolls,
-                "out.dxf", // generates dxf curves
-            )
-            .unwrap();
-        } else {
-            let hmap = contours::xyz2heightmap(fs, config, tmpfolder, "xyztemp.xyz.bin")
-                .expect("could not generate heightmap");
-            contours::heightmap2contours(
-                fs,
-                tmpfolder,
-                halfinterval,
-                &hmap,
-                "out.dxf", // generate dxf curves
-            )
-            .unwrap();
+
+        let index = TileIndex::update(fs, lazfolder).with_file(lazfolder)?;
+        let removed = jobs::remove_abandoned_workspaces(fs, conf)?;
+        if removed > 0 {
+            info!("Removed {} workspace(s) of stopped workers", removed);
         }
-        info!("Contour generation part 3");
-        timing.start_section("contour generation part 3");
-        merge::smoothjoin(fs, config, tmpfolder).unwrap();
 
-        info!("Contour generation part 4");
-        timing.start_section("contour generation part 4");
-        knolls::dotknolls(fs, config, tmpfolder).unwrap();
+        Ok(Self {
+            index,
+            zip_files,
+            queue: JobQueue::new(tiles),
+        })
     }
 
-    if !cliffsonly && !contoursonly {
-        info!("Vegetation generation");
-        timing.start_section("vegetation generation");
-        vegetation::makevege(fs, config, tmpfolder).unwrap();
+    pub fn index(&self) -> &TileIndex {
+        &self.index
     }
 
-    if !vegeonly && !contoursonly {
-        info!("Cliff generation");
-        timing.start_section("cliff generation");
-        cliffs::makecliffs(fs, config, tmpfolder).unwrap();
-    }
-    if !vegeonly && !contoursonly && !cliffsonly && config.detectbuildings {
-        info!("Detecting buildings");
-        timing.start_section("detecting buildings");
-        blocks::blocks(fs, tmpfolder).unwrap();
+    /// Processes the tiles in `workers` threads, each of them in a workspace of its own. Returns
+    /// the number of tiles that failed.
+    pub fn run(
+        &self,
+        fs: &(impl FileSystem + Sync),
+        conf: &Config,
+        workers: usize,
+    ) -> Result<usize> {
+        thread::scope(|scope| {
+            let handles: Vec<_> = (1..=workers)
+                .map(|n| {
+                    scope.spawn(move || {
+                        let workspace = jobs::worker_workspace(conf, &n.to_string());
+                        self.work(fs, conf, &workspace)
+                    })
+                })
+                .collect();
+            let mut failed = 0;
+            for handle in handles {
+                match handle.join() {
+                    Ok(result) => failed += result?,
+                    Err(_) => return Err(Error::invalid("A processing thread panicked")),
+                }
+            }
+            Ok(failed)
+        })
     }
-    if !skip_rendering && !vegeonly && !contoursonly && !cliffsonly {
-        info!("Rendering png map with depressions");
-        timing.start_section("rendering png map with depressions");
-        render::render(
-            fs,
-            config,
-            thread,
-            tmpfolder,
-            pnorthlinesangle,
-            pnorthlineswidth,
-            false,
-        )
-        .unwrap();
 
-        info!("Rendering png map without depressions");
-        timing.start_section("rendering png map without depressions");
-        render::render(
-            fs,
-            config,
-            thread,
-            tmpfolder,
-            pnorthlinesangle,
-            pnorthlineswidth,
-            true,
-        )
-        .unwrap();
-    } else if contoursonly {
-        info!("Rendering formlines");
-        timing.start_section("rendering formlines");
-        let mut img = RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 0]));
-        render::draw_curves(fs, config, &mut img, tmpfolder, false, false).unwrap();
-    } else {
-        info!("Skipped rendering");
+    /// Processes tiles from the queue in a workspace until none are left. A failed tile is tried
+    /// again `retries` times, and then recorded failed, see [`crate::jobs`]. Returns the number of
+    /// tiles that failed.
+    pub fn work(
+        &self,
+        fs: &(impl FileSystem + Sync),
+        conf: &Config,
+        workspace: &Workspace,
+    ) -> Result<usize> {
+        debug!("Worker starting in {}", workspace.dir().display());
+        let heartbeat = Duration::from_secs((conf.stale_timeout / 4).clamp(1, 60));
+
+        let mut failed = 0;
+        while !jobs::interrupted() {
+            // a tile that cannot be claimed or released fails, but not the other tiles
+            let claim = match self.queue.next(fs, conf) {
+                Ok(Some(claim)) => claim,
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Could not claim a tile: {}", e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            let laz = claim.tile().to_string();
+            let laz = laz.as_str();
+
+            info!("{} -> {}.png", laz, laz);
+            let attempts = conf.retries + 1;
+            let mut attempt = 1;
+            let result = claim.with_heartbeat(fs, heartbeat, || loop {
+                let result = jobs::catch_panic(|| {
+                    workspace.clear(fs)?;
+                    batch_tile(fs, conf, &self.index, laz, &self.zip_files, workspace)
+                        .with_tile(laz)
+                });
+                match result {
+                    Err(e) if attempt < attempts && !jobs::interrupted() => {
+                        warn!("{}, trying again ({}/{})", e, attempt + 1, attempts);
+                        claim.remove_outputs(fs)?;
+                        attempt += 1;
+                    }
+                    result => return result,
+                }
+            });
+            let (succeeded, recorded) = match result {
+                Ok(()) => (true, claim.finish(fs)),
+                Err(e) => {
+                    error!("{}", e);
+                    (false, claim.fail(fs, &e, attempt))
+                }
+            };
+            if let Err(e) = &recorded {
+                error!("Could not record the result of {}: {}", laz, e);
+            }
+            if !succeeded || recorded.is_err() {
+                failed += 1;
+            }
+        }
+
+        if fs.exists(workspace.dir()) {
+            fs.remove_dir_all(workspace.dir())
+                .with_file(workspace.dir())?;
+        }
+        debug!("Worker done");
+        Ok(failed)
     }
-    info!("All done!");
-    Ok(())
 }
 
-pub fn batch_process(conf: &Config, fs: &impl FileSystem, thread: &String) {
+/// Processes one tile of a batch with the buffer from its neighbours, and crops the results to
+/// the tile in the output folder.
+fn batch_tile(
+    fs: &impl FileSystem,
+    conf: &Config,
+    index: &TileIndex,
+    laz: &str,
+    zip_files: &[String],
+    workspace: &Workspace,
+) -> Result<()> {
     let &Config {
         vegeonly,
         cliffsonly,
         contoursonly,
         savetempfolders,
         savetempfiles,
+        qareport,
+        geotiff,
         scalefactor,
         vege_bitmode,
         zoff,
-        thinfactor,
+        thinning,
         ..
     } = conf;
 
@@ -366,152 +452,162 @@ pub fn batch_process(conf: &Config, fs: &impl FileSystem, thread: &String) {
         ..
     } = conf;
 
-    let mut rng = rand::thread_rng();
-    let randdist = distributions::Bernoulli::new(thinfactor).unwrap();
+    let outfile = format!("{}/{}.png", batchoutfolder, laz);
+    // the world files and dxf files of the tile, which get .prj files
+    let mut outputs: Vec<PathBuf> = Vec::new();
 
-    fs.create_dir_all(batchoutfolder)
-        .expect("Could not create output folder");
+    let tmpfolder = workspace.tmpfolder();
+    let headerfile = workspace.file("header.xyz");
+    if fs.exists(&headerfile) {
+        fs.remove_file(&headerfile).with_file(&headerfile)?;
+    }
 
-    let mut zip_files: Vec<String> = Vec::new();
-    let mut laz_files: Vec<PathBuf> = Vec::new();
-    for path in fs.list(lazfolder).unwrap() {
-        if let Some(extension) = path.extension() {
-            if extension == "zip" {
-                zip_files.push(String::from(path.to_str().unwrap()));
-            } else if extension == "laz" || extension == "las" {
-                laz_files.push(path);
+    let tile = index
+        .get(laz)
+        .ok_or("File is missing from the tile index")?;
+    let Bounds {
+        min_x: minx,
+        min_y: miny,
+        max_x: maxx,
+        max_y: maxy,
+    } = tile.bounds;
+
+    // the tile is processed together with a 127 m buffer from its neighbours
+    let buffer = thinning.read_bounds(&tile.bounds.expand(127.0));
+
+    let tmp_filename = workspace.file("temp.xyz.bin");
+    debug!("Writing records to {:?}", &tmp_filename);
+    // the optional fields are chosen by the point format of the tile itself
+    let fields = XyzFields::from_las_format(
+        &las::point::Format::new(tile.point_format).with_file(Path::new(lazfolder).join(laz))?,
+    );
+    let mut writer = XyzInternalWriter::compressed(
+        BufWriter::new(fs.create(&tmp_filename).with_file(&tmp_filename)?),
+        fields,
+        conf.temp_resolution,
+    );
+    let mut stats = qareport.then(|| TileStats::new(&tile.bounds, conf.water_class));
+    // the thinning depends only on the points, so the buffers match the neighbouring tiles
+    let mut thinner = Thinner::new(thinning, &buffer).with_file(Path::new(lazfolder).join(laz))?;
+    for neighbour in index.intersecting(&buffer) {
+        let neighbour_path = Path::new(lazfolder).join(&neighbour.filename);
+        debug!("Reading buffer points from {}", neighbour.filename);
+        let mut result = Ok(());
+        query_points(fs, &neighbour_path, &neighbour.chunks, &buffer, |pt| {
+            if let Some(stats) = stats.as_mut() {
+                stats.add(pt.x, pt.y, u8::from(pt.classification));
             }
-        }
+            let record = XyzRecord {
+                z: pt.z + zoff,
+                ..XyzRecord::from_las_point(&pt)
+            };
+            if result.is_ok() {
+                result = thinner.push((pt.x, pt.y, pt.z), record, &mut writer);
+            }
+        })
+        .with_file(&neighbour_path)?;
+        result.with_file(&tmp_filename)?;
     }
+    thinner.finish(&mut writer).with_file(&tmp_filename)?;
+    writer.finish().with_file(&tmp_filename)?;
+    Crs::write_file(tile.crs.as_ref(), fs, Crs::sidecar_path(&tmp_filename))?;
 
-    for laz_path in &laz_files {
-        let laz = laz_path.file_name().unwrap().to_str().unwrap();
-        let outfile = format!("{}/{}.png", batchoutfolder, laz);
-        if fs.exists(&outfile) {
-            info!("Skipping {}.png it exists already in output folder.", laz);
-            continue;
+    if zip_files.is_empty() {
+        process_tile(fs, conf, workspace, &tmp_filename, false)?;
+    } else {
+        process_tile(fs, conf, workspace, &tmp_filename, true)?;
+        if !vegeonly && !cliffsonly && !contoursonly {
+            process_zip(fs, conf, workspace, zip_files)?;
         }
+    }
+    if let Some(stats) = &stats {
+        report::write_tile_report(fs, conf, laz, stats, &tmpfolder)?;
+    }
 
-        info!("{} -> {}.png", laz, laz);
-        fs.create(&outfile).unwrap();
+    // crop
+    let tfw_in = workspace.map(true, "pgw");
+    if fs.exists(&tfw_in) {
+        let [tfw0, tfw1, tfw2, tfw3, tfw4, tfw5] = read_world_file(fs, &tfw_in)?;
+
+        let dx = minx - tfw4;
+        let dy = -maxy + tfw5;
+
+        let pgw_file_out = fs.create(&tfw_in).with_file(&tfw_in)?;
+        let mut pgw_file_out = BufWriter::new(pgw_file_out);
+        write!(
+            &mut pgw_file_out,
+            "{}\r\n{}\r\n{}\r\n{}\r\n{}\r\n{}\r\n",
+            tfw0,
+            tfw1,
+            tfw2,
+            tfw3,
+            minx + tfw0 / 2.0,
+            maxy - tfw0 / 2.0
+        )
+        .with_file(&tfw_in)?;
+
+        pgw_file_out.flush().with_file(&tfw_in)?;
+        fs.copy(&tfw_in, workspace.map(false, "pgw"))?;
 
-        let headerfile = PathBuf::from(format!("header{}.xyz", thread));
-        if fs.exists(&headerfile) {
-            fs.remove_file(&headerfile).unwrap();
+        for png in [workspace.map(true, "png"), workspace.map(false, "png")] {
+            let orig_img = fs.read_image_png(&png)?;
+            let mut img = RgbImage::from_pixel(
+                ((maxx - minx) * 600.0 / 254.0 / scalefactor + 2.0) as u32,
+                ((maxy - miny) * 600.0 / 254.0 / scalefactor + 2.0) as u32,
+                Rgb([255, 255, 255]),
+            );
+            image::imageops::overlay(
+                &mut img,
+                &orig_img.to_rgb8(),
+                (-dx * 600.0 / 254.0 / scalefactor) as i64,
+                (-dy * 600.0 / 254.0 / scalefactor) as i64,
+            );
+            write_png(fs, &img, &png)?;
         }
 
-        let mut file = fs.open(format!("{}/{}", lazfolder, laz)).unwrap();
-        let header = Header::read_from(&mut file).unwrap();
-        let minx = header.min_x;
-        let miny = header.min_y;
-        let maxx = header.max_x;
-        let maxy = header.max_y;
-
-        let minx2 = minx - 127.0;
-        let miny2 = miny - 127.0;
-        let maxx2 = maxx + 127.0;
-        let maxy2 = maxy + 127.0;
-
-        let tmp_filename = PathBuf::from(format!("temp{}.xyz.bin", thread));
-        debug!("Writing records to {:?}", &tmp_filename);
-        let mut writer = XyzInternalWriter::new(BufWriter::new(
-            fs.create(&tmp_filename).expect("Could not create writer"),
-        ));
-
-        for laz_p in &laz_files {
-            let laz = laz_p.as_path().file_name().unwrap().to_str().unwrap();
-            let mut file = fs.open(format!("{}/{}", lazfolder, laz)).unwrap();
-            let header = Header::read_from(&mut file).unwrap();
-            if header.max_x > minx2
-                && header.min_x < maxx2
-                && header.max_y > miny2
-                && header.min_y < maxy2
-            {
-                let mut reader =
-                    Reader::new(BufReader::new(fs.open(laz_p).expect("Could not open file")))
-                        .expect("Could not create reader");
-                for ptu in reader.points() {
-                    let pt = ptu.unwrap();
-                    if pt.x > minx2
-                        && pt.x < maxx2
-                        && pt.y > miny2
-                        && pt.y < maxy2
-                        && (thinfactor == 1.0 || rng.sample(randdist))
-                    {
-                        writer
-                            .write_record(&crate::io::xyz::XyzRecord {
-                                x: pt.x,
-                                y: pt.y,
-                                z: pt.z + zoff,
-                                classification: u8::from(pt.classification),
-                                number_of_returns: pt.number_of_returns,
-                                return_number: pt.return_number,
-                            })
-                            .expect("Could not write record");
-                    }
-                }
-            }
+        for (from, to) in [
+            (workspace.map(true, "png"), outfile),
+            (
+                workspace.map(true, "pgw"),
+                format!("{}/{}.pgw", batchoutfolder, laz),
+            ),
+            (
+                workspace.map(false, "png"),
+                format!("{}/{}_depr.png", batchoutfolder, laz),
+            ),
+            (
+                workspace.map(false, "pgw"),
+                format!("{}/{}_depr.pgw", batchoutfolder, laz),
+            ),
+        ] {
+            fs.copy(&from, &to).with_file(&to)?;
+            outputs.push(PathBuf::from(to));
         }
-        writer.finish().expect("Unable to finish writing");
-
-        let tmpfolder = PathBuf::from(format!("temp{}", thread));
-        if zip_files.is_empty() {
-            process_tile(fs, conf, thread, &tmpfolder, &tmp_filename, false).unwrap();
-        } else {
-            process_tile(fs, conf, thread, &tmpfolder, &tmp_filename, true).unwrap();
-            if !vegeonly && !cliffsonly && !contoursonly {
-                process_zip(fs, conf, thread, &tmpfolder, &zip_files).unwrap();
+
+        if geotiff {
+            let crs = Crs::from_file(fs, tmpfolder.join(CRS_FILE));
+            for (png, output) in [
+                (workspace.map(true, "png"), format!("{}.tif", laz)),
+                (workspace.map(false, "png"), format!("{}_depr.tif", laz)),
+            ] {
+                png_to_geotiff(fs, &png, crs.as_ref()).with_file(&png)?;
+                let output = Path::new(batchoutfolder).join(output);
+                fs.copy(png.with_extension("tif"), &output)
+                    .with_file(&output)?;
             }
         }
+    }
 
-        // crop
-        let tfw_in = PathBuf::from(format!("pullautus{}.pgw", thread));
-        if fs.exists(&tfw_in) {
-            let mut lines =
-                BufReader::new(fs.open(&tfw_in).expect("PGW file does not exist")).lines();
-            let tfw0 = lines
-                .next()
-                .expect("no 1 line")
-                .expect("Could not read line 1")
-                .parse::<f64>()
-                .unwrap();
-            let tfw1 = lines
-                .next()
-                .expect("no 2 line")
-                .expect("Could not read line 2")
-                .parse::<f64>()
-                .unwrap();
-            let tfw2 = lines
-                .next()
-                .expect("no 3 line")
-                .expect("Could not read line 3")
-                .parse::<f64>()
-                .unwrap();
-            let tfw3 = lines
-                .next()
-                .expect("no 4 line")
-                .expect("Could not read line 4")
-                .parse::<f64>()
-                .unwrap();
-            let tfw4 = lines
-                .next()
-                .expect("no 5 line")
-                .expect("Could not read line 5")
-                .parse::<f64>()
-                .unwrap();
-            let tfw5 = lines
-                .next()
-                .expect("no 6 line")
-                .expect("Could not read line 6")
-                .parse::<f64>()
-                .unwrap();
-
-            drop(lines);
+    if savetempfiles {
+        if !contoursonly && !cliffsonly {
+            let path = tmpfolder.join("undergrowth.pgw");
+            let [tfw0, tfw1, tfw2, tfw3, tfw4, tfw5] = read_world_file(fs, &path)?;
 
             let dx = minx - tfw4;
             let dy = -maxy + tfw5;
 
-            let pgw_file_out = fs.create(&tfw_in).expect("Unable to create file");
+            let pgw_out = format!("{}/{}_undergrowth.pgw", batchoutfolder, laz);
+            let pgw_file_out = fs.create(&pgw_out).with_file(&pgw_out)?;
             let mut pgw_file_out = BufWriter::new(pgw_file_out);
             write!(
                 &mut pgw_file_out,
@@ -523,221 +619,54 @@ pub fn batch_process(conf: &Config, fs: &impl FileSystem, thread: &String) {
                 minx + tfw0 / 2.0,
                 maxy - tfw0 / 2.0
             )
-            .expect("Unable to write to file");
-
-            pgw_file_out.flush().unwrap();
-            fs.copy(
-                Path::new(&format!("pullautus{}.pgw", thread)),
-                Path::new(&format!("pullautus_depr{}.pgw", thread)),
-            )
-            .expect("Could not copy file");
+            .with_file(&pgw_out)?;
+            pgw_file_out.flush().with_file(&pgw_out)?;
+            outputs.push(PathBuf::from(&pgw_out));
 
-            let orig_img = fs
-                .read_image_png(format!("pullautus{}.png", thread))
-                .expect("Opening image failed");
-            let mut img = RgbImage::from_pixel(
+            let orig_img = read_png(fs, tmpfolder.join("undergrowth.png"))?;
+            let mut img = RgbaImage::from_pixel(
                 ((maxx - minx) * 600.0 / 254.0 / scalefactor + 2.0) as u32,
                 ((maxy - miny) * 600.0 / 254.0 / scalefactor + 2.0) as u32,
-                Rgb([255, 255, 255]),
+                Rgba([255, 255, 255, 0]),
             );
             image::imageops::overlay(
                 &mut img,
-                &orig_img.to_rgb8(),
+                &orig_img,
                 (-dx * 600.0 / 254.0 / scalefactor) as i64,
                 (-dy * 600.0 / 254.0 / scalefactor) as i64,
             );
+            write_png(
+                fs,
+                &img,
+                format!("{}/{}_undergrowth.png", batchoutfolder, laz),
+            )?;
 
-            img.write_to(
-                &mut BufWriter::new(
-                    fs.create(format!("pullautus{}.png", thread))
-                        .expect("could not save output png"),
-                ),
-                image::ImageFormat::Png,
-            )
-            .expect("could not save output png");
-
-            let orig_img = fs
-                .read_image_png(format!("pullautus_depr{}.png", thread))
-                .expect("Opening image failed");
+            let orig_img = read_png(fs, tmpfolder.join("vegetation.png"))?;
             let mut img = RgbImage::from_pixel(
-                ((maxx - minx) * 600.0 / 254.0 / scalefactor + 2.0) as u32,
-                ((maxy - miny) * 600.0 / 254.0 / scalefactor + 2.0) as u32,
+                ((maxx - minx) + 1.0) as u32,
+                ((maxy - miny) + 1.0) as u32,
                 Rgb([255, 255, 255]),
             );
-            image::imageops::overlay(
-                &mut img,
-                &orig_img.to_rgb8(),
-                (-dx * 600.0 / 254.0 / scalefactor) as i64,
-                (-dy * 600.0 / 254.0 / scalefactor) as i64,
-            );
-
-            img.write_to(
-                &mut BufWriter::new(
-                    fs.create(format!("pullautus_depr{}.png", thread))
-                        .expect("could not save output png"),
-                ),
-                image::ImageFormat::Png,
-            )
-            .expect("could not save output png");
+            image::imageops::overlay(&mut img, &orig_img.to_rgb8(), -dx as i64, -dy as i64);
+            write_png(fs, &img, format!("{}/{}_vege.png", batchoutfolder, laz))?;
 
-            fs.copy(format!("pullautus{}.png", thread), &outfile)
-                .expect("Could not copy file to output folder");
-            fs.copy(
-                format!("pullautus{}.pgw", thread),
-                format!("{}/{}.pgw", batchoutfolder, laz),
-            )
-            .expect("Could not copy file to output folder");
-            fs.copy(
-                format!("pullautus_depr{}.png", thread),
-                format!("{}/{}_depr.png", batchoutfolder, laz),
-            )
-            .expect("Could not copy file to output folder");
-            fs.copy(
-                format!("pullautus_depr{}.pgw", thread),
-                format!("{}/{}_depr.pgw", batchoutfolder, laz),
+            let pgw_out = format!("{}/{}_vege.pgw", batchoutfolder, laz);
+            let pgw_file_out = fs.create(&pgw_out).with_file(&pgw_out)?;
+            let mut pgw_file_out = BufWriter::new(pgw_file_out);
+            write!(
+                &mut pgw_file_out,
+                "1.0\r\n0.0\r\n0.0\r\n-1.0\r\n{}\r\n{}\r\n",
+                minx + 0.5,
+                maxy - 0.5
             )
-            .expect("Could not copy file to output folder");
-        }
+            .with_file(&pgw_out)?;
 
-        if savetempfiles {
-            if !contoursonly && !cliffsonly {
-                let path = format!("temp{}/undergrowth.pgw", thread);
-                let tfw_in = Path::new(&path);
-                let mut lines =
-                    BufReader::new(fs.open(tfw_in).expect("PGW file does not exist")).lines();
-                let tfw0 = lines
-                    .next()
-                    .expect("no 1 line")
-                    .expect("Could not read line 1")
-                    .parse::<f64>()
-                    .unwrap();
-                let tfw1 = lines
-                    .next()
-                    .expect("no 2 line")
-                    .expect("Could not read line 2")
-                    .parse::<f64>()
-                    .unwrap();
-                let tfw2 = lines
-                    .next()
-                    .expect("no 3 line")
-                    .expect("Could not read line 3")
-                    .parse::<f64>()
-                    .unwrap();
-                let tfw3 = lines
-                    .next()
-                    .expect("no 4 line")
-                    .expect("Could not read line 4")
-                    .parse::<f64>()
-                    .unwrap();
-                let tfw4 = lines
-                    .next()
-                    .expect("no 5 line")
-                    .expect("Could not read line 5")
-                    .parse::<f64>()
-                    .unwrap();
-                let tfw5 = lines
-                    .next()
-                    .expect("no 6 line")
-                    .expect("Could not read line 6")
-                    .parse::<f64>()
-                    .unwrap();
-
-                let dx = minx - tfw4;
-                let dy = -maxy + tfw5;
-
-                let pgw_file_out = fs
-                    .create(PathBuf::from(&format!(
-                        "{}/{}_undergrowth.pgw",
-                        batchoutfolder, laz
-                    )))
-                    .expect("Unable to create file");
-                let mut pgw_file_out = BufWriter::new(pgw_file_out);
-                write!(
-                    &mut pgw_file_out,
-                    "{}\r\n{}\r\n{}\r\n{}\r\n{}\r\n{}\r\n",
-                    tfw0,
-                    tfw1,
-                    tfw2,
-                    tfw3,
-                    minx + tfw0 / 2.0,
-                    maxy - tfw0 / 2.0
-                )
-                .expect("Unable to write to file");
-                pgw_file_out.flush().unwrap();
-
-                let mut orig_img_reader = image::ImageReader::new(BufReader::new(
-                    fs.open(format!("temp{}/undergrowth.png", thread))
-                        .expect("Opening undergrowth image failed"),
-                ));
-                orig_img_reader.set_format(image::ImageFormat::Png);
-                orig_img_reader.no_limits();
-                let orig_img = orig_img_reader.decode().unwrap();
-                let mut img = RgbaImage::from_pixel(
-                    ((maxx - minx) * 600.0 / 254.0 / scalefactor + 2.0) as u32,
-                    ((maxy - miny) * 600.0 / 254.0 / scalefactor + 2.0) as u32,
-                    Rgba([255, 255, 255, 0]),
-                );
-                image::imageops::overlay(
-                    &mut img,
-                    &orig_img,
-                    (-dx * 600.0 / 254.0 / scalefactor) as i64,
-                    (-dy * 600.0 / 254.0 / scalefactor) as i64,
-                );
+            pgw_file_out.flush().with_file(&pgw_out)?;
+            outputs.push(PathBuf::from(&pgw_out));
 
-                img.write_to(
-                    &mut BufWriter::new(
-                        fs.create(format!("{}/{}_undergrowth.png", batchoutfolder, laz))
-                            .expect("could not save output png"),
-                    ),
-                    image::ImageFormat::Png,
-                )
-                .expect("could not save output png");
-
-                let mut orig_img_reader = image::ImageReader::new(BufReader::new(
-                    fs.open(format!("temp{}/vegetation.png", thread))
-                        .expect("Opening vegetation image failed"),
-                ));
-                orig_img_reader.set_format(image::ImageFormat::Png);
-                orig_img_reader.no_limits();
-                let orig_img = orig_img_reader.decode().unwrap();
-                let mut img = RgbImage::from_pixel(
-                    ((maxx - minx) + 1.0) as u32,
-                    ((maxy - miny) + 1.0) as u32,
-                    Rgb([255, 255, 255]),
-                );
-                image::imageops::overlay(&mut img, &orig_img.to_rgb8(), -dx as i64, -dy as i64);
-
-                img.write_to(
-                    &mut BufWriter::new(
-                        fs.create(format!("{}/{}_vege.png", batchoutfolder, laz))
-                            .expect("could not save output png"),
-                    ),
-                    image::ImageFormat::Png,
-                )
-                .expect("could not save output png");
-
-                let pgw_file_out = fs
-                    .create(format!("{}/{}_vege.pgw", batchoutfolder, laz))
-                    .expect("Unable to create file");
-                let mut pgw_file_out = BufWriter::new(pgw_file_out);
-                write!(
-                    &mut pgw_file_out,
-                    "1.0\r\n0.0\r\n0.0\r\n-1.0\r\n{}\r\n{}\r\n",
-                    minx + 0.5,
-                    maxy - 0.5
-                )
-                .expect("Unable to write to file");
-
-                pgw_file_out.flush().unwrap();
-
-                if vege_bitmode {
-                    let mut orig_img_reader = image::ImageReader::new(BufReader::new(
-                        fs.open(format!("temp{}/vegetation_bit.png", thread))
-                            .expect("Opening vegetation bit bit image failed"),
-                    ));
-                    orig_img_reader.set_format(image::ImageFormat::Png);
-                    orig_img_reader.no_limits();
-                    let orig_img = orig_img_reader.decode().unwrap();
+            if vege_bitmode {
+                for name in ["vegetation", "undergrowth"] {
+                    let orig_img = read_png(fs, tmpfolder.join(format!("{}_bit.png", name)))?;
                     let mut img = GrayImage::from_pixel(
                         ((maxx - minx) + 1.0) as u32,
                         ((maxy - miny) + 1.0) as u32,
@@ -749,124 +678,73 @@ pub fn batch_process(conf: &Config, fs: &impl FileSystem, thread: &String) {
                         -dx as i64,
                         -dy as i64,
                     );
-                    img.write_to(
-                        &mut BufWriter::new(
-                            fs.create(format!("{}/{}_vege_bit.png", batchoutfolder, laz))
-                                .expect("could not save output png"),
-                        ),
-                        image::ImageFormat::Png,
-                    )
-                    .expect("could not save output png");
-
-                    let mut orig_img_reader = image::ImageReader::new(BufReader::new(
-                        fs.open(format!("temp{}/undergrowth_bit.png", thread))
-                            .expect("Opening undergrowth bit image failed"),
-                    ));
-                    orig_img_reader.set_format(image::ImageFormat::Png);
-                    orig_img_reader.no_limits();
-                    let orig_img = orig_img_reader.decode().unwrap();
-                    let mut img = GrayImage::from_pixel(
-                        ((maxx - minx) + 1.0) as u32,
-                        ((maxy - miny) + 1.0) as u32,
-                        Luma([0]),
-                    );
-                    image::imageops::overlay(
-                        &mut img,
-                        &orig_img.to_luma8(),
-                        -dx as i64,
-                        -dy as i64,
-                    );
-                    img.write_to(
-                        &mut BufWriter::new(
-                            fs.create(format!("{}/{}_undergrowth_bit.png", batchoutfolder, laz))
-                                .expect("could not save output png"),
-                        ),
-                        image::ImageFormat::Png,
-                    )
-                    .expect("could not save output png");
-
-                    fs.copy(
-                        format!("{}/{}_vege.pgw", batchoutfolder, laz),
-                        format!("{}/{}_vege_bit.pgw", batchoutfolder, laz),
-                    )
-                    .expect("Could not copy file");
-
-                    fs.copy(
-                        format!("{}/{}_vege.pgw", batchoutfolder, laz),
-                        format!("{}/{}_undergrowth_bit.pgw", batchoutfolder, laz),
-                    )
-                    .expect("Could not copy file");
+                    let output = match name {
+                        "vegetation" => "vege_bit",
+                        _ => "undergrowth_bit",
+                    };
+                    write_png(
+                        fs,
+                        &img,
+                        format!("{}/{}_{}.png", batchoutfolder, laz, output),
+                    )?;
                 }
-            }
 
-            let out2_path = PathBuf::from(format!("temp{}/out2.dxf", thread));
-            if fs.exists(&out2_path) {
-                crop::polylinedxfcrop(
-                    fs,
-                    &out2_path,
-                    Path::new(&format!("{}/{}_contours.dxf", batchoutfolder, laz)),
-                    minx,
-                    miny,
-                    maxx,
-                    maxy,
-                )
-                .unwrap();
-            }
-            let dxf_files = ["c2g", "c3g", "contours03", "detected", "formlines"];
-            for dxf_file in dxf_files.iter() {
-                let dxf_path = PathBuf::from(format!("temp{}/{}.dxf", thread, dxf_file));
-                if fs.exists(&dxf_path) {
-                    crop::polylinedxfcrop(
-                        fs,
-                        &dxf_path,
-                        Path::new(&format!("{}/{}_{}.dxf", batchoutfolder, laz, dxf_file)),
-                        minx,
-                        miny,
-                        maxx,
-                        maxy,
-                    )
-                    .unwrap();
+                for output in ["vege_bit", "undergrowth_bit"] {
+                    let pgw_out = format!("{}/{}_{}.pgw", batchoutfolder, laz, output);
+                    fs.copy(format!("{}/{}_vege.pgw", batchoutfolder, laz), &pgw_out)?;
+                    outputs.push(PathBuf::from(&pgw_out));
                 }
             }
-            let dotknolls_file = PathBuf::from(format!("temp{}/dotknolls.dxf", thread));
-            if fs.exists(&dotknolls_file) {
-                crop::pointdxfcrop(
-                    fs,
-                    &dotknolls_file,
-                    Path::new(&format!("{}/{}_dotknolls.dxf", batchoutfolder, laz)),
-                    minx,
-                    miny,
-                    maxx,
-                    maxy,
-                )
-                .unwrap();
-            }
         }
 
-        let basemap_file = PathBuf::from(format!("temp{}/basemap.dxf", thread));
-        if fs.exists(&basemap_file) {
-            crop::polylinedxfcrop(
-                fs,
-                &basemap_file,
-                Path::new(&format!("{}/{}_basemap.dxf", batchoutfolder, laz)),
-                minx,
-                miny,
-                maxx,
-                maxy,
-            )
-            .unwrap();
+        let out2_path = tmpfolder.join("out2.dxf");
+        if fs.exists(&out2_path) {
+            let output = PathBuf::from(format!("{}/{}_contours.dxf", batchoutfolder, laz));
+            crop::polylinedxfcrop(fs, &out2_path, &output, minx, miny, maxx, maxy)
+                .with_file(&out2_path)?;
+            outputs.push(output);
+        }
+        let dxf_files = ["c2g", "c3g", "contours03", "detected", "formlines"];
+        for dxf_file in dxf_files.iter() {
+            let dxf_path = tmpfolder.join(format!("{}.dxf", dxf_file));
+            if fs.exists(&dxf_path) {
+                let output = PathBuf::from(format!("{}/{}_{}.dxf", batchoutfolder, laz, dxf_file));
+                crop::polylinedxfcrop(fs, &dxf_path, &output, minx, miny, maxx, maxy)
+                    .with_file(&dxf_path)?;
+                outputs.push(output);
+            }
+        }
+        let dotknolls_file = tmpfolder.join("dotknolls.dxf");
+        if fs.exists(&dotknolls_file) {
+            let output = PathBuf::from(format!("{}/{}_dotknolls.dxf", batchoutfolder, laz));
+            crop::pointdxfcrop(fs, &dotknolls_file, &output, minx, miny, maxx, maxy)
+                .with_file(&dotknolls_file)?;
+            outputs.push(output);
         }
+    }
 
-        if savetempfolders {
-            fs.create_dir_all(format!("temp_{}_dir", laz))
-                .expect("Could not create output folder");
-            for path in fs.list(format!("temp{}", thread)).unwrap() {
-                if fs.exists(&path) {
-                    let filename = path.file_name().unwrap().to_str().unwrap();
-                    fs.copy(&path, Path::new(&format!("temp_{}_dir/{}", laz, filename)))
-                        .unwrap();
-                }
+    let basemap_file = tmpfolder.join("basemap.dxf");
+    if fs.exists(&basemap_file) {
+        let output = PathBuf::from(format!("{}/{}_basemap.dxf", batchoutfolder, laz));
+        crop::polylinedxfcrop(fs, &basemap_file, &output, minx, miny, maxx, maxy)
+            .with_file(&basemap_file)?;
+        outputs.push(output);
+    }
+
+    Crs::write_prj_sidecars(
+        Crs::from_file(fs, tmpfolder.join(CRS_FILE)).as_ref(),
+        fs,
+        &outputs,
+    )?;
+
+    if savetempfolders {
+        let folder = PathBuf::from(format!("temp_{}_dir", laz));
+        fs.create_dir_all(&folder).with_file(&folder)?;
+        for path in fs.list(&tmpfolder).with_file(&tmpfolder)? {
+            if let Some(filename) = path.file_name().filter(|_| fs.exists(&path)) {
+                fs.copy(&path, folder.join(filename)).with_file(&path)?;
             }
         }
     }
+    Ok(())
 }
@@ -0,0 +1,335 @@
+use image::{Rgb, RgbImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_line_segment_mut};
+use imageproc::rect::Rect;
+use log::info;
+use rustc_hash::FxHashMap as HashMap;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::io::dxf::read_polylines;
+use crate::io::fs::FileSystem;
+use crate::io::heightmap::HeightMap;
+use crate::io::xyz::XyzInternalReader;
+
+const CHART_WIDTH: u32 = 1200;
+const CHART_HEIGHT: u32 = 400;
+const CHART_MARGIN: u32 = 20;
+
+/// A single sample of the profile.
+#[derive(Debug, Clone, PartialEq)]
+struct Sample {
+    distance: f64,
+    x: f64,
+    y: f64,
+    ground: Option<f64>,
+    /// Height of the highest non-ground point above the ground.
+    vegetation: f64,
+    points: u32,
+    ground_points: u32,
+}
+
+/// Samples the heightmap and the point cloud of `tmpfolder` along a polyline and writes the
+/// profile to `output` as a CSV file and a PNG chart (with the extension changed to .csv and
+/// .png).
+///
+/// The polyline is either a DXF file (the first polyline is used), a GeoJSON file (the first
+/// LineString is used) or a list of coordinates like `x1,y1;x2,y2;...`. Points within `step`
+/// meters of a sample are used for the vegetation height of that sample.
+pub fn profile(
+    fs: &impl FileSystem,
+    config: &Config,
+    tmpfolder: &Path,
+    line: &str,
+    output: &Path,
+    step: f64,
+) -> Result<()> {
+    info!("Making profile along {}", line);
+
+    if step <= 0.0 {
+        return Err(format!("Invalid profile step {}", step).into());
+    }
+    let vertices = read_line(fs, line)?;
+    if vertices.len() < 2 {
+        return Err(format!("The profile line {} needs at least two points", line).into());
+    }
+
+    let hmap = HeightMap::from_file(fs, tmpfolder.join("xyz2.hmap"))?;
+    let mut samples = sample_line(&vertices, step);
+    for sample in samples.iter_mut() {
+        sample.ground = hmap.sample(sample.x, sample.y);
+    }
+
+    // find the points near each sample, using a grid of the samples with cells of size `step`
+    let cell = |x: f64, y: f64| ((x / step).floor() as i64, (y / step).floor() as i64);
+    let mut buckets: HashMap<(i64, i64), Vec<usize>> = HashMap::default();
+    for (i, sample) in samples.iter().enumerate() {
+        buckets.entry(cell(sample.x, sample.y)).or_default().push(i);
+    }
+    let xyz_file = tmpfolder.join("xyztemp.xyz.bin");
+    let mut reader = XyzInternalReader::new(BufReader::new(fs.open(xyz_file)?))?;
+    while let Some(r) = reader.next()? {
+        let (cx, cy) = cell(r.x, r.y);
+        for nx in cx - 1..=cx + 1 {
+            for ny in cy - 1..=cy + 1 {
+                let Some(indices) = buckets.get(&(nx, ny)) else {
+                    continue;
+                };
+                for &i in indices {
+                    let sample = &mut samples[i];
+                    if (sample.x - r.x).powi(2) + (sample.y - r.y).powi(2) > step * step {
+                        continue;
+                    }
+                    sample.points += 1;
+                    if r.classification == 2 {
+                        sample.ground_points += 1;
+                    } else if let Some(ground) = sample.ground {
+                        sample.vegetation = sample.vegetation.max(r.z - ground);
+                    }
+                }
+            }
+        }
+    }
+
+    let csv_file = output.with_extension("csv");
+    info!("Writing {}", csv_file.display());
+    let mut f = BufWriter::new(fs.create(&csv_file)?);
+    writeln!(
+        f,
+        "distance,x,y,ground,vegetation_height,points,ground_points"
+    )?;
+    for s in samples.iter() {
+        writeln!(
+            f,
+            "{:.2},{:.2},{:.2},{},{:.2},{},{}",
+            s.distance,
+            s.x,
+            s.y,
+            s.ground.map(|g| format!("{:.2}", g)).unwrap_or_default(),
+            s.vegetation,
+            s.points,
+            s.ground_points
+        )?;
+    }
+    f.flush()?;
+
+    let png_file = output.with_extension("png");
+    info!("Writing {}", png_file.display());
+    draw_chart(&samples, config.contour_interval).write_to(
+        &mut BufWriter::new(fs.create(&png_file)?),
+        image::ImageFormat::Png,
+    )?;
+
+    info!("Done");
+    Ok(())
+}
+
+/// Reads the vertices of the profile line from a DXF or GeoJSON file, or parses them from a list
+/// of coordinates.
+fn read_line(fs: &impl FileSystem, line: &str) -> Result<Vec<(f64, f64)>> {
+    let lowercase = line.to_lowercase();
+    if lowercase.ends_with(".dxf") {
+        let polylines = read_polylines(fs, Path::new(line))?;
+        let polyline = polylines
+            .into_iter()
+            .next()
+            .ok_or_else(|| format!("No polylines in {}", line))?;
+        Ok(polyline.points)
+    } else if lowercase.ends_with(".geojson") || lowercase.ends_with(".json") {
+        let json: serde_json::Value = serde_json::from_str(&fs.read_to_string(line)?)?;
+        find_linestring(&json).ok_or_else(|| format!("No LineString in {}", line).into())
+    } else {
+        parse_coordinates(line)
+    }
+}
+
+/// Parses a list of coordinates, the numbers separated by commas, semicolons or whitespace and
+/// taken in pairs: `x1,y1;x2,y2`, `x1 y1, x2 y2` and `x1, y1 x2, y2` are all the same line.
+fn parse_coordinates(line: &str) -> Result<Vec<(f64, f64)>> {
+    let numbers = line
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .filter(|n| !n.is_empty())
+        .map(|n| {
+            n.parse::<f64>()
+                .map_err(|_| format!("Invalid coordinate {:?}", n).into())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if numbers.len() % 2 != 0 {
+        return Err(format!("Odd number of coordinates in {:?}", line).into());
+    }
+    Ok(numbers.chunks_exact(2).map(|c| (c[0], c[1])).collect())
+}
+
+/// Finds the coordinates of the first LineString in a GeoJSON object.
+fn find_linestring(json: &serde_json::Value) -> Option<Vec<(f64, f64)>> {
+    match json.get("type")?.as_str()? {
+        "LineString" => json
+            .get("coordinates")?
+            .as_array()?
+            .iter()
+            .map(|c| Some((c.get(0)?.as_f64()?, c.get(1)?.as_f64()?)))
+            .collect(),
+        "Feature" => find_linestring(json.get("geometry")?),
+        "FeatureCollection" => json
+            .get("features")?
+            .as_array()?
+            .iter()
+            .find_map(find_linestring),
+        _ => None,
+    }
+}
+
+/// Places samples every `step` meters along the polyline, and at its end.
+fn sample_line(vertices: &[(f64, f64)], step: f64) -> Vec<Sample> {
+    let sample = |distance, x, y| Sample {
+        distance,
+        x,
+        y,
+        ground: None,
+        vegetation: 0.0,
+        points: 0,
+        ground_points: 0,
+    };
+
+    let mut samples = Vec::new();
+    // distance along the line at the start of the segment and of the next sample
+    let mut start = 0.0;
+    let mut next = 0.0;
+    for segment in vertices.windows(2) {
+        let ((x0, y0), (x1, y1)) = (segment[0], segment[1]);
+        let length = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+        while next <= start + length {
+            let t = if length > 0.0 {
+                (next - start) / length
+            } else {
+                0.0
+            };
+            samples.push(sample(next, x0 + t * (x1 - x0), y0 + t * (y1 - y0)));
+            next += step;
+        }
+        start += length;
+    }
+    if samples.last().is_some_and(|s| s.distance < start) {
+        let (x, y) = vertices[vertices.len() - 1];
+        samples.push(sample(start, x, y));
+    }
+    samples
+}
+
+/// Draws the profile: terrain in brown, vegetation heights as green bars above it, and grey grid
+/// lines at every contour interval and at even distances.
+fn draw_chart(samples: &[Sample], contour_interval: f64) -> RgbImage {
+    let mut img = RgbImage::from_pixel(CHART_WIDTH, CHART_HEIGHT, Rgb([255, 255, 255]));
+
+    let grounds = samples.iter().filter_map(|s| s.ground.map(|g| (s, g)));
+    let low = grounds.clone().map(|(_, g)| g).fold(f64::MAX, f64::min);
+    let high = grounds
+        .clone()
+        .map(|(s, g)| g + s.vegetation)
+        .fold(f64::MIN, f64::max);
+    if low > high {
+        // no part of the line is on the heightmap
+        return img;
+    }
+    let low = low - 1.0;
+    let high = high + 1.0;
+    let length = samples.last().map(|s| s.distance).unwrap_or(1.0).max(1.0);
+
+    let left = CHART_MARGIN as f32;
+    let bottom = (CHART_HEIGHT - CHART_MARGIN) as f32;
+    let width = (CHART_WIDTH - 2 * CHART_MARGIN) as f32;
+    let height = (CHART_HEIGHT - 2 * CHART_MARGIN) as f32;
+    let px = |d: f64| left + (d / length) as f32 * width;
+    let py = |z: f64| bottom - ((z - low) / (high - low)) as f32 * height;
+
+    // grid lines
+    let grey = Rgb([210, 210, 210]);
+    if contour_interval > 0.0 {
+        let mut z = (low / contour_interval).ceil() * contour_interval;
+        while z < high {
+            draw_line_segment_mut(&mut img, (left, py(z)), (left + width, py(z)), grey);
+            z += contour_interval;
+        }
+    }
+    let spacing = 10f64.powf((length / 2.0).log10().floor()).max(1.0);
+    let mut d = 0.0;
+    while d <= length {
+        draw_line_segment_mut(&mut img, (px(d), bottom), (px(d), bottom - height), grey);
+        d += spacing;
+    }
+
+    // terrain and vegetation columns
+    let column = (width / samples.len() as f32).ceil().max(1.0) as u32;
+    for (s, g) in grounds.clone() {
+        let x = px(s.distance) as i32 - column as i32 / 2;
+        let top = py(g);
+        if s.vegetation > 0.0 {
+            let veg_top = py(g + s.vegetation);
+            draw_filled_rect_mut(
+                &mut img,
+                Rect::at(x, veg_top as i32).of_size(column, (top - veg_top).max(1.0) as u32),
+                Rgb([120, 200, 90]),
+            );
+        }
+        draw_filled_rect_mut(
+            &mut img,
+            Rect::at(x, top as i32).of_size(column, (bottom - top).max(1.0) as u32),
+            Rgb([235, 215, 180]),
+        );
+    }
+
+    // terrain outline
+    let points = grounds
+        .map(|(s, g)| (px(s.distance), py(g)))
+        .collect::<Vec<_>>();
+    for segment in points.windows(2) {
+        draw_line_segment_mut(&mut img, segment[0], segment[1], Rgb([120, 70, 20]));
+    }
+    img
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_line() {
+        let samples = sample_line(&[(0.0, 0.0), (3.0, 0.0), (3.0, 2.5)], 2.0);
+        let coords = samples
+            .iter()
+            .map(|s| (s.distance, s.x, s.y))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            coords,
+            vec![
+                (0.0, 0.0, 0.0),
+                (2.0, 2.0, 0.0),
+                (4.0, 3.0, 1.0),
+                (5.5, 3.0, 2.5)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_coordinates() {
+        let line = vec![(1.0, 2.0), (3.5, 4.0)];
+        assert_eq!(parse_coordinates("1,2;3.5,4").unwrap(), line);
+        assert_eq!(parse_coordinates("1, 2; 3.5, 4").unwrap(), line);
+        assert_eq!(parse_coordinates(" 1 2\t3.5 4 ").unwrap(), line);
+        assert!(parse_coordinates("1,2;3").is_err());
+        assert!(parse_coordinates("1,2;3,north").is_err());
+    }
+
+    #[test]
+    fn test_find_linestring() {
+        let json = serde_json::json!({
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "geometry": {"type": "Point", "coordinates": [1.0, 2.0]}},
+                {"type": "Feature", "geometry": {"type": "LineString", "coordinates": [[1.0, 2.0], [3.0, 4.0]]}}
+            ]
+        });
+        assert_eq!(find_linestring(&json), Some(vec![(1.0, 2.0), (3.0, 4.0)]));
+    }
+}
@@ -1,13 +1,17 @@
 use crate::config::Config;
+use crate::error::{missing, Result, ResultExt};
 use crate::io::bytes::FromToBytes;
+use crate::io::crs::{Crs, CRS_FILE};
 use crate::io::fs::FileSystem;
+use crate::io::geotiff::png_to_geotiff;
 use crate::io::heightmap::HeightMap;
+use crate::io::{read_png, read_world_file, write_png};
+use crate::workspace::Workspace;
 use image::ImageBuffer;
 use image::Rgba;
 use imageproc::drawing::{draw_filled_circle_mut, draw_line_segment_mut};
 use log::info;
 use rustc_hash::FxHashMap as HashMap;
-use std::error::Error;
 use std::f64::consts::PI;
 use std::io::BufRead;
 use std::io::BufReader;
@@ -17,49 +21,24 @@ use std::path::Path;
 pub fn render(
     fs: &impl FileSystem,
     config: &Config,
-    thread: &String,
-    tmpfolder: &Path,
+    workspace: &Workspace,
     angle_deg: f64,
     nwidth: usize,
     nodepressions: bool,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<()> {
     info!("Rendering...");
 
+    let tmpfolder = &workspace.tmpfolder();
+
     let scalefactor = config.scalefactor;
 
     let angle = -angle_deg / 180.0 * PI;
 
     // Draw vegetation ----------
-    let tfw_in = tmpfolder.join("vegetation.pgw");
-    let mut lines = BufReader::new(fs.open(tfw_in).expect("PGW file does not exist")).lines();
-    let x0 = lines
-        .nth(4)
-        .expect("no 4 line")
-        .expect("Could not read line 5")
-        .parse::<f64>()
-        .unwrap();
-    let y0 = lines
-        .next()
-        .expect("no 5 line")
-        .expect("Could not read line 6")
-        .parse::<f64>()
-        .unwrap();
-
-    let mut img_reader = image::ImageReader::new(BufReader::new(
-        fs.open(tmpfolder.join("vegetation.png"))
-            .expect("Opening vegetation image failed"),
-    ));
-    img_reader.set_format(image::ImageFormat::Png);
-    img_reader.no_limits();
-    let img = img_reader.decode().unwrap();
-
-    let mut imgug_reader = image::ImageReader::new(BufReader::new(
-        fs.open(tmpfolder.join("undergrowth.png"))
-            .expect("Opening undergrowth image failed"),
-    ));
-    imgug_reader.set_format(image::ImageFormat::Png);
-    imgug_reader.no_limits();
-    let imgug = imgug_reader.decode().unwrap();
+    let [.., x0, y0] = read_world_file(fs, tmpfolder.join("vegetation.pgw"))?;
+
+    let img = read_png(fs, tmpfolder.join("vegetation.png"))?;
+    let imgug = read_png(fs, tmpfolder.join("undergrowth.png"))?;
 
     let w = img.width();
     let h = img.height();
@@ -89,12 +68,7 @@ pub fn render(
 
     let low_file = tmpfolder.join("low.png");
     if fs.exists(&low_file) {
-        let mut low_reader = image::ImageReader::new(BufReader::new(
-            fs.open(low_file).expect("Opening low image failed"),
-        ));
-        low_reader.set_format(image::ImageFormat::Png);
-        low_reader.no_limits();
-        let low = low_reader.decode().unwrap();
+        let low = read_png(fs, &low_file)?;
         let low = image::imageops::resize(
             &low,
             new_width,
@@ -104,6 +78,8 @@ pub fn render(
         image::imageops::overlay(&mut img, &low, 0, 0);
     }
 
+    draw_nodata_hatch(fs, config, &mut img, tmpfolder, x0, y0)?;
+
     // north lines ----------------
     if angle != 999.0 {
         let mut i: f64 = eastoff - 600.0 * 250.0 / 254.0 / angle.cos() * 100.0 / scalefactor;
@@ -125,28 +101,31 @@ pub fn render(
         }
     }
 
-    draw_curves(fs, config, &mut img, tmpfolder, nodepressions, true).unwrap();
+    draw_curves(fs, config, &mut img, tmpfolder, nodepressions, true)?;
 
     // dotknolls----------
     let input = tmpfolder.join("dotknolls.dxf");
-    let data = fs.read_to_string(input).expect("Can not read input file");
+    let data = fs.read_to_string(&input).with_file(&input)?;
     let data = data.split("POINT");
 
+    let mut line = 1;
     for (j, rec) in data.enumerate() {
+        let first_line = line;
+        line += rec.matches('\n').count();
         let mut x: f64 = 0.0;
         let mut y: f64 = 0.0;
         if j > 0 {
             let val = rec.split('\n').collect::<Vec<&str>>();
-            let layer = val[2].trim();
+            let layer = val.get(2).map_or("", |l| l.trim());
             for (i, v) in val.iter().enumerate() {
                 let vt = v.trim_end();
                 if vt == " 10" {
-                    x = (val[i + 1].trim().parse::<f64>().unwrap() - x0) * 600.0
+                    x = (dxf_value(&val, i + 1, &input, first_line)? - x0) * 600.0
                         / 254.0
                         / scalefactor;
                 }
                 if vt == " 20" {
-                    y = (y0 - val[i + 1].trim().parse::<f64>().unwrap()) * 600.0
+                    y = (y0 - dxf_value(&val, i + 1, &input, first_line)?) * 600.0
                         / 254.0
                         / scalefactor;
                 }
@@ -161,12 +140,7 @@ pub fn render(
     // blocks -------------
     let blocks_file = tmpfolder.join("blocks.png");
     if fs.exists(&blocks_file) {
-        let mut blockpurple_reader = image::ImageReader::new(BufReader::new(
-            fs.open(blocks_file).expect("Opening blocks image failed"),
-        ));
-        blockpurple_reader.set_format(image::ImageFormat::Png);
-        blockpurple_reader.no_limits();
-        let blockpurple = blockpurple_reader.decode().unwrap();
+        let blockpurple = read_png(fs, &blocks_file)?;
         let mut blockpurple = blockpurple.to_rgba8();
         for p in blockpurple.pixels_mut() {
             if p[0] == 255 && p[1] == 255 && p[2] == 255 {
@@ -196,13 +170,7 @@ pub fn render(
     // blueblack -------------
     let blueblack_file = tmpfolder.join("blueblack.png");
     if fs.exists(&blueblack_file) {
-        let mut imgbb_reader = image::ImageReader::new(BufReader::new(
-            fs.open(blueblack_file)
-                .expect("Opening blueblack image failed"),
-        ));
-        imgbb_reader.set_format(image::ImageFormat::Png);
-        imgbb_reader.no_limits();
-        let imgbb = imgbb_reader.decode().unwrap();
+        let imgbb = read_png(fs, &blueblack_file)?;
         let mut imgbb = imgbb.to_rgba8();
         for p in imgbb.pixels_mut() {
             if p[0] == 255 && p[1] == 255 && p[2] == 255 {
@@ -230,21 +198,28 @@ pub fn render(
             ("cliff4", Rgba([100, 100, 0, 255])),
         ]);
     }
-    let input = tmpfolder.join("c2g.dxf");
-    let data = fs.read_to_string(input).expect("Can not read input file");
-    let data: Vec<&str> = data.split("POLYLINE").collect();
-
-    for (j, rec) in data.iter().enumerate() {
-        let mut x = Vec::<f64>::new();
-        let mut y = Vec::<f64>::new();
-        let mut xline = 0;
-        let mut yline = 0;
-        let mut layer = "";
-        if j > 0 {
+    for file in ["c2g.dxf", "c3g.dxf"] {
+        let input = tmpfolder.join(file);
+        let data = fs.read_to_string(&input).with_file(&input)?;
+        let data: Vec<&str> = data.split("POLYLINE").collect();
+
+        let mut line = 1;
+        for (j, rec) in data.iter().enumerate() {
+            let first_line = line;
+            line += rec.matches('\n').count();
+            if j == 0 {
+                continue;
+            }
+            let mut x = Vec::<f64>::new();
+            let mut y = Vec::<f64>::new();
+            let mut xline = 0;
+            let mut yline = 0;
             let r = rec.split("VERTEX").collect::<Vec<&str>>();
-            let apu = r[1];
+            let Some(apu) = r.get(1) else {
+                continue;
+            };
             let val = apu.split('\n').collect::<Vec<&str>>();
-            layer = val[2].trim();
+            let layer = val.get(2).map_or("", |l| l.trim());
             for (i, v) in val.iter().enumerate() {
                 let vt = v.trim_end();
                 if vt == " 10" {
@@ -254,146 +229,60 @@ pub fn render(
                     yline = i + 1;
                 }
             }
-            for (i, v) in r.iter().enumerate() {
-                if i > 0 {
-                    let val = v.trim_end().split('\n').collect::<Vec<&str>>();
-                    x.push(
-                        (val[xline].trim().parse::<f64>().unwrap() - x0) * 600.0
-                            / 254.0
-                            / scalefactor,
-                    );
-                    y.push(
-                        (y0 - val[yline].trim().parse::<f64>().unwrap()) * 600.0
-                            / 254.0
-                            / scalefactor,
-                    );
-                }
-            }
-        }
-        let last_idx = x.len() - 1;
-        if x.first() != x.last() || y.first() != y.last() {
-            let dist = ((x[0] - x[last_idx]).powi(2) + (y[0] - y[last_idx]).powi(2)).sqrt();
-            if dist > 0.0 {
-                let dx = x[0] - x[last_idx];
-                let dy = y[0] - y[last_idx];
-                x[0] += dx / dist * 1.5;
-                y[0] += dy / dist * 1.5;
-                x[last_idx] -= dx / dist * 1.5;
-                y[last_idx] -= dy / dist * 1.5;
-                draw_filled_circle_mut(
-                    &mut img,
-                    (x[0] as i32, y[0] as i32),
-                    3,
-                    *cliffcolor.get(&layer).unwrap_or(&black),
+            let mut vertex_line = first_line + r[0].matches('\n').count();
+            for v in r.iter().skip(1) {
+                let val = v.trim_end().split('\n').collect::<Vec<&str>>();
+                x.push(
+                    (dxf_value(&val, xline, &input, vertex_line)? - x0) * 600.0
+                        / 254.0
+                        / scalefactor,
                 );
-                draw_filled_circle_mut(
-                    &mut img,
-                    (x[last_idx] as i32, y[last_idx] as i32),
-                    3,
-                    *cliffcolor.get(&layer).unwrap_or(&black),
+                y.push(
+                    (y0 - dxf_value(&val, yline, &input, vertex_line)?) * 600.0
+                        / 254.0
+                        / scalefactor,
                 );
+                vertex_line += v.matches('\n').count();
             }
-        }
-        for i in 1..x.len() {
-            for n in 0..6 {
-                for m in 0..6 {
-                    draw_line_segment_mut(
-                        &mut img,
-                        (
-                            (x[i - 1] + (n as f64) - 3.0).floor() as f32,
-                            (y[i - 1] + (m as f64) - 3.0).floor() as f32,
-                        ),
-                        (
-                            (x[i] + (n as f64) - 3.0).floor() as f32,
-                            (y[i] + (m as f64) - 3.0).floor() as f32,
-                        ),
-                        *cliffcolor.get(&layer).unwrap_or(&black),
-                    )
-                }
-            }
-        }
-    }
-
-    let input = &tmpfolder.join("c3g.dxf");
-    let data = fs.read_to_string(input).expect("Can not read input file");
-    let data: Vec<&str> = data.split("POLYLINE").collect();
-
-    for (j, rec) in data.iter().enumerate() {
-        let mut x = Vec::<f64>::new();
-        let mut y = Vec::<f64>::new();
-        let mut xline = 0;
-        let mut yline = 0;
-        let mut layer = "";
-        if j > 0 {
-            let r = rec.split("VERTEX").collect::<Vec<&str>>();
-            let apu = r[1];
-            let val = apu.split('\n').collect::<Vec<&str>>();
-            layer = val[2].trim();
-            for (i, v) in val.iter().enumerate() {
-                let vt = v.trim_end();
-                if vt == " 10" {
-                    xline = i + 1;
-                }
-                if vt == " 20" {
-                    yline = i + 1;
-                }
+            if x.is_empty() {
+                continue;
             }
-            for (i, v) in r.iter().enumerate() {
-                if i > 0 {
-                    let val = v.trim_end().split('\n').collect::<Vec<&str>>();
-                    x.push(
-                        (val[xline].trim().parse::<f64>().unwrap() - x0) * 600.0
-                            / 254.0
-                            / scalefactor,
-                    );
-                    y.push(
-                        (y0 - val[yline].trim().parse::<f64>().unwrap()) * 600.0
-                            / 254.0
-                            / scalefactor,
+            let color = *cliffcolor.get(&layer).unwrap_or(&black);
+            let last_idx = x.len() - 1;
+            if x.first() != x.last() || y.first() != y.last() {
+                let dist = ((x[0] - x[last_idx]).powi(2) + (y[0] - y[last_idx]).powi(2)).sqrt();
+                if dist > 0.0 {
+                    let dx = x[0] - x[last_idx];
+                    let dy = y[0] - y[last_idx];
+                    x[0] += dx / dist * 1.5;
+                    y[0] += dy / dist * 1.5;
+                    x[last_idx] -= dx / dist * 1.5;
+                    y[last_idx] -= dy / dist * 1.5;
+                    draw_filled_circle_mut(&mut img, (x[0] as i32, y[0] as i32), 3, color);
+                    draw_filled_circle_mut(
+                        &mut img,
+                        (x[last_idx] as i32, y[last_idx] as i32),
+                        3,
+                        color,
                     );
                 }
             }
-        }
-        let last_idx = x.len() - 1;
-        if x.first() != x.last() || y.first() != y.last() {
-            let dist = ((x[0] - x[last_idx]).powi(2) + (y[0] - y[last_idx]).powi(2)).sqrt();
-            if dist > 0.0 {
-                let dx = x[0] - x[last_idx];
-                let dy = y[0] - y[last_idx];
-                x[0] += dx / dist * 1.5;
-                y[0] += dy / dist * 1.5;
-                x[last_idx] -= dx / dist * 1.5;
-                y[last_idx] -= dy / dist * 1.5;
-
-                draw_filled_circle_mut(
-                    &mut img,
-                    (x[0] as i32, y[0] as i32),
-                    3,
-                    *cliffcolor.get(&layer).unwrap_or(&black),
-                );
-                draw_filled_circle_mut(
-                    &mut img,
-                    (x[last_idx] as i32, y[last_idx] as i32),
-                    3,
-                    *cliffcolor.get(&layer).unwrap_or(&black),
-                );
-            }
-        }
-        for i in 1..x.len() {
-            for n in 0..6 {
-                for m in 0..6 {
-                    draw_line_segment_mut(
-                        &mut img,
-                        (
-                            (x[i - 1] + (n as f64) - 3.0).floor() as f32,
-                            (y[i - 1] + (m as f64) - 3.0).floor() as f32,
-                        ),
-                        (
-                            (x[i] + (n as f64) - 3.0).floor() as f32,
-                            (y[i] + (m as f64) - 3.0).floor() as f32,
-                        ),
-                        *cliffcolor.get(&layer).unwrap_or(&black),
-                    )
+            for i in 1..x.len() {
+                for n in 0..6 {
+                    for m in 0..6 {
+                        draw_line_segment_mut(
+                            &mut img,
+                            (
+                                (x[i - 1] + (n as f64) - 3.0).floor() as f32,
+                                (y[i - 1] + (m as f64) - 3.0).floor() as f32,
+                            ),
+                            (
+                                (x[i] + (n as f64) - 3.0).floor() as f32,
+                                (y[i] + (m as f64) - 3.0).floor() as f32,
+                            ),
+                            color,
+                        )
+                    }
                 }
             }
         }
@@ -401,12 +290,7 @@ pub fn render(
     // high -------------
     let high_file = tmpfolder.join("high.png");
     if fs.exists(&high_file) {
-        let mut high_reader = image::ImageReader::new(BufReader::new(
-            fs.open(high_file).expect("Opening high image failed"),
-        ));
-        high_reader.set_format(image::ImageFormat::Png);
-        high_reader.no_limits();
-        let high = high_reader.decode().unwrap();
+        let high = read_png(fs, &high_file)?;
         let high_thumb = image::imageops::resize(
             &high,
             new_width,
@@ -416,43 +300,82 @@ pub fn render(
         image::imageops::overlay(&mut img, &high_thumb, 0, 0);
     }
 
-    let filename = if nodepressions {
-        format!("pullautus{}", thread)
-    } else {
-        format!("pullautus_depr{}", thread)
-    };
-
-    img.write_to(
-        &mut BufWriter::new(
-            fs.create(format!("{}.png", filename))
-                .expect("could not save output png"),
-        ),
-        image::ImageFormat::Png,
-    )
-    .expect("could not write image");
+    let png_out = workspace.map(nodepressions, "png");
+    write_png(fs, &img, &png_out)?;
 
     let file_in = tmpfolder.join("vegetation.pgw");
-    let pgw_file_out = fs
-        .create(format!("{}.pgw", filename))
-        .expect("Unable to create file");
+    let pgw_out = workspace.map(nodepressions, "pgw");
+    let pgw_file_out = fs.create(&pgw_out).with_file(&pgw_out)?;
     let mut pgw_file_out = BufWriter::new(pgw_file_out);
 
-    if let Ok(lines) = fs.open(file_in) {
+    if let Ok(lines) = fs.open(&file_in) {
         for (i, line) in BufReader::new(lines).lines().enumerate() {
             let ip = line.unwrap_or(String::new());
-            let x: f64 = ip.parse::<f64>().unwrap();
+            let x: f64 = ip.parse::<f64>().with_line(&file_in, i + 1)?;
             if i == 0 || i == 3 {
                 write!(&mut pgw_file_out, "{}\r\n", x / 600.0 * 254.0 * scalefactor)
-                    .expect("Unable to write to file");
+                    .with_file(&pgw_out)?;
             } else {
-                write!(&mut pgw_file_out, "{}\r\n", ip).expect("Unable to write to file");
+                write!(&mut pgw_file_out, "{}\r\n", ip).with_file(&pgw_out)?;
             }
         }
     }
+    pgw_file_out.flush().with_file(&pgw_out)?;
+    drop(pgw_file_out);
+
+    let crs = Crs::from_file(fs, tmpfolder.join(CRS_FILE));
+    Crs::write_prj(crs.as_ref(), fs, &pgw_out)?;
+    if config.geotiff {
+        png_to_geotiff(fs, &png_out, crs.as_ref())?;
+    }
     info!("Done");
     Ok(())
 }
 
+/// Hatches the areas farther than `nodatadistance` from real points with diagonal lines.
+fn draw_nodata_hatch(
+    fs: &impl FileSystem,
+    config: &Config,
+    img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    tmpfolder: &Path,
+    x0: f64,
+    y0: f64,
+) -> Result<()> {
+    let &Config {
+        scalefactor,
+        nodata_distance,
+        nodata_hatch_spacing,
+        nodata_hatch_color: (r, g, b),
+        ..
+    } = config;
+    if nodata_distance <= 0.0 || nodata_hatch_spacing <= 0.0 {
+        return Ok(());
+    }
+    let hmap = HeightMap::from_file(fs, tmpfolder.join("xyz2.hmap"))?;
+    if hmap.distance.is_none() {
+        return Ok(());
+    }
+
+    // size of a pixel in meters, and the hatch spacing and line width in pixels
+    let pixel = 254.0 / 600.0 * scalefactor;
+    let spacing = ((nodata_hatch_spacing / pixel).round() as u32).max(2);
+    let width = (spacing / 4).clamp(1, 4);
+    let color = Rgba([r, g, b, 255]);
+    for py in 0..img.height() {
+        for px in 0..img.width() {
+            if (px + py) % spacing >= width {
+                continue;
+            }
+            let x = x0 + (px as f64 + 0.5) * pixel;
+            let y = y0 - (py as f64 + 0.5) * pixel;
+            if hmap.is_unmapped_at(x, y, nodata_distance) {
+                img.put_pixel(px, py, color);
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn draw_curves(
     fs: &impl FileSystem,
     config: &Config,
@@ -460,7 +383,7 @@ pub fn draw_curves(
     tmpfolder: &Path,
     nodepressions: bool,
     draw_image: bool,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<()> {
     // Drawing curves --------------
     let &Config {
         scalefactor,
@@ -598,23 +521,25 @@ pub fn draw_curves(
     }
 
     let input = &tmpfolder.join("out2.dxf");
-    let data = fs.read_to_string(input).expect("Can not read input file");
+    let data = fs.read_to_string(input).with_file(input)?;
     let data: Vec<&str> = data.split("POLYLINE").collect();
 
     // only create the file if condition is met
+    let output = tmpfolder.join("formlines.dxf");
     let mut fp = if formline == 2.0 && !nodepressions {
-        let output = tmpfolder.join("formlines.dxf");
-        let fp = fs.create(output).expect("Unable to create file");
+        let fp = fs.create(&output).with_file(&output)?;
         let mut fp = BufWriter::new(fp);
-        fp.write_all(data[0].as_bytes())
-            .expect("Could not write file");
+        fp.write_all(data[0].as_bytes()).with_file(&output)?;
 
         Some(fp)
     } else {
         None
     };
 
+    let mut line = 1;
     for (j, rec) in data.iter().enumerate() {
+        let first_line = line;
+        line += rec.matches('\n').count();
         let mut x = Vec::<f64>::new();
         let mut y = Vec::<f64>::new();
         let mut xline = 0;
@@ -622,9 +547,11 @@ pub fn draw_curves(
         let mut layer = "";
         if j > 0 {
             let r = rec.split("VERTEX").collect::<Vec<&str>>();
-            let apu = r[1];
+            let Some(apu) = r.get(1) else {
+                continue;
+            };
             let val = apu.split('\n').collect::<Vec<&str>>();
-            layer = val[2].trim();
+            layer = val.get(2).map_or("", |l| l.trim());
             for (i, v) in val.iter().enumerate() {
                 let vt = v.trim_end();
                 if vt == " 10" {
@@ -634,20 +561,20 @@ pub fn draw_curves(
                     yline = i + 1;
                 }
             }
-            for (i, v) in r.iter().enumerate() {
-                if i > 0 {
-                    let val = v.trim_end().split('\n').collect::<Vec<&str>>();
-                    x.push(
-                        (val[xline].trim().parse::<f64>().unwrap() - x0) * 600.0
-                            / 254.0
-                            / scalefactor,
-                    );
-                    y.push(
-                        (y0 - val[yline].trim().parse::<f64>().unwrap()) * 600.0
-                            / 254.0
-                            / scalefactor,
-                    );
-                }
+            let mut vertex_line = first_line + r[0].matches('\n').count();
+            for v in r.iter().skip(1) {
+                let val = v.trim_end().split('\n').collect::<Vec<&str>>();
+                x.push(
+                    (dxf_value(&val, xline, input, vertex_line)? - x0) * 600.0
+                        / 254.0
+                        / scalefactor,
+                );
+                y.push(
+                    (y0 - dxf_value(&val, yline, input, vertex_line)?) * 600.0
+                        / 254.0
+                        / scalefactor,
+                );
+                vertex_line += v.matches('\n').count();
             }
         }
         let mut color = Rgba([200, 0, 200, 255]); // purple
@@ -807,7 +734,7 @@ pub fn draw_curves(
                     if let (Some(fp), true) = (fp.as_mut(), curvew == 1.5) {
                         if !formlinestart {
                             write!(fp, "POLYLINE\r\n 66\r\n1\r\n  8\r\n{}\r\n  0\r\n", f_label)
-                                .expect("Could not write file");
+                                .with_file(&output)?;
                             formlinestart = true;
                         }
                         write!(
@@ -817,7 +744,7 @@ pub fn draw_curves(
                             x[i] / 600.0 * 254.0 * scalefactor + x0,
                             -y[i] / 600.0 * 254.0 * scalefactor + y0
                         )
-                        .expect("Could not write file");
+                        .with_file(&output)?;
                     }
 
                     if draw_image {
@@ -853,7 +780,7 @@ pub fn draw_curves(
                                     onegapdone = true;
                                 }
                             }
-                            if !onegapdone && (i < x.len() - 9) && i > 6 {
+                            if !onegapdone && i > 6 && i + 9 < x.len() {
                                 gap = gaplength * 0.82;
                                 onegapdone = true;
                                 linedist = 0.0
@@ -918,20 +845,32 @@ pub fn draw_curves(
                         }
                     }
                 } else if let (Some(fp), true) = (fp.as_mut(), formlinestart) {
-                    fp.write_all(b"SEQEND\r\n  0\r\n")
-                        .expect("Could not write file");
+                    fp.write_all(b"SEQEND\r\n  0\r\n").with_file(&output)?;
                     formlinestart = false;
                 }
             }
             if let (Some(fp), true) = (fp.as_mut(), formlinestart) {
-                fp.write_all(b"SEQEND\r\n  0\r\n")
-                    .expect("Could not write file");
+                fp.write_all(b"SEQEND\r\n  0\r\n").with_file(&output)?;
             }
         }
     }
     if let Some(fp) = fp.as_mut() {
         fp.write_all(b"ENDSEC\r\n  0\r\nEOF\r\n")
-            .expect("Could not write file");
+            .with_file(&output)?;
+        fp.flush().with_file(&output)?;
     }
     Ok(())
 }
+
+/// Parses the value at `index` of the lines of a dxf record starting at line `first_line` of
+/// the file.
+fn dxf_value(val: &[&str], index: usize, path: &Path, first_line: usize) -> Result<f64> {
+    let value = val
+        .get(index)
+        .ok_or_else(|| missing("value"))
+        .with_line(path, first_line + index)?;
+    value
+        .trim()
+        .parse::<f64>()
+        .with_line(path, first_line + index)
+}
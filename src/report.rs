@@ -0,0 +1,468 @@
+use image::{Rgb, RgbImage};
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::io::fs::FileSystem;
+use crate::io::tileindex::Bounds;
+use crate::util::Timing;
+use crate::vec2d::Vec2D;
+
+/// File of the temp folder the timing sections of the processing are written to.
+pub const TIMING_FILE: &str = "timing.json";
+
+/// Folder of the batch output folder the reports are written to.
+pub const REPORT_FOLDER: &str = "report";
+
+/// Size in meters of the cells used for the coverage and the voids.
+const REPORT_CELL: f64 = 2.0;
+
+/// Smallest area in square meters of a void to be reported.
+const MIN_VOID_AREA: f64 = 100.0;
+
+/// Number of points, ground points and water points in a cell.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct CellCount {
+    points: u32,
+    ground: u32,
+    water: u32,
+}
+
+/// A connected area of cells without any points.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Void {
+    pub area: f64,
+    pub bounds: Bounds,
+}
+
+/// Point statistics of a tile, collected while its points are read. Points outside the tile
+/// are ignored.
+pub struct TileStats {
+    bounds: Bounds,
+    water_class: u8,
+    points: u64,
+    classes: [u64; 256],
+    cells: Vec2D<CellCount>,
+}
+
+impl TileStats {
+    pub fn new(bounds: &Bounds, water_class: u8) -> Self {
+        let w = ((bounds.max_x - bounds.min_x) / REPORT_CELL)
+            .ceil()
+            .max(1.0) as usize;
+        let h = ((bounds.max_y - bounds.min_y) / REPORT_CELL)
+            .ceil()
+            .max(1.0) as usize;
+        Self {
+            bounds: *bounds,
+            water_class,
+            points: 0,
+            classes: [0; 256],
+            cells: Vec2D::new(w, h, CellCount::default()),
+        }
+    }
+
+    pub fn add(&mut self, x: f64, y: f64, classification: u8) {
+        let b = &self.bounds;
+        if x < b.min_x || y < b.min_y || x >= b.max_x || y >= b.max_y {
+            return;
+        }
+        self.points += 1;
+        self.classes[classification as usize] += 1;
+
+        let cx = (((x - b.min_x) / REPORT_CELL) as usize).min(self.cells.width() - 1);
+        let cy = (((y - b.min_y) / REPORT_CELL) as usize).min(self.cells.height() - 1);
+        let cell = &mut self.cells[(cx, cy)];
+        cell.points += 1;
+        if classification == 2 {
+            cell.ground += 1;
+        } else if classification == self.water_class {
+            cell.water += 1;
+        }
+    }
+
+    fn area(&self) -> f64 {
+        (self.bounds.max_x - self.bounds.min_x) * (self.bounds.max_y - self.bounds.min_y)
+    }
+
+    /// Share of the cells where `covered` holds.
+    fn coverage(&self, covered: impl Fn(&CellCount) -> bool) -> f64 {
+        let count = self.cells.iter().filter(|(_, _, c)| covered(c)).count();
+        count as f64 / (self.cells.width() * self.cells.height()) as f64
+    }
+
+    /// Areas without any points, largest first. Voids smaller than `MIN_VOID_AREA` are left out.
+    pub fn voids(&self) -> Vec<Void> {
+        let (w, h) = (self.cells.width(), self.cells.height());
+        let mut visited = Vec2D::new(w, h, false);
+        let mut voids = Vec::new();
+        let mut stack = Vec::new();
+        for (x, y, c) in self.cells.iter() {
+            if c.points > 0 || visited[(x, y)] {
+                continue;
+            }
+            // flood fill the empty cells connected to this one
+            let (mut cells, mut min, mut max) = (0, (x, y), (x, y));
+            visited[(x, y)] = true;
+            stack.push((x, y));
+            while let Some((cx, cy)) = stack.pop() {
+                cells += 1;
+                min = (min.0.min(cx), min.1.min(cy));
+                max = (max.0.max(cx), max.1.max(cy));
+                let neighbours = [
+                    (cx.wrapping_sub(1), cy),
+                    (cx + 1, cy),
+                    (cx, cy.wrapping_sub(1)),
+                    (cx, cy + 1),
+                ];
+                for (nx, ny) in neighbours {
+                    if nx < w && ny < h && !visited[(nx, ny)] && self.cells[(nx, ny)].points == 0 {
+                        visited[(nx, ny)] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+            let area = cells as f64 * REPORT_CELL * REPORT_CELL;
+            if area >= MIN_VOID_AREA {
+                voids.push(Void {
+                    area,
+                    bounds: Bounds {
+                        min_x: self.bounds.min_x + min.0 as f64 * REPORT_CELL,
+                        min_y: self.bounds.min_y + min.1 as f64 * REPORT_CELL,
+                        max_x: (self.bounds.min_x + (max.0 + 1) as f64 * REPORT_CELL)
+                            .min(self.bounds.max_x),
+                        max_y: (self.bounds.min_y + (max.1 + 1) as f64 * REPORT_CELL)
+                            .min(self.bounds.max_y),
+                    },
+                });
+            }
+        }
+        voids.sort_by(|a, b| b.area.total_cmp(&a.area));
+        voids
+    }
+
+    /// Point density thumbnail, darker is denser. Densities of twice the average or more are black.
+    fn density_image(&self) -> RgbImage {
+        let (w, h) = (self.cells.width(), self.cells.height());
+        let full = (2.0 * self.points as f64 / (w * h) as f64).max(1.0);
+        RgbImage::from_fn(w as u32, h as u32, |x, y| {
+            let c = self.cells[(x as usize, h - 1 - y as usize)];
+            let v = 255.0 * (1.0 - (c.points as f64 / full).min(1.0));
+            Rgb([v as u8, v as u8, v as u8])
+        })
+    }
+
+    /// Coverage thumbnail, the reported voids in red, smaller empty areas in orange and the
+    /// cells without ground or water points in gray.
+    fn voids_image(&self, voids: &[Void]) -> RgbImage {
+        let (w, h) = (self.cells.width(), self.cells.height());
+        let mut img = RgbImage::from_fn(w as u32, h as u32, |x, y| {
+            let c = self.cells[(x as usize, h - 1 - y as usize)];
+            if c.points == 0 {
+                Rgb([255, 190, 120])
+            } else if c.ground + c.water == 0 {
+                Rgb([200, 200, 200])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        });
+        for void in voids {
+            let x0 = ((void.bounds.min_x - self.bounds.min_x) / REPORT_CELL).round() as usize;
+            let x1 = ((void.bounds.max_x - self.bounds.min_x) / REPORT_CELL).ceil() as usize;
+            let y0 = ((void.bounds.min_y - self.bounds.min_y) / REPORT_CELL).round() as usize;
+            let y1 = ((void.bounds.max_y - self.bounds.min_y) / REPORT_CELL).ceil() as usize;
+            for x in x0..x1.min(w) {
+                for y in y0..y1.min(h) {
+                    if self.cells[(x, y)].points == 0 {
+                        img.put_pixel(x as u32, (h - 1 - y) as u32, Rgb([220, 0, 0]));
+                    }
+                }
+            }
+        }
+        img
+    }
+
+    /// Summary of the statistics as JSON.
+    fn to_json(&self, voids: &[Void]) -> Value {
+        let area = self.area();
+        let classes = self
+            .classes
+            .iter()
+            .enumerate()
+            .filter(|(_, &n)| n > 0)
+            .map(|(class, &n)| (class.to_string(), json!(n)))
+            .collect::<serde_json::Map<_, _>>();
+        let void_area: f64 = voids.iter().map(|v| v.area).sum();
+        json!({
+            "bounds": bounds_json(&self.bounds),
+            "area": area,
+            "points": self.points,
+            "density": self.points as f64 / area,
+            "ground_density": self.classes[2] as f64 / area,
+            "classes": classes,
+            "ground_coverage": self.coverage(|c| c.ground > 0),
+            "water_coverage": self.coverage(|c| c.water > 0),
+            "ground_water_coverage": self.coverage(|c| c.ground + c.water > 0),
+            "voids": {
+                "count": voids.len(),
+                "area": void_area,
+                "ratio": void_area / area,
+                "largest": voids.first().map_or(0.0, |v| v.area),
+                "regions": voids
+                    .iter()
+                    .map(|v| json!({"area": v.area, "bounds": bounds_json(&v.bounds)}))
+                    .collect::<Vec<_>>(),
+            },
+        })
+    }
+}
+
+fn bounds_json(b: &Bounds) -> Value {
+    json!([b.min_x, b.min_y, b.max_x, b.max_y])
+}
+
+/// Writes the timing sections of the processing of a tile to its temp folder.
+pub fn write_timing(fs: &impl FileSystem, tmpfolder: &Path, timing: &mut Timing) -> Result<()> {
+    let sections = timing
+        .sections()
+        .iter()
+        .map(|(name, duration)| json!({"name": name, "seconds": duration.as_secs_f64()}))
+        .collect::<Vec<_>>();
+    let json = json!({
+        "total": timing.elapsed().as_secs_f64(),
+        "sections": sections,
+    });
+    let mut f = BufWriter::new(fs.create(tmpfolder.join(TIMING_FILE))?);
+    serde_json::to_writer_pretty(&mut f, &json)?;
+    Ok(())
+}
+
+/// Writes the report of a tile, `<tile>.json` with its density and void thumbnails, to the report
+/// folder of the batch output folder. The index of the reported tiles is written once the batch
+/// is done, see [`write_report_index`].
+pub fn write_tile_report(
+    fs: &impl FileSystem,
+    config: &Config,
+    tile: &str,
+    stats: &TileStats,
+    tmpfolder: &Path,
+) -> Result<()> {
+    info!("Writing the QA report of {}", tile);
+    let folder = Path::new(&config.batchoutfolder).join(REPORT_FOLDER);
+    fs.create_dir_all(&folder)?;
+
+    let voids = stats.voids();
+    let density_png = format!("{}_density.png", tile);
+    let voids_png = format!("{}_voids.png", tile);
+    for (img, name) in [
+        (stats.density_image(), &density_png),
+        (stats.voids_image(&voids), &voids_png),
+    ] {
+        img.write_to(
+            &mut BufWriter::new(fs.create(folder.join(name))?),
+            image::ImageFormat::Png,
+        )?;
+    }
+
+    let mut json = stats.to_json(&voids);
+    json["tile"] = json!(tile);
+    json["thumbnails"] = json!({"density": density_png, "voids": voids_png});
+    let timing = tmpfolder.join(TIMING_FILE);
+    if fs.exists(&timing) {
+        json["timing"] = serde_json::from_str(&fs.read_to_string(&timing)?)?;
+    }
+
+    // the report is written under a temporary name and renamed into place, so that readers of
+    // the report folder never see it half written
+    let report = folder.join(format!("{}.json", tile));
+    let tmp = folder.join(format!("{}.json.{}.tmp", tile, std::process::id()));
+    let mut f = BufWriter::new(fs.create(&tmp)?);
+    serde_json::to_writer_pretty(&mut f, &json)?;
+    f.flush()?;
+    drop(f);
+    fs.rename(&tmp, &report)?;
+    Ok(())
+}
+
+/// Writes `index.html` listing the reports of the report folder of the batch output folder, if
+/// any tiles were reported. Reports that cannot be read are left out with a warning.
+pub fn write_report_index(fs: &impl FileSystem, config: &Config) -> Result<()> {
+    let folder = Path::new(&config.batchoutfolder).join(REPORT_FOLDER);
+    if !fs.exists(&folder) {
+        return Ok(());
+    }
+    let mut reports = Vec::new();
+    for path in fs.list(&folder)? {
+        if path.extension().is_some_and(|e| e == "json") {
+            let json = fs
+                .read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|s| serde_json::from_str::<Value>(&s).map_err(|e| e.to_string()));
+            match json {
+                Ok(json) => reports.push(json),
+                Err(e) => warn!("Leaving {} out of the report index: {}", path.display(), e),
+            }
+        }
+    }
+    reports.sort_by(|a, b| a["tile"].as_str().cmp(&b["tile"].as_str()));
+
+    let mut f = BufWriter::new(fs.create(folder.join("index.html"))?);
+    f.write_all(
+        b"<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Karttapullautin QA report</title>\n\
+<style>\nbody { font-family: sans-serif; }\ntable { border-collapse: collapse; }\n\
+td, th { border: 1px solid #ccc; padding: 4px 8px; text-align: right; }\n\
+td:first-child { text-align: left; }\nimg { width: 160px; image-rendering: pixelated; }\n</style>\n\
+</head>\n<body>\n<h1>QA report</h1>\n<table>\n\
+<tr><th>Tile</th><th>Points</th><th>Density (pts/m&sup2;)</th><th>Ground density (pts/m&sup2;)</th>\
+<th>Ground coverage</th><th>Water coverage</th><th>Voids</th><th>Void area (m&sup2;)</th>\
+<th>Largest void (m&sup2;)</th><th>Time (s)</th><th>Density</th><th>Voids</th></tr>\n",
+    )?;
+    for r in &reports {
+        let tile = escape(r["tile"].as_str().unwrap_or_default());
+        let number = |v: &Value, decimals: usize| {
+            v.as_f64()
+                .map_or(String::from("-"), |v| format!("{:.*}", decimals, v))
+        };
+        let percent = |v: &Value| {
+            v.as_f64()
+                .map_or(String::from("-"), |v| format!("{:.1} %", 100.0 * v))
+        };
+        let image = |v: &Value| {
+            let name = escape(v.as_str().unwrap_or_default());
+            format!("<a href=\"{0}\"><img src=\"{0}\"></a>", name)
+        };
+        writeln!(
+            f,
+            "<tr><td><a href=\"{tile}.json\">{tile}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            number(&r["points"], 0),
+            number(&r["density"], 2),
+            number(&r["ground_density"], 2),
+            percent(&r["ground_coverage"]),
+            percent(&r["water_coverage"]),
+            number(&r["voids"]["count"], 0),
+            number(&r["voids"]["area"], 0),
+            number(&r["voids"]["largest"], 0),
+            number(&r["timing"]["total"], 1),
+            image(&r["thumbnails"]["density"]),
+            image(&r["thumbnails"]["voids"]),
+        )?;
+    }
+    f.write_all(b"</table>\n</body>\n</html>\n")?;
+    Ok(())
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::fs::memory::MemoryFileSystem;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn test_tile_stats() {
+        let bounds = Bounds {
+            min_x: 0.0,
+            min_y: 0.0,
+            max_x: 40.0,
+            max_y: 40.0,
+        };
+        let mut stats = TileStats::new(&bounds, 9);
+        // one point per cell, except for a 20 x 10 m gap in the south east
+        for x in 0..20 {
+            for y in 0..20 {
+                let (x, y) = (x as f64 * 2.0 + 1.0, y as f64 * 2.0 + 1.0);
+                if !(x > 20.0 && y < 10.0) {
+                    stats.add(x, y, if x < 10.0 { 9 } else { 2 });
+                }
+            }
+        }
+        stats.add(50.0, 50.0, 2);
+
+        assert_eq!(stats.points, 350);
+        assert_eq!(stats.classes[9], 100);
+        assert_eq!(stats.coverage(|c| c.water > 0), 0.25);
+        assert_eq!(
+            stats.voids(),
+            vec![Void {
+                area: 200.0,
+                bounds: Bounds {
+                    min_x: 20.0,
+                    min_y: 0.0,
+                    max_x: 40.0,
+                    max_y: 10.0,
+                },
+            }]
+        );
+
+        let json = stats.to_json(&stats.voids());
+        assert_eq!(json["density"], 350.0 / 1600.0);
+        assert_eq!(json["classes"]["2"], 250);
+        assert_eq!(json["voids"]["ratio"], 0.125);
+    }
+
+    #[test]
+    fn test_concurrent_reports() {
+        let fs = MemoryFileSystem::new();
+        let mut config = Config::from_file(Path::new("pullauta.default.ini")).unwrap();
+        config.batchoutfolder = String::from("out");
+        let bounds = Bounds {
+            min_x: 0.0,
+            min_y: 0.0,
+            max_x: 20.0,
+            max_y: 20.0,
+        };
+        let mut stats = TileStats::new(&bounds, 9);
+        stats.add(1.0, 1.0, 2);
+        let folder = Path::new("out").join(REPORT_FOLDER);
+
+        // the reports being written are never seen half written by the readers of the folder
+        let done = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            let writers = (0..4)
+                .map(|n| {
+                    let (fs, config, stats) = (&fs, &config, &stats);
+                    scope.spawn(move || {
+                        for i in 0..20 {
+                            let tile = format!("tile_{}_{}.laz", n, i);
+                            write_tile_report(fs, config, &tile, stats, Path::new("temp")).unwrap();
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            scope.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    for path in fs.list(&folder).unwrap_or_default() {
+                        if path.extension().is_some_and(|e| e == "json") {
+                            let data = fs.read_to_string(&path).unwrap();
+                            serde_json::from_str::<Value>(&data).unwrap();
+                        }
+                    }
+                }
+            });
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+
+        // a broken report is left out of the index
+        fs.create(folder.join("broken.json"))
+            .unwrap()
+            .write_all(b"{")
+            .unwrap();
+        write_report_index(&fs, &config).unwrap();
+        let index = fs.read_to_string(folder.join("index.html")).unwrap();
+        assert_eq!(index.matches("<tr><td>").count(), 80);
+        assert!(index.contains("tile_3_19.laz"));
+        assert!(!index.contains("broken"));
+    }
+}
@@ -5,6 +5,8 @@ use skia_safe::{
 use std::fs::File;
 use std::io::Write;
 
+use crate::error::{Error, Result, ResultExt};
+
 pub struct Canvas {
     surface: Surface,
     paint: Paint,
@@ -95,12 +97,12 @@ impl Canvas {
     }
 
     #[inline]
-    pub fn data(&mut self) -> Data {
+    pub fn data(&mut self) -> Result<Data> {
         let image = self.surface.image_snapshot();
         let mut context = self.surface.direct_context();
         image
             .encode(context.as_mut(), EncodedImageFormat::PNG, None)
-            .unwrap()
+            .ok_or_else(|| Error::invalid("Could not encode the image as png"))
     }
 
     #[inline]
@@ -109,20 +111,24 @@ impl Canvas {
     }
 
     #[inline]
-    pub fn save_as(&mut self, filename: &std::path::Path) {
-        let d = self.data();
-        let mut file = File::create(filename).unwrap();
+    pub fn save_as(&mut self, filename: &std::path::Path) -> Result<()> {
+        let d = self.data().with_file(filename)?;
+        let mut file = File::create(filename).with_file(filename)?;
         let bytes = d.as_bytes();
-        file.write_all(bytes).unwrap();
+        file.write_all(bytes).with_file(filename)
     }
 
     #[inline]
-    pub fn load_from(filename: &std::path::Path) -> Canvas {
-        let data = Data::from_filename(filename).unwrap();
-        let image = Image::from_encoded(data).unwrap();
+    pub fn load_from(filename: &std::path::Path) -> Result<Canvas> {
+        let data = Data::from_filename(filename)
+            .ok_or_else(|| Error::invalid("Could not read the image"))
+            .with_file(filename)?;
+        let image = Image::from_encoded(data)
+            .ok_or_else(|| Error::invalid("Could not decode the image"))
+            .with_file(filename)?;
         let mut c = Canvas::new(image.width(), image.height());
         c.draw_image(image);
-        c
+        Ok(c)
     }
 
     #[inline]
@@ -1,7 +1,8 @@
-use std::{error::Error, path::Path};
+use std::path::Path;
 
 use log::info;
 
+use crate::error::{Result, ResultExt};
 use crate::{config::Config, io::fs::FileSystem};
 
 mod canvas;
@@ -16,27 +17,27 @@ pub fn unzip_and_render(
     config: &Config,
     tmpfolder: &Path,
     filenames: &[String],
-) -> Result<(), Box<dyn Error>> {
+) -> Result<()> {
     let low_file = tmpfolder.join("low.png");
     if fs.exists(&low_file) {
-        fs.remove_file(low_file).unwrap();
+        fs.remove_file(&low_file).with_file(&low_file)?;
     }
 
     let high_file = tmpfolder.join("high.png");
     if fs.exists(&high_file) {
-        fs.remove_file(high_file).unwrap();
+        fs.remove_file(&high_file).with_file(&high_file)?;
     }
 
     for zip_name in filenames.iter() {
         info!("Opening zip file {}", zip_name);
-        let file = fs.open(zip_name).unwrap();
-        let mut archive = zip::ZipArchive::new(file).unwrap();
+        let file = fs.open(zip_name).with_file(zip_name)?;
+        let mut archive = zip::ZipArchive::new(file).with_file(zip_name)?;
         info!(
             "Extracting {:?} MB from {zip_name}",
             archive.decompressed_size().map(|s| s / 1024 / 1024)
         );
-        archive.extract(tmpfolder).unwrap();
-        render::render(fs, config, tmpfolder).unwrap();
+        archive.extract(tmpfolder).with_file(zip_name)?;
+        render::render(fs, config, tmpfolder)?;
     }
     Ok(())
 }
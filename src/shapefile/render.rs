@@ -1,15 +1,14 @@
 use std::{
-    error::Error,
-    io::BufReader,
     path::{Path, PathBuf},
     str::FromStr,
 };
 
 use log::info;
 
+use crate::error::{Result, ResultExt};
 use crate::{
     config::Config,
-    io::fs::FileSystem,
+    io::{fs::FileSystem, read_png, read_world_file},
     shapefile::{
         canvas::Canvas,
         mapping::{Mapping, Operator},
@@ -18,11 +17,7 @@ use crate::{
 use shapefile::dbase::{FieldValue, Record};
 use shapefile::{Shape, ShapeType};
 
-pub fn render(
-    fs: &impl FileSystem,
-    config: &Config,
-    tmpfolder: &Path,
-) -> Result<(), Box<dyn Error>> {
+pub fn render(fs: &impl FileSystem, config: &Config, tmpfolder: &Path) -> Result<()> {
     let scalefactor = config.scalefactor;
 
     let vectorconf = &config.vectorconf;
@@ -30,9 +25,7 @@ pub fn render(
 
     let mut vectorconf_mappings: Vec<Mapping> = vec![];
     if !vectorconf.is_empty() {
-        let vectorconf_lines = fs
-            .read_to_string(vectorconf)
-            .expect("Can not read input file");
+        let vectorconf_lines = fs.read_to_string(vectorconf).with_file(vectorconf)?;
 
         // parse all the lines in the vectorconf file into a list of mappings
         vectorconf_mappings = vectorconf_lines
@@ -47,20 +40,9 @@ pub fn render(
         return Ok(());
     }
 
-    let data = fs.read_to_string(input).expect("Can not read input file");
-    let d: Vec<&str> = data.split('\n').collect();
+    let [_, _, _, _, x0, y0] = read_world_file(fs, &input)?;
 
-    let x0 = d[4].trim().parse::<f64>().unwrap();
-    let y0 = d[5].trim().parse::<f64>().unwrap();
-    // let resvege = d[0].trim().parse::<f64>().unwrap();
-
-    let mut img_reader = image::ImageReader::new(BufReader::new(
-        fs.open(tmpfolder.join("vegetation.png"))
-            .expect("Opening vegetation image failed"),
-    ));
-    img_reader.set_format(image::ImageFormat::Png);
-    img_reader.no_limits();
-    let img = img_reader.decode().unwrap();
+    let img = read_png(fs, tmpfolder.join("vegetation.png"))?;
     let w = img.width() as f64;
     let h = img.height() as f64;
 
@@ -90,7 +72,7 @@ pub fn render(
     let olive = (194, 176, 33);
 
     let mut shp_files: Vec<PathBuf> = Vec::new();
-    for path in fs.list(tmpfolder).unwrap() {
+    for path in fs.list(tmpfolder).with_file(tmpfolder)? {
         if let Some(extension) = path.extension() {
             if extension == "shp" {
                 shp_files.push(path);
@@ -101,7 +83,9 @@ pub fn render(
     info!("Processing shapefiles: {:?}", shp_files);
 
     for shp_file in shp_files.iter() {
-        let file = shp_file.as_path().file_name().unwrap().to_str().unwrap();
+        let Some(file) = shp_file.file_name() else {
+            continue;
+        };
         let mut file = tmpfolder.join(file);
 
         info!("Processing shapefile: {:?}", file);
@@ -673,12 +657,12 @@ pub fn render(
         }
 
         // remove the shapefile and all associated files
-        fs.remove_file(&file).unwrap();
+        fs.remove_file(&file).with_file(&file)?;
         for ext in ["dbf", "sbx", "prj", "shx", "sbn", "cpg", "qmd"].iter() {
             file.set_extension(ext);
             if fs.exists(&file) {
                 println!("Removing file: {:?}", file);
-                fs.remove_file(&file).unwrap();
+                fs.remove_file(&file).with_file(&file)?;
             }
         }
     }
@@ -712,16 +696,15 @@ pub fn render(
 
     let low_file = tmpfolder.join("low.png");
     if fs.exists(&low_file) {
-        let mut low = Canvas::load_from(&low_file);
+        let mut low = Canvas::load_from(&low_file)?;
         imgyellow.overlay(&mut low, 0.0, 0.0);
     }
 
     let high_file = tmpfolder.join("high.png");
     if fs.exists(&high_file) {
-        let mut high = Canvas::load_from(&high_file);
+        let mut high = Canvas::load_from(&high_file)?;
         imgblue.overlay(&mut high, 0.0, 0.0);
     }
-    imgblue.save_as(&high_file);
-    imgyellow.save_as(&low_file);
-    Ok(())
+    imgblue.save_as(&high_file)?;
+    imgyellow.save_as(&low_file)
 }
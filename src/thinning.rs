@@ -0,0 +1,351 @@
+use std::io::{Seek, Write};
+
+use rand::distributions::{Bernoulli, Distribution};
+
+use crate::error::{Error, Result};
+use crate::io::tileindex::Bounds;
+use crate::io::xyz::{XyzInternalWriter, XyzRecord};
+use crate::vec2d::Vec2D;
+
+/// Largest number of cells of the grid of [`Thinning::Grid`], 1 GB of cell indices.
+const MAX_GRID_CELLS: usize = 1 << 28;
+
+/// Marks the cells of the grid without any point.
+const EMPTY_CELL: u32 = u32::MAX;
+
+/// How the points of las/laz files are thinned when they are read, see the `thinmode` config key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Thinning {
+    /// Keeps every point.
+    None,
+    /// Keeps each point with the probability `thinfactor`, differently on every run.
+    Random(f64),
+    /// Keeps each point with the probability `thinfactor`, decided by a hash of its coordinates
+    /// and the seed. The same point is kept or dropped on every run and in every tile it is in.
+    Seeded { factor: f64, seed: u64 },
+    /// Keeps one point of each cell of a grid aligned to the coordinate origin.
+    Grid { cell_size: f64, keep: GridKeep },
+}
+
+impl Thinning {
+    /// The bounds to read the points of `bounds` from. Grid thinning needs all the points of the
+    /// cells at the edges, so that a tile keeps the same point in them as its neighbours.
+    pub fn read_bounds(&self, bounds: &Bounds) -> Bounds {
+        match *self {
+            Thinning::Grid { cell_size, .. } => Bounds {
+                min_x: (bounds.min_x / cell_size).floor() * cell_size,
+                min_y: (bounds.min_y / cell_size).floor() * cell_size,
+                max_x: (bounds.max_x / cell_size).ceil() * cell_size,
+                max_y: (bounds.max_y / cell_size).ceil() * cell_size,
+            },
+            _ => *bounds,
+        }
+    }
+}
+
+/// Which point of a grid cell is kept by [`Thinning::Grid`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridKeep {
+    Lowest,
+    Highest,
+    /// The first point read, in the order of the files.
+    First,
+}
+
+impl GridKeep {
+    /// Parses a value of the `thinkeep` config key.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.trim().to_lowercase().as_str() {
+            "" | "lowest" => Self::Lowest,
+            "highest" => Self::Highest,
+            "first" => Self::First,
+            _ => return None,
+        })
+    }
+}
+
+/// Thins the points written to an internal point file.
+pub struct Thinner {
+    thinning: Thinning,
+    random: Option<Bernoulli>,
+    grid: Option<Grid>,
+}
+
+/// The points kept by grid thinning so far.
+struct Grid {
+    /// Cell of the lower left corner of the grid.
+    origin: (i64, i64),
+    /// Index in `kept` of the point kept in each cell.
+    cells: Vec2D<u32>,
+    /// The kept points, with the height they were compared by, in the order their cells were
+    /// first seen.
+    kept: Vec<(f64, XyzRecord)>,
+}
+
+impl Thinner {
+    /// Thinner for the points inside `bounds`, in the coordinates of the las/laz files.
+    pub fn new(thinning: Thinning, bounds: &Bounds) -> Result<Self> {
+        let random = match thinning {
+            Thinning::Random(factor) => Bernoulli::new(factor).ok(),
+            _ => None,
+        };
+        let grid = match thinning {
+            Thinning::Grid { cell_size, .. } => {
+                let cell = |v: f64| (v / cell_size).floor() as i64;
+                let origin = (cell(bounds.min_x), cell(bounds.min_y));
+                let w = (cell(bounds.max_x) - origin.0 + 1).max(1) as usize;
+                let h = (cell(bounds.max_y) - origin.1 + 1).max(1) as usize;
+                if w.saturating_mul(h) > MAX_GRID_CELLS {
+                    return Err(Error::invalid(format!(
+                        "thincellsize {} is too small, the area would have {} x {} cells",
+                        cell_size, w, h
+                    )));
+                }
+                Some(Grid {
+                    origin,
+                    cells: Vec2D::new(w, h, EMPTY_CELL),
+                    kept: Vec::new(),
+                })
+            }
+            _ => None,
+        };
+        Ok(Self {
+            thinning,
+            random,
+            grid,
+        })
+    }
+
+    /// Writes the point if it is kept. Whether it is kept is decided by `raw`, the coordinates
+    /// of the point in the las/laz file, so the same points are kept whatever the scaling of the
+    /// coordinates. With grid thinning the points are only written by [`Thinner::finish`], once
+    /// all of them have been seen, but the points outside the grid are kept at once.
+    pub fn push<W: Write + Seek>(
+        &mut self,
+        raw: (f64, f64, f64),
+        record: XyzRecord,
+        writer: &mut XyzInternalWriter<W>,
+    ) -> std::io::Result<()> {
+        let keep = match self.thinning {
+            Thinning::None => true,
+            Thinning::Random(_) => self
+                .random
+                .is_none_or(|random| random.sample(&mut rand::thread_rng())),
+            Thinning::Seeded { factor, seed } => keep_seeded(raw, factor, seed),
+            Thinning::Grid { cell_size, keep } => match self.grid.as_mut() {
+                Some(grid) => {
+                    let cell = (
+                        (raw.0 / cell_size).floor() as i64 - grid.origin.0,
+                        (raw.1 / cell_size).floor() as i64 - grid.origin.1,
+                    );
+                    let (w, h) = (grid.cells.width() as i64, grid.cells.height() as i64);
+                    if cell.0 < 0 || cell.1 < 0 || cell.0 >= w || cell.1 >= h {
+                        true
+                    } else {
+                        let index = &mut grid.cells[(cell.0 as usize, cell.1 as usize)];
+                        if *index == EMPTY_CELL {
+                            *index = grid.kept.len() as u32;
+                            grid.kept.push((raw.2, record));
+                        } else {
+                            let kept = &mut grid.kept[*index as usize];
+                            let replace = match keep {
+                                GridKeep::Lowest => raw.2 < kept.0,
+                                GridKeep::Highest => raw.2 > kept.0,
+                                GridKeep::First => false,
+                            };
+                            if replace {
+                                *kept = (raw.2, record);
+                            }
+                        }
+                        return Ok(());
+                    }
+                }
+                None => true,
+            },
+        };
+        if keep {
+            writer.write_record(&record)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the points kept by grid thinning.
+    pub fn finish<W: Write + Seek>(self, writer: &mut XyzInternalWriter<W>) -> std::io::Result<()> {
+        if let Some(grid) = self.grid {
+            for (_, record) in &grid.kept {
+                writer.write_record(record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether seeded thinning keeps a point, with the probability `factor`.
+fn keep_seeded(raw: (f64, f64, f64), factor: f64, seed: u64) -> bool {
+    let mut hash = seed;
+    for v in [raw.0, raw.1, raw.2] {
+        hash = splitmix64(hash ^ v.to_bits());
+    }
+    // the top 53 bits as a number between 0 and 1
+    ((hash >> 11) as f64 / (1u64 << 53) as f64) < factor
+}
+
+/// The SplitMix64 mixing function, spreads nearby values over all the 64 bits.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::io::xyz::XyzInternalReader;
+
+    fn thin(thinning: Thinning, points: &[(f64, f64, f64)]) -> Vec<(f64, f64, f64)> {
+        let bounds = Bounds {
+            min_x: -10.0,
+            min_y: -10.0,
+            max_x: 100.0,
+            max_y: 100.0,
+        };
+        thin_in(thinning, &bounds, points)
+    }
+
+    fn thin_in(
+        thinning: Thinning,
+        bounds: &Bounds,
+        points: &[(f64, f64, f64)],
+    ) -> Vec<(f64, f64, f64)> {
+        let mut writer = XyzInternalWriter::new(Cursor::new(Vec::new()));
+        let mut thinner = Thinner::new(thinning, bounds).unwrap();
+        for &(x, y, z) in points {
+            let record = XyzRecord {
+                x,
+                y,
+                z,
+                ..Default::default()
+            };
+            thinner.push((x, y, z), record, &mut writer).unwrap();
+        }
+        thinner.finish(&mut writer).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        let mut reader = XyzInternalReader::new(Cursor::new(data)).unwrap();
+        let mut points = vec![];
+        while let Some(r) = reader.next().unwrap() {
+            points.push((r.x, r.y, r.z));
+        }
+        points
+    }
+
+    #[test]
+    fn test_seeded() {
+        let points: Vec<(f64, f64, f64)> = (0..10000)
+            .map(|i| {
+                (
+                    (i % 100) as f64 * 0.5,
+                    (i / 100) as f64 * 0.5,
+                    i as f64 * 0.01,
+                )
+            })
+            .collect();
+        let thinning = Thinning::Seeded {
+            factor: 0.25,
+            seed: 7,
+        };
+        let kept = thin(thinning, &points);
+        assert!((2300..2700).contains(&kept.len()), "{}", kept.len());
+        // the same points are kept whatever the order or the other points
+        let mut reversed = points.clone();
+        reversed.reverse();
+        let mut kept_reversed = thin(thinning, &reversed[..5000]);
+        kept_reversed.reverse();
+        assert_eq!(
+            kept_reversed,
+            kept.iter()
+                .filter(|p| points[5000..].contains(p))
+                .copied()
+                .collect::<Vec<_>>()
+        );
+        // another seed keeps other points
+        let other = thin(
+            Thinning::Seeded {
+                factor: 0.25,
+                seed: 8,
+            },
+            &points,
+        );
+        assert_ne!(other, kept);
+    }
+
+    #[test]
+    fn test_grid() {
+        let points = [
+            (0.1, 0.1, 5.0),
+            (0.9, 0.2, 3.0),
+            (1.5, 0.5, 8.0),
+            (0.5, 0.5, 4.0),
+            (-0.5, 0.5, 1.0),
+        ];
+        let grid = |keep| Thinning::Grid {
+            cell_size: 1.0,
+            keep,
+        };
+        assert_eq!(
+            thin(grid(GridKeep::Lowest), &points),
+            vec![(0.9, 0.2, 3.0), (1.5, 0.5, 8.0), (-0.5, 0.5, 1.0)]
+        );
+        assert_eq!(
+            thin(grid(GridKeep::Highest), &points),
+            vec![(0.1, 0.1, 5.0), (1.5, 0.5, 8.0), (-0.5, 0.5, 1.0)]
+        );
+        assert_eq!(
+            thin(grid(GridKeep::First), &points),
+            vec![(0.1, 0.1, 5.0), (1.5, 0.5, 8.0), (-0.5, 0.5, 1.0)]
+        );
+    }
+
+    #[test]
+    fn test_grid_tile_edges() {
+        let grid = Thinning::Grid {
+            cell_size: 2.0,
+            keep: GridKeep::Lowest,
+        };
+        // the cell from 0 to 2 straddles the edge of the buffer at 1
+        let points = [(0.5, 0.5, 1.0), (1.5, 0.5, 2.0), (3.0, 0.5, 4.0)];
+        let buffer = Bounds {
+            min_x: 1.0,
+            min_y: 0.0,
+            max_x: 10.0,
+            max_y: 10.0,
+        };
+        let read = grid.read_bounds(&buffer);
+        assert_eq!(read.min_x, 0.0);
+        let inside = |b: &Bounds| {
+            points
+                .iter()
+                .filter(|p| p.0 >= b.min_x && p.0 <= b.max_x)
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        // the tile keeps the same point of the cell as a tile reading all of it
+        assert_eq!(
+            thin_in(grid, &read, &inside(&read)),
+            vec![(0.5, 0.5, 1.0), (3.0, 0.5, 4.0)]
+        );
+
+        // points outside the grid are kept
+        assert_eq!(
+            thin_in(grid, &buffer, &[(-5.0, 0.5, 1.0), (-5.1, 0.5, 0.0)]).len(),
+            2
+        );
+        let tiny = Thinning::Grid {
+            cell_size: 0.0001,
+            keep: GridKeep::First,
+        };
+        assert!(Thinner::new(tiny, &buffer).is_err());
+    }
+}
@@ -2,11 +2,12 @@ use std::{
     fmt::Debug,
     io::{self, BufRead, BufReader},
     path::Path,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use log::debug;
 
+use crate::error::{Context, Error, Result, ResultExt};
 use crate::io::fs::FileSystem;
 
 /// Iterates over the lines in a file and calls the callback with a &str reference to each line.
@@ -19,6 +20,41 @@ pub fn read_lines_no_alloc<P>(
 ) -> io::Result<()>
 where
     P: AsRef<Path> + Debug,
+{
+    read_lines_until_error(fs, filename, |line, _| {
+        line_callback(line);
+        Ok(())
+    })
+}
+
+/// Like [`read_lines_no_alloc`], but stops at the first error returned by the callback and adds
+/// the file and the line to it.
+pub fn try_read_lines_no_alloc<P>(
+    fs: &impl FileSystem,
+    filename: P,
+    mut line_callback: impl FnMut(&str) -> Result<()>,
+) -> Result<()>
+where
+    P: AsRef<Path> + Debug,
+{
+    let path = filename.as_ref().to_path_buf();
+    read_lines_until_error(fs, filename, |line, line_number| {
+        line_callback(line).with_line(&path, line_number)
+    })
+    .map_err(|e| match e {
+        Error::Context(..) => e,
+        e => e.context(Context::File(path.clone())),
+    })
+}
+
+fn read_lines_until_error<P, E>(
+    fs: &impl FileSystem,
+    filename: P,
+    mut line_callback: impl FnMut(&str, usize) -> std::result::Result<(), E>,
+) -> std::result::Result<(), E>
+where
+    P: AsRef<Path> + Debug,
+    E: From<io::Error>,
 {
     debug!("Reading lines from {filename:?}");
     let start = Instant::now();
@@ -41,7 +77,7 @@ where
 
         // the read line contains the newline delimiter, so we need to trim it off
         let line = line_buffer.trim_end();
-        line_callback(line);
+        line_callback(line, line_count as usize)?;
         line_buffer.clear();
     }
 
@@ -71,6 +107,7 @@ pub struct Timing {
     name: &'static str,
     start: Instant,
     current_section: Option<TimingSection>,
+    sections: Vec<(&'static str, Duration)>,
 }
 
 struct TimingSection {
@@ -86,6 +123,7 @@ impl Timing {
             name,
             start: Instant::now(),
             current_section: None,
+            sections: Vec::new(),
         }
     }
 
@@ -110,11 +148,23 @@ impl Timing {
                 s.name,
                 now - s.start
             );
+            self.sections.push((s.name, now - s.start));
             Some(now)
         } else {
             None
         }
     }
+
+    /// Ends the currently active section and returns the name and duration of every section so far.
+    pub fn sections(&mut self) -> &[(&'static str, Duration)] {
+        self.end_section();
+        &self.sections
+    }
+
+    /// Time elapsed since the timing started.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
 }
 
 impl Drop for Timing {
@@ -128,3 +178,31 @@ impl Drop for Timing {
         );
     }
 }
+
+/// Finds the candidate closest to a misspelled name, if any is close enough to be a likely
+/// suggestion.
+pub fn suggest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(&name, &c.to_lowercase()), c))
+        .filter(|&(d, c)| d <= (c.len() / 3).max(1))
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, c)| c)
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
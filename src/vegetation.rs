@@ -4,22 +4,266 @@ use imageproc::filter::median_filter;
 use imageproc::rect::Rect;
 use log::info;
 use rustc_hash::FxHashMap as HashMap;
-use std::error::Error;
 use std::f32::consts::SQRT_2;
 use std::io::{BufReader, BufWriter, Write};
 use std::path::Path;
 
 use crate::config::{Config, Zone};
+use crate::error::{Result, ResultExt};
 use crate::io::bytes::FromToBytes;
+use crate::io::crs::{Crs, CRS_FILE};
 use crate::io::fs::FileSystem;
+use crate::io::geotiff::png_to_geotiff;
 use crate::io::heightmap::HeightMap;
+use crate::io::write_png;
 use crate::io::xyz::XyzInternalReader;
+use crate::vec2d::Vec2D;
 
-pub fn makevege(
+/// Cell size in meters of the vegetation height raster.
+const VEGETATION_HEIGHT_CELL: f64 = 1.0;
+
+/// Generates the vegetation images, detecting green and yellow from the point hits (vegemode=0)
+/// or from the vegetation height raster (vegemode=2), which is first made again from the points
+/// so it is never left over from an earlier run.
+pub fn makevege(fs: &impl FileSystem, config: &Config, tmpfolder: &Path) -> Result<()> {
+    if config.vege_height {
+        makeheight(fs, config, tmpfolder)?;
+    }
+    generate_vegetation(fs, config, tmpfolder, config.vege_height)
+}
+
+/// Generates the vegetation images from the existing vegetation height raster, as with
+/// vegemode=2.
+pub fn vege_from_height(fs: &impl FileSystem, config: &Config, tmpfolder: &Path) -> Result<()> {
+    generate_vegetation(fs, config, tmpfolder, true)
+}
+
+/// Generates the vegetation height raster: the height above ground of the highest non-ground
+/// point of every cell, 0 for cells without vegetation. It is written to vegeheight.hmap and as
+/// a grayscale image vegeheight.png (one shade per 10 cm, capped at 25.5 m) with a world file.
+pub fn makeheight(fs: &impl FileSystem, config: &Config, tmpfolder: &Path) -> Result<()> {
+    info!("Generating vegetation height raster...");
+
+    let hmap = HeightMap::from_file(fs, tmpfolder.join("xyz2.hmap"))?;
+    let w = ((hmap.maxx() - hmap.minx()) / VEGETATION_HEIGHT_CELL).floor() as usize + 1;
+    let h = ((hmap.maxy() - hmap.miny()) / VEGETATION_HEIGHT_CELL).floor() as usize + 1;
+    let mut grid = Vec2D::new(w, h, 0.0);
+
+    let &Config {
+        vegezoffset: zoffset,
+        vegethin,
+        water,
+        buildings,
+        ..
+    } = config;
+
+    let mut i = 0;
+    let xyz_file_in = tmpfolder.join("xyztemp.xyz.bin");
+    let mut reader = XyzInternalReader::new(BufReader::new(fs.open(&xyz_file_in)?))?;
+    while let Some(r) = reader.next()? {
+        i += 1;
+        if vegethin != 0 && !(i as u32).is_multiple_of(vegethin) {
+            continue;
+        }
+        let c = r.classification;
+        if c == 2 || (water > 0 && c == water) || (buildings > 0 && c == buildings) {
+            continue;
+        }
+        let Some(ground) = hmap.sample(r.x, r.y) else {
+            continue;
+        };
+        let xx = ((r.x - hmap.minx()) / VEGETATION_HEIGHT_CELL).floor() as usize;
+        let yy = ((r.y - hmap.miny()) / VEGETATION_HEIGHT_CELL).floor() as usize;
+        if xx < w && yy < h {
+            let cell = &mut grid[(xx, yy)];
+            *cell = f64::max(*cell, r.z - zoffset - ground);
+        }
+    }
+
+    let heights = HeightMap {
+        xoffset: hmap.xoffset,
+        yoffset: hmap.yoffset,
+        scale: VEGETATION_HEIGHT_CELL,
+        grid,
+        distance: None,
+    };
+    write_heights(fs, tmpfolder, &heights)
+}
+
+/// Generates the vegetation height raster from a canopy height raster, for maps made from an
+/// elevation raster instead of a point cloud. Cells without data are taken as open ground.
+pub fn height_from_raster(
+    fs: &impl FileSystem,
+    tmpfolder: &Path,
+    raster: &HeightMap,
+) -> Result<()> {
+    info!("Generating vegetation height raster from the canopy height raster...");
+
+    let hmap = HeightMap::from_file(fs, tmpfolder.join("xyz2.hmap"))?;
+    let w = ((hmap.maxx() - hmap.minx()) / VEGETATION_HEIGHT_CELL).floor() as usize + 1;
+    let h = ((hmap.maxy() - hmap.miny()) / VEGETATION_HEIGHT_CELL).floor() as usize + 1;
+    let mut heights = HeightMap {
+        xoffset: hmap.xoffset,
+        yoffset: hmap.yoffset,
+        scale: VEGETATION_HEIGHT_CELL,
+        grid: Vec2D::new(w, h, 0.0),
+        distance: None,
+    };
+    for (x, y, v) in heights.grid.iter_mut() {
+        let wx = hmap.xoffset + (x as f64 + 0.5) * VEGETATION_HEIGHT_CELL;
+        let wy = hmap.yoffset + (y as f64 + 0.5) * VEGETATION_HEIGHT_CELL;
+        *v = raster
+            .sample(wx, wy)
+            .filter(|v| !v.is_nan())
+            .map_or(0.0, |v| v.max(0.0));
+    }
+    write_heights(fs, tmpfolder, &heights)
+}
+
+/// Writes the vegetation height raster to vegeheight.hmap and as a grayscale image
+/// vegeheight.png with a world file.
+fn write_heights(fs: &impl FileSystem, tmpfolder: &Path, heights: &HeightMap) -> Result<()> {
+    let (w, h) = (heights.grid.width(), heights.grid.height());
+    let mut img = GrayImage::new(w as u32, h as u32);
+    for (x, y, v) in heights.grid.iter() {
+        img.put_pixel(
+            x as u32,
+            (h - 1 - y) as u32,
+            Luma([(v * 10.0).round().clamp(0.0, 255.0) as u8]),
+        );
+    }
+    img.write_to(
+        &mut BufWriter::new(fs.create(tmpfolder.join("vegeheight.png"))?),
+        image::ImageFormat::Png,
+    )?;
+    let pgw = tmpfolder.join("vegeheight.pgw");
+    let mut f = fs.create(pgw)?;
+    write!(
+        &mut f,
+        "{}\r\n0.0\r\n0.0\r\n-{}\r\n{}\r\n{}\r\n",
+        heights.scale,
+        heights.scale,
+        heights.minx(),
+        heights.maxy()
+    )?;
+
+    heights.to_file(fs, tmpfolder.join("vegeheight.hmap"))?;
+    Ok(())
+}
+
+/// Writes empty vegetation images covering the heightmap, for maps made from an elevation
+/// raster without vegetation data. Only water below `waterele` is drawn.
+pub fn blank_vegetation(fs: &impl FileSystem, config: &Config, tmpfolder: &Path) -> Result<()> {
+    info!("Skipping vegetation");
+
+    let hmap = HeightMap::from_file(fs, tmpfolder.join("xyz2.hmap"))?;
+    let (xmin, ymax) = (hmap.minx(), hmap.maxy());
+    let width = (hmap.maxx() - xmin).floor() as u32;
+    let height = (ymax - hmap.miny()).floor() as u32;
+    let tmpfactor = 600.0 / 254.0 / config.scalefactor;
+
+    RgbImage::from_pixel(width, height, Rgb([255, 255, 255])).write_to(
+        &mut BufWriter::new(fs.create(tmpfolder.join("vegetation.png"))?),
+        image::ImageFormat::Png,
+    )?;
+    RgbaImage::from_pixel(
+        (width as f64 * tmpfactor) as u32,
+        (height as f64 * tmpfactor) as u32,
+        Rgba([255, 255, 255, 0]),
+    )
+    .write_to(
+        &mut BufWriter::new(fs.create(tmpfolder.join("undergrowth.png"))?),
+        image::ImageFormat::Png,
+    )?;
+
+    let mut imgwater = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+    for (x, y, hh) in hmap.iter() {
+        if hh < config.waterele {
+            draw_filled_rect_mut(
+                &mut imgwater,
+                Rect::at((x - xmin) as i32 - 1, (ymax - y) as i32 - 1).of_size(3, 3),
+                Rgb([29, 190, 255]),
+            );
+        }
+    }
+    imgwater.write_to(
+        &mut BufWriter::new(fs.create(tmpfolder.join("blueblack.png"))?),
+        image::ImageFormat::Png,
+    )?;
+
+    let mut writer = BufWriter::new(fs.create(tmpfolder.join("undergrowth.pgw"))?);
+    write!(
+        &mut writer,
+        "{}\r\n0.0\r\n0.0\r\n{}\r\n{}\r\n{}\r\n",
+        1.0 / tmpfactor,
+        -1.0 / tmpfactor,
+        xmin,
+        ymax,
+    )?;
+    drop(writer);
+    let mut writer = BufWriter::new(fs.create(tmpfolder.join("vegetation.pgw"))?);
+    write!(
+        &mut writer,
+        "1.0\r\n0.0\r\n0.0\r\n-1.0\r\n{}\r\n{}\r\n",
+        xmin, ymax
+    )?;
+    drop(writer);
+
+    if config.geotiff {
+        let crs = Crs::from_file(fs, tmpfolder.join(CRS_FILE));
+        for png in ["vegetation.png", "undergrowth.png"] {
+            png_to_geotiff(fs, &tmpfolder.join(png), crs.as_ref())?;
+        }
+    }
+    Ok(())
+}
+
+/// Values of the vegetation height raster cells inside the square of world coordinates starting
+/// at (`x0`, `y0`).
+fn height_cells(
+    heights: &HeightMap,
+    x0: f64,
+    y0: f64,
+    size: f64,
+) -> impl Iterator<Item = f64> + '_ {
+    let (w, h) = (heights.grid.width(), heights.grid.height());
+    let range = |start: f64, offset: f64, len: usize| {
+        let from = ((start - offset) / heights.scale).round().max(0.0) as usize;
+        let to = (((start + size - offset) / heights.scale).round().max(0.0) as usize).min(len);
+        from..to
+    };
+    let ys = range(y0, heights.yoffset, h);
+    range(x0, heights.xoffset, w).flat_map(move |x| ys.clone().map(move |y| heights.grid[(x, y)]))
+}
+
+/// Weighted fraction of the vegetation height raster cells inside the square of world
+/// coordinates starting at (`x0`, `y0`), 0 if there are none.
+fn height_cover(
+    heights: &HeightMap,
+    x0: f64,
+    y0: f64,
+    size: f64,
+    weight: impl Fn(f64) -> f64,
+) -> f64 {
+    let mut sum = 0.0;
+    let mut count = 0;
+    for hh in height_cells(heights, x0, y0, size) {
+        sum += weight(hh);
+        count += 1;
+    }
+    if count == 0 {
+        0.0
+    } else {
+        sum / count as f64
+    }
+}
+
+fn generate_vegetation(
     fs: &impl FileSystem,
     config: &Config,
     tmpfolder: &Path,
-) -> Result<(), Box<dyn Error>> {
+    from_height: bool,
+) -> Result<()> {
     info!("Generating vegetation...");
 
     let heightmap_in = tmpfolder.join("xyz2.hmap");
@@ -65,53 +309,63 @@ pub fn makevege(
     let mut xmax: f64 = f64::MIN;
     let mut ymax: f64 = f64::MIN;
 
+    // in vegemode 2 yellow and green are detected from the vegetation height raster instead
+    let heights = if from_height {
+        Some(HeightMap::from_file(fs, tmpfolder.join("vegeheight.hmap"))?)
+    } else {
+        None
+    };
+
     let mut top: HashMap<(u64, u64), f64> = HashMap::default();
     let mut yhit: HashMap<(u64, u64), u64> = HashMap::default();
     let mut noyhit: HashMap<(u64, u64), u64> = HashMap::default();
 
-    let mut i = 0;
-    let mut reader = XyzInternalReader::new(BufReader::new(fs.open(&xyz_file_in)?))?;
-    while let Some(r) = reader.next()? {
-        if vegethin == 0 || ((i + 1) as u32) % vegethin == 0 {
-            let x: f64 = r.x;
-            let y: f64 = r.y;
-            let h: f64 = r.z;
-            let r3 = r.classification;
-            let r4 = r.number_of_returns;
-            let r5 = r.return_number;
-
-            if xmax < x {
-                xmax = x;
-            }
-            if ymax < y {
-                ymax = y;
-            }
-            if x > xmin && y > ymin {
-                let xx = ((x - xmin) / block).floor() as u64;
-                let yy = ((y - ymin) / block).floor() as u64;
-                if h > *top.get(&(xx, yy)).unwrap_or(&0.0) {
-                    top.insert((xx, yy), h);
+    // the point hits of yellow and the tops of the blocks are only used without the raster
+    if heights.is_none() {
+        let mut i = 0;
+        let mut reader = XyzInternalReader::new(BufReader::new(fs.open(&xyz_file_in)?))?;
+        while let Some(r) = reader.next()? {
+            if vegethin == 0 || ((i + 1) as u32).is_multiple_of(vegethin) {
+                let x: f64 = r.x;
+                let y: f64 = r.y;
+                let h: f64 = r.z;
+                let r3 = r.classification;
+                let r4 = r.number_of_returns;
+                let r5 = r.return_number;
+
+                if xmax < x {
+                    xmax = x;
                 }
-                let xx = ((x - xmin) / 3.0).floor() as u64;
-                let yy = ((y - ymin) / 3.0).floor() as u64;
-
-                if r3 == 2
-                    || h < yellowheight
-                        + xyz[(
-                            ((x - xmin) / size).floor() as usize,
-                            ((y - ymin) / size).floor() as usize,
-                        )]
-                {
-                    *yhit.entry((xx, yy)).or_insert(0) += 1;
-                } else if r4 == 1 && r5 == 1 {
-                    *noyhit.entry((xx, yy)).or_insert(0) += yellowfirstlast;
-                } else {
-                    *noyhit.entry((xx, yy)).or_insert(0) += 1;
+                if ymax < y {
+                    ymax = y;
+                }
+                if x > xmin && y > ymin {
+                    let xx = ((x - xmin) / block).floor() as u64;
+                    let yy = ((y - ymin) / block).floor() as u64;
+                    if h > *top.get(&(xx, yy)).unwrap_or(&0.0) {
+                        top.insert((xx, yy), h);
+                    }
+                    let xx = ((x - xmin) / 3.0).floor() as u64;
+                    let yy = ((y - ymin) / 3.0).floor() as u64;
+
+                    if r3 == 2
+                        || h < yellowheight
+                            + xyz[(
+                                ((x - xmin) / size).floor() as usize,
+                                ((y - ymin) / size).floor() as usize,
+                            )]
+                    {
+                        *yhit.entry((xx, yy)).or_insert(0) += 1;
+                    } else if r4 == 1 && r5 == 1 {
+                        *noyhit.entry((xx, yy)).or_insert(0) += yellowfirstlast;
+                    } else {
+                        *noyhit.entry((xx, yy)).or_insert(0) += 1;
+                    }
                 }
             }
-        }
 
-        i += 1;
+            i += 1;
+        }
     }
     // rebind the variables to be non-mut for the rest of the function
     let (yhit, noyhit) = (yhit, noyhit);
@@ -127,7 +381,7 @@ pub fn makevege(
     let mut i = 0;
     let mut reader = XyzInternalReader::new(BufReader::new(fs.open(&xyz_file_in)?))?;
     while let Some(r) = reader.next()? {
-        if vegethin == 0 || ((i + 1) as u32) % vegethin == 0 {
+        if vegethin == 0 || ((i + 1) as u32).is_multiple_of(vegethin) {
             let x: f64 = r.x;
             let y: f64 = r.y;
             let h: f64 = r.z - zoffset;
@@ -135,6 +389,12 @@ pub fn makevege(
             let r4 = r.number_of_returns;
             let r5 = r.return_number;
 
+            if xmax < x {
+                xmax = x;
+            }
+            if ymax < y {
+                ymax = y;
+            }
             if x > xmin && y > ymin {
                 if r5 == 1 {
                     let xx = ((x - xmin) / block + 0.5).floor() as u64;
@@ -248,20 +508,32 @@ pub fn makevege(
         }
     }
     let aveg = aveg as f64 / avecount as f64;
+
     let ye2 = Rgba([255, 219, 166, 255]);
     let mut imgye2 = RgbaImage::from_pixel(img_width, img_height, Rgba([255, 255, 255, 0]));
     for x in 4..(wy as usize - 3) {
         for y in 4..(hy as usize - 3) {
-            let mut ghit2 = 0;
-            let mut highhit2 = 0;
+            let open = if let Some(heights) = &heights {
+                height_cover(
+                    heights,
+                    xmin + x as f64 * 3.0,
+                    ymin + y as f64 * 3.0,
+                    6.0,
+                    |hh| if hh < yellowheight { 1.0 } else { 0.0 },
+                )
+            } else {
+                let mut ghit2 = 0;
+                let mut highhit2 = 0;
 
-            for i in x..x + 2 {
-                for j in y..y + 2 {
-                    ghit2 += *yhit.get(&(i as u64, j as u64)).unwrap_or(&0);
-                    highhit2 += *noyhit.get(&(i as u64, j as u64)).unwrap_or(&0);
+                for i in x..x + 2 {
+                    for j in y..y + 2 {
+                        ghit2 += *yhit.get(&(i as u64, j as u64)).unwrap_or(&0);
+                        highhit2 += *noyhit.get(&(i as u64, j as u64)).unwrap_or(&0);
+                    }
                 }
-            }
-            if ghit2 as f64 / (highhit2 as f64 + ghit2 as f64 + 0.01) > yellowthreshold {
+                ghit2 as f64 / (highhit2 as f64 + ghit2 as f64 + 0.01)
+            };
+            if open > yellowthreshold {
                 draw_filled_rect_mut(
                     &mut imgye2,
                     Rect::at(x as i32 * 3 + 2, (hy as i32 - y as i32) * 3 - 3).of_size(3, 3),
@@ -274,11 +546,19 @@ pub fn makevege(
     let mut imggr1 = RgbImage::from_pixel(img_width, img_height, Rgb([255, 255, 255]));
     for x in 2..w as usize {
         for y in 2..h as usize {
-            let roof = *top.get(&(x as u64, y as u64)).unwrap_or(&0.0)
-                - xyz[(
-                    (x as f64 * block / size).floor() as usize,
-                    (y as f64 * block / size).floor() as usize,
-                )];
+            // the block in world coordinates, for the vegetation height raster
+            let x0 = xmin + (x as f64 - 0.5) * block;
+            let y0 = ymin + (y as f64 - 0.5) * block;
+            let roof = if let Some(heights) = &heights {
+                // the vegetation height raster may not come from the points
+                height_cells(heights, x0, y0, block).fold(0.0, f64::max)
+            } else {
+                *top.get(&(x as u64, y as u64)).unwrap_or(&0.0)
+                    - xyz[(
+                        (x as f64 * block / size).floor() as usize,
+                        (y as f64 * block / size).floor() as usize,
+                    )]
+            };
 
             let mut firsthit2 = *firsthit.get(&(x as u64, y as u64)).unwrap_or(&0);
             for i in (x - 2)..x + 3_usize {
@@ -302,12 +582,31 @@ pub fn makevege(
                 }
             }
 
-            let thevalue = greenhit2 / (ghit2 as f64 + greenhit2 + 1.0)
-                * (1.0 - topweight
-                    + topweight * highit2 as f64
-                        / (ghit2 as f64 + greenhit2 + highit2 as f64 + 1.0))
-                * (1.0 - pointvolumefactor * firsthit2 as f64 / (aveg + 0.00001))
-                    .powf(pointvolumeexponent);
+            let thevalue = if let Some(heights) = &heights {
+                // the share of vegetation in the green zones, weighted by the zone factors
+                let cover = height_cover(heights, x0, y0, block, |hh| {
+                    config
+                        .zones
+                        .iter()
+                        .find(|zone| hh >= zone.low && hh < zone.high && roof < zone.roof)
+                        .map_or(0.0, |zone| zone.factor)
+                });
+                let high = height_cover(heights, x0, y0, block, |hh| {
+                    if hh > greenhigh {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                });
+                cover * (1.0 - topweight + topweight * high)
+            } else {
+                greenhit2 / (ghit2 as f64 + greenhit2 + 1.0)
+                    * (1.0 - topweight
+                        + topweight * highit2 as f64
+                            / (ghit2 as f64 + greenhit2 + highit2 as f64 + 1.0))
+                    * (1.0 - pointvolumefactor * firsthit2 as f64 / (aveg + 0.00001))
+                        .powf(pointvolumeexponent)
+            };
             if thevalue > 0.0 {
                 let mut greenshade = 0;
                 for (i, &shade) in greenshades.iter().enumerate() {
@@ -350,45 +649,20 @@ pub fn makevege(
         }
     }
 
-    imgye2
-        .write_to(
-            &mut BufWriter::new(
-                fs.create(tmpfolder.join("yellow.png"))
-                    .expect("error saving png"),
-            ),
-            image::ImageFormat::Png,
-        )
-        .expect("could not save output png");
-
-    imggr1
-        .write_to(
-            &mut BufWriter::new(
-                fs.create(tmpfolder.join("greens.png"))
-                    .expect("error saving png"),
-            ),
-            image::ImageFormat::Png,
-        )
-        .expect("could not save output png");
+    write_png(fs, &imgye2, tmpfolder.join("yellow.png"))?;
+
+    write_png(fs, &imggr1, tmpfolder.join("greens.png"))?;
 
     let mut img = DynamicImage::ImageRgb8(imggr1);
     image::imageops::overlay(&mut img, &DynamicImage::ImageRgba8(imgye2), 0, 0);
 
-    img.write_to(
-        &mut BufWriter::new(
-            fs.create(tmpfolder.join("vegetation.png"))
-                .expect("error saving png"),
-        ),
-        image::ImageFormat::Png,
-    )
-    .expect("could not save output png");
+    write_png(fs, &img, tmpfolder.join("vegetation.png"))?;
 
     // drop img to free memory
     drop(img);
 
     if vege_bitmode {
-        let g_img = fs
-            .read_image_png(tmpfolder.join("greens.png"))
-            .expect("Opening image failed");
+        let g_img = fs.read_image_png(tmpfolder.join("greens.png"))?;
         let mut g_img = g_img.to_rgb8();
         for pixel in g_img.pixels_mut() {
             let mut found = false;
@@ -405,19 +679,9 @@ pub fn makevege(
         }
         let g_img = DynamicImage::ImageRgb8(g_img).to_luma8();
 
-        g_img
-            .write_to(
-                &mut BufWriter::new(
-                    fs.create(tmpfolder.join("greens_bit.png"))
-                        .expect("error saving png"),
-                ),
-                image::ImageFormat::Png,
-            )
-            .expect("could not save output png");
-
-        let y_img = fs
-            .read_image_png(tmpfolder.join("yellow.png"))
-            .expect("Opening image failed");
+        write_png(fs, &g_img, tmpfolder.join("greens_bit.png"))?;
+
+        let y_img = fs.read_image_png(tmpfolder.join("yellow.png"))?;
         let mut y_img = y_img.to_rgba8();
         for pixel in y_img.pixels_mut() {
             if pixel[0] == ye2[0] && pixel[1] == ye2[1] && pixel[2] == ye2[2] && pixel[3] == ye2[3]
@@ -429,29 +693,13 @@ pub fn makevege(
         }
         let y_img = DynamicImage::ImageRgba8(y_img).to_luma_alpha8();
 
-        y_img
-            .write_to(
-                &mut BufWriter::new(
-                    fs.create(tmpfolder.join("yellow_bit.png"))
-                        .expect("error saving png"),
-                ),
-                image::ImageFormat::Png,
-            )
-            .expect("could not save output png");
+        write_png(fs, &y_img, tmpfolder.join("yellow_bit.png"))?;
 
         let mut img_bit = DynamicImage::ImageLuma8(g_img);
         let img_bit2 = DynamicImage::ImageLumaA8(y_img);
         image::imageops::overlay(&mut img_bit, &img_bit2, 0, 0);
 
-        img_bit
-            .write_to(
-                &mut BufWriter::new(
-                    fs.create(tmpfolder.join("vegetation_bit.png"))
-                        .expect("error saving png"),
-                ),
-                image::ImageFormat::Png,
-            )
-            .expect("could not save output png");
+        write_png(fs, &img_bit, tmpfolder.join("vegetation_bit.png"))?;
     }
 
     let mut imgwater = RgbImage::from_pixel(img_width, img_height, Rgb([255, 255, 255]));
@@ -492,15 +740,7 @@ pub fn makevege(
         }
     }
 
-    imgwater
-        .write_to(
-            &mut BufWriter::new(
-                fs.create(tmpfolder.join("blueblack.png"))
-                    .expect("error saving png"),
-            ),
-            image::ImageFormat::Png,
-        )
-        .expect("could not save output png");
+    write_png(fs, &imgwater, tmpfolder.join("blueblack.png"))?;
 
     drop(imgwater); // explicitly drop imgwater to free memory
 
@@ -637,32 +877,14 @@ pub fn makevege(
         }
         x += bf32 * step;
     }
-    imgug
-        .write_to(
-            &mut BufWriter::new(
-                fs.create(tmpfolder.join("undergrowth.png"))
-                    .expect("error saving png"),
-            ),
-            image::ImageFormat::Png,
-        )
-        .expect("could not save output png");
+    write_png(fs, &imgug, tmpfolder.join("undergrowth.png"))?;
 
     let img_ug_bit_b = median_filter(&img_ug_bit, (bf32 * step) as u32, (bf32 * step) as u32);
 
-    img_ug_bit_b
-        .write_to(
-            &mut BufWriter::new(
-                fs.create(tmpfolder.join("undergrowth_bit.png"))
-                    .expect("error saving png"),
-            ),
-            image::ImageFormat::Png,
-        )
-        .expect("could not save output png");
-
-    let mut writer = BufWriter::new(
-        fs.create(tmpfolder.join("undergrowth.pgw"))
-            .expect("cannot create pgw file"),
-    );
+    write_png(fs, &img_ug_bit_b, tmpfolder.join("undergrowth_bit.png"))?;
+
+    let pgw = tmpfolder.join("undergrowth.pgw");
+    let mut writer = BufWriter::new(fs.create(&pgw).with_file(&pgw)?);
     write!(
         &mut writer,
         "{}\r\n0.0\r\n0.0\r\n{}\r\n{}\r\n{}\r\n",
@@ -670,20 +892,54 @@ pub fn makevege(
         -1.0 / tmpfactor,
         xmin,
         ymax,
-    )
-    .expect("Cannot write pgw file");
+    )?;
+    drop(writer);
 
-    let mut writer = BufWriter::new(
-        fs.create(tmpfolder.join("vegetation.pgw"))
-            .expect("cannot create pgw file"),
-    );
+    let pgw = tmpfolder.join("vegetation.pgw");
+    let mut writer = BufWriter::new(fs.create(&pgw).with_file(&pgw)?);
     write!(
         &mut writer,
         "1.0\r\n0.0\r\n0.0\r\n-1.0\r\n{}\r\n{}\r\n",
         xmin, ymax
-    )
-    .expect("Cannot write pgw file");
+    )?;
+    drop(writer);
+
+    if config.geotiff {
+        let crs = Crs::from_file(fs, tmpfolder.join(CRS_FILE));
+        for png in ["vegetation.png", "undergrowth.png"] {
+            png_to_geotiff(fs, &tmpfolder.join(png), crs.as_ref())?;
+        }
+    }
 
     info!("Done");
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_height_cover() {
+        // left half is 5 m high trees, right half is open
+        let mut grid = Vec2D::new(10, 10, 0.0);
+        for (x, _, v) in grid.iter_mut() {
+            if x < 5 {
+                *v = 5.0;
+            }
+        }
+        let heights = HeightMap {
+            xoffset: 100.0,
+            yoffset: 200.0,
+            scale: 1.0,
+            grid,
+            distance: None,
+        };
+
+        let tall = |hh: f64| if hh > 2.0 { 1.0 } else { 0.0 };
+        assert_eq!(height_cover(&heights, 100.0, 200.0, 10.0, tall), 0.5);
+        assert_eq!(height_cover(&heights, 103.0, 200.0, 4.0, tall), 0.5);
+        assert_eq!(height_cover(&heights, 105.0, 205.0, 5.0, tall), 0.0);
+        assert_eq!(height_cover(&heights, 120.0, 200.0, 5.0, tall), 0.0);
+    }
+}
@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, ResultExt};
+use crate::io::fs::FileSystem;
+
+/// Where a tile is processed: the temp folder with the intermediate files, the internal point
+/// file of a batch tile and the rendered maps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workspace {
+    dir: PathBuf,
+    /// Added to the names of the files, to tell apart the workspaces sharing a folder.
+    suffix: String,
+}
+
+impl Workspace {
+    /// The workspace of a thread of the command line, in the working directory: `temp<thread>`,
+    /// `pullautus<thread>.png` and so on.
+    pub fn thread(thread: &str) -> Self {
+        Self {
+            dir: PathBuf::new(),
+            suffix: thread.to_string(),
+        }
+    }
+
+    /// A workspace of its own in `dir`, that no other worker uses.
+    pub fn isolated(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            suffix: String::new(),
+        }
+    }
+
+    /// The folder of the workspace, empty for the working directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The path of a file of the workspace, with the suffix of the workspace added to the name
+    /// before the extension.
+    pub fn file(&self, name: &str) -> PathBuf {
+        let name = match name.split_once('.') {
+            Some((stem, extension)) => format!("{}{}.{}", stem, self.suffix, extension),
+            None => format!("{}{}", name, self.suffix),
+        };
+        self.dir.join(name)
+    }
+
+    /// The folder of the intermediate files.
+    pub fn tmpfolder(&self) -> PathBuf {
+        self.file("temp")
+    }
+
+    /// The rendered map, with or without the depressions, with the given extension.
+    pub fn map(&self, nodepressions: bool, extension: &str) -> PathBuf {
+        let name = if nodepressions {
+            "pullautus"
+        } else {
+            "pullautus_depr"
+        };
+        self.file(&format!("{}.{}", name, extension))
+    }
+
+    /// Empties an isolated workspace, so a tile starts without the files of the previous one.
+    pub fn clear(&self, fs: &impl FileSystem) -> Result<()> {
+        if fs.exists(&self.dir) {
+            fs.remove_dir_all(&self.dir).with_file(&self.dir)?;
+        }
+        fs.create_dir_all(self.tmpfolder())
+            .with_file(self.tmpfolder())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::fs::memory::MemoryFileSystem;
+
+    #[test]
+    fn test_paths() {
+        let thread = Workspace::thread("2");
+        assert_eq!(thread.tmpfolder(), PathBuf::from("temp2"));
+        assert_eq!(thread.file("temp.xyz.bin"), PathBuf::from("temp2.xyz.bin"));
+        assert_eq!(
+            thread.map(false, "pgw"),
+            PathBuf::from("pullautus_depr2.pgw")
+        );
+        assert_eq!(
+            Workspace::thread("").map(true, "png"),
+            PathBuf::from("pullautus.png")
+        );
+
+        let isolated = Workspace::isolated("work/host-1-1");
+        assert_eq!(isolated.tmpfolder(), PathBuf::from("work/host-1-1/temp"));
+        assert_eq!(
+            isolated.map(true, "png"),
+            PathBuf::from("work/host-1-1/pullautus.png")
+        );
+    }
+
+    #[test]
+    fn test_clear() {
+        let fs = MemoryFileSystem::new();
+        let workspace = Workspace::isolated("work/host-1-1");
+        workspace.clear(&fs).unwrap();
+        fs.create(workspace.map(true, "png")).unwrap();
+        fs.create(workspace.tmpfolder().join("xyz2.hmap")).unwrap();
+
+        workspace.clear(&fs).unwrap();
+        assert!(fs.exists(workspace.tmpfolder()));
+        assert!(!fs.exists(workspace.map(true, "png")));
+        assert!(!fs.exists(workspace.tmpfolder().join("xyz2.hmap")));
+    }
+}